@@ -0,0 +1,336 @@
+//! Rocket routes for administering a cache managed by Rocket, enabled with the `admin` feature.
+
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use rocket::{Route, Request, Data, State};
+use rocket::handler::{Handler, Outcome};
+use rocket::http::{Method, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::content::Json;
+
+use cache::Cache;
+use cache_registry::CacheRegistry;
+use file_source::FileSource;
+use summary::CacheSummary;
+
+
+/// A cache that can be administered with the routes from `routes()`.
+pub trait AdminCache: Send + Sync + 'static {
+    /// Removes the file from the cache, returning false if it wasn't in the cache.
+    fn remove(&self, path: &Path) -> bool;
+    /// Reads the file in the cache again from the filesystem.
+    fn refresh(&self, path: &Path) -> FileSource;
+    /// Removes every file from the cache, returning the number of files removed.
+    fn clear(&self) -> usize;
+    /// Gets a summary of the state of the cache.
+    fn summary(&self) -> CacheSummary;
+}
+
+impl AdminCache for Cache {
+    fn remove(&self, path: &Path) -> bool {
+        Cache::remove(self, path)
+    }
+
+    fn refresh(&self, path: &Path) -> FileSource {
+        Cache::refresh_source(self, path)
+    }
+
+    fn clear(&self) -> usize {
+        Cache::clear(self)
+    }
+
+    fn summary(&self) -> CacheSummary {
+        Cache::summary(self)
+    }
+}
+
+impl AdminCache for CacheRegistry {
+    fn remove(&self, path: &Path) -> bool {
+        CacheRegistry::remove(self, path)
+    }
+
+    fn refresh(&self, path: &Path) -> FileSource {
+        CacheRegistry::refresh_source(self, path)
+    }
+
+    fn clear(&self) -> usize {
+        CacheRegistry::clear(self)
+    }
+
+    fn summary(&self) -> CacheSummary {
+        CacheRegistry::summary(self)
+    }
+}
+
+
+/// Gets the admin routes for the cache of type `C` managed by Rocket, to be mounted under any prefix.
+///
+/// * `POST /purge?path=…` removes the file from the cache.
+/// * `POST /refresh?path=…` reads the file in the cache again, or responds with 404 if it isn't in the cache.
+/// * `POST /clear` removes every file from the cache.
+/// * `GET /stats` responds with the `CacheSummary` of the cache as JSON.
+///
+/// Each request must be accepted by the request guard `G` before the cache is touched.
+/// If the guard fails, its status is returned, and if it forwards, the request is forwarded.
+/// A request for a cache that isn't managed by Rocket fails with 500.
+///
+/// # Example
+///
+/// ```no_run
+/// # extern crate rocket;
+/// # extern crate rocket_file_cache;
+/// use rocket::Outcome;
+/// use rocket::http::Status;
+/// use rocket::request::{self, FromRequest, Request};
+/// use rocket_file_cache::{admin, Cache, CacheBuilder};
+///
+/// struct Admin;
+///
+/// impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+///     type Error = ();
+///     fn from_request(request: &'a Request<'r>) -> request::Outcome<Admin, ()> {
+///         match request.headers().get_one("X-Admin-Token") {
+///             Some("secret") => Outcome::Success(Admin),
+///             _ => Outcome::Failure((Status::Forbidden, ())),
+///         }
+///     }
+/// }
+///
+/// fn main() {
+///     let cache: Cache = CacheBuilder::new().build().unwrap();
+///     rocket::ignite()
+///         .manage(cache)
+///         .mount("/admin/cache", admin::routes::<Cache, Admin>())
+///         .launch();
+/// }
+/// ```
+pub fn routes<C, G>() -> Vec<Route>
+    where C: AdminCache,
+          G: for<'a, 'r> FromRequest<'a, 'r> + 'static
+{
+    vec![
+        Route::new(Method::Post, "/purge", AdminHandler::<C, G>::new(Action::Purge)),
+        Route::new(Method::Post, "/refresh", AdminHandler::<C, G>::new(Action::Refresh)),
+        Route::new(Method::Post, "/clear", AdminHandler::<C, G>::new(Action::Clear)),
+        Route::new(Method::Get, "/stats", AdminHandler::<C, G>::new(Action::Stats)),
+    ]
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Purge,
+    Refresh,
+    Clear,
+    Stats,
+}
+
+struct AdminHandler<C, G> {
+    action: Action,
+    // The handler holds neither the cache nor the guard, so it is Send and Sync regardless of them.
+    marker: PhantomData<fn() -> (C, G)>,
+}
+
+impl<C, G> AdminHandler<C, G> {
+    fn new(action: Action) -> AdminHandler<C, G> {
+        AdminHandler {
+            action,
+            marker: PhantomData,
+        }
+    }
+}
+
+// Derived Clone would require the cache and guard to be Clone.
+impl<C, G> Clone for AdminHandler<C, G> {
+    fn clone(&self) -> AdminHandler<C, G> {
+        AdminHandler::new(self.action)
+    }
+}
+
+impl<C, G> Handler for AdminHandler<C, G>
+    where C: AdminCache,
+          G: for<'a, 'r> FromRequest<'a, 'r> + 'static
+{
+    fn handle<'r>(&self, request: &'r Request, data: Data) -> Outcome<'r> {
+        match request.guard::<G>() {
+            request::Outcome::Success(_) => {}
+            request::Outcome::Failure((status, _)) => return Outcome::failure(status),
+            request::Outcome::Forward(()) => return Outcome::forward(data),
+        }
+        let cache: &C = match request.guard::<State<C>>() {
+            request::Outcome::Success(state) => state.inner(),
+            _ => {
+                error!("The cache administered by the admin routes is not managed by Rocket.");
+                return Outcome::failure(Status::InternalServerError);
+            }
+        };
+
+        match self.action {
+            Action::Purge => {
+                let path: PathBuf = match path_query(request) {
+                    Some(path) => path,
+                    None => return Outcome::failure(Status::BadRequest),
+                };
+                let removed: bool = cache.remove(&path);
+                info!("Purged {:?} from the cache, removed: {}.", path, removed);
+                Outcome::from(request, Json(format!("{{\"removed\":{}}}", removed)))
+            }
+            Action::Refresh => {
+                let path: PathBuf = match path_query(request) {
+                    Some(path) => path,
+                    None => return Outcome::failure(Status::BadRequest),
+                };
+                match cache.refresh(&path) {
+                    FileSource::Cached(_) => {
+                        info!("Refreshed {:?} in the cache.", path);
+                        Outcome::from(request, Json(String::from("{\"refreshed\":true}")))
+                    }
+                    _ => Outcome::failure(Status::NotFound),
+                }
+            }
+            Action::Clear => {
+                let removed: usize = cache.clear();
+                info!("Cleared {} files from the cache.", removed);
+                Outcome::from(request, Json(format!("{{\"removed\":{}}}", removed)))
+            }
+            Action::Stats => Outcome::from(request, Json(cache.summary().to_json())),
+        }
+    }
+}
+
+/// Gets the path from the `path` query parameter.
+fn path_query(request: &Request) -> Option<PathBuf> {
+    match request.get_query_value::<String>("path") {
+        Some(Ok(ref path)) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use self::tempdir::TempDir;
+    use cache_builder::CacheBuilder;
+    use rocket::http::Header;
+    use rocket::local::Client;
+    use std::fs;
+
+    struct AdminKey;
+
+    impl<'a, 'r> FromRequest<'a, 'r> for AdminKey {
+        type Error = ();
+        fn from_request(request: &'a Request<'r>) -> request::Outcome<AdminKey, ()> {
+            match request.headers().get_one("X-Admin-Key") {
+                Some("secret") => request::Outcome::Success(AdminKey),
+                _ => request::Outcome::Failure((Status::Forbidden, ())),
+            }
+        }
+    }
+
+    fn key() -> Header<'static> {
+        Header::new("X-Admin-Key", "secret")
+    }
+
+    fn client<C: AdminCache>(cache: C) -> Client {
+        let rocket = ::rocket::ignite()
+            .manage(cache)
+            .mount("/admin/cache", routes::<C, AdminKey>());
+        Client::new(rocket).unwrap()
+    }
+
+    fn managed_cache(client: &Client) -> &Cache {
+        let request = client.get("/");
+        let cache: &Cache = match request.inner().guard::<State<Cache>>() {
+            request::Outcome::Success(state) => state.inner(),
+            _ => panic!("Expected the cache to be managed."),
+        };
+        cache
+    }
+
+    #[test]
+    fn admin_routes_act_on_the_managed_cache() {
+        let temp_dir = TempDir::new("admin").unwrap();
+        let a = temp_dir.path().join("a.txt");
+        let b = temp_dir.path().join("b.txt");
+        fs::write(&a, b"aaaa").unwrap();
+        fs::write(&b, b"bbbbbbbb").unwrap();
+
+        let client = client(CacheBuilder::new().size_limit(1024).build().unwrap());
+        let cache: &Cache = managed_cache(&client);
+        cache.get_source(&a);
+        cache.get_source(&b);
+        assert_eq!(cache.used_bytes(), 12);
+
+        let mut response = client.get("/admin/cache/stats").header(key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let stats: String = response.body_string().unwrap();
+        assert!(stats.contains("\"used_bytes\":12,\"file_count\":2,"), "{}", stats);
+
+        fs::write(&a, b"aaaaaaaaaaaaaaaa").unwrap();
+        let mut response = client.post(format!("/admin/cache/refresh?path={}", a.display())).header(key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some(String::from("{\"refreshed\":true}")));
+        assert_eq!(cache.used_bytes(), 24);
+
+        let mut response = client.post(format!("/admin/cache/purge?path={}", b.display())).header(key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some(String::from("{\"removed\":true}")));
+        assert!(!cache.contains_key(&b));
+        assert_eq!(cache.used_bytes(), 16);
+
+        // Files that aren't in the cache can't be refreshed.
+        let response = client.post(format!("/admin/cache/refresh?path={}", b.display())).header(key()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.post("/admin/cache/purge").header(key()).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let mut response = client.post("/admin/cache/clear").header(key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some(String::from("{\"removed\":1}")));
+        assert!(!cache.contains_key(&a));
+        assert_eq!(cache.used_bytes(), 0);
+    }
+
+    #[test]
+    fn admin_routes_require_the_guard() {
+        let temp_dir = TempDir::new("admin").unwrap();
+        let a = temp_dir.path().join("a.txt");
+        fs::write(&a, b"aaaa").unwrap();
+
+        let client = client(CacheBuilder::new().size_limit(1024).build().unwrap());
+        let cache: &Cache = managed_cache(&client);
+        cache.get_source(&a);
+
+        let response = client.post("/admin/cache/clear").dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client.post(format!("/admin/cache/purge?path={}", a.display())).header(Header::new("X-Admin-Key", "guess")).dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(cache.contains_key(&a));
+    }
+
+    #[test]
+    fn admin_routes_administer_a_registry() {
+        let temp_dir = TempDir::new("admin").unwrap();
+        let a = temp_dir.path().join("a.txt");
+        fs::write(&a, b"aaaa").unwrap();
+
+        let mut registry = CacheRegistry::new();
+        registry.set_default(CacheBuilder::new().size_limit(1024).build().unwrap());
+        let client = client(registry);
+        let request = client.get("/");
+        let registry: &CacheRegistry = match request.inner().guard::<State<CacheRegistry>>() {
+            request::Outcome::Success(state) => state.inner(),
+            _ => panic!("Expected the registry to be managed."),
+        };
+        registry.get_source(&a);
+        assert_eq!(registry.summary().used_bytes, 4);
+
+        let response = client.post("/admin/cache/clear").header(key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(registry.summary().used_bytes, 0);
+    }
+}
@@ -0,0 +1,50 @@
+use std::path::{PathBuf, Path};
+
+use cache::CacheError;
+
+
+/// The outcome `Cache::check()` predicts for inserting a file into the cache.
+#[derive(Debug, PartialEq)]
+pub enum AdmissionPrediction {
+    /// The file is already in the cache.
+    AlreadyInCache,
+    /// The file would not be stored in the cache, for the given reason.
+    Rejected(CacheError),
+    /// The file would be stored in the cache.
+    /// The token can be passed to `Cache::commit_insert()` to store it.
+    Admit(AdmissionToken),
+}
+
+
+/// The work done by `Cache::check()` to decide that a file should be inserted.
+///
+/// `Cache::commit_insert()` uses this to insert the file without repeating the work,
+/// as long as the file and the files chosen for eviction haven't changed in the meantime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdmissionToken {
+    pub(crate) path: PathBuf,
+    pub(crate) size: usize,
+    /// The files chosen for eviction, and their sizes when they were chosen.
+    pub(crate) victims: Vec<(PathBuf, usize)>,
+}
+
+impl AdmissionToken {
+    /// The path of the file that would be inserted.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The size of the file when it was checked.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The paths of the files that would be evicted to make room for the file,
+    /// in the order they were chosen.
+    pub fn victims(&self) -> Vec<&Path> {
+        self.victims
+            .iter()
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+}
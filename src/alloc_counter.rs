@@ -0,0 +1,48 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+
+/// Counts the heap allocations made by each thread, so tests can check that a code path doesn't allocate.
+struct CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+thread_local! {
+    // Initialized without allocating, as it is used by the allocator itself.
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count_allocation() {
+    // The counter is gone while the thread is being torn down, and those allocations don't matter.
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+}
+
+/// Runs the function, returning its result and the number of allocations it made on the current thread.
+pub(crate) fn allocations_during<R, F: FnOnce() -> R>(function: F) -> (R, usize) {
+    let before: usize = ALLOCATIONS.with(|allocations| allocations.get());
+    let result: R = function();
+    let after: usize = ALLOCATIONS.with(|allocations| allocations.get());
+    (result, after - before)
+}
@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use policy::CandidateFile;
+
+
+/// The eviction candidates shared by the files requested together with `Cache::get_source_many()`,
+/// so the files in the cache are sorted once for the whole batch instead of once for every file that needs room.
+#[derive(Debug)]
+pub(crate) struct BatchPlan {
+    state: Mutex<Option<BatchState>>,
+}
+
+#[derive(Debug)]
+struct BatchState {
+    /// The files requested in the batch, which are never chosen to make room for each other.
+    paths: HashSet<PathBuf>,
+    /// The candidates that haven't been chosen yet, once they were sorted.
+    candidates: Option<Vec<CandidateFile>>,
+}
+
+impl BatchPlan {
+    pub(crate) fn new() -> BatchPlan {
+        BatchPlan {
+            state: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn start(&self, paths: &[PathBuf]) {
+        *self.lock() = Some(BatchState {
+            paths: paths.iter().cloned().collect(),
+            candidates: None,
+        });
+    }
+
+    pub(crate) fn finish(&self) {
+        *self.lock() = None;
+    }
+
+    /// Gets the candidates of the batch, sorting them with `sort` the first time they are needed.
+    /// Returns None if no batch is in progress.
+    pub(crate) fn candidates<F: FnOnce() -> Vec<CandidateFile>>(&self, sort: F) -> Option<Vec<CandidateFile>> {
+        let mut state = self.lock();
+        let state: &mut BatchState = state.as_mut()?;
+        if state.candidates.is_none() {
+            let paths: &HashSet<PathBuf> = &state.paths;
+            let mut candidates: Vec<CandidateFile> = sort();
+            candidates.retain(|candidate| !paths.contains(&*candidate.key));
+            state.candidates = Some(candidates);
+        }
+        state.candidates.clone()
+    }
+
+    /// Removes the files chosen for eviction from the candidates of the batch.
+    pub(crate) fn on_chosen(&self, victims: &[(PathBuf, usize)]) {
+        if let Some(BatchState { candidates: Some(ref mut candidates), .. }) = *self.lock() {
+            let chosen: HashSet<&Path> = victims.iter().map(|(key, _)| key.as_path()).collect();
+            candidates.retain(|candidate| !chosen.contains(&*candidate.key));
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Option<BatchState>> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
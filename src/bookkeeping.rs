@@ -0,0 +1,13 @@
+use std::mem;
+use std::path::{Path, PathBuf};
+
+
+/// The bytes a hash map spends on each entry besides its key and value:
+/// the stored hash, its control bytes, and a share of the slots kept empty so the map doesn't fill up.
+const ENTRY_OVERHEAD: usize = 16;
+
+/// Estimates the bytes held by an entry of a map keyed by the path, with values of type `V`.
+/// Memory the value owns on the heap isn't counted, so callers add it themselves.
+pub(crate) fn path_entry_bytes<V>(path: &Path) -> usize {
+    mem::size_of::<PathBuf>() + path.as_os_str().len() + mem::size_of::<V>() + ENTRY_OVERHEAD
+}
@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use priority_function::MAX_PRIORITY;
+
+
+/// A temporary multiplier for the priority of the files under a prefix, as returned by `Cache::active_boosts()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Boost {
+    /// The files under this prefix are boosted.
+    pub prefix: PathBuf,
+    /// The number the priority of the files is multiplied by.
+    pub multiplier: f32,
+    /// When the boost ends, by the clock of the cache.
+    pub expires_at: Instant,
+}
+
+
+/// The boosts set with `Cache::boost()`, including ones that may have expired since.
+#[derive(Debug, Default)]
+pub(crate) struct Boosts {
+    boosts: Vec<Boost>,
+}
+
+impl Boosts {
+    pub(crate) fn new() -> Boosts {
+        Boosts {
+            boosts: vec![],
+        }
+    }
+
+    /// Adds the boost, forgetting the boosts that expired before now.
+    pub(crate) fn add(&mut self, boost: Boost, now: Instant) {
+        self.boosts.retain(|boost| boost.expires_at > now);
+        self.boosts.push(boost);
+    }
+
+    /// Removes every boost for the prefix, returning whether there were any.
+    pub(crate) fn clear(&mut self, prefix: &Path) -> bool {
+        let boost_count: usize = self.boosts.len();
+        self.boosts.retain(|boost| boost.prefix != prefix);
+        self.boosts.len() != boost_count
+    }
+
+    /// The boosts that haven't expired by now, in the order they were set.
+    pub(crate) fn active(&self, now: Instant) -> Vec<Boost> {
+        self.boosts.iter().filter(|boost| boost.expires_at > now).cloned().collect()
+    }
+
+    /// Whether no boosts were set, so priorities don't have to be boosted, and the clock doesn't have to be read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.boosts.is_empty()
+    }
+
+    /// Multiplies the priority of the file by every boost it is under that hasn't expired by now.
+    pub(crate) fn apply(&self, path: &Path, priority: usize, now: Instant) -> usize {
+        let multiplier: f64 = self.boosts
+            .iter()
+            .filter(|boost| boost.expires_at > now && path.starts_with(&boost.prefix))
+            .fold(1.0, |multiplier, boost| multiplier * boost.multiplier as f64);
+        (priority as f64 * multiplier).min(MAX_PRIORITY as f64) as usize
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn overlapping_boosts_multiply_until_they_expire() {
+        let now: Instant = Instant::now();
+        let mut boosts = Boosts::new();
+        let boost = |prefix: &str, multiplier: f32, seconds: u64| Boost {
+            prefix: PathBuf::from(prefix),
+            multiplier,
+            expires_at: now + Duration::from_secs(seconds),
+        };
+        boosts.add(boost("www/landing", 2.0, 60), now);
+        boosts.add(boost("www/landing/hero", 3.0, 60), now);
+        boosts.add(boost("www/old", 10.0, 0), now);
+
+        assert_eq!(boosts.apply(Path::new("www/landing/hero/video.mp4"), 100, now), 600);
+        assert_eq!(boosts.apply(Path::new("www/landing/index.html"), 100, now), 200);
+        assert_eq!(boosts.apply(Path::new("www/landing_page.html"), 100, now), 100);
+        assert_eq!(boosts.apply(Path::new("www/old/index.html"), 100, now), 100);
+        assert_eq!(boosts.apply(Path::new("www/landing/index.html"), MAX_PRIORITY, now), MAX_PRIORITY);
+        assert_eq!(boosts.active(now).len(), 2);
+
+        assert!(boosts.clear(Path::new("www/landing")));
+        assert!(!boosts.clear(Path::new("www/landing")));
+        assert_eq!(boosts.apply(Path::new("www/landing/hero/video.mp4"), 100, now), 300);
+    }
+}
@@ -1,27 +1,278 @@
-use std::path::{PathBuf, Path};
+use std::path::{PathBuf, Path, Component};
 use std::usize;
-use rocket::response::NamedFile;
 use std::fs::Metadata;
 use std::fs;
+use std::ffi::OsString;
 use named_in_memory_file::NamedInMemoryFile;
+#[cfg(feature = "rocket")]
 use cached_file::CachedFile;
-use in_memory_file::InMemoryFile;
+use file_source::FileSource;
+use in_memory_file::{InMemoryFile, FileBytes, FileIdentity};
+use classification_filter::ClassificationFilter;
+use extension_stats::{ExtensionStats, ExtensionCounters, OTHER_EXTENSIONS, extension_of};
 use concurrent_hashmap::ConcHashMap;
 use std::collections::hash_map::RandomState;
 use std::fmt::Debug;
+use std::cmp;
 use std::fmt;
 use std::fmt::Formatter;
 use in_memory_file::FileStats;
-
-#[derive(Debug, PartialEq)]
-enum CacheError {
+use in_flight::{InFlightReads, Flight};
+use cold_tier::{ColdTier, ColdTierConfig};
+use summary::CacheSummary;
+use snapshot::CacheSnapshot;
+use entry_handle::EntryHandle;
+use content_index::{self, ContentIndex, ContentAddressedConfig, Departure};
+use content_hash;
+use deadline;
+use get_options::GetOptions;
+use key_case::KeyCase;
+use read_limiter::{ReadLimiter, ReadPermit};
+use observer::{CacheObserver, EvictionVote, EvictionFilter};
+use admission::{AdmissionPrediction, AdmissionToken};
+use verify::{VerifyReport, Inconsistency, InvariantViolation};
+use decision_log::{DecisionLog, Decision, Outcome};
+use prefetch::{self, PrefetchReport, PrefetchSkipReason, PrefetchConfig, SiblingPrefetch, PreloadOptions, PreloadProgress, PreloadTracker};
+use manifest::{self, ManifestReport, ManifestOutcome};
+use size::ParseSizeError;
+use cache_builder::CacheBuilder;
+use resolution::Resolution;
+use refresh_options::{RefreshOptions, RefreshOutcome};
+use directory_floors::DirectoryFloors;
+use directory_quotas::DirectoryQuotas;
+use boosts::{Boost, Boosts};
+use churn::ChurnTracker;
+use ghost_entries::GhostEntries;
+use fallbacks::FallbackResolutions;
+use zombie_bytes::ZombieBytes;
+use latency::{LatencyRecorder, LatencyReport, Phase};
+use storage::Storage;
+use tombstones::Tombstones;
+use served_bytes::{ServedBytes, PathMetrics};
+use bookkeeping::path_entry_bytes;
+use policy::{self, CandidateFile, RoomRequest, VictimComparator};
+use replay::{TraceRecorder, Trace};
+use thrash_protection::{ThrashProtection, ThrashConfig};
+use fs_health::{FsHealth, DegradedModeConfig};
+use refresh_limiter::{RefreshLimiter, RateLimit};
+use batch_plan::BatchPlan;
+use small_file_arena::{SmallFileArena, SmallFileArenaConfig};
+use labeled_metrics::{LabeledMetrics, LabeledSummary};
+use priority_function::{MAX_PRIORITY, PriorityFunctionReport};
+use operations::{Operations, Operation};
+use serve_class::ServeClass;
+use std::collections::{HashMap, HashSet};
+use spill::{self, SpillSummary, SpillRecord, RestoreOptions, RestoreSummary};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::{Duration, SystemTime, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, AtomicU64, AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::mem;
+use std::thread;
+
+/// Error types that can be encountered when the cache tries to store a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheError {
     NoMoreFilesToRemove,
     NewPriorityIsNotHighEnough,
     InvalidMetadata,
     InvalidPath,
+    TooManyEvictionCandidates,
+    /// The file is smaller than the min_file_size, or larger than the max_file_size.
+    FileSizeOutOfBounds,
+    /// The file is larger than the request allowed to be inserted.
+    SkippedByRequest,
+    /// The file couldn't be read into memory.
+    CouldNotReadFile,
+    /// The space freed for the file was used by another insertion,
+    /// after another thread removed the files that were evicted for it.
+    SpaceTakenByConcurrentInsertion,
+    /// The file is already in the cache.
+    AlreadyInCache,
+    /// The path can't be used as a key in the cache.
+    InvalidKey(InvalidKeyReason),
+    /// The cache is frozen, so its contents can't be changed.
+    CacheFrozen,
+    /// Another request was reading the file into the cache, and didn't store it in time.
+    ConcurrentReadNotFinished,
+    /// The file isn't in the cache.
+    NotCached,
+    /// Storing the file would take a directory over its quota, and not enough files under that directory
+    /// could be evicted to make room for it.
+    DirectoryQuotaExceeded,
+    /// The file kept being evicted and inserted again, so thrash protection is keeping it out of the cache for a while.
+    Thrashing,
+    /// The file was removed with `Cache::remove_and_block()`, and is kept out of the cache until the block ends.
+    Blocked,
+    /// The cache has a size limit of 0, so it never stores files.
+    CacheDisabled,
+    /// The path was added to the bypass list with `Cache::always_bypass()`, so it is never stored.
+    Bypassed,
+    /// Files had to be evicted to make room for the file, but the eviction filter denied every one of them.
+    AllCandidatesVetoed,
+    /// The cache is in `Mode::Manual`, and the file doesn't fit without evicting other files,
+    /// which a cache in manual mode never does on its own.
+    CacheFull,
+    /// The path is a directory. `get()` serves its index file instead, if it has one, see `Cache::set_index_files()`.
+    IsDirectory,
+    /// There is no file at the path, as reported by `get_result()`.
+    NotFound,
+    /// The file at the path can't be read with the permissions of the server, as reported by `get_result()`.
+    PermissionDenied,
+    /// Storing the file would take the used bytes, together with the bytes of evicted files that are still being served,
+    /// over the ceiling set with `Cache::set_memory_ceiling()`.
+    MemoryCeilingExceeded,
+}
+
+/// The reason a path was rejected as a key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvalidKeyReason {
+    /// The path is empty.
+    Empty,
+    /// The path contains a NUL byte.
+    ContainsNul,
+    /// The path contains a `..` component, and the cache was configured to reject those.
+    ContainsParentDirectory,
+}
+
+
+/// Describes how a file was added to the cache.
+#[derive(Debug, PartialEq)]
+pub enum CacheInsertionSuccess {
+    /// The file fit into the cache without removing any other files.
+    SimpleAddition,
+    /// Files had to be evicted to make room for the new file.
+    /// The evicted files are listed in the order they were chosen for eviction.
+    ReplacedFile(Vec<EvictedEntry>),
+    /// Another insertion of the same file, like a concurrent request for it, stored it after it was found missing.
+    /// The file replaced that copy, and only the difference in their sizes was accounted for.
+    ReplacedExistingEntry,
+}
+
+/// A file that was evicted from the cache.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvictedEntry {
+    /// The key the file was stored under.
+    pub path: PathBuf,
+    /// The number of bytes the file held.
+    pub size: usize,
+    /// The access count of the file when it was evicted.
+    pub access_count: usize,
+}
+
+
+/// Allows insertions to temporarily push the cache over its size limit,
+/// deferring the eviction of files to later cache operations.
+///
+/// This keeps the cost of evicting many files off of the request that caused the insertion.
+/// The cache will never hold more than `size_limit * (1 + fraction)` bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OvershootConfig {
+    /// The fraction of the size limit that the cache is allowed to exceed it by.
+    /// A value of 0.1 lets the cache grow to 110% of its size limit before insertions have to evict files.
+    pub fraction: f64,
+    /// The maximum number of files that will be evicted by a single cache operation
+    /// while the cache is over its size limit.
+    pub evictions_per_operation: usize,
+}
+
+
+/// Determines how often the priority of a file in the cache is recalculated when the file is accessed.
+///
+/// Regardless of this setting, priorities are always recalculated when a file is inserted or refreshed,
+/// and before files are chosen for eviction, so evictions are based on current access counts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recompute {
+    /// Recalculate the priority every time the file is accessed. This is the default.
+    EveryAccess,
+    /// Recalculate the priority once every N accesses of the file.
+    EveryN(usize),
+    /// Only calculate the priority when the file is inserted into the cache.
+    OnInsertOnly,
+}
+
+/// Determines whether cache hits are checked against the file in the filesystem before they are served.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Consistency {
+    /// Serve cached files without looking at the filesystem. This is the default.
+    /// Changes to files are only picked up when they are refreshed.
+    Fast,
+    /// Read the metadata of the file on every hit, and refresh the file before serving it if its size or
+    /// modification time has changed. If the file no longer exists, it is handled according to `OnMissing`.
+    ///
+    /// This costs one `stat` syscall per hit.
+    Paranoid,
+}
+
+
+/// Determines how a cached file is compared with the file in the filesystem to tell if it has changed,
+/// when `Consistency::Paranoid` checks a hit, or a soft purged file is requested.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Revalidation {
+    /// The file has changed if its size or modification time has. This is the default.
+    SizeAndModified,
+    /// The file has also changed if it was replaced by another file, like when a deploy renames a new file over it,
+    /// even if the new file has the same size and modification time.
+    ///
+    /// Files are identified by their device and inode on unix. On other platforms, files have no identity,
+    /// so this is the same as `SizeAndModified`.
+    ByIdentity,
+}
+
+
+/// Determines what happens to a cached file when a refresh finds that it no longer exists in the filesystem.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnMissing {
+    /// Keep the entry, and keep serving the cached bytes.
+    ServeStale,
+    /// Remove the entry from the cache, so the file is no longer served. This is the default.
+    /// The access count of the file is kept, like with `Cache::remove()`.
+    Remove,
+}
+
+
+/// Determines when a request for a file that isn't in the cache counts as an access of the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CountPolicy {
+    /// Count the access as soon as the metadata of the file has been read. This is the default.
+    OnRequest,
+    /// Count the access only once the file has been stored in the cache, or opened to be served from the filesystem,
+    /// so files that exist but can't be read don't gain priority from requests that fail.
+    ///
+    /// The request still counts towards the priority the file is admitted with.
+    OnSuccess,
 }
 
 
+/// Determines whether the cache decides on its own which files it holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Requested files are inserted if they are worth their space, evicting files with lower priorities to make room.
+    /// This is the default.
+    Automatic,
+    /// The cache only holds the files that are inserted explicitly, with `insert()` for instance.
+    ///
+    /// `get()` serves the files the cache holds and opens every other file from the filesystem,
+    /// without counting accesses, updating stats, or trying to insert anything.
+    /// Insertions still respect the size limit, but never evict files to make room:
+    /// a file that doesn't fit fails with `CacheError::CacheFull`, and `remove()` is what makes room.
+    Manual,
+}
+
+
+/// Determines the key the index file of a directory is cached under, see `Cache::set_index_files()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexKey {
+    /// The index file is cached under its own path, so the directory and its index file share one entry.
+    /// Requests for the directory read the metadata of the directory and of its index file every time.
+    /// This is the default.
+    File,
+    /// The index file is cached under the path of the directory, separately from the index file itself.
+    /// Requests for the directory are hits once it is cached, but the index file may be cached twice.
+    Directory,
+}
+
 
 /// The cache holds a number of files whose bytes fit into its size_limit.
 /// The cache acts as a proxy to the filesystem, returning cached files if they are in the cache,
@@ -42,6 +293,10 @@ enum CacheError {
 /// This will repeat until either enough space can be freed for the new file, and the new file is
 /// inserted, or until the priority of the cached files is greater than that of the new file,
 /// in which case, the new file isn't inserted.
+///
+/// The cache is `Send` and `Sync`, so one cache can be shared by every thread of a server, as Rocket managed state
+/// or behind an `Arc`. Every method that serves files takes `&self`, except `get_source_many()`.
+/// The files it returns while they are locked in the cache can't be sent to another thread, `detach()` them first.
 pub struct Cache {
     /// The number of bytes the file_map should be able hold at once.
     pub(crate) size_limit: usize,
@@ -49,45 +304,211 @@ pub struct Cache {
     pub(crate) min_file_size: usize,
     /// The maximum number of bytes a file can have in order to be accepted into the Cache.
     pub(crate) max_file_size: usize,
+    /// The share of the size limit the largest file may take, if the max_file_size follows the size limit.
+    pub(crate) max_file_fraction: Option<f32>,
     /// The function that is used to calculate the priority score that is used to determine which files should be in the cache.
     pub(crate) priority_function: fn(usize, usize) -> usize,
     /// If a given file's access count modulo this value equals 0, then that file will be refreshed from the FileSystem instead of from the Cache.
     pub(crate) accesses_per_refresh: Option<usize>,
     pub(crate) file_map: ConcHashMap<PathBuf, InMemoryFile, RandomState>, // Holds the files that the cache is caching
     pub(crate) access_count_map: ConcHashMap<PathBuf, usize, RandomState>, // Every file that is accessed will have the number of times it is accessed logged in this map.
+    /// Paths that are currently being read into memory, used to coalesce concurrent misses for the same file.
+    pub(crate) in_flight_reads: InFlightReads,
+    /// How long a miss will wait for another thread's read of the same file before falling back to the filesystem.
+    pub(crate) coalesce_timeout: Duration,
+    /// The number of times a file has been read from the filesystem into memory.
+    pub(crate) file_reads: AtomicUsize,
+    /// The number of times a file has been opened to be served from the filesystem.
+    pub(crate) fs_opens: AtomicUsize,
+    /// How far past the size_limit insertions may go before they have to evict files themselves.
+    pub(crate) overshoot: Option<OvershootConfig>,
+    /// Set when an insertion may have left the cache over its size_limit.
+    pub(crate) overshoot_eviction_pending: AtomicBool,
+    /// Holds compressed copies of files evicted from the cache, if configured.
+    pub(crate) cold_tier: Option<ColdTier>,
+    /// Maps alternative paths to the path of the file they should be served as.
+    pub(crate) aliases: ConcHashMap<PathBuf, PathBuf, RandomState>,
+    /// If set, `content_hash()` will hash files that aren't in the cache by reading them from the filesystem.
+    pub(crate) hash_uncached_files: bool,
+    /// The number of bytes held by the files in the cache, plus the bytes reserved by insertions in progress.
+    pub(crate) resident_bytes: AtomicUsize,
+    /// Whether paths that differ only in case share an entry. Paths are cleaned up lexically whatever it is.
+    pub(crate) key_case: KeyCase,
+    /// If set, paths containing `..` components are rejected.
+    pub(crate) reject_parent_directories: bool,
+    /// Limits how many files can be read into memory by insertions at once.
+    pub(crate) read_limiter: ReadLimiter,
+    /// Paths of files that were found to be outside of the min_file_size and max_file_size bounds,
+    /// along with whether they were too large or too small.
+    pub(crate) out_of_bounds: ConcHashMap<PathBuf, ServeClass, RandomState>,
+    /// Records the paths that were found to be outside of the size bounds instead of `out_of_bounds`, if set.
+    pub(crate) classification_filter: Option<ClassificationFilter>,
+    /// Counts the hits, misses, insertions and evictions of the files with each extension.
+    pub(crate) extension_counters: ExtensionCounters,
+    /// The number of times the metadata of a file was read to get its size, or to check if it changed.
+    pub(crate) metadata_reads: AtomicUsize,
+    /// Maps keys added with `get_keyed()` to the paths of their files in the filesystem.
+    pub(crate) fs_paths: ConcHashMap<PathBuf, PathBuf, RandomState>,
+    /// The paths files requested with `get_source_with_fallbacks()` were found at.
+    pub(crate) fallback_resolutions: FallbackResolutions,
+    /// Notified of evictions, if set.
+    pub(crate) observer: Option<Box<dyn CacheObserver>>,
+    /// Decides which files may be evicted, if set.
+    pub(crate) eviction_filter: Option<EvictionFilter>,
+    /// Orders every file for eviction instead of their priorities and the tie breaker, if set.
+    pub(crate) victim_comparator: Option<VictimComparator>,
+    /// How often the priority of a file is recalculated when it is accessed.
+    pub(crate) priority_recompute: Recompute,
+    /// How long a request will wait for a file to be read into memory, if set.
+    pub(crate) read_deadline: Option<Duration>,
+    /// The number of bytes the cache may hold before insertions have to evict files.
+    pub(crate) high_watermark: usize,
+    /// The number of bytes insertions evict down to once the high watermark is crossed.
+    pub(crate) low_watermark: usize,
+    /// Orders files with the same priority for eviction.
+    pub(crate) tie_breaker: fn(&Path, &FileStats, &Path, &FileStats) -> cmp::Ordering,
+    /// Incremented every time a file is stored or accessed, to record the order files were last used in.
+    pub(crate) access_clock: AtomicUsize,
+    /// While set, the cache serves what it holds without changing anything.
+    pub(crate) frozen: AtomicBool,
+    /// Records recent decisions made by `get()`, if set.
+    pub(crate) decision_log: Option<DecisionLog>,
+    /// Whether hits are checked against the filesystem before they are served.
+    pub(crate) consistency: Consistency,
+    /// How cached files are compared with the filesystem.
+    pub(crate) revalidation: Revalidation,
+    /// What happens to cached files that no longer exist in the filesystem.
+    pub(crate) on_missing: OnMissing,
+    /// What happens to soft purged files that can't be checked against the filesystem.
+    pub(crate) soft_purge_on_missing: OnMissing,
+    /// When requests for files that aren't in the cache count as accesses.
+    pub(crate) count_policy: CountPolicy,
+    /// Whether the cache inserts and evicts files on its own.
+    pub(crate) mode: Mode,
+    /// The names of the files served for a directory, in the order they are tried.
+    pub(crate) index_files: Vec<String>,
+    /// The key the index file of a directory is cached under.
+    pub(crate) index_key: IndexKey,
+    /// The number of bytes under each directory that are never evicted.
+    pub(crate) directory_floors: DirectoryFloors,
+    /// The most bytes the files under each directory may hold.
+    pub(crate) directory_quotas: DirectoryQuotas,
+    /// Temporary multipliers for the priorities of the files under some prefixes.
+    pub(crate) boosts: Boosts,
+    /// How often each file was evicted and inserted again.
+    pub(crate) churn: ChurnTracker,
+    /// Counts the bytes served from the cache and from the filesystem.
+    pub(crate) served_bytes: ServedBytes,
+    /// Paths that are kept out of the cache after they were removed.
+    pub(crate) tombstones: Tombstones,
+    /// Paths that are always served from the filesystem.
+    pub(crate) bypassed: HashSet<PathBuf>,
+    /// Keeps files that thrash out of the cache, if set.
+    pub(crate) thrash_protection: Option<ThrashProtection>,
+    /// Remembers recently evicted files to estimate the hits of a larger cache, if set.
+    pub(crate) ghost_entries: Option<GhostEntries>,
+    /// The files inserted after a miss whose siblings wait to be prefetched, if set.
+    pub(crate) sibling_prefetch: Option<SiblingPrefetch>,
+    /// The bytes of files that left the cache while they were still shared.
+    pub(crate) zombie_bytes: ZombieBytes,
+    /// The most bytes the used bytes and the zombie bytes may add up to before files stop being stored, if set.
+    pub(crate) memory_ceiling: Option<usize>,
+    /// The source of the time used by thrash protection, which tests can replace.
+    pub(crate) clock: fn() -> Instant,
+    /// Where the bytes of files read from the filesystem are kept.
+    pub(crate) storage: Storage,
+    /// Whether the phases of serving files are timed.
+    pub(crate) timing: bool,
+    /// Whether the invariants of the cache are checked after it is changed, in debug builds.
+    pub(crate) strict_invariants: bool,
+    /// The operations changing the cache, so the invariants are only checked while no other change is halfway done.
+    pub(crate) operations: Operations,
+    /// The timings of the phases of serving files, and their budgets.
+    pub(crate) latency: LatencyRecorder,
+    /// Records the operations on the cache, if set.
+    pub(crate) trace: Option<TraceRecorder>,
+    /// How long files are spared from eviction after they are stored, if set.
+    pub(crate) min_residency: Option<Duration>,
+    /// Whether files are hashed when they are stored, and checked against the hash every time they are served.
+    pub(crate) verify_checksums: bool,
+    /// The number of times a file was served whose declared size disagreed with its bytes.
+    pub(crate) size_mismatches: AtomicUsize,
+    /// The number of times a file was about to be served whose bytes no longer matched their stored hash.
+    pub(crate) checksum_mismatches: AtomicUsize,
+    /// Makes misses fail fast while the filesystem keeps failing, if set.
+    pub(crate) fs_health: Option<FsHealth>,
+    /// Limits the rate of the refreshes triggered by serving requests, if set.
+    pub(crate) refresh_limiter: Option<RefreshLimiter>,
+    /// Points paths at the content they are stored under, if the cache is content addressed.
+    pub(crate) content_index: Option<ContentIndex>,
+    /// The eviction candidates shared by the files of a call to `get_source_many()`.
+    pub(crate) batch_plan: BatchPlan,
+    /// The number of times the files in the cache were sorted to choose files to evict.
+    pub(crate) candidate_sorts: AtomicUsize,
+    /// Packs small files into shared slabs, if set.
+    pub(crate) small_file_arena: Option<SmallFileArena>,
+    /// Incremented every time a file is stored, replaced, evicted or removed.
+    pub(crate) generation: AtomicU64,
+    /// Counts hits and misses by the label of the `CacheHandle` files are requested with, if set.
+    pub(crate) labeled_metrics: Option<LabeledMetrics>,
 }
 
 
+/// Shows the configuration and the files of the cache, without the contents of the files.
 impl Debug for Cache {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct("Cache")
+            .field("size_limit", &self.size_limit)
+            .field("used_bytes", &self.used_bytes())
+            .field("min_file_size", &self.min_file_size)
+            .field("max_file_size", &self.max_file_size)
+            .field("files", &FileMapDebug(&self.file_map))
+            .finish()
+    }
+}
+
+/// Formats the files in the cache by reference, so they don't have to be copied to be logged.
+struct FileMapDebug<'a>(&'a ConcHashMap<PathBuf, InMemoryFile, RandomState>);
+
+impl<'a> Debug for FileMapDebug<'a> {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         fmt.debug_map()
-            .entries(self.file_map.iter().map(
-                |(ref k, ref v)| (k.clone(), v.clone()),
-            ))
+            .entries(self.0.iter())
             .finish()
     }
 }
 
 impl Cache {
 
-    /// Either gets the file from the cache if it exists there, gets it from the filesystem and
-    /// tries to cache it, or fails to find the file.
+    /// Creates a cache with the default settings and a size limit given as a human friendly size, like `"30MiB"`.
+    /// See `parse_size()` for the sizes it accepts.
     ///
-    /// The CachedFile that is returned takes a lock out on that file in the cache, if that file happens to exist in the cache.
-    /// This lock will release when the CachedFile goes out of scope.
+    /// # Example
     ///
-    /// # Arguments
+    /// ```
+    /// use rocket_file_cache::{Cache, ParseSizeError};
     ///
-    /// * `path` - A path that represents the path of the file in the filesystem. The path
-    /// also acts as a key for the file in the cache.
-    /// The path will be used to find a cached file in the cache or find a file in the filesystem if
-    /// an entry in the cache doesn't exist.
+    /// let cache: Cache = Cache::with_size("30MiB").unwrap();
+    /// assert_eq!(cache.size_limit_bytes(), 30 * 1024 * 1024);
+    /// assert_eq!(Cache::with_size("30MB").unwrap().size_limit_bytes(), 30_000_000);
+    /// assert!(Cache::with_size("a lot").is_err());
+    /// ```
+    pub fn with_size(size_limit: &str) -> Result<Cache, ParseSizeError> {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit_str(size_limit)?
+            .build()
+            .expect("Only the size limit was set, which can't make the builder fail.");
+        Ok(cache)
+    }
+
+    /// Gets the file like `get_source()`, as a `CachedFile` that can be used as a Rocket responder.
+    ///
+    /// The CachedFile that is returned takes a lock out on that file in the cache, if that file happens to exist in the cache.
+    /// This lock will release when the CachedFile goes out of scope.
     ///
     /// # Example
     ///
     /// ```
-    /// #![feature(attr_literals)]
-    /// #![feature(custom_attribute)]
     /// # extern crate rocket;
     /// # extern crate rocket_file_cache;
     ///
@@ -95,1143 +516,9936 @@ impl Cache {
     /// use rocket_file_cache::{Cache, CachedFile};
     /// use std::path::{Path, PathBuf};
     /// use rocket::State;
-    /// use std::sync::Arc;
-    /// use std::sync::atomic::AtomicPtr;
-    ///
     ///
-    /// #[get("/<file..>")]
+    /// // Mounted as `#[get("/<file..>")]`.
     /// fn files<'a>(file: PathBuf,  cache: State<'a, Cache> ) -> CachedFile<'a> {
     ///     let path: PathBuf = Path::new("www/").join(file).to_owned();
     ///     cache.inner().get(path)
     /// }
     /// # }
     /// ```
+    #[cfg(feature = "rocket")]
     pub fn get<P: AsRef<Path>>(&self, path: P) -> CachedFile {
-        trace!("{:#?}", self);
-        // First, try to get the file in the cache that corresponds to the desired path.
-
-        if self.contains_key(&path.as_ref().to_path_buf()) {
-            // File is in the cache, increment the count, update the stats attached to the cache entry.
-            self.increment_access_count(&path);
-            self.update_stats(&path);
-
-            // See if the file should be refreshed
-            if let Some(accesses_per_refresh) = self.accesses_per_refresh {
-                match self.access_count_map.find(&path.as_ref().to_path_buf()) {
-                    Some(a) => {
-                        let access_count: usize = a.get().clone();
-                        // If the access count is a multiple of the refresh parameter, then refresh the file.
-                        if access_count % accesses_per_refresh == 0 {
-                            debug!( "Refreshing entry for path: {:?}", path.as_ref() );
-                            return self.refresh(path.as_ref())
-                        }
-                    }
-                    None => warn!("Cache contains entry for {:?}, but does not tract its access counts.", path.as_ref())
-                }
-            }
-
-        } else {
-            return self.try_insert(path);
-        }
-
-        self.get_from_cache(&path)
+        self.get_with(path, &GetOptions::new())
     }
 
-
-    /// If a file has changed on disk, the cache will not automatically know that a change has occurred.
-    /// Calling this function will check if the file exists, read the new file into memory,
-    /// replace the old file, and update the priority score to reflect the new size of the file.
+    /// Gets the file like `get()`, but fails with the reason the file couldn't be served, instead of a CachedFile that isn't found.
     ///
-    /// # Arguments
+    /// Keys that `validate_key()` rejects fail with `InvalidKey`. A file that isn't found is looked up in the filesystem again
+    /// to tell a missing file (`NotFound`) from one the server may not read (`PermissionDenied`) and from a directory
+    /// without an index file (`IsDirectory`). Any other failure to read the file is `CouldNotReadFile`.
     ///
-    /// * `path` - A path that represents the path of the file in the filesystem, and key to
-    /// the file in the cache.
-    /// The path will be used to find the new file in the filesystem and to find the old file to replace in
-    /// the cache.
+    /// The result can be turned into a `CacheResponse`, which responds with a status that fits the error.
     ///
-    /// # Return
+    /// # Example
     ///
-    /// The CachedFile will indicate NotFound if the file isn't already in the cache or if it can't
-    /// be found in the filesystem.
-    /// It will otherwise return a CachedFile::InMemory variant.
-    pub fn refresh<P: AsRef<Path>>(&self, path: P) -> CachedFile {
-
-        let mut is_ok_to_refresh: bool = false;
-
-        // Check if the file exists in the cache
-        if self.contains_key(&path.as_ref().to_path_buf()) {
-            // See if the new file exists.
-            let path_string: String = match path.as_ref().to_str() {
-                Some(s) => String::from(s),
-                None => return CachedFile::NotFound,
-            };
-            if let Ok(metadata) = fs::metadata(path_string.as_str()) {
-                if metadata.is_file() {
-                    // If the entry for the old file exists
-                    if self.file_map.find(&path.as_ref().to_path_buf()).is_some() {
-                        is_ok_to_refresh = true;
-                    }
-                }
-            };
-        }
-
-        if is_ok_to_refresh {
-            if let Ok(new_file) = InMemoryFile::open(path.as_ref().to_path_buf()) {
-                debug!("Refreshing file: {:?}", path.as_ref());
-                {
-                    self.file_map.remove(&path.as_ref().to_path_buf());
-                    self.file_map.insert(path.as_ref().to_path_buf(), new_file);
-                }
-                self.update_stats(&path);
-
-                return self.get_from_cache(path)
-            }
-        }
+    /// ```
+    /// # extern crate rocket;
+    /// # extern crate rocket_file_cache;
+    ///
+    /// # fn main() {
+    /// use rocket_file_cache::{Cache, CacheResponse};
+    /// use std::path::{Path, PathBuf};
+    /// use rocket::State;
+    ///
+    /// // Mounted as `#[get("/<file..>")]`.
+    /// fn files<'a>(file: PathBuf, cache: State<'a, Cache>) -> CacheResponse<'a> {
+    ///     let path: PathBuf = Path::new("www/").join(file);
+    ///     CacheResponse::from(cache.inner().get_result(path))
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "rocket")]
+    pub fn get_result<P: AsRef<Path>>(&self, path: P) -> Result<CachedFile, CacheError> {
+        let (file_source, class): (FileSource, ServeClass) = self.serve_result(path)?;
+        Ok(CachedFile::served(file_source, class))
+    }
 
-        CachedFile::NotFound
+    /// Gets the file like `get_source()`, but fails with the reason the file couldn't be served, like `get_result()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, CacheError};
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let file = cache.get_source_result("www/missing.html");
+    /// match file {
+    ///     Ok(_) => println!("Found it"),
+    ///     Err(CacheError::NotFound) => println!("There is no such file"),
+    ///     Err(error) => println!("Couldn't serve the file: {:?}", error),
+    /// }
+    /// ```
+    pub fn get_source_result<P: AsRef<Path>>(&self, path: P) -> Result<FileSource, CacheError> {
+        self.serve_result(path).map(|(file_source, _)| file_source)
     }
 
-    /// Removes the file from the cache.
-    /// This will not reset the access count, so the next time the file is accessed, it will be added to the cache again.
-    /// The access count will have to be reset separately using `alter_access_count()`.
+    /// Either gets the file from the cache if it exists there, gets it from the filesystem and
+    /// tries to cache it, or fails to find the file.
+    ///
+    /// The FileSource that is returned takes a lock out on that file in the cache, if that file happens to exist in the cache.
+    /// This lock will release when the FileSource goes out of scope.
     ///
     /// # Arguments
     ///
-    /// * `path` - A path that acts as a key to look up the file that should be removed from the cache.
+    /// * `path` - A path that represents the path of the file in the filesystem. The path
+    ///   also acts as a key for the file in the cache.
+    ///   The path will be used to find a cached file in the cache or find a file in the filesystem if
+    ///   an entry in the cache doesn't exist.
     ///
     /// # Example
     ///
     /// ```
-    /// use rocket_file_cache::{Cache, CacheBuilder};
-    /// use std::path::PathBuf;
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::io::Read;
     ///
-    /// let mut cache = CacheBuilder::new().build().unwrap();
-    /// let pathbuf = PathBuf::new();
-    /// cache.remove(&pathbuf);
-    /// assert!(cache.contains_key(&pathbuf) == false);
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let mut contents: Vec<u8> = vec![];
+    /// if let Some(mut reader) = cache.get_source("www/index.html").into_reader() {
+    ///     reader.read_to_end(&mut contents).unwrap();
+    /// }
+    /// println!("Read {} bytes", contents.len());
     /// ```
-    pub fn remove<P: AsRef<Path>>(&self, path: P) -> bool {
-        if let Some(_) = self.file_map.remove(&path.as_ref().to_path_buf()) {
-            true
-        } else {
-            false
-        }
+    pub fn get_source<P: AsRef<Path>>(&self, path: P) -> FileSource {
+        self.get_source_with(path, &GetOptions::new())
     }
 
-    /// Returns a boolean indicating if the cache has an entry corresponding to the given key.
+    /// Tries to store the file in the cache without responding with it.
     ///
-    /// # Arguments
+    /// The file goes through the same admission process as a file requested with `get()`,
+    /// and counts as an access of the file.
     ///
-    /// * `path` - A path that is used as a key to look up the file.
+    /// # Return
+    ///
+    /// If the file was stored, the result describes the files that had to be evicted to make room for it.
+    /// Otherwise, the error describes why the file wasn't stored.
     ///
     /// # Example
     ///
     /// ```
-    /// use rocket_file_cache::{CacheBuilder};
-    /// use std::path::PathBuf;
+    /// use rocket_file_cache::{CacheBuilder, CacheError};
     ///
-    /// let mut cache = CacheBuilder::new().build().unwrap();
-    /// let pathbuf: PathBuf = PathBuf::new();
-    /// cache.get(&pathbuf);
-    /// assert!(cache.contains_key(&pathbuf) == false);
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// assert_eq!(cache.insert("www/does_not_exist.html"), Err(CacheError::InvalidMetadata));
     /// ```
-    pub fn contains_key<P: AsRef<Path>>(&self, path: P) -> bool {
-        self.file_map.find(&path.as_ref().to_path_buf()).is_some()
+    pub fn insert<P: AsRef<Path>>(&self, path: P) -> Result<CacheInsertionSuccess, CacheError> {
+        let _operation: Operation = self.operation();
+        self.validate_key(&path)?;
+        if self.is_disabled() {
+            return Err(CacheError::CacheDisabled);
+        }
+        if self.is_frozen() {
+            return Err(CacheError::CacheFrozen);
+        }
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            self.remember_spelling(&key, path.as_ref());
+            return self.insert(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.insert(target);
+        }
+        if self.contains_key(&path) {
+            return Err(CacheError::AlreadyInCache);
+        }
+
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let mut preloaded: Option<InMemoryFile> = self.take_from_cold_tier(&path);
+        let result = self.admit(&path, &mut preloaded, &GetOptions::new());
+        if let (Some(file), Some(cold_tier)) = (preloaded, self.cold_tier.as_ref()) {
+            cold_tier.demote(path.clone(), &file);
+        }
+        self.debug_check_invariants();
+        result
     }
 
-    /// Alters the access count value of one file in the access_count_map.
-    /// # Arguments
+    /// Decides whether the file would be stored in the cache, without changing the cache.
     ///
-    /// * `path` - The key to look up the file.
-    /// * `alter_count_function` - A function that determines how to alter the access_count for the file.
+    /// This is the first half of a two-phase insertion. Callers can inspect the prediction,
+    /// for instance to see which files would be evicted, and then pass the token from an
+    /// `AdmissionPrediction::Admit` to `commit_insert_source()` to actually store the file.
+    /// Checking doesn't count as an access of the file.
     ///
     /// # Example
     ///
     /// ```
-    /// use rocket_file_cache::{Cache, CacheBuilder};
-    /// use std::path::PathBuf;
+    /// use rocket_file_cache::{CacheBuilder, CacheError, AdmissionPrediction};
     ///
-    /// let mut cache = CacheBuilder::new().build().unwrap();
-    /// let pathbuf = PathBuf::new();
-    /// cache.get(&pathbuf); // Add a file to the cache
-    /// cache.remove(&pathbuf); // Removing the file will not reset its access count.
-    /// cache.alter_access_count(&pathbuf, | x | { 0 }); // Set the access count to 0.
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// match cache.check("www/index.html") {
+    ///     AdmissionPrediction::Admit(token) => {
+    ///         println!("Inserting the file would evict {:?}", token.victims());
+    ///         let _ = cache.commit_insert_source("www/index.html", token);
+    ///     }
+    ///     AdmissionPrediction::AlreadyInCache => {}
+    ///     AdmissionPrediction::Rejected(error) => println!("The file would not be stored: {:?}", error),
+    /// }
     /// ```
+    pub fn check<P: AsRef<Path>>(&self, path: P) -> AdmissionPrediction {
+        if let Err(error) = self.validate_key(&path) {
+            return AdmissionPrediction::Rejected(error);
+        }
+        if self.is_disabled() {
+            return AdmissionPrediction::Rejected(CacheError::CacheDisabled);
+        }
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            self.remember_spelling(&key, path.as_ref());
+            return self.check(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.check(target);
+        }
+        if self.contains_key(&path) {
+            return AdmissionPrediction::AlreadyInCache;
+        }
+
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let size: usize = match self.get_file_size_from_metadata(&path) {
+            Ok(size) => size,
+            Err(error) => return AdmissionPrediction::Rejected(error),
+        };
+        if let Err(error) = policy::check_size_bounds(size, self.min_file_size, self.max_file_size) {
+            return AdmissionPrediction::Rejected(error);
+        }
+        if self.is_thrashing(&path) {
+            return AdmissionPrediction::Rejected(CacheError::Thrashing);
+        }
+        if self.is_blocked(&path) {
+            return AdmissionPrediction::Rejected(CacheError::Blocked);
+        }
+        if self.bypassed.contains(&path) {
+            return AdmissionPrediction::Rejected(CacheError::Bypassed);
+        }
+        if !self.within_memory_ceiling(size) {
+            return AdmissionPrediction::Rejected(CacheError::MemoryCeilingExceeded);
+        }
+
+        let quota_overflow: Option<(PathBuf, usize)> = self.directory_quotas.overflow(&path, size);
+        if quota_overflow.is_none() && policy::fits_without_eviction(self.used_bytes(), size, self.size_limit, self.effective_size_limit()) {
+            return AdmissionPrediction::Admit(AdmissionToken {
+                path,
+                size,
+                victims: vec![],
+            });
+        }
+
+        // Inserting the file would count as another access, so the priority is calculated as if it had happened.
+        let new_file_access_count: usize = match self.access_count_map.find(&path) {
+            Some(access_count) => *access_count.get(),
+            None => 0,
+        };
+        let new_file_priority: usize = self.boosted_priority(&path, self.priority(new_file_access_count + 1, size));
+
+        let victims: Result<Vec<(PathBuf, usize)>, CacheError> = match quota_overflow {
+            Some((prefix, quota_space)) => self.make_room_within_quota(&prefix, quota_space, size, new_file_priority, None),
+            None => {
+                let required_space_for_new_file: usize = policy::required_space(self.used_bytes(), size, self.effective_size_limit());
+                let batch_space: usize = self.batch_eviction_space(size);
+                let request = RoomRequest {
+                    required_space: required_space_for_new_file,
+                    batch_space,
+                    new_file_priority,
+                    max_candidates: None,
+                };
+                self.make_room_for_new_file(&request, None, None)
+            }
+        };
+        match victims {
+            Ok(victims) => AdmissionPrediction::Admit(AdmissionToken { path, size, victims }),
+            Err(error) => AdmissionPrediction::Rejected(error),
+        }
+    }
+
+    /// Stores the file that `check()` decided to admit, evicting the files chosen by the check.
     ///
-    pub fn alter_access_count<P: AsRef<Path>>(&self, path: P, alter_count_function: fn(&usize) -> usize) -> bool {
-        let new_count: usize;
-        {
-            match self.access_count_map.find(&path.as_ref().to_path_buf()) {
-                Some(access_count_entry) => {
-                    new_count = alter_count_function(&access_count_entry.get());
-                }
-                None => return false, // Can't update a file that isn't in the cache.
+    /// Before anything is evicted, the token is checked against the current state of the cache.
+    /// If the file has changed size, or any of the files chosen for eviction have been removed or changed
+    /// since the check, the file goes through the full admission process again instead,
+    /// so the decision is never made on stale information.
+    /// This counts as an access of the file.
+    ///
+    /// # Return
+    ///
+    /// The cached file if it was stored, or the error describing why it wasn't.
+    pub fn commit_insert_source<P: AsRef<Path>>(&self, path: P, token: AdmissionToken) -> Result<FileSource, CacheError> {
+        let _operation: Operation = self.operation();
+        self.validate_key(&path)?;
+        if self.is_frozen() {
+            return Err(CacheError::CacheFrozen);
+        }
+        let path: PathBuf = match self.key_case.normalize(path.as_ref()) {
+            Some(key) => {
+                self.remember_spelling(&key, path.as_ref());
+                key
             }
+            None => path.as_ref().to_path_buf(),
+        };
+        let path: PathBuf = self.alias_target(&path).unwrap_or(path);
+        if path != token.path {
+            warn!("The admission token for {:?} was used to insert {:?}.", token.path, path);
+            return self.insert(&path).map(|_| self.get_from_cache(self.content_target(&path).unwrap_or(path)));
         }
-        {
-            self.access_count_map.insert(
-                path.as_ref().to_path_buf(),
-                new_count,
-            );
+        if !self.token_is_current(&token) {
+            debug!("The admission token for {:?} is out of date, checking the file again.", path);
+            return self.insert(&path).map(|_| self.get_from_cache(self.content_target(&path).unwrap_or(path)));
         }
-        self.update_stats(&path);
-        return true;
+
+        self.count_miss(&path, CountPolicy::OnRequest);
+        let preloaded: Option<InMemoryFile> = self.take_from_cold_tier(&path);
+        let file: InMemoryFile = self.load_file(&path, preloaded).map_err(|_| CacheError::CouldNotReadFile)?;
+        self.evict_with_reservation(file.stats.size, &token.victims)?;
+        self.count_miss(&path, CountPolicy::OnSuccess);
+        let key: PathBuf = self.store_admitted(&path, file).0;
+        self.update_stats(&key);
+        self.schedule_overshoot_eviction();
+        self.remove_if_blocked(&path)?;
+        self.debug_check_invariants();
+        Ok(self.get_from_cache(&key))
     }
 
-    /// Alters the access count value of every file in the access_count_map.
-    /// This is useful for manually aging-out entries in the cache.
+    /// Stores the file like `commit_insert_source()`, returning it as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn commit_insert<P: AsRef<Path>>(&self, path: P, token: AdmissionToken) -> Result<CachedFile, CacheError> {
+        self.commit_insert_source(path, token).map(CachedFile::from)
+    }
+
+    /// Gets the lowest priority of the files that could be evicted, once the cache is full.
     ///
-    /// # Arguments
+    /// Making room for a new file means evicting at least one file, so a file with a lower priority
+    /// than this can't be admitted while the cache is full.
     ///
-    /// * `alter_count_function` - A function that determines how to alter the access_count for the file.
+    /// Returns None while the cache has free space, or if none of its files can be evicted.
     ///
     /// # Example
     ///
     /// ```
-    /// use rocket_file_cache::{Cache, CacheBuilder};
-    /// use std::path::PathBuf;
+    /// use rocket_file_cache::CacheBuilder;
     ///
-    /// let mut cache = CacheBuilder::new().build().unwrap();
-    /// let pathbuf = PathBuf::new();
-    /// let other_pathbuf = PathBuf::new();
-    /// cache.get(&pathbuf);
-    /// cache.get(&other_pathbuf);
-    /// // Reduce all access counts by half,
-    /// // allowing newer files to enter the cache more easily.
-    /// cache.alter_all_access_counts(| x | { x / 2 });
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// assert_eq!(cache.current_floor_priority(), None);
     /// ```
-    ///
-    pub fn alter_all_access_counts(&self, alter_count_function: fn(&usize) -> usize) {
-        let all_counts: Vec<PathBuf>;
-        {
-            all_counts = self.access_count_map
-                .iter()
-                .map(|x: (&PathBuf, &usize)| x.0.clone())
-                .collect();
+    pub fn current_floor_priority(&self) -> Option<usize> {
+        if self.used_bytes() < self.effective_size_limit() {
+            return None;
         }
-        for pathbuf in all_counts {
-            self.alter_access_count(&pathbuf, alter_count_function);
-        }
-
+        self.file_map
+            .iter()
+            .filter(|x| !x.1.is_embedded())
+            .map(|x| self.eviction_priority(&x.1.stats))
+            .min()
     }
 
-    /// Gets the sum of the sizes of the files that are stored in the cache.
+    /// Decides whether a file of the size and priority would be admitted into the cache,
+    /// comparing its priority against the files that would have to be evicted for it, like an insertion does.
+    ///
+    /// This lets callers serve requests that can't be admitted with `GetOptions::skip_insert_above()`,
+    /// without the cache working out what it would have to evict for them.
+    /// The priority should come from the cache's priority function, counting the request as an access.
+    /// Directory quotas aren't considered, as they depend on the path of the file.
+    /// Priorities above `MAX_PRIORITY` are clamped to it, like the ones the cache calculates.
     ///
     /// # Example
     ///
     /// ```
-    /// use rocket_file_cache::{Cache, CacheBuilder};
+    /// use rocket_file_cache::CacheBuilder;
     ///
-    /// let cache = CacheBuilder::new().build().unwrap();
-    /// assert!(cache.used_bytes() == 0);
+    /// let cache = CacheBuilder::new()
+    ///     .size_limit(1024)
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(cache.would_admit_priority(0, 512));
+    /// assert!(!cache.would_admit_priority(0, 2048));
     /// ```
-    pub fn used_bytes(&self) -> usize {
-        self.file_map.iter().fold(
-            0usize,
-            |size, x| size + x.1.stats.size,
-        )
+    pub fn would_admit_priority(&self, priority: usize, size: usize) -> bool {
+        let priority: usize = cmp::min(priority, MAX_PRIORITY);
+        if self.is_frozen() || policy::check_size_bounds(size, self.min_file_size, self.max_file_size).is_err() {
+            return false;
+        }
+        if policy::fits_without_eviction(self.used_bytes(), size, self.size_limit, self.effective_size_limit()) {
+            return true;
+        }
+        // Every file that could be evicted has at least the floor priority, so there is no need to sort them.
+        if self.current_floor_priority().is_some_and(|floor| floor > priority) {
+            return false;
+        }
+        let required_space: usize = policy::required_space(self.used_bytes(), size, self.effective_size_limit());
+        let request = RoomRequest {
+            required_space,
+            batch_space: required_space,
+            new_file_priority: priority,
+            max_candidates: None,
+        };
+        self.make_room_for_new_file(&request, None, None).is_ok()
     }
 
-    /// Gets the size of the file from the file's metadata.
-    /// This avoids having to read the file into memory in order to get the file size.
-    fn get_file_size_from_metadata<P: AsRef<Path>>(path: P) -> Result<usize, CacheError> {
-        let path_string: String = match path.as_ref().to_str() {
-            Some(s) => String::from(s),
-            None => return Err(CacheError::InvalidPath),
-        };
-        let metadata: Metadata = match fs::metadata(path_string.as_str()) {
-            Ok(m) => m,
-            Err(_) => return Err(CacheError::InvalidMetadata),
+    /// Runs the priority function over a grid of access counts and sizes, and reports behavior
+    /// that would make the order files are evicted in arbitrary.
+    ///
+    /// The access counts run from 1 to `samples`, and the sizes are `samples` powers of 4 from 1 byte,
+    /// stopping at the max_file_size of the cache.
+    /// This is meant for checking a custom priority function in a test, or when a server starts.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new()
+    ///     .priority_function(|_, _| 0)
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(cache.validate_priority_function(10).constant);
+    /// ```
+    pub fn validate_priority_function(&self, samples: usize) -> PriorityFunctionReport {
+        let mut sizes: Vec<usize> = vec![];
+        let mut size: Option<usize> = Some(1);
+        while let Some(next) = size {
+            if sizes.len() >= samples || next > self.max_file_size {
+                break;
+            }
+            sizes.push(next);
+            size = next.checked_mul(4);
+        }
+
+        let mut report = PriorityFunctionReport {
+            samples: 0,
+            constant: true,
+            decreasing_with_accesses: vec![],
+            clamped: 0,
         };
-        let size: usize = metadata.len() as usize;
-        Ok(size)
+        let mut first: Option<usize> = None;
+        for &size in &sizes {
+            let mut previous: Option<usize> = None;
+            for access_count in 1..samples.saturating_add(1) {
+                let raw: usize = (self.priority_function)(access_count, size);
+                let priority: usize = cmp::min(raw, MAX_PRIORITY);
+                report.samples += 1;
+                if raw > MAX_PRIORITY {
+                    report.clamped += 1;
+                }
+                if *first.get_or_insert(priority) != priority {
+                    report.constant = false;
+                }
+                if previous.is_some_and(|previous| priority < previous) {
+                    report.decreasing_with_accesses.push((access_count, size));
+                }
+                previous = Some(priority);
+            }
+        }
+        report
     }
 
-
-    /// Attempt to store a given file in the the cache.
-    /// Storing will fail if the current files have more access attempts than the file being added.
-    /// If the provided file has more more access attempts than one of the files in the cache,
-    /// but the cache is full, a file will have to be removed from the cache to make room
-    /// for the new file.
+    /// Gets the file like `get_source()`, but stores it under a key that is separate from its path in the filesystem.
     ///
-    /// If the insertion works, the cache will update the priority score for the file being inserted.
-    /// The cached priority score requires the file in question to exist in the file map, so it will
-    /// have a size to use when calculating.
+    /// This allows the cache to be keyed by something like the request's URL path, while the file lives
+    /// somewhere else on the machine.
+    /// The path is remembered for the key, so `refresh_source()` called with the key will re-read the file from the path.
+    /// Paths of files that aren't found aren't remembered.
+    /// Keys are independent of each other, so two keys with the same path will be stored as separate entries.
     ///
-    /// It will get the size of the file to be inserted.
-    /// If will use this size to check if the file could be inserted.
-    /// If it can be inserted, it reads the file into memory, stores a copy of the in-memory
-    /// file behind a pointer, and constructs a CachedFile to return.
+    /// # Arguments
     ///
-    /// If the file can't be added, it will open a NamedFile and construct a CachedFile from that,
-    /// and return it.
-    /// This means that it doesn't need to read the whole file into memory before reading through it
-    /// again to set the response body.
-    /// The lack of the need to read the whole file twice keeps performance of cache misses on par
-    /// with just normally reading the file without a cache.
+    /// * `key` - The key the file will be stored under in the cache.
+    /// * `path` - The path of the file in the filesystem.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let file = cache.get_source_keyed("/assets/app.js", "/srv/www/assets/app.js");
+    /// ```
+    pub fn get_source_keyed<K: AsRef<Path>, P: AsRef<Path>>(&self, key: K, path: P) -> FileSource {
+        if let Err(error) = self.validate_key(&key).and(self.validate_key(&path)) {
+            warn!("Rejected key {:?} for path {:?}: {:?}", key.as_ref(), path.as_ref(), error);
+            return FileSource::NotFound;
+        }
+        if self.is_disabled() {
+            return self.pass_through(path, ServeClass::FsDisabled, &GetOptions::new()).0;
+        }
+        let key: PathBuf = self.key_case.normalize(key.as_ref()).unwrap_or(key.as_ref().to_path_buf());
+        if self.is_frozen() {
+            let (file, _) = self.get_frozen(&key, path.as_ref().to_path_buf(), &GetOptions::new());
+            self.served_bytes.record(&key, &file);
+            return file;
+        }
+        if key.as_path() == path.as_ref() {
+            self.fs_paths.remove(&key);
+        } else {
+            self.fs_paths.insert(key.clone(), path.as_ref().to_path_buf());
+        }
+        let file: FileSource = self.get_source(&key);
+        if matches!(file, FileSource::NotFound | FileSource::FileMissing) && self.file_map.find(&key).is_none() {
+            // The path of a key that doesn't lead to a file isn't kept, so requests for missing files don't take any memory.
+            self.fs_paths.remove(&key);
+        }
+        file
+    }
+
+    /// Gets the file like `get_source_keyed()`, as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn get_keyed<K: AsRef<Path>, P: AsRef<Path>>(&self, key: K, path: P) -> CachedFile {
+        self.get_source_keyed(key, path).into()
+    }
+
+    /// Gets the file at the relative path from the first of the root directories that has it.
+    /// This allows a directory of files to be overlaid over a directory of defaults.
     ///
+    /// The file is cached under the path it was found at.
+    /// The root that had the file is remembered for the relative path, so later requests go straight to it,
+    /// until the file is removed with `remove()` or refreshed with `refresh_source()`.
+    /// After that, the roots are searched in order again, so a file that was added to an earlier root is found.
+    ///
+    /// Relative paths that are absolute, or contain `..`, are rejected, so files outside of the roots can't be served.
     ///
     /// # Arguments
     ///
-    /// * `path` - The path of the file to be stored. Acts as a key for the file in the cache. Is used
-    /// look up the location of the file in the filesystem if the file is not in the cache.
+    /// * `relative` - The path of the file, relative to each of the roots.
+    /// * `roots` - The directories to look for the file in, in order.
     ///
+    /// # Example
     ///
-    fn try_insert<P: AsRef<Path>>(&self, path: P) -> CachedFile {
-        let path: PathBuf = path.as_ref().to_path_buf();
-        trace!("Trying to insert file {:?}", path);
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::path::PathBuf;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let roots: Vec<PathBuf> = vec![PathBuf::from("/srv/site"), PathBuf::from("/srv/default")];
+    /// let file = cache.get_source_with_fallbacks("index.html", &roots);
+    /// ```
+    pub fn get_source_with_fallbacks<P: AsRef<Path>>(&self, relative: P, roots: &[PathBuf]) -> FileSource {
+        let relative: &Path = relative.as_ref();
+        let is_relative: bool = relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !is_relative {
+            warn!("Rejected {:?}, because it isn't relative to the roots.", relative);
+            return FileSource::NotFound;
+        }
 
-        // If the FS can read metadata for a file, then the file exists, and it should be safe to increment
-        // the access_count and update.
-        let size: usize = match Cache::get_file_size_from_metadata(&path) {
-            Ok(size) => size,
-            Err(_) => return CachedFile::NotFound // Could not open file to read metadata.
-        };
+        if let Some(resolved) = self.fallback_resolutions.get(relative) {
+            if roots.iter().any(|root| root.join(relative) == resolved) {
+                match self.get_source(&resolved) {
+                    FileSource::NotFound | FileSource::FileMissing => {
+                        // The file is gone from the root it was found in, so the other roots may have it.
+                        self.fallback_resolutions.forget(&resolved);
+                    }
+                    file => return file,
+                }
+            }
+        }
+
+        for root in roots {
+            let path: PathBuf = root.join(relative);
+            let is_file: bool = self.contains_key(&path) || fs::metadata(&path).map(|metadata| metadata.is_file()).unwrap_or(false);
+            if is_file {
+                debug!("Found {:?} in {:?}.", relative, root);
+                if !self.is_disabled() {
+                    self.fallback_resolutions.remember(relative, &path);
+                }
+                return self.get_source(path);
+            }
+        }
+        FileSource::NotFound
+    }
+
+    /// Gets the file like `get_source_with_fallbacks()`, as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn get_with_fallbacks<P: AsRef<Path>>(&self, relative: P, roots: &[PathBuf]) -> CachedFile {
+        self.get_source_with_fallbacks(relative, roots).into()
+    }
 
-        // Determine how much space can still be used (represented by a negative value) or how much
-        // space needs to be freed in order to make room for the new file
-        let required_space_for_new_file: isize = (self.used_bytes() as isize + size as isize) - self.size_limit as isize;
+    /// Gets the file like `get_source()`, but limits how much work is done when the file isn't in the cache.
+    /// See `GetOptions` for the available limits.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, GetOptions};
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// // Don't let this request spend time admitting files larger than 1 MB.
+    /// let file = cache.get_source_with("www/video.mp4", GetOptions::new().skip_insert_above(1024 * 1024));
+    /// ```
+    pub fn get_source_with<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> FileSource {
+        self.serve(path, options).0
+    }
 
+    /// Gets the file like `get_source_with()`, as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn get_with<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> CachedFile {
+        let (file, class): (FileSource, ServeClass) = self.serve(path, options);
+        CachedFile::served(file, class)
+    }
 
-        if size > self.max_file_size || size < self.min_file_size {
-            self.get_file_from_fs(&path)
-        } else if required_space_for_new_file < 0 && size < self.size_limit {
-            self.get_file_from_fs_and_add_to_cache(&path)
-        } else {
-            debug!("Trying to make room for the file");
+    /// Gets the file like `get()`, counting whether it was served from the cache under the label.
+    #[cfg(feature = "rocket")]
+    pub(crate) fn get_labeled<P: AsRef<Path>>(&self, path: P, label: &str) -> CachedFile {
+        let (file, class): (FileSource, ServeClass) = self.serve(path, &GetOptions::new());
+        if let Some(ref labeled_metrics) = self.labeled_metrics {
+            labeled_metrics.record(label, class == ServeClass::Hit);
+        }
+        CachedFile::served(file, class)
+    }
+
+    /// Gets the file like `get_source()`, along with where it was served from, and if it wasn't served from the cache, why.
+    /// This allows the effectiveness of the cache to be measured separately for each reason files miss it.
+    ///
+    /// Returns None if the file couldn't be found.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, ServeClass};
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let class: Option<ServeClass> = cache.get_source_classified("www/index.html").map(|(_file, class)| class);
+    /// match class {
+    ///     Some(ServeClass::FsPriorityTooLow) => println!("The file is not popular enough to be cached."),
+    ///     Some(class) => println!("The file was served as {:?}", class),
+    ///     None => println!("The file doesn't exist."),
+    /// }
+    /// ```
+    pub fn get_source_classified<P: AsRef<Path>>(&self, path: P) -> Option<(FileSource, ServeClass)> {
+        self.get_source_classified_with(path, &GetOptions::new())
+    }
+
+    /// Gets the file like `get_source_with()`, along with how it was served, like `get_source_classified()`.
+    pub fn get_source_classified_with<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> Option<(FileSource, ServeClass)> {
+        match self.serve(path, options) {
+            (FileSource::NotFound, _) | (FileSource::FileMissing, _) => None,
+            served => Some(served),
+        }
+    }
+
+    /// Decides where the file should be served from like `get_source()`, but leaves files that should be served
+    /// from the filesystem for the caller to open, instead of opening them.
+    ///
+    /// This is meant for integrations that serve passed through files in their own way, like a throttled stream,
+    /// or that already have the file open. The file counts as an access, and is inserted into the cache if it is
+    /// accepted, exactly like with `get_source()`. The bytes of passed through files aren't counted in the served bytes
+    /// of the `summary()`, because the cache never learns their size.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Resolution};
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let resolution = cache.resolve("www/index.html");
+    /// match resolution {
+    ///     Resolution::Cached(file) => println!("Serving {:?} from memory", file),
+    ///     Resolution::PassThrough { path, reason } => println!("Opening {:?}, because of {:?}", path, reason),
+    ///     Resolution::NotFound => println!("Not found"),
+    /// }
+    /// ```
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Resolution {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            self.remember_spelling(&key, path.as_ref());
+            return self.resolve(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.resolve(target);
+        }
+        let mut options: GetOptions = GetOptions::new();
+        options.defer_open = true;
+        match self.serve(&path, &options) {
+            (FileSource::Cached(cached_file), _) => Resolution::Cached(cached_file),
+            (FileSource::Deferred(fs_path), reason) | (FileSource::Fs(_, fs_path, _), reason) => Resolution::PassThrough {
+                path: fs_path,
+                reason,
+            },
+            (FileSource::Detached(_), _) | (FileSource::NotFound, _) | (FileSource::FileMissing, _) => Resolution::NotFound,
+        }
+    }
+
+    /// Gets several files at once, like calling `get_source()` for each path in order,
+    /// returning the files in the same order as the paths.
+    ///
+    /// The files in the cache are only sorted once to choose which of them to evict for every new file of the batch,
+    /// instead of once for each new file that needs room, and the files of the batch are never evicted to make room
+    /// for each other. This is meant for responses that need a few files together, like a page and the stylesheet
+    /// that is inlined into it.
+    ///
+    /// The shared eviction candidates would be wrong for insertions made by other threads at the same time,
+    /// so this takes the cache mutably, unlike every other method that serves files.
+    /// Files served from the cache stay locked until they are dropped, like with `get_source()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, FileSource};
+    /// use std::path::PathBuf;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// let paths: Vec<PathBuf> = vec![PathBuf::from("www/index.html"), PathBuf::from("www/critical.css")];
+    /// let files: Vec<FileSource> = cache.get_source_many(&paths);
+    /// assert_eq!(files.len(), 2);
+    /// ```
+    pub fn get_source_many(&mut self, paths: &[PathBuf]) -> Vec<FileSource> {
+        let cache: &Cache = self;
+        cache.batch_plan.start(paths);
+        // Files held in the cache stay locked while they are held, which would block inserting the rest of the batch,
+        // so only their keys are kept until every file has been served.
+        let served: Vec<Result<FileSource, Arc<Path>>> = paths
+            .iter()
+            .map(|path| match cache.serve(path, &GetOptions::new()).0 {
+                FileSource::Cached(cached_file) => Err(cached_file.path.clone()),
+                file => Ok(file),
+            })
+            .collect();
+        cache.batch_plan.finish();
+
+        served
+            .into_iter()
+            .map(|served| match served {
+                Ok(file) => file,
+                Err(key) => match cache.get_from_cache(&key) {
+                    FileSource::NotFound => {
+                        // Only a concurrent removal can take the file out of the cache in the meantime.
+                        warn!("{:?} was removed from the cache before the batch was served, serving it from the filesystem.", key);
+                        cache.open_for_response(cache.fs_path(&key), &GetOptions::new()).unwrap_or(FileSource::NotFound)
+                    }
+                    file => file,
+                },
+            })
+            .collect()
+    }
+
+    /// Gets several files at once like `get_source_many()`, as `CachedFile`s.
+    #[cfg(feature = "rocket")]
+    pub fn get_many(&mut self, paths: &[PathBuf]) -> Vec<CachedFile> {
+        self.get_source_many(paths).into_iter().map(CachedFile::from).collect()
+    }
+
+    /// Gets the file like `get_source_classified()`, as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn get_classified<P: AsRef<Path>>(&self, path: P) -> Option<(CachedFile, ServeClass)> {
+        self.get_classified_with(path, &GetOptions::new())
+    }
+
+    /// Gets the file like `get_source_classified_with()`, as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn get_classified_with<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> Option<(CachedFile, ServeClass)> {
+        self.get_source_classified_with(path, options)
+            .map(|(file, class)| (CachedFile::served(file, class), class))
+    }
+
+    /// Gets the file like `get_source_with()`, classifying how it was served.
+    fn serve<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> (FileSource, ServeClass) {
+        let _operation: Operation = self.operation();
+        if let Err(error) = self.validate_key(&path) {
+            warn!("Rejected path {:?}: {:?}", path.as_ref(), error);
+            self.record_decision(&path, Outcome::Error);
+            return (FileSource::NotFound, ServeClass::FsError);
+        }
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            self.remember_spelling(&key, path.as_ref());
+            return self.serve(key, options);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.serve(target, options);
+        }
+        if let Some(key) = self.adopt_sidecar_hash(path.as_ref()) {
+            return self.serve(key, options);
+        }
+        if self.is_disabled() {
+            return self.pass_through(self.fs_path(&path), ServeClass::FsDisabled, options);
+        }
+        if self.bypassed.contains(path.as_ref()) {
+            return self.pass_through(self.fs_path(&path), ServeClass::FsBypassed, options);
+        }
+        if self.mode == Mode::Manual {
+            let served: (FileSource, ServeClass) = self.serve_manual(path.as_ref(), options);
+            self.record_extension_request(path.as_ref(), &served);
+            return served;
+        }
+        // The file that is served stays locked until it is dropped, so the cache is checked before it is served.
+        self.debug_check_invariants();
+        // Files in the cache have no ghost entries, so the entry is taken before a miss stores the file again.
+        let had_ghost_entry: bool = self.ghost_entries.as_ref().is_some_and(|ghost_entries| ghost_entries.take(path.as_ref()));
+        self.prefetch_siblings();
+        let served: (FileSource, ServeClass) = self.serve_key(&path, options);
+        if served.1 == ServeClass::Inserted {
+            if let Some(ref sibling_prefetch) = self.sibling_prefetch {
+                sibling_prefetch.push(path.as_ref());
+            }
+        }
+        self.served_bytes.record(path.as_ref(), &served.0);
+        self.record_extension_request(path.as_ref(), &served);
+        if had_ghost_entry && served.1 != ServeClass::Hit {
+            if let Some(ref ghost_entries) = self.ghost_entries {
+                ghost_entries.record_hit();
+            }
+        }
+        if let Some(ref trace) = self.trace {
+            trace.record_get(path.as_ref(), served.0.size(), (self.clock)());
+        }
+        served
+    }
+
+    /// Counts the request under the extension of the file, unless the file wasn't found,
+    /// so requests for arbitrary paths don't add extensions to the counts.
+    fn record_extension_request(&self, path: &Path, served: &(FileSource, ServeClass)) {
+        match served.0 {
+            FileSource::NotFound | FileSource::FileMissing => {}
+            _ => self.extension_counters.record_request(path, served.1 == ServeClass::Hit),
+        }
+    }
+
+    /// Serves the file stored under the key, which has already been normalized.
+    fn serve_key<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> (FileSource, ServeClass) {
+        if self.is_frozen() {
+            return self.get_frozen(path.as_ref(), self.fs_path(&path), options);
+        }
+        // Formatting the cache walks every entry, so it is only done when it will actually be logged.
+        if log_enabled!(::log::Level::Trace) {
+            trace!("{:#?}", self);
+        }
+
+        // Files outside of the size bounds are served without updating any stats.
+        if self.out_of_bounds_class(path.as_ref()).is_some() {
+            if !self.allows_miss() {
+                return self.fail_degraded_miss(&path);
+            }
+            return self.serve_from_fs(&path, CacheError::FileSizeOutOfBounds, options);
+        }
+
+        self.compact_small_file_arena();
+        self.evict_overshoot();
+        // First, try to get the file in the cache that corresponds to the desired path.
+
+        let is_hit: bool = self.timed(Phase::Lookup, || {
+            let is_hit: bool = self.contains_key(&path);
+            if is_hit {
+                // File is in the cache, increment the count, update the stats attached to the cache entry.
+                self.increment_access_count(&path);
+                self.update_stats_on_access(&path);
+            }
+            is_hit
+        });
+        if is_hit {
+            self.record_decision(&path, Outcome::Hit);
 
-            // Because the size was gotten from the file's metadata, we know that it exists,
-            // so its fine to increment the account
-            self.increment_access_count(&path);
+            if self.is_soft_purged(path.as_ref()) {
+                return (self.revalidate_soft_purged(path.as_ref()), ServeClass::Hit);
+            }
 
-            // The access_count should have incremented since the last time this was called, so the priority must be recalculated.
-            // Also, the size generally
-            let new_file_priority: usize;
+            if self.consistency == Consistency::Paranoid
+                && self.is_backed_by_fs(&path)
+                && self.matches_filesystem(&path) != Some(true)
             {
-                let new_file_access_count: &usize = match self.access_count_map.find(&path) {
-                    Some(access_count) => &access_count.get(),
-                    None => &1,
-                };
-                new_file_priority = (self.priority_function)(new_file_access_count.clone(), size);
-            }
-
-
-            match self.make_room_for_new_file(required_space_for_new_file as usize, new_file_priority) {
-                Ok(files_to_be_removed) => {
-                    debug!("Made room for new file");
-                    match InMemoryFile::open(path.as_path()) {
-                        Ok(file) => {
-
-                            // We have read a new file into memory, it is safe to
-                            // remove the old files.
-                            for file_key in files_to_be_removed {
-                                // The file was accessed with this key earlier when sorting priorities, which should make removal safe.
-                                match self.file_map.remove(&file_key) {
-                                    Some(_) => {},
-                                    None => warn!("Likely due to concurrent mutations, a file being removed from the cache was not found because another thread removed it first.")
-                                };
-                            }
-
-                            self.file_map.insert(path.clone(), file);
-                            self.update_stats(&path);
-
-                            let cache_file_accessor = match self.file_map.find(&path) {
-                                Some(accessor_to_file) => accessor_to_file,
-                                None => {
-                                    // If a concurrent remove operation removes the file before
-                                    // it can be gotten via an accessor lock, recursively try to add
-                                    // the file to the Cache until the lock can be attained.
-
-                                    // Because this action takes place after room was made for
-                                    // the new file in the cache, those files will be left out of the cache.
-                                    warn!("Tried to add file to cache, but it was removed before it could be added. Attempting to insert file again.");
-                                    // Because this recursion only occurs under extremely rare
-                                    // circumstances due to concurrent removal of the file being
-                                    // added between the insertion into the map, and locking an
-                                    // accessor, a stack overflow is almost impossible. This would require
-                                    // the file to be removed on every recursive attempt to re-insert it,
-                                    // with the exact same timing required to invalidate the `find()` method,
-                                    // for as many times as it takes to fill up the stack. It's not
-                                    // going to happen.
-                                    return self.try_insert(path);
-                                }
-                            };
-
-                            let named_in_memory_file: NamedInMemoryFile = NamedInMemoryFile::new(
-                                path.clone(),
-                                cache_file_accessor
-                            );
-
-                            return CachedFile::from(named_in_memory_file);
+                debug!("{:?} has changed in the filesystem, refreshing it before serving it.", path.as_ref());
+                return (self.refresh_on_access(path.as_ref()), ServeClass::Hit);
+            }
+
+            if self.verify_checksums && self.is_backed_by_fs(&path) && !self.checksum_matches(path.as_ref()) {
+                self.checksum_mismatches.fetch_add(1, Ordering::Relaxed);
+                error!("The bytes of {:?} no longer match their checksum, reading it again.", path.as_ref());
+                return (self.replace_corrupted(path.as_ref()), ServeClass::Hit);
+            }
+
+            // See if the file should be refreshed
+            if let (Some(accesses_per_refresh), true) = (self.accesses_per_refresh, self.is_backed_by_fs(&path)) {
+                match self.access_count_map.find(path.as_ref()) {
+                    Some(a) => {
+                        let access_count: usize = a.get().clone();
+                        // If the access count is a multiple of the refresh parameter, then refresh the file.
+                        if access_count % accesses_per_refresh == 0 {
+                            debug!( "Refreshing entry for path: {:?}", path.as_ref() );
+                            return (self.refresh_on_access(path.as_ref()), ServeClass::Hit);
                         }
-                        Err(_) => return CachedFile::NotFound
-                    }
-                }
-                Err(_) => {
-                    debug!("The file does not have enough priority or is too large to be accepted into the cache.");
-                    // The new file would not be accepted by the cache, so instead of reading the whole file
-                    // into memory, and then copying it yet again when it is attached to the body of the
-                    // response, use a NamedFile instead.
-                    match NamedFile::open(path.clone()) {
-                        Ok(named_file) => CachedFile::from(named_file),
-                        Err(_) => CachedFile::NotFound,
                     }
+                    None => warn!("Cache contains entry for {:?}, but does not tract its access counts.", path.as_ref())
                 }
             }
+
+        } else {
+            if !self.allows_miss() {
+                return self.fail_degraded_miss(&path);
+            }
+            if let Some(file) = self.take_from_cold_tier(&path) {
+                debug!("Promoting file from the cold tier: {:?}", path.as_ref());
+                return self.try_insert_file(path, Some(file), options);
+            }
+            return self.try_insert_coalesced(path, options);
         }
+
+        (self.timed(Phase::Response, || self.get_from_cache(&path)), ServeClass::Hit)
     }
 
-    /// Gets a file from the filesystem and converts it to a CachedFile.
+
+    /// Allows insertions to temporarily exceed the size limit.
     ///
-    /// This should be used when the cache knows that the new file won't make it into the cache.
-    fn get_file_from_fs< P: AsRef<Path>>(&self, path: P) -> CachedFile{
-        debug!("File does not fit size constraints of the cache.");
-        match NamedFile::open(path.as_ref().to_path_buf()) {
-            Ok(named_file) => {
-                self.increment_access_count(path);
-                return CachedFile::from(named_file);
+    /// Instead of evicting every file needed to make room for a new file while serving the request that missed,
+    /// the cache will insert the file as long as it stays under `size_limit * (1 + fraction)`,
+    /// and the following calls to `get()` will each evict up to `evictions_per_operation` of the
+    /// lowest priority files until the cache is back under its size limit.
+    ///
+    /// Passing `None` restores the default behavior of evicting files synchronously.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, OvershootConfig};
+    ///
+    /// let mut cache = CacheBuilder::new()
+    ///     .size_limit(1024 * 1024 * 10)
+    ///     .build()
+    ///     .unwrap();
+    /// cache.set_overshoot(Some(OvershootConfig {
+    ///     fraction: 0.1,
+    ///     evictions_per_operation: 4,
+    /// }));
+    /// ```
+    pub fn set_overshoot(&mut self, overshoot: Option<OvershootConfig>) {
+        self.overshoot = overshoot;
+    }
+
+    /// Makes insertions evict files in batches, instead of evicting just enough to fit each new file.
+    ///
+    /// Files are inserted without evicting anything while the cache stays under `high` bytes.
+    /// When an insertion would cross `high`, the lowest priority files are evicted until the cache
+    /// is down to `low` bytes including the new file, so the following insertions have room again.
+    /// Only the files needed to stay under `high` are compared against the priority of the new file.
+    /// If those are more important than the new file, nothing is evicted.
+    ///
+    /// `high` is capped at the size limit of the cache, and `low` is capped at `high`.
+    /// By default, both are equal to the size limit, which evicts just enough space for each file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new()
+    ///     .size_limit(1024 * 1024 * 100)
+    ///     .build()
+    ///     .unwrap();
+    /// // Once the cache holds 100 MB, evict down to 80 MB in one go.
+    /// cache.set_watermarks(1024 * 1024 * 80, 1024 * 1024 * 100);
+    /// ```
+    pub fn set_watermarks(&mut self, low: usize, high: usize) {
+        self.high_watermark = usize::min(high, self.size_limit);
+        self.low_watermark = usize::min(low, self.high_watermark);
+    }
+
+    /// Changes the size limit of the cache, evicting files until it fits under the new limit.
+    ///
+    /// Watermarks that were at the old size limit move with it, the others are kept under the new limit.
+    /// If the largest file size was set with `CacheBuilder::max_file_fraction()`, it is recomputed for the new limit,
+    /// and files in the cache that are now larger than it are evicted as well.
+    ///
+    /// Returns the evicted files, the ones that became too large first, then the rest from the lowest priority up.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new()
+    ///     .size_limit(1024 * 1024 * 100)
+    ///     .max_file_fraction(0.25)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(cache.max_file_size_bytes(), 1024 * 1024 * 25);
+    /// cache.resize(1024 * 1024 * 40);
+    /// assert_eq!(cache.max_file_size_bytes(), 1024 * 1024 * 10);
+    /// ```
+    pub fn resize(&mut self, size_limit: usize) -> Vec<EvictedEntry> {
+        let old_size_limit: usize = self.size_limit;
+        self.size_limit = size_limit;
+        self.high_watermark = if self.high_watermark == old_size_limit { size_limit } else { usize::min(self.high_watermark, size_limit) };
+        self.low_watermark = if self.low_watermark == old_size_limit { size_limit } else { self.low_watermark };
+        self.low_watermark = usize::min(self.low_watermark, self.high_watermark);
+
+        let mut evicted: Vec<EvictedEntry> = Vec::new();
+        if let Some(fraction) = self.max_file_fraction {
+            self.max_file_size = policy::max_file_size_for_fraction(size_limit, fraction);
+            // Paths recorded as outside of the old bounds may be inside of the new ones.
+            self.out_of_bounds = ConcHashMap::new();
+            self.reset_classification_filter();
+
+            let oversized: Vec<PathBuf> = self.file_map
+                .iter()
+                .filter(|&(_, file)| !file.is_embedded() && file.stats.size > self.max_file_size)
+                .map(|(path, _)| path.clone())
+                .collect();
+            for path in oversized {
+                if let Some(evicted_entry) = self.evict(&path) {
+                    debug!("Evicted {:?}, which is larger than the new max_file_size.", path);
+                    evicted.push(evicted_entry);
+                }
+            }
+        }
+
+        let mut candidates: Vec<CandidateFile> = self.sorted_priorities();
+        let mut floor_bytes: Vec<usize> = self.directory_floor_bytes();
+        while self.used_bytes() > self.high_watermark {
+            let lowest: CandidateFile = match candidates.pop() {
+                Some(lowest) => lowest,
+                None => break,
+            };
+            if !self.directory_floors.allows_eviction(&mut floor_bytes, &lowest.key, lowest.stats.size) {
+                continue;
+            }
+            if let Some(evicted_entry) = self.evict(&lowest.key) {
+                debug!("Evicted {:?} to fit the cache under its new size limit.", lowest.key);
+                evicted.push(evicted_entry);
             }
-            Err(_) => return CachedFile::NotFound
         }
+        evicted
     }
 
-    /// Reads a file from the filesystem into memory and stores it in the cache.
+    /// Overrides how files with the same priority are ordered for eviction.
     ///
-    /// This is the slowest operation the cache can perform, slower than just getting the file.
-    /// It should only be used when the cache decides to store the file.
-    fn get_file_from_fs_and_add_to_cache<P: AsRef<Path>>(&self, path: P) -> CachedFile {
-        debug!("Cache has room for the file.");
-        match InMemoryFile::open(&path) {
-            Ok(file) => {
-                self.file_map.insert(path.as_ref().to_path_buf(), file);
-
-                self.increment_access_count(&path);
-                self.update_stats(&path);
+    /// The function is given two files, and returns `Ordering::Less` if the first one should be evicted
+    /// before the second one. Returning `Ordering::Equal` leaves the order of the two files unspecified.
+    /// By default, `default_tie_breaker` is used, which evicts the file that was accessed longest ago,
+    /// then the larger file, then the file whose path sorts first.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, FileStats};
+    /// use std::cmp::Ordering;
+    /// use std::path::Path;
+    ///
+    /// // Among files with the same priority, evict the smallest first.
+    /// fn smallest_first(_: &Path, stats: &FileStats, _: &Path, other_stats: &FileStats) -> Ordering {
+    ///     stats.size.cmp(&other_stats.size)
+    /// }
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_tie_breaker(smallest_first);
+    /// ```
+    pub fn set_tie_breaker(&mut self, tie_breaker: fn(&Path, &FileStats, &Path, &FileStats) -> cmp::Ordering) {
+        self.tie_breaker = tie_breaker;
+    }
 
-                let cache_file_accessor = match self.file_map.find(path.as_ref()) {
-                    Some(accessor_to_file) => accessor_to_file,
-                    None => {
-                        // If for whatever reason, a concurrent remove operation removes the file
-                        // before it can be gotten via an accessor lock, recursively try to add
-                        // the file to the Cache until the lock can be attained.
-                        warn!("Tried to add file to cache, but it was removed before it could be added. Attempting to get file again.");
-                        // Because this recursion only occurs under extremely rare circumstances
-                        // due to a concurrent removal of the file being added between the insertion
-                        // into the map, and locking an accessor, a stack overflow is almost impossible.
-                        return self.get_file_from_fs_and_add_to_cache(path);
-                    }
-                };
+    /// Orders the files for eviction by the comparator, instead of by their priorities and the tie breaker.
+    ///
+    /// The comparator is given two files that could be evicted, and returns `Ordering::Less` if the first one
+    /// should be evicted before the second one. It replaces the whole order, so a comparator that only breaks ties
+    /// has to compare the `priority` in the stats of the files first, where lower priorities are evicted first.
+    /// The priorities of the files that would be evicted are still added up and compared to the priority of the new file,
+    /// so whether a file is admitted still depends on the priority function.
+    ///
+    /// While a comparator is set, the time each file was last stored or served is recorded for it, in `last_accessed`.
+    /// Passing `None` goes back to ordering by priority and the tie breaker, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, VictimCandidate};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// // Evict the files that weren't used in the last hour first, then the lowest priority files.
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_victim_comparator(Some(Box::new(|l: &VictimCandidate, r: &VictimCandidate| {
+    ///     let idle = |file: &VictimCandidate| file.last_accessed.map_or(true, |at| at.elapsed() > Duration::from_secs(3600));
+    ///     idle(r).cmp(&idle(l)).then_with(|| l.stats.priority.cmp(&r.stats.priority))
+    /// })));
+    /// ```
+    pub fn set_victim_comparator(&mut self, comparator: Option<VictimComparator>) {
+        self.victim_comparator = comparator;
+    }
+
+    /// Keeps at least `min_bytes` of the files under the prefix in the cache.
+    ///
+    /// When files are evicted, files under the prefix are skipped if evicting them would leave fewer than
+    /// `min_bytes` under the prefix, and the next lowest priority file is chosen instead.
+    /// The floor only protects files that are in the cache; it doesn't cause any files to be inserted.
+    ///
+    /// Floors can be nested. Each file counts towards the floor with the most specific prefix it is under,
+    /// so the files under `www/critical/fonts/` don't count towards a floor on `www/critical/`.
+    /// Setting a floor for a prefix replaces its previous floor, and a floor of 0 bytes removes it.
+    /// `summary()` reports how many bytes each floor is protecting.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::path::PathBuf;
+    ///
+    /// let mut cache = CacheBuilder::new()
+    ///     .size_limit(1024 * 1024 * 10)
+    ///     .build()
+    ///     .unwrap();
+    /// cache.set_directory_floor(PathBuf::from("www/critical"), 1024 * 1024 * 2);
+    /// assert_eq!(cache.summary().directory_floors[0].protected_bytes, 0);
+    /// ```
+    pub fn set_directory_floor(&mut self, prefix: PathBuf, min_bytes: usize) {
+        let prefix: PathBuf = self.key_case.normalize(&prefix).unwrap_or(prefix);
+        self.directory_floors.set(prefix, min_bytes);
+    }
+
+    /// Limits the files under the prefix to holding at most `max_bytes` in the cache,
+    /// so that one directory can't take over the whole cache.
+    ///
+    /// If inserting a file would take a directory over its quota, only files under that directory are
+    /// considered for eviction to make room for it, and files outside of it are left alone.
+    /// If enough room can't be made under the directory, the file is served from the filesystem instead.
+    /// Files that are already in the cache count towards the quota as soon as it is set.
+    ///
+    /// Quotas can be nested, and a file counts towards every quota it is under.
+    /// Setting a quota for a prefix replaces its previous quota.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::path::PathBuf;
+    ///
+    /// let size_limit: usize = 1024 * 1024 * 10;
+    /// let mut cache = CacheBuilder::new()
+    ///     .size_limit(size_limit)
+    ///     .build()
+    ///     .unwrap();
+    /// // Thumbnails may use up to 20% of the cache.
+    /// cache.set_directory_quota(PathBuf::from("www/videos/thumbnails"), size_limit / 5);
+    /// ```
+    pub fn set_directory_quota(&mut self, prefix: PathBuf, max_bytes: usize) {
+        let prefix: PathBuf = self.key_case.normalize(&prefix).unwrap_or(prefix);
+        let file_map = &self.file_map;
+        self.directory_quotas.set(prefix, max_bytes, file_map.iter().map(|(path, file)| (path.as_path(), file.stats.size)));
+    }
+
+    /// Removes the quota for the prefix, returning whether it had one.
+    pub fn remove_directory_quota<P: AsRef<Path>>(&mut self, prefix: P) -> bool {
+        let prefix: PathBuf = self.key_case.normalize(prefix.as_ref()).unwrap_or(prefix.as_ref().to_path_buf());
+        self.directory_quotas.remove(&prefix)
+    }
+
+    /// Multiplies the priority of the files under the prefix for a while, such as ahead of traffic that is known to be coming.
+    ///
+    /// The boost applies to the files in the cache when they are ranked for eviction, and to files that aren't
+    /// when they are compared against them for admission, so boosted files are admitted sooner and evicted later.
+    /// It isn't stored in the stats of the files. Boosts that overlap multiply, and each one ends after the `duration`,
+    /// by the clock of the cache. A duration too long to be represented, like `Duration::from_secs(u64::MAX)`, never ends.
+    ///
+    /// Returns false, without adding the boost, if the multiplier is negative, infinite or NaN.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::path::Path;
+    /// use std::time::Duration;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// // The campaign starts tomorrow, and the landing pages will be popular for a week.
+    /// cache.boost(Path::new("www/landing"), 4.0, Duration::from_secs(60 * 60 * 24 * 8));
+    /// assert_eq!(cache.active_boosts().len(), 1);
+    /// ```
+    pub fn boost(&mut self, prefix: &Path, multiplier: f32, duration: Duration) -> bool {
+        if !multiplier.is_finite() || multiplier < 0.0 {
+            warn!("Not boosting {:?} by {}, as the multiplier must be a finite number of at least 0.", prefix, multiplier);
+            return false;
+        }
+        let prefix: PathBuf = self.key_case.normalize(prefix).unwrap_or(prefix.to_path_buf());
+        let now: Instant = (self.clock)();
+        self.boosts.add(Boost { prefix, multiplier, expires_at: deadline::after(now, duration) }, now);
+        true
+    }
+
+    /// Ends every boost for the prefix early, returning whether it had any.
+    pub fn clear_boost<P: AsRef<Path>>(&mut self, prefix: P) -> bool {
+        let prefix: PathBuf = self.key_case.normalize(prefix.as_ref()).unwrap_or(prefix.as_ref().to_path_buf());
+        self.boosts.clear(&prefix)
+    }
+
+    /// Gets the boosts that haven't ended yet, in the order they were set.
+    pub fn active_boosts(&self) -> Vec<Boost> {
+        self.boosts.active((self.clock)())
+    }
+
+    /// Adds a second, compressed tier to the cache.
+    ///
+    /// Files that are evicted from the cache are compressed and stored in the cold tier, within its own size limit,
+    /// instead of being dropped.
+    /// When a file in the cold tier is requested, it is decompressed and goes through the normal admission
+    /// process to be promoted back into the cache, without being read from the filesystem.
+    ///
+    /// Passing `None` removes the cold tier, dropping all files it holds.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, ColdTierConfig, Compression};
+    ///
+    /// let mut cache = CacheBuilder::new()
+    ///     .size_limit(1024 * 1024 * 10)
+    ///     .build()
+    ///     .unwrap();
+    /// cache.set_cold_tier(Some(ColdTierConfig {
+    ///     size_limit: 1024 * 1024 * 5,
+    ///     compression: Compression::Default,
+    /// }));
+    /// ```
+    pub fn set_cold_tier(&mut self, cold_tier: Option<ColdTierConfig>) {
+        self.cold_tier = cold_tier.map(ColdTier::new);
+    }
+
+    /// Sets whether paths that differ only in case refer to the same file.
+    ///
+    /// With `KeyCase::Insensitive`, the ASCII letters of paths are lowercased before every lookup.
+    /// The file is read from the first spelling of its path that was requested and exists,
+    /// so on a case-sensitive filesystem, it is found with any spelling once it was requested with its own.
+    /// This should be set before any files are added, as existing entries are not renamed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, KeyCase};
+    ///
+    /// static LOGO: &'static [u8] = &[0, 1, 2, 3];
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_key_case(KeyCase::Insensitive);
+    /// cache.seed_embedded(&[("www/logo.png", LOGO)]);
+    /// assert!(cache.contains_key("WWW/Logo.PNG"));
+    /// ```
+    pub fn set_key_case(&mut self, key_case: KeyCase) {
+        self.key_case = key_case;
+    }
+
+    /// Limits how many files can be read into memory at once by cache misses.
+    ///
+    /// When the limit is reached, further misses don't wait for a read to finish.
+    /// They skip inserting the file, and stream it from the filesystem instead,
+    /// keeping a stampede of misses from saturating the disk and holding many files in memory at once.
+    ///
+    /// Passing `None` removes the limit, which is the default.
+    pub fn set_max_concurrent_reads(&mut self, max_concurrent_reads: Option<usize>) {
+        self.read_limiter = ReadLimiter::new(max_concurrent_reads);
+    }
+
+    /// Sets how often the priority of a file is recalculated when the file is accessed.
+    ///
+    /// Recalculating priorities less often can save CPU time on every cache hit if the priority function
+    /// is expensive to run.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Recompute};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_priority_recompute(Recompute::EveryN(100));
+    /// ```
+    pub fn set_priority_recompute(&mut self, priority_recompute: Recompute) {
+        self.priority_recompute = priority_recompute;
+    }
+
+    /// Sets an observer that is notified when files are evicted from the cache, including evictions
+    /// caused by calls to `get()`, which can't report them in their response.
+    ///
+    /// Passing `None` removes the observer.
+    pub fn set_observer(&mut self, observer: Option<Box<dyn CacheObserver>>) {
+        self.observer = observer;
+    }
+
+    /// Sets a filter that is asked about every file the cache considers evicting to make room for another one,
+    /// given its key and stats.
+    ///
+    /// Files the filter prefers are evicted before any of the files it allows,
+    /// and files it denies are skipped, so the next candidate is considered instead.
+    /// If it denies every candidate, the new file isn't stored, failing with `CacheError::AllCandidatesVetoed`.
+    /// A filter that denies every file therefore freezes the contents of the cache once it is full.
+    /// Files can still be removed explicitly, with `remove()` for instance.
+    ///
+    /// Like an observer, the filter is called in the middle of an insertion, so it should be quick,
+    /// and must not call back into the cache.
+    /// Passing `None` removes the filter.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, EvictionVote};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// // Fonts are needed by every page, so they are kept once they are cached.
+    /// cache.set_eviction_filter(Some(Box::new(|path, _stats| match path.extension() {
+    ///     Some(extension) if extension == "woff2" => EvictionVote::Deny,
+    ///     _ => EvictionVote::Allow,
+    /// })));
+    /// ```
+    pub fn set_eviction_filter(&mut self, eviction_filter: Option<EvictionFilter>) {
+        self.eviction_filter = eviction_filter;
+    }
+
+    /// Sets how long a request will wait for a file to be read into memory when it is inserted.
+    ///
+    /// With a deadline, files are read on a separate thread.
+    /// If the read doesn't finish before the deadline, the insertion is abandoned and the file is served from
+    /// the filesystem instead, so slow storage can't hold up the request for longer than the deadline.
+    /// The abandoned read is left to finish on its own, and its result is discarded.
+    ///
+    /// Passing `None` removes the deadline, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::time::Duration;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_read_deadline(Some(Duration::from_millis(250)));
+    /// ```
+    pub fn set_read_deadline(&mut self, read_deadline: Option<Duration>) {
+        self.read_deadline = read_deadline;
+    }
+
+    /// Sets how long a file must have been in the cache before it is evicted to make room for a new file.
+    ///
+    /// A file that was only just inserted has had little chance to be accessed, so its priority says
+    /// little about how useful it is. Files younger than the minimum residency are only evicted if
+    /// the older files can't make enough room on their own, so the new file is never refused just to protect them.
+    /// Replacing or refreshing a file doesn't restart its residency.
+    /// Evicting files to bring the cache back under its size limit ignores the minimum residency.
+    ///
+    /// Passing `None` removes the minimum residency, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::time::Duration;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_min_residency(Some(Duration::from_secs(30)));
+    /// ```
+    pub fn set_min_residency(&mut self, min_residency: Option<Duration>) {
+        self.min_residency = min_residency;
+    }
+
+    /// Sets where the bytes of files read from the filesystem are kept.
+    /// See `Storage` for the available storages.
+    ///
+    /// Files already in the cache keep the storage they were read into.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Storage};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_storage(Storage::Heap);
+    /// ```
+    pub fn set_storage(&mut self, storage: Storage) {
+        self.storage = storage;
+    }
+
+    /// Packs the bytes of small files into shared slabs, instead of giving each of them its own allocation.
+    /// See `SmallFileArenaConfig` for how the slabs are managed.
+    ///
+    /// With an arena, `used_bytes()` counts the space in the slabs that isn't used by any file,
+    /// so small files are charged for the memory they actually hold.
+    /// Files already in the cache keep their bytes where they are, and files read into the slabs
+    /// stay in them after the arena is turned off with `None`, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, SmallFileArenaConfig};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_small_file_arena(Some(SmallFileArenaConfig {
+    ///     threshold: 4 * 1024,
+    ///     slab_size: 1024 * 1024,
+    /// }));
+    /// ```
+    pub fn set_small_file_arena(&mut self, config: Option<SmallFileArenaConfig>) {
+        self.small_file_arena = config.map(SmallFileArena::new);
+    }
+
+    /// Sets whether the phases of serving files are timed for `latency_report()`.
+    ///
+    /// Timing is enabled by default. Each timed phase reads the clock twice.
+    pub fn set_timing(&mut self, timing: bool) {
+        self.timing = timing;
+    }
+
+    /// Sets whether the cache checks its invariants with `check_invariants()` after every change, in debug builds,
+    /// and panics if any are broken. Release builds never check them.
+    ///
+    /// Strict mode is on by default, as every check walks every file in the cache only in debug builds.
+    /// A check is skipped while another thread is changing the cache, as the change could look like a broken invariant.
+    /// Priorities that are stale because a file was refreshed without recalculating its priority aren't checked.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_strict_invariants(false);
+    /// cache.get_source("www/index.html");
+    /// ```
+    pub fn set_strict_invariants(&mut self, strict_invariants: bool) {
+        self.strict_invariants = strict_invariants;
+    }
+
+    /// Sets how long a phase of serving a file should take.
+    ///
+    /// Timings that take longer than the budget are counted in the `over_budget` field of the `latency_report()`.
+    /// The budget of `Phase::FileRead` is also enforced like the deadline set with `set_read_deadline()`,
+    /// so a file that can't be read within it is served from the filesystem instead.
+    /// If both are set, the shorter one applies.
+    ///
+    /// Passing `None` removes the budget, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Phase};
+    /// use std::time::Duration;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_latency_budget(Phase::Lookup, Some(Duration::from_micros(50)));
+    /// cache.set_latency_budget(Phase::FileRead, Some(Duration::from_millis(100)));
+    /// ```
+    pub fn set_latency_budget(&mut self, phase: Phase, budget: Option<Duration>) {
+        self.latency.set_budget(phase, budget);
+    }
+
+    /// Gets how long each phase of serving files has taken, counted into buckets by latency.
+    ///
+    /// The phases are timed while serving files with `get()` and inserting them with `insert()`,
+    /// unless timing was disabled with `set_timing()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Phase};
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.get_source("www/index.html");
+    /// let report = cache.latency_report();
+    /// println!("{} lookups took {:?}", report.phase(Phase::Lookup).count(), report.phase(Phase::Lookup).total);
+    /// ```
+    pub fn latency_report(&self) -> LatencyReport {
+        self.latency.report()
+    }
+
+    /// Sets whether cache hits are checked against the file in the filesystem before they are served.
+    /// See `Consistency` for the available modes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Consistency};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// // Every hit will stat the file, so changes on disk are never served stale.
+    /// cache.set_consistency(Consistency::Paranoid);
+    /// ```
+    pub fn set_consistency(&mut self, consistency: Consistency) {
+        self.consistency = consistency;
+    }
+
+    /// Sets how cached files are compared with the filesystem to tell if they have changed.
+    /// See `Revalidation` for the available modes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Consistency, Revalidation};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// // Files that are deployed by renaming them over the old ones are picked up on their next hit.
+    /// cache.set_consistency(Consistency::Paranoid);
+    /// cache.set_revalidation(Revalidation::ByIdentity);
+    /// ```
+    pub fn set_revalidation(&mut self, revalidation: Revalidation) {
+        self.revalidation = revalidation;
+    }
+
+    /// Sets whether the bytes of cached files are checked against a hash every time they are served.
+    ///
+    /// Files are hashed when they are stored. If the bytes of a file no longer match its hash when it is hit,
+    /// the mismatch is counted in the summary, and the file is read from the filesystem again before it is served.
+    /// This costs a pass over the bytes of every hit, so it is only meant for paranoid deployments.
+    /// Files that were stored before checksums were enabled are hashed the first time they are hit.
+    /// Embedded files are never checked.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_verify_checksums(true);
+    /// ```
+    pub fn set_verify_checksums(&mut self, verify_checksums: bool) {
+        self.verify_checksums = verify_checksums;
+    }
+
+    /// Sets what happens to a cached file when it is refreshed, but no longer exists in the filesystem.
+    /// See `OnMissing` for the available behaviors.
+    ///
+    /// This applies to calls to `refresh()`, and to the refreshes done by `get()` because of
+    /// `accesses_per_refresh` or `Consistency::Paranoid`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, OnMissing};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// // Keep serving files that are deleted from the webroot until they are removed explicitly.
+    /// cache.set_on_missing(OnMissing::ServeStale);
+    /// ```
+    pub fn set_on_missing(&mut self, on_missing: OnMissing) {
+        self.on_missing = on_missing;
+    }
+
+    /// Sets what happens to a soft purged file when it is requested, but can't be checked against the filesystem
+    /// or read again, like when it no longer exists.
+    ///
+    /// By default, the stale copy is served with a warning, so a soft purge never makes a file unavailable.
+    /// With `OnMissing::Remove`, the file is removed from the cache and not found instead.
+    pub fn set_soft_purge_on_missing(&mut self, on_missing: OnMissing) {
+        self.soft_purge_on_missing = on_missing;
+    }
+
+    /// Sets when a request for a file that isn't in the cache counts as an access of the file.
+    /// See `CountPolicy` for the available behaviors.
+    ///
+    /// Hits are always counted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, CountPolicy};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// // Requests for files that can't be read don't make them more likely to be admitted later.
+    /// cache.set_count_policy(CountPolicy::OnSuccess);
+    /// ```
+    pub fn set_count_policy(&mut self, count_policy: CountPolicy) {
+        self.count_policy = count_policy;
+    }
+
+    /// Sets whether the cache decides on its own which files it holds, or only holds the files inserted explicitly.
+    /// See `Mode` for the available behaviors.
+    ///
+    /// In manual mode, the hits and misses are still counted in the extension stats of the summary.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Mode};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_mode(Mode::Manual);
+    /// // Only the files inserted at boot are ever served from memory.
+    /// let _ = cache.insert("www/index.html");
+    /// ```
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Sets the names of the files `get()` serves when it is asked for a directory, which is `index.html` by default.
+    ///
+    /// A request that finds a directory where it expected a file tries each of the names in order,
+    /// and serves the first one that is a file in the directory, cached under the key chosen with `set_index_key()`.
+    /// Names that are directories themselves are skipped.
+    /// If none of the names are files, or the list is empty, the directory is not found.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_index_files(vec![String::from("index.html"), String::from("index.htm")]);
+    /// // Serves www/docs/index.html, or www/docs/index.htm if there is no index.html.
+    /// let _ = cache.get_source("www/docs");
+    /// ```
+    pub fn set_index_files(&mut self, index_files: Vec<String>) {
+        self.index_files = index_files;
+    }
+
+    /// Sets the key the index file of a directory is cached under. See `IndexKey` for the available behaviors.
+    pub fn set_index_key(&mut self, index_key: IndexKey) {
+        self.index_key = index_key;
+    }
+
+    /// Stops files that keep being evicted and inserted again from being inserted for a while.
+    ///
+    /// Once a file has been evicted and inserted again `cycles` times within the `window`,
+    /// it is served from the filesystem without being considered for insertion until the `cooldown` has passed.
+    /// This keeps a working set that doesn't fit into the cache from evicting files only to read them again.
+    /// Passing `None` turns thrash protection off, which is the default, and forgives every penalized file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, ThrashConfig};
+    /// use std::time::Duration;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_thrash_protection(Some(ThrashConfig {
+    ///     cycles: 5,
+    ///     window: Duration::from_secs(60),
+    ///     cooldown: Duration::from_secs(600),
+    /// }));
+    /// ```
+    pub fn set_thrash_protection(&mut self, config: Option<ThrashConfig>) {
+        self.thrash_protection = config.map(ThrashProtection::new);
+    }
+
+    /// Prefetches the files next to a file that was inserted after a miss, as a page is usually followed by its assets.
+    ///
+    /// The siblings aren't read during the miss. They wait in a queue of up to `queue_capacity` misses,
+    /// and the next request that gets a file takes the oldest miss out of the queue and inserts its siblings first.
+    /// Up to `max_siblings` files in the same directory that aren't in the cache, and are within its size bounds,
+    /// are inserted into the free space of the cache, as long as they fit into the `byte_budget`.
+    /// Like `prefetch_top()`, this never evicts files, and doesn't count the prefetched files as accessed.
+    /// Only the first few hundred entries of the directory are looked at.
+    /// Passing `None` turns prefetching off, which is the default, and forgets the queued misses.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, PrefetchConfig};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_sibling_prefetch(Some(PrefetchConfig {
+    ///     max_siblings: 8,
+    ///     byte_budget: 1024 * 1024,
+    ///     queue_capacity: 16,
+    /// }));
+    /// ```
+    pub fn set_sibling_prefetch(&mut self, config: Option<PrefetchConfig>) {
+        self.sibling_prefetch = config.map(SiblingPrefetch::new);
+    }
+
+    /// Stops storing files once the used bytes and the `zombie_bytes()` would add up to more than the ceiling,
+    /// so memory held by evicted files that are still being served can't grow without bounds.
+    ///
+    /// Files that would go over the ceiling are served from the filesystem, without evicting anything for them,
+    /// as evicting files that are being served doesn't free their bytes.
+    /// Passing `None` removes the ceiling, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().size_limit(64 * 1024 * 1024).build().unwrap();
+    /// cache.set_memory_ceiling(Some(96 * 1024 * 1024));
+    /// ```
+    pub fn set_memory_ceiling(&mut self, ceiling: Option<usize>) {
+        self.memory_ceiling = ceiling;
+    }
+
+    /// Estimates how many misses would have been hits if the cache could hold `shadow_limit` bytes.
+    ///
+    /// The key and size of every evicted file, but not its bytes, are remembered as a ghost entry,
+    /// until the ghost entries add up to more than the difference between the shadow limit and the size limit,
+    /// and the ones evicted longest ago are forgotten.
+    /// A miss for a file that still has a ghost entry is counted in the `ghost_hits` field of the summary,
+    /// as a request the larger cache would likely have served from memory.
+    /// Passing `None` stops tracking ghost entries, which is the default, and resets the count.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().size_limit(1024 * 1024 * 10).build().unwrap();
+    /// // Would twice the memory be worth it?
+    /// cache.set_shadow_limit(Some(1024 * 1024 * 20));
+    /// assert_eq!(cache.summary().ghost_hits, 0);
+    /// ```
+    pub fn set_shadow_limit(&mut self, shadow_limit: Option<usize>) {
+        let size_limit: usize = self.size_limit;
+        self.ghost_entries = shadow_limit.map(|shadow_limit| GhostEntries::new(shadow_limit.saturating_sub(size_limit)));
+    }
+
+    /// Serves only the files held by the cache while the filesystem keeps failing.
+    ///
+    /// Once `errors` filesystem errors happen in a row while serving misses, like an unmounted network share
+    /// failing every read, misses fail fast as if their files weren't found, without reading metadata,
+    /// reading files, or evicting anything, so the files already in the cache aren't thrown away for files
+    /// that can't be read. Hits are served as usual.
+    /// Once the `backoff` has passed, the next miss probes the filesystem. If the probe succeeds, misses are
+    /// served normally again; otherwise misses fail fast for another backoff.
+    ///
+    /// Files that don't exist count as errors, so the threshold should be well above the number of requests
+    /// for missing files that are expected in a row.
+    /// Passing `None` turns degraded mode off, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, DegradedModeConfig};
+    /// use std::time::Duration;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_degraded_mode(Some(DegradedModeConfig {
+    ///     errors: 50,
+    ///     backoff: Duration::from_secs(5),
+    /// }));
+    /// ```
+    pub fn set_degraded_mode(&mut self, config: Option<DegradedModeConfig>) {
+        self.fs_health = config.map(FsHealth::new);
+    }
+
+    /// Limits how many files are refreshed while serving requests for them, like right after a deploy
+    /// changed thousands of files at once, so the first requests for them don't all read their files again together.
+    ///
+    /// The limit is a token bucket: `burst` refreshes are allowed at once, and the bucket refills at `per_second`.
+    /// When it is empty, soft purged files that changed, and files that changed under `Consistency::Paranoid`,
+    /// are served from their stale copies and are checked again on their next request.
+    /// Refreshes every `accesses_per_refresh` requests are skipped until the next one is due.
+    /// Refreshes asked for directly, like with `refresh_source()` or `try_refresh()`, are never limited.
+    /// Passing `None` removes the limit, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, RateLimit};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_refresh_rate_limit(Some(RateLimit {
+    ///     per_second: 100,
+    ///     burst: 500,
+    /// }));
+    /// ```
+    pub fn set_refresh_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.refresh_limiter = limit.map(RefreshLimiter::new);
+    }
+
+    /// Whether the cache is serving only the files it holds, because the filesystem kept failing.
+    /// See `set_degraded_mode()`.
+    pub fn is_degraded(&self) -> bool {
+        self.fs_health.as_ref().is_some_and(|fs_health| fs_health.is_degraded())
+    }
+
+    /// Counts the hits and misses of the files requested with a `CacheHandle` under the label of the handle,
+    /// so they can be read with `labeled_summary()`.
+    ///
+    /// Labels are kept for as long as the cache lives, so they should come from a small, fixed set, like the routes
+    /// of a site, and never from anything a client chooses. Turning them off, which is the default, forgets every count.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_labeled_metrics(true);
+    /// assert_eq!(cache.labeled_summary("img").hits, 0);
+    /// ```
+    pub fn set_labeled_metrics(&mut self, enabled: bool) {
+        if enabled != self.labeled_metrics.is_some() {
+            self.labeled_metrics = if enabled { Some(LabeledMetrics::new()) } else { None };
+        }
+    }
+
+    /// Gets the hits and misses counted under the label, which are 0 if labels aren't counted.
+    /// See `set_labeled_metrics()`.
+    pub fn labeled_summary(&self, label: &str) -> LabeledSummary {
+        self.labeled_metrics.as_ref().map_or(LabeledSummary::default(), |labeled_metrics| labeled_metrics.summary(label))
+    }
+
+    /// Gets the files that thrash protection is keeping out of the cache, and how long each of them will be kept out for.
+    pub fn penalized_paths(&self) -> Vec<(PathBuf, Duration)> {
+        let now: Instant = (self.clock)();
+        match self.thrash_protection {
+            Some(ref thrash_protection) => thrash_protection
+                .penalties(now)
+                .into_iter()
+                .map(|(path, until)| (path, until.duration_since(now)))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Always serves the file at the path from the filesystem, without counting its accesses or ever storing it.
+    /// If the file is in the cache, it is removed, and its access count is forgotten.
+    ///
+    /// This is meant for small, popular files that change all the time, which the priority function would keep in the cache.
+    /// Only the exact path is bypassed, after its case is normalized.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, CacheError};
+    /// use std::path::PathBuf;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.always_bypass(PathBuf::from("www/version.json"));
+    /// assert_eq!(cache.insert("www/version.json"), Err(CacheError::Bypassed));
+    /// ```
+    pub fn always_bypass(&mut self, path: PathBuf) {
+        let path: PathBuf = self.key_case.normalize(&path).unwrap_or(path);
+        self.remove(&path);
+        self.access_count_map.remove(&path);
+        self.bypassed.insert(path);
+    }
+
+    /// Takes the path off the bypass list, so the file can be stored in the cache again.
+    /// Returns false if the path wasn't bypassed.
+    pub fn remove_bypass<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        let path: PathBuf = self.key_case.normalize(path.as_ref()).unwrap_or(path.as_ref().to_path_buf());
+        self.bypassed.remove(&path)
+    }
+
+    /// Gets the paths that are always served from the filesystem, in order.
+    pub fn bypassed_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.bypassed.iter().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Keeps a log of the most recent decisions made by `get()`, for debugging why requests missed.
+    ///
+    /// Up to `capacity` decisions are kept, after which the oldest are overwritten.
+    /// Passing `None` stops recording decisions and drops the log, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_decision_log(Some(100));
+    /// cache.get_source("www/index.html");
+    /// for decision in cache.recent_decisions() {
+    ///     println!("{:?} {:?}: {:?}", decision.time, decision.path, decision.outcome);
+    /// }
+    /// ```
+    pub fn set_decision_log(&mut self, capacity: Option<usize>) {
+        self.decision_log = capacity.map(DecisionLog::new);
+    }
+
+    /// Gets the decisions in the decision log, oldest first.
+    /// This is empty unless the log was enabled with `set_decision_log()`.
+    pub fn recent_decisions(&self) -> Vec<Decision> {
+        match self.decision_log {
+            Some(ref decision_log) => decision_log.recent(),
+            None => vec![],
+        }
+    }
+
+    /// Removes every decision from the decision log.
+    pub fn clear_decisions(&self) {
+        if let Some(ref decision_log) = self.decision_log {
+            decision_log.clear();
+        }
+    }
+
+    /// Starts or stops recording the requests, removals and refreshes the cache handles into a trace.
+    ///
+    /// Each operation is recorded with the key it was made with, after case normalization and aliases were applied,
+    /// and the size of the file it found. Requests the cache passes through to the filesystem,
+    /// because it is disabled or the path is bypassed, aren't recorded.
+    /// Starting to record drops any trace recorded before, and stopping drops the trace,
+    /// so it should be taken with `trace()` first.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.record_trace(true);
+    /// cache.get_source("www/index.html");
+    /// assert_eq!(cache.trace().ops.len(), 1);
+    /// ```
+    pub fn record_trace(&mut self, record: bool) {
+        self.trace = if record { Some(TraceRecorder::new((self.clock)())) } else { None };
+    }
+
+    /// Gets the operations recorded since `record_trace()` started recording, oldest first.
+    /// This is empty unless the cache is recording.
+    pub fn trace(&self) -> Trace {
+        match self.trace {
+            Some(ref trace) => trace.trace(),
+            None => Trace { ops: vec![] },
+        }
+    }
+
+    /// Freezes or unfreezes the cache.
+    ///
+    /// A frozen cache keeps serving the files it holds, but nothing about it changes:
+    /// `get()` serves hits without counting the access, and serves misses from the filesystem
+    /// without inserting anything or recording the miss.
+    /// `insert()` and `commit_insert()` fail with `CacheError::CacheFrozen`, `remove()` and
+    /// `alter_access_count()` return false, and `refresh()` serves the cached copy without reading the file again.
+    ///
+    /// Unlike the other settings, this can be changed while the cache is in use.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, CacheError};
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_frozen(true);
+    /// assert_eq!(cache.insert("www/index.html"), Err(CacheError::CacheFrozen));
+    /// cache.set_frozen(false);
+    /// ```
+    pub fn set_frozen(&self, frozen: bool) {
+        self.frozen.store(frozen, Ordering::Release);
+    }
+
+    /// Checks if the cache is frozen.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::Acquire)
+    }
+
+    /// Sets whether paths containing `..` components are rejected by `get()`.
+    /// This is disabled by default.
+    ///
+    /// Paths that are empty or contain NUL bytes are always rejected.
+    pub fn set_reject_parent_directories(&mut self, reject_parent_directories: bool) {
+        self.reject_parent_directories = reject_parent_directories;
+    }
+
+    /// Checks if the path can be used as a key in the cache.
+    ///
+    /// `get()` performs this check before touching any of the cache's state,
+    /// and responds with `FileSource::NotFound` for paths that are rejected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, CacheError, InvalidKeyReason};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// assert_eq!(cache.validate_key("www/index.html"), Ok(()));
+    /// assert_eq!(cache.validate_key(""), Err(CacheError::InvalidKey(InvalidKeyReason::Empty)));
+    ///
+    /// cache.set_reject_parent_directories(true);
+    /// assert_eq!(
+    ///     cache.validate_key("www/../secret.txt"),
+    ///     Err(CacheError::InvalidKey(InvalidKeyReason::ContainsParentDirectory))
+    /// );
+    /// ```
+    pub fn validate_key<P: AsRef<Path>>(&self, path: P) -> Result<(), CacheError> {
+        let path: &Path = path.as_ref();
+        if path.as_os_str().is_empty() {
+            return Err(CacheError::InvalidKey(InvalidKeyReason::Empty));
+        }
+        if path.to_string_lossy().contains('\0') {
+            return Err(CacheError::InvalidKey(InvalidKeyReason::ContainsNul));
+        }
+        if self.reject_parent_directories && path.components().any(|component| component == Component::ParentDir) {
+            return Err(CacheError::InvalidKey(InvalidKeyReason::ContainsParentDirectory));
+        }
+        Ok(())
+    }
+
+    /// Seeds the cache with files embedded in the binary, such as those included with `include_bytes!`.
+    ///
+    /// Each entry is stored under its virtual path and served through `get()` like any other file,
+    /// without the embedded bytes ever being copied.
+    /// Embedded files are never evicted, and refreshing them does nothing, as they have no backing file
+    /// in the filesystem. They still count towards `used_bytes()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - Pairs of the virtual path to serve the file under, and the file's bytes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// static FAVICON: &'static [u8] = &[0, 0, 1, 0];
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.seed_embedded(&[("www/favicon.ico", FAVICON)]);
+    /// assert!(cache.contains_key("www/favicon.ico"));
+    /// assert_eq!(cache.used_bytes(), FAVICON.len());
+    /// ```
+    pub fn seed_embedded(&self, entries: &[(&str, &'static [u8])]) {
+        let _operation: Operation = self.operation();
+        for &(path, bytes) in entries {
+            let path: PathBuf = PathBuf::from(path);
+            let path: PathBuf = self.key_case.normalize(&path).unwrap_or(path);
+            debug!("Seeding embedded file: {:?}", path);
+            self.add_space(bytes.len());
+            self.store_file(path.clone(), InMemoryFile::from_static(bytes));
+            self.update_stats(&path);
+        }
+        self.debug_check_invariants();
+    }
+
+    /// If a file has changed on disk, the cache will not automatically know that a change has occurred.
+    /// Calling this function will check if the file exists, read the new file into memory,
+    /// replace the old file, and update the priority score to reflect the new size of the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path that represents the path of the file in the filesystem, and key to
+    /// the file in the cache.
+    /// The path will be used to find the new file in the filesystem and to find the old file to replace in
+    /// the cache.
+    ///
+    /// # Return
+    ///
+    /// The FileSource will indicate FileMissing if the file is in the cache, but no longer exists in the filesystem.
+    /// Depending on `set_on_missing()`, the entry is either removed or left in place.
+    /// The FileSource will indicate NotFound if the file isn't already in the cache or if it can't
+    /// be read from the filesystem.
+    /// It will otherwise return a FileSource::Cached variant.
+    pub fn refresh_source<P: AsRef<Path>>(&self, path: P) -> FileSource {
+        self.refresh_source_with(path, &RefreshOptions::new())
+    }
+
+    /// Refreshes the file like `refresh_source()`, returning it as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn refresh<P: AsRef<Path>>(&self, path: P) -> CachedFile {
+        self.refresh_source(path).into()
+    }
+
+    /// Refreshes the file like `refresh_source()`, with control over which of its stats are kept.
+    /// See `RefreshOptions` for the available options.
+    ///
+    /// Unless the access count is reset, the file keeps the access count it had before it was refreshed.
+    pub fn refresh_source_with<P: AsRef<Path>>(&self, path: P, options: &RefreshOptions) -> FileSource {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.refresh_source_with(key, options);
+        }
+        if let Some(target) = self.added_alias_target(&path) {
+            return self.refresh_source_with(target, options);
+        }
+        let refreshed: FileSource = match self.refresh_path(path.as_ref(), options) {
+            RefreshOutcome::Refreshed | RefreshOutcome::NotRefreshable | RefreshOutcome::TooLarge => {
+                // In content addressed mode, the path may now point at the key of its new content.
+                self.get_from_cache(self.content_target(path.as_ref()).unwrap_or_else(|| path.as_ref().to_path_buf()))
+            }
+            RefreshOutcome::Evicted => FileSource::open(self.fs_path(path.as_ref())).unwrap_or(FileSource::NotFound),
+            RefreshOutcome::FileMissing => FileSource::FileMissing,
+            RefreshOutcome::NotCached | RefreshOutcome::NotAFile | RefreshOutcome::OpenFailed => FileSource::NotFound,
+        };
+        if let Some(ref trace) = self.trace {
+            trace.record_refresh(path.as_ref(), refreshed.size(), (self.clock)());
+        }
+        refreshed
+    }
+
+    /// Refreshes the file like `refresh_source_with()`, telling what happened instead of returning the file.
+    ///
+    /// `Refreshed` is only returned once the new bytes are in the cache. If the file changes between being checked
+    /// and being read again, like a file that is deleted or replaced by a directory, the cached copy and its stats are
+    /// left as they were, and the outcome tells why.
+    ///
+    /// If the file grew, lower priority files are evicted to make room for it, but never the file itself.
+    /// If they can't be, the file is either removed from the cache or kept as it was, as `RefreshOptions::keep_when_too_large()` says.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, RefreshOptions, RefreshOutcome};
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// assert_eq!(cache.try_refresh("www/index.html", &RefreshOptions::new()), RefreshOutcome::NotCached);
+    /// ```
+    pub fn try_refresh<P: AsRef<Path>>(&self, path: P, options: &RefreshOptions) -> RefreshOutcome {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.try_refresh(key, options);
+        }
+        if let Some(target) = self.added_alias_target(&path) {
+            return self.try_refresh(target, options);
+        }
+        self.refresh_path(path.as_ref(), options)
+    }
+
+    /// Refreshes the file of the path, which has already been normalized, and isn't an alias added with `add_alias()`.
+    ///
+    /// In content addressed mode, only the path is refreshed, and not the other paths sharing its content.
+    fn refresh_path(&self, path: &Path, options: &RefreshOptions) -> RefreshOutcome {
+        let _operation: Operation = self.operation();
+        let outcome: RefreshOutcome = match self.content_target(path) {
+            Some(_) if self.is_frozen() => RefreshOutcome::NotRefreshable,
+            Some(key) => self.refresh_content_path(path, &key, options),
+            None => self.refresh_key(path, options),
+        };
+        self.debug_check_invariants();
+        outcome
+    }
+
+    /// Refreshes the file stored under the key, which has already been normalized.
+    fn refresh_key(&self, path: &Path, options: &RefreshOptions) -> RefreshOutcome {
+        // Embedded files have nothing in the filesystem to be refreshed from.
+        // A frozen cache keeps serving the copy it has.
+        if self.is_embedded(path) || self.is_frozen() {
+            return RefreshOutcome::NotRefreshable;
+        }
+
+        // The file may have changed size, so it should be checked against the size bounds again.
+        self.out_of_bounds.remove(path);
+        // A root earlier in the fallback chain may have gained the file.
+        self.fallback_resolutions.forget(path);
+
+        // A compressed copy of the file would be just as out of date as the cached one.
+        if let Some(ref cold_tier) = self.cold_tier {
+            cold_tier.remove(path);
+        }
+
+        if let Some(ref content_index) = self.content_index {
+            if content_index::hash_of_key(path).is_some() {
+                return self.refresh_content(path, content_index.paths_of(path), options);
+            }
+        }
+        if let Err(outcome) = self.check_refreshable(path) {
+            return outcome;
+        }
+        self.reread(path, options)
+    }
+
+    /// Checks that the file is in the cache, and is still a file in the filesystem, before it is read again.
+    fn check_refreshable(&self, path: &Path) -> Result<(), RefreshOutcome> {
+        if !self.contains_key(path) {
+            return Err(RefreshOutcome::NotCached);
+        }
+        match fs::metadata(self.fs_path(path)) {
+            Ok(ref metadata) if metadata.is_file() => Ok(()),
+            Ok(_) => Err(RefreshOutcome::NotAFile),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                if self.on_missing == OnMissing::Remove {
+                    debug!("{:?} no longer exists in the filesystem, removing it from the cache.", path);
+                    self.remove(path);
+                }
+                Err(RefreshOutcome::FileMissing)
+            }
+            Err(_) => Err(RefreshOutcome::OpenFailed),
+        }
+    }
+
+    /// Reads the file again, and replaces the cached copy with it.
+    ///
+    /// The file may have changed since it was checked, so the cached copy is only touched once the new bytes
+    /// have been read, and is left as it was if they can't be.
+    fn reread(&self, path: &Path, options: &RefreshOptions) -> RefreshOutcome {
+        let new_file: InMemoryFile = match self.read_file(path, None) {
+            Ok(new_file) => new_file,
+            Err(error) => {
+                warn!("Could not read {:?} again, keeping the cached copy: {}", path, error);
+                return RefreshOutcome::OpenFailed;
+            }
+        };
+        // A file removed from the cache while it was read isn't stored again, as that would skip admission.
+        let old_size: usize = match self.file_map.find(path) {
+            Some(file) => file.get().stats.size,
+            None => return RefreshOutcome::NotCached,
+        };
+        let new_size: usize = new_file.stats.size;
+        if new_size > self.max_file_size {
+            return self.outgrown(path, options);
+        }
+        let growth: usize = usize::saturating_sub(new_size, old_size);
+        if growth > 0 && !self.reserve_space(growth) && !self.make_room_to_grow(path, new_size, growth) {
+            return self.outgrown(path, options);
+        }
+        debug!("Refreshing file: {:?}", path);
+        self.store_refreshed(path, new_file, options);
+        self.release_space(growth);
+        RefreshOutcome::Refreshed
+    }
+
+    /// Evicts lower priority files to make room for the file to grow, and reserves the growth.
+    ///
+    /// The file itself is never one of the victims, so it can't be evicted to make room for its own new copy.
+    /// Returns false if the growth can't be reserved.
+    fn make_room_to_grow(&self, path: &Path, new_size: usize, growth: usize) -> bool {
+        debug!("Trying to make room for {:?} to grow", path);
+        let access_count: usize = self.access_count_map.find(path).map_or(0, |access_count| *access_count.get());
+        let required_space: usize = policy::required_space(self.used_bytes(), growth, self.effective_size_limit());
+        let batch_space: usize = self.batch_eviction_space(growth);
+        let request = RoomRequest {
+            required_space,
+            batch_space,
+            new_file_priority: self.priority(access_count, new_size),
+            max_candidates: None,
+        };
+        let victims: Vec<(PathBuf, usize)> = match self.make_room_for_new_file(&request, Some(path), None) {
+            Ok(victims) => victims,
+            Err(_) => return false,
+        };
+        self.evict_with_reservation(growth, &victims).is_ok()
+    }
+
+    /// Refreshes every path pointing at the content stored under the key in content addressed mode.
+    ///
+    /// Paths whose content changed are pointed at the key of their new content, and the others keep sharing the old one.
+    /// The outcome is `Refreshed` if every path was refreshed, and otherwise tells why the last one that failed wasn't.
+    fn refresh_content(&self, key: &Path, paths: Vec<PathBuf>, options: &RefreshOptions) -> RefreshOutcome {
+        if paths.is_empty() {
+            return RefreshOutcome::NotCached;
+        }
+        paths
+            .iter()
+            .map(|path| self.refresh_content_path(path, key, options))
+            .fold(RefreshOutcome::Refreshed, |outcome, path_outcome| match path_outcome {
+                RefreshOutcome::Refreshed => outcome,
+                path_outcome => path_outcome,
+            })
+    }
+
+    /// Hashes the file of the path again, and points the path at its new content if it changed.
+    fn refresh_content_path(&self, path: &Path, key: &Path, options: &RefreshOptions) -> RefreshOutcome {
+        let mut new_file: InMemoryFile = match self.read_file(path, None) {
+            Ok(new_file) => new_file,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                if self.on_missing == OnMissing::Remove {
+                    debug!("{:?} no longer exists in the filesystem, forgetting its content.", path);
+                    self.unpoint_content(path);
+                }
+                return RefreshOutcome::FileMissing;
+            }
+            Err(error) => {
+                warn!("Could not read {:?} again, keeping the content it points at: {}", path, error);
+                return RefreshOutcome::OpenFailed;
+            }
+        };
+        let hash: u64 = content_hash::hash(new_file.bytes.as_slice());
+        let new_key: PathBuf = content_index::content_key(hash, path);
+        let modified: Option<SystemTime> = new_file.modified;
+        let same_content: Option<bool> = self.file_map
+            .find(&new_key)
+            .map(|stored| stored.get().bytes.as_slice() == new_file.bytes.as_slice());
+        if same_content == Some(false) {
+            // The next request reads the file again, and stores it under its path.
+            warn!("{:?} has the content hash of {:?}, but not its content, forgetting its content.", path, new_key);
+            self.unpoint_content(path);
+            return RefreshOutcome::Evicted;
+        }
+        if new_key == key {
+            self.point_to_content(path, key.to_path_buf(), modified);
+            return RefreshOutcome::Refreshed;
+        }
+        if same_content.is_none() {
+            debug!("{:?} changed, storing its new content under {:?}", path, new_key);
+            let access_count: usize = if options.reset_access_count {
+                0
+            } else {
+                self.access_count_map.find(key).map_or(0, |access_count| *access_count.get())
+            };
+            self.access_count_map.insert(new_key.clone(), access_count);
+            let size: usize = new_file.stats.size;
+            if size > self.max_file_size || (!self.reserve_space(size) && !self.make_room_to_grow(&new_key, size, size)) {
+                return self.outgrown_content(path, options);
+            }
+            new_file.content_hash = Some(hash);
+            self.store_file(new_key.clone(), new_file);
+            self.update_stats(&new_key);
+        }
+        self.point_to_content(path, new_key, modified);
+        RefreshOutcome::Refreshed
+    }
+
+    /// Handles a path whose new content is too large to be stored, as the options say.
+    fn outgrown_content(&self, path: &Path, options: &RefreshOptions) -> RefreshOutcome {
+        if options.keep_when_too_large {
+            debug!("The new content of {:?} is too large to be stored, keeping the old content.", path);
+            return RefreshOutcome::TooLarge;
+        }
+        debug!("The new content of {:?} is too large to be stored, forgetting its content.", path);
+        self.unpoint_content(path);
+        RefreshOutcome::Evicted
+    }
+
+    /// Handles a file that grew too large to stay in the cache, as the options say.
+    fn outgrown(&self, path: &Path, options: &RefreshOptions) -> RefreshOutcome {
+        if options.keep_when_too_large {
+            debug!("{:?} grew too large to be refreshed, keeping the cached copy.", path);
+            return RefreshOutcome::TooLarge;
+        }
+        debug!("{:?} grew too large to stay in the cache, removing it.", path);
+        if let Some(file) = self.take_entry(path) {
+            self.zombie_bytes.on_removed(&file.bytes);
+        }
+        RefreshOutcome::Evicted
+    }
+
+    /// Replaces the cached file with its refreshed copy, carrying its stats over as the options say.
+    ///
+    /// The stats are filled in before the copy replaces the old file in place, so requests served during the refresh
+    /// find either the old file or the new one with its stats, and never miss or see a file without stats.
+    fn store_refreshed(&self, path: &Path, mut new_file: InMemoryFile, options: &RefreshOptions) {
+        let old_priority: Option<usize> = self.file_map.find(path).map(|file| file.get().stats.priority);
+        if options.reset_access_count {
+            self.access_count_map.insert(path.to_path_buf(), 0);
+        }
+        // The new file starts without stats, so the access count is carried over from the access count map.
+        let access_count: usize = self.access_count_map.find(path).map_or(0, |access_count| *access_count.get());
+        new_file.stats.access_count = access_count;
+        new_file.stats.priority = match (options.recompute_priority, old_priority) {
+            (false, Some(old_priority)) => old_priority,
+            _ => self.priority(access_count, new_file.stats.size),
+        };
+        self.add_space(new_file.stats.size);
+        self.store_file(path.to_path_buf(), new_file);
+    }
+
+    /// Refreshes the file like `refresh_source_with()`, returning it as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn refresh_with<P: AsRef<Path>>(&self, path: P, options: &RefreshOptions) -> CachedFile {
+        self.refresh_source_with(path, options).into()
+    }
+
+    /// Replaces the bytes of a file in the cache without going through the filesystem.
+    /// This is meant for files whose contents are generated rather than read from disk.
+    ///
+    /// The file keeps its access count, and its priority is recalculated for its new size.
+    /// If the file grew, lower priority files are evicted to make room for it the same way they would be
+    /// for a new file, and the update is rejected if they can't be.
+    /// The file is marked as modified now, and its content hash is recalculated from the new bytes,
+    /// so `last_modified()` and `content_hash()` both reflect the update.
+    ///
+    /// The new bytes are never checked against the filesystem: `Consistency::Paranoid`, checksum verification,
+    /// periodic refreshes, `soft_purge()` and `verify()` all leave them alone.
+    /// Only a file that is explicitly refreshed with `refresh()` has these bytes replaced by the file on disk.
+    ///
+    /// # Return
+    ///
+    /// Returns `NotCached` if the file isn't in the cache. The file is never inserted by this method.
+    /// If the update is rejected, the file is left as it was.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, CacheError};
+    ///
+    /// static FEED: &'static [u8] = b"<rss></rss>";
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// assert_eq!(cache.update_bytes("www/feed.xml", FEED.to_vec()), Err(CacheError::NotCached));
+    ///
+    /// cache.seed_embedded(&[("www/feed.xml", FEED)]);
+    /// cache.update_bytes("www/feed.xml", b"<rss><item/></rss>".to_vec()).unwrap();
+    /// assert_eq!(cache.used_bytes(), 18);
+    /// ```
+    pub fn update_bytes<P: AsRef<Path>>(&self, path: P, bytes: Vec<u8>) -> Result<(), CacheError> {
+        let _operation: Operation = self.operation();
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.update_bytes(key, bytes);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.update_bytes(target, bytes);
+        }
+        if self.is_frozen() {
+            return Err(CacheError::CacheFrozen);
+        }
+
+        let old_size: usize = match self.file_map.find(path.as_ref()) {
+            Some(file) => file.get().stats.size,
+            None => return Err(CacheError::NotCached),
+        };
+        let new_size: usize = bytes.len();
+        policy::check_size_bounds(new_size, self.min_file_size, self.max_file_size)?;
+        let access_count: usize = match self.access_count_map.find(path.as_ref()) {
+            Some(access_count) => *access_count.get(),
+            None => 0,
+        };
+
+        let growth: usize = usize::saturating_sub(new_size, old_size);
+        if growth > 0 && !self.reserve_space(growth) {
+            debug!("Trying to make room for {:?} to grow", path.as_ref());
+            let required_space: usize = policy::required_space(self.used_bytes(), growth, self.effective_size_limit());
+            let new_priority: usize = self.priority(access_count, new_size);
+            let batch_space: usize = self.batch_eviction_space(growth);
+            let request = RoomRequest {
+                required_space,
+                batch_space,
+                new_file_priority: new_priority,
+                max_candidates: None,
+            };
+            let files_to_be_removed: Vec<(PathBuf, usize)> = self.make_room_for_new_file(&request, Some(path.as_ref()), None)?;
+            self.evict_with_reservation(growth, &files_to_be_removed)?;
+        }
+
+        let mut new_file: InMemoryFile = InMemoryFile::from_bytes(bytes);
+        new_file.modified = Some(SystemTime::now());
+        new_file.pushed = true;
+        new_file.stats.access_count = access_count;
+        new_file.stats.priority = self.priority(access_count, new_size);
+        new_file.stats.last_access = self.next_access_tick();
+
+        // The file is replaced while its entry is locked, so a concurrent removal can't cause it to be inserted again.
+        let replaced_size: Option<usize> = match self.file_map.find_mut(path.as_ref()) {
+            Some(mut file) => {
+                new_file.key = file.get().key.take();
+                new_file.inserted_at = file.get().inserted_at;
+                new_file.generation = self.next_generation();
+                let replaced: InMemoryFile = mem::replace(file.get(), new_file);
+                // Responses still serving the old bytes keep them in memory.
+                self.zombie_bytes.on_removed(&replaced.bytes);
+                Some(replaced.stats.size)
+            }
+            None => None,
+        };
+        self.release_space(growth);
+        match replaced_size {
+            Some(replaced_size) => {
+                // A file that was seeded embedded has never had its accesses counted.
+                self.access_count_map.upsert(path.as_ref().to_path_buf(), access_count, &|_| {});
+                self.add_space(new_size);
+                self.release_space(replaced_size);
+                self.directory_quotas.on_stored(path.as_ref(), new_size);
+                self.directory_quotas.on_removed(path.as_ref(), replaced_size);
+                self.schedule_overshoot_eviction();
+                self.debug_check_invariants();
+                Ok(())
+            }
+            None => Err(CacheError::NotCached),
+        }
+    }
+
+    /// Removes the file from the cache.
+    /// This will not reset the access count, so the next time the file is accessed, it will be added to the cache again.
+    /// The access count will have to be reset separately using `alter_access_count()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path that acts as a key to look up the file that should be removed from the cache.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{Cache, CacheBuilder};
+    /// use std::path::PathBuf;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let pathbuf = PathBuf::from("www/index.html");
+    /// cache.remove(&pathbuf);
+    /// assert!(cache.contains_key(&pathbuf) == false);
+    /// ```
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> bool {
+        let _operation: Operation = self.operation();
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.remove(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.remove(target);
+        }
+        if self.is_frozen() {
+            warn!("Not removing {:?}, because the cache is frozen.", path.as_ref());
+            return false;
+        }
+        let (file, removed_from_cold_tier): (Option<InMemoryFile>, bool) = self.remove_key(path.as_ref());
+        let removed: bool = match file {
+            Some(file) => {
+                self.zombie_bytes.on_removed(&file.bytes);
+                true
+            }
+            None => removed_from_cold_tier,
+        };
+        self.debug_check_invariants();
+        removed
+    }
+
+    /// Removes the file from the cache like `remove()`, returning a handle to it.
+    ///
+    /// The handle shares the bytes of the file, so they stay in memory until the handle is dropped,
+    /// and are counted by `zombie_bytes()` until then.
+    ///
+    /// # Return
+    ///
+    /// Returns None if the file wasn't in memory, including files that were only compressed in the cold tier,
+    /// which are removed as well, or if the cache is frozen.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// static INDEX: &'static [u8] = b"<html></html>";
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.seed_embedded(&[("www/index.html", INDEX)]);
+    /// let taken = cache.take("www/index.html").unwrap();
+    /// assert_eq!(taken.bytes(), INDEX);
+    /// assert!(!cache.contains_key("www/index.html"));
+    /// assert!(cache.take("www/index.html").is_none());
+    /// ```
+    pub fn take<P: AsRef<Path>>(&self, path: P) -> Option<EntryHandle> {
+        let _operation: Operation = self.operation();
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.take(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.take(target);
+        }
+        if self.is_frozen() {
+            warn!("Not removing {:?}, because the cache is frozen.", path.as_ref());
+            return None;
+        }
+        let handle: Option<EntryHandle> = self.remove_key(path.as_ref()).0.map(|file| {
+            // The handle shares the bytes before they are checked, so they are counted until it is dropped.
+            let handle: EntryHandle = EntryHandle::new(path.as_ref(), &file);
+            self.zombie_bytes.on_removed(&file.bytes);
+            handle
+        });
+        self.debug_check_invariants();
+        handle
+    }
+
+    /// Removes the file stored under the key, which has already been normalized, and everything remembered about its path.
+    /// Returns the file if it was in memory, and whether it was removed from the cold tier.
+    fn remove_key(&self, path: &Path) -> (Option<InMemoryFile>, bool) {
+        if let Some(ref trace) = self.trace {
+            trace.record_remove(path, (self.clock)());
+        }
+        self.out_of_bounds.remove(path);
+        self.fs_paths.remove(path);
+        self.fallback_resolutions.forget(path);
+        let removed_from_cold_tier: bool = match self.cold_tier {
+            Some(ref cold_tier) => cold_tier.remove(path),
+            None => false,
+        };
+        (self.take_entry(path), removed_from_cold_tier)
+    }
+
+    /// Takes the file out of the file map, giving back the space it held.
+    /// The caller checks whether its bytes are still shared with `zombie_bytes`, once it is done with them.
+    fn take_entry(&self, path: &Path) -> Option<InMemoryFile> {
+        let file: InMemoryFile = self.file_map.remove(&path.to_path_buf())?;
+        self.next_generation();
+        self.release_space(file.stats.size);
+        self.directory_quotas.on_removed(path, file.stats.size);
+        Some(file)
+    }
+
+    /// Marks the file in the cache as stale without freeing its bytes, like the soft purge of a CDN.
+    ///
+    /// The next time the file is requested, its size and modification time are checked against the filesystem.
+    /// If they haven't changed, the file is served from the cache and is no longer stale.
+    /// If they have, the file is refreshed before it is served.
+    /// If the file no longer exists or can't be read, the stale copy is served, unless
+    /// `set_soft_purge_on_missing()` says to remove it instead.
+    ///
+    /// # Return
+    ///
+    /// Returns true if the file was marked. Files that aren't in the cache, embedded files,
+    /// and files whose bytes were pushed with `update_bytes()`, which have nothing to be checked against, are never marked.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.get_source("www/index.html");
+    /// cache.soft_purge("www/index.html");
+    /// ```
+    pub fn soft_purge<P: AsRef<Path>>(&self, path: P) -> bool {
+        let _operation: Operation = self.operation();
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.soft_purge(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.soft_purge(target);
+        }
+        match self.file_map.find_mut(path.as_ref()) {
+            Some(mut file) => {
+                let file: &mut InMemoryFile = file.get();
+                if !file.is_backed_by_fs() {
+                    return false;
+                }
+                file.stale = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks every file in the cache under the directory as stale, like `soft_purge()`.
+    ///
+    /// The prefix is matched by whole path components, so `www/img` matches `www/img/logo.png`, but not `www/images/logo.png`.
+    ///
+    /// # Return
+    ///
+    /// Returns the number of files that were marked.
+    pub fn soft_purge_prefix<P: AsRef<Path>>(&self, prefix: P) -> usize {
+        let _operation: Operation = self.operation();
+        if let Some(prefix) = self.key_case.normalize(prefix.as_ref()) {
+            return self.soft_purge_prefix(prefix);
+        }
+        let paths: Vec<PathBuf> = self.file_map
+            .iter()
+            .filter(|&(path, _)| path.starts_with(prefix.as_ref()))
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths.iter().filter(|path| self.soft_purge(path)).count()
+    }
+
+    /// Checks if the file stored under the key was soft purged, and hasn't been checked against the filesystem since.
+    pub(crate) fn is_soft_purged(&self, path: &Path) -> bool {
+        self.file_map.find(path).is_some_and(|file| file.get().stale)
+    }
+
+    /// Removes the file from the cache, and keeps it out of the cache for the duration.
+    ///
+    /// Until the block ends, the file is served from the filesystem without being considered for insertion,
+    /// so requests for the file that are already in progress can't insert it again right after it was removed.
+    /// Blocking a path that is already blocked replaces its block.
+    /// A duration too long to be represented, like `Duration::from_secs(u64::MAX)`, blocks the path for good.
+    /// The block can be ended early with `unblock()`.
+    ///
+    /// # Return
+    ///
+    /// Returns true if the file was in the cache.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::time::Duration;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.remove_and_block("www/index.html", Duration::from_secs(10));
+    /// cache.get_source("www/index.html");
+    /// assert!(!cache.contains_key("www/index.html"));
+    /// ```
+    pub fn remove_and_block<P: AsRef<Path>>(&self, path: P, duration: Duration) -> bool {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.remove_and_block(key, duration);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.remove_and_block(target, duration);
+        }
+        // The path is blocked before the file is removed, see `remove_if_blocked()`.
+        self.tombstones.block(path.as_ref(), deadline::after((self.clock)(), duration));
+        self.remove(path)
+    }
+
+    /// Ends the block of a file removed with `remove_and_block()`, so it can be inserted into the cache again.
+    ///
+    /// Returns false if the file wasn't blocked.
+    pub fn unblock<P: AsRef<Path>>(&self, path: P) -> bool {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.unblock(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.unblock(target);
+        }
+        self.tombstones.unblock(path.as_ref())
+    }
+
+    /// Removes every file from the cache and its cold tier.
+    /// Embedded files are kept, as they can't be read into the cache again.
+    ///
+    /// The access counts of the files are kept, so the most popular files make it back into the cache
+    /// the next time they are requested.
+    ///
+    /// # Return
+    ///
+    /// The number of files that were removed from the cache, not counting the cold tier.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.clear();
+    /// assert_eq!(cache.used_bytes(), 0);
+    /// ```
+    pub fn clear(&self) -> usize {
+        let _operation: Operation = self.operation();
+        if self.is_frozen() {
+            warn!("Not clearing the cache, because it is frozen.");
+            return 0;
+        }
+        let paths: Vec<PathBuf> = self.file_map
+            .iter()
+            .filter(|&(_, file)| !file.is_embedded())
+            .map(|(path, _)| path.clone())
+            .collect();
+        let removed: usize = paths.iter().filter(|path| self.remove(path)).count();
+        if let Some(ref cold_tier) = self.cold_tier {
+            cold_tier.clear();
+        }
+        self.debug_check_invariants();
+        removed
+    }
+
+    /// Returns a boolean indicating if the cache has an entry corresponding to the given key.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path that is used as a key to look up the file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder};
+    /// use std::path::PathBuf;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let pathbuf: PathBuf = PathBuf::from("www/does_not_exist.html");
+    /// cache.get_source(&pathbuf);
+    /// assert!(cache.contains_key(&pathbuf) == false);
+    /// ```
+    pub fn contains_key<P: AsRef<Path>>(&self, path: P) -> bool {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.contains_key(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.contains_key(target);
+        }
+        self.file_map.find(path.as_ref()).is_some()
+    }
+
+    /// Serves the file stored under the target path when the alias is requested.
+    ///
+    /// Only one copy of the file is held in the cache.
+    /// The alias and the target share the same entry, including its access count and priority,
+    /// so `get()`, `refresh()`, `remove()`, and `contains_key()` called with the alias act on the target.
+    /// Aliases may point to other aliases, but an alias that would create a cycle is rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `alias` - The path that should be resolved to the target.
+    /// * `target` - The path of the file in the filesystem that will be served for the alias.
+    ///
+    /// # Return
+    ///
+    /// Returns false if the alias would create a cycle, in which case the alias is not added.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::path::PathBuf;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// assert!(cache.add_alias(PathBuf::from("www/"), PathBuf::from("www/index.html")));
+    /// assert!(!cache.add_alias(PathBuf::from("www/index.html"), PathBuf::from("www/")));
+    /// ```
+    pub fn add_alias(&self, alias: PathBuf, target: PathBuf) -> bool {
+        let alias: PathBuf = self.key_case.normalize(&alias).unwrap_or(alias);
+        let target: PathBuf = self.key_case.normalize(&target).unwrap_or(target);
+        if target == alias {
+            warn!("Rejected alias {:?}, because it points to itself.", alias);
+            return false;
+        }
+        let resolved_target: PathBuf = self.added_alias_target(&target).unwrap_or(target);
+        if resolved_target == alias {
+            warn!("Rejected alias {:?}, because it would create a cycle.", alias);
+            return false;
+        }
+        self.aliases.insert(alias, resolved_target);
+        true
+    }
+
+    /// Removes the alias, returning true if it existed.
+    /// The file the alias pointed to stays in the cache.
+    pub fn remove_alias<P: AsRef<Path>>(&self, alias: P) -> bool {
+        if let Some(key) = self.key_case.normalize(alias.as_ref()) {
+            return self.remove_alias(key);
+        }
+        self.aliases.remove(alias.as_ref()).is_some()
+    }
+
+    /// Follows the aliases for the path, returning the key of the file that should be served for it,
+    /// or None if the path isn't an alias.
+    ///
+    /// In content addressed mode, the path the aliases lead to is followed to the content it points at.
+    fn alias_target<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        match self.added_alias_target(&path) {
+            Some(target) => Some(self.content_target(&target).unwrap_or(target)),
+            None => self.content_target(path.as_ref()),
+        }
+    }
+
+    /// Follows the aliases added with `add_alias()` for the path, returning the path they lead to,
+    /// or None if the path isn't an alias.
+    fn added_alias_target<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        let mut target: PathBuf = self.aliases.find(path.as_ref())?.get().clone();
+        // Cycles are rejected when aliases are added, but aliases that point to other aliases still need to be followed.
+        loop {
+            let next: PathBuf = match self.aliases.find(&target) {
+                Some(next) => next.get().clone(),
+                None => return Some(target),
+            };
+            if next == path.as_ref() {
+                return Some(target);
+            }
+            target = next;
+        }
+    }
+
+    /// Finds the key of the content the path points at in content addressed mode.
+    /// A path whose content left the cache is forgotten, so the next request for it reads the file again.
+    fn content_target(&self, path: &Path) -> Option<PathBuf> {
+        let content_index: &ContentIndex = self.content_index.as_ref()?;
+        let key: PathBuf = content_index.key_of(path)?;
+        if self.file_map.find(&key).is_some() {
+            return Some(key);
+        }
+        content_index.forget(path);
+        None
+    }
+
+    /// Points the path at the content stored under the key, removing the content it pointed at before if nothing else does.
+    ///
+    /// The content is read from the path that pointed at it last, as paths that pointed at it before
+    /// are the most likely to have been renamed or deleted since.
+    /// The content is kept as modified at the latest time any of its paths was, so it isn't reported as modified since cached.
+    fn point_to_content(&self, path: &Path, key: PathBuf, modified: Option<SystemTime>) {
+        let content_index: &ContentIndex = match self.content_index {
+            Some(ref content_index) => content_index,
+            None => return,
+        };
+        if let Some(mut entry) = self.file_map.find_mut(&key) {
+            let file: &mut InMemoryFile = entry.get();
+            if modified > file.modified {
+                file.modified = modified;
+            }
+        }
+        self.fs_paths.insert(key.clone(), self.fs_path(path));
+        if let Some(departure) = content_index.point(path.to_path_buf(), key) {
+            self.settle_departure(departure);
+        }
+    }
+
+    /// Forgets the content the path points at, removing it if nothing else points at it.
+    fn unpoint_content(&self, path: &Path) {
+        if let Some(departure) = self.content_index.as_ref().and_then(|content_index| content_index.forget(path)) {
+            self.settle_departure(departure);
+        }
+    }
+
+    /// Reads content that a path stopped pointing at from a path that still points at it, or removes it if none does.
+    fn settle_departure(&self, departure: Departure) {
+        match departure.remaining {
+            Some(remaining) => {
+                self.fs_paths.insert(departure.key, self.fs_path(&remaining));
+            }
+            None => {
+                debug!("No path points at {:?} anymore, removing it from the cache.", departure.key);
+                self.fs_paths.remove(&departure.key);
+                if let Some(file) = self.take_entry(&departure.key) {
+                    self.zombie_bytes.on_removed(&file.bytes);
+                }
+            }
+        }
+    }
+
+    /// Points a path that isn't in the cache at content that is, if a sidecar file next to it holds the hash of that content.
+    ///
+    /// The size of the file is checked against the content as well,
+    /// so a sidecar that wasn't updated with its file is less likely to serve the wrong content.
+    fn adopt_sidecar_hash(&self, path: &Path) -> Option<PathBuf> {
+        let extension: &str = self.content_index.as_ref()?.sidecar_extension()?;
+        if content_index::hash_of_key(path).is_some() || self.file_map.find(path).is_some() {
+            return None;
+        }
+        let fs_path: PathBuf = self.fs_path(path);
+        let mut sidecar: OsString = fs_path.clone().into_os_string();
+        sidecar.push(".");
+        sidecar.push(extension);
+        let digest: String = fs::read_to_string(sidecar).ok()?;
+        let key: PathBuf = content_index::content_key(content_hash::from_hex(digest.trim())?, path);
+        let size: usize = self.file_map.find(&key)?.get().stats.size;
+        self.metadata_reads.fetch_add(1, Ordering::Relaxed);
+        let metadata: fs::Metadata = fs::metadata(&fs_path).ok()?;
+        if metadata.len() as usize != size {
+            return None;
+        }
+        debug!("{:?} has the content stored under {:?}, according to its sidecar.", path, key);
+        self.point_to_content(path, key.clone(), metadata.modified().ok());
+        Some(key)
+    }
+
+    /// Gets a short hex digest of the file's bytes, suitable for cache-busting URLs like `/app.js?v={hash}`.
+    ///
+    /// The hash is computed the first time it is requested and is remembered until the file is
+    /// refreshed or leaves the cache.
+    /// The hash is 64 bit FNV-1a, which is fast, but not cryptographically secure.
+    ///
+    /// # Return
+    ///
+    /// Returns None if the file isn't in the cache, unless `set_hash_uncached_files(true)` was called,
+    /// in which case the file is read from the filesystem and hashed without being admitted into the cache.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// static SCRIPT: &'static [u8] = b"a";
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.seed_embedded(&[("www/app.js", SCRIPT)]);
+    /// assert_eq!(cache.content_hash("www/app.js"), Some(String::from("af63dc4c8601ec8c")));
+    /// ```
+    pub fn content_hash<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.content_hash(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.content_hash(target);
+        }
+
+        let (hash, is_memoized, generation): (u64, bool, u64) = match self.file_map.find(path.as_ref()) {
+            Some(file) => {
+                let file: &InMemoryFile = file.get();
+                match file.content_hash {
+                    Some(hash) => (hash, true, file.generation),
+                    None => (content_hash::hash(file.bytes.as_slice()), false, file.generation),
+                }
+            }
+            None => {
+                if !self.hash_uncached_files {
+                    return None;
+                }
+                return fs::read(self.fs_path(&path))
+                    .ok()
+                    .map(|bytes| content_hash::to_hex(content_hash::hash(&bytes)));
+            }
+        };
+
+        if !is_memoized {
+            self.memoize_content_hash(path.as_ref(), generation, hash);
+        }
+        Some(content_hash::to_hex(hash))
+    }
+
+    /// Stores the hash of the bytes of the file, unless the file was replaced since it was hashed.
+    ///
+    /// The file is hashed without locking its entry for writing, so a refresh can replace the file in the meantime,
+    /// and its new bytes must not get the hash of the old ones.
+    fn memoize_content_hash(&self, path: &Path, generation: u64, hash: u64) {
+        if let Some(mut file) = self.file_map.find_mut(path) {
+            let file: &mut InMemoryFile = file.get();
+            if file.generation == generation {
+                file.content_hash = Some(hash);
+            }
+        }
+    }
+
+    /// Gets the generation of the cache, which changes every time a file is inserted, refreshed, replaced,
+    /// evicted or removed, including by `clear()`, which removes the files one at a time.
+    /// Serving a file that is already in the cache doesn't change it.
+    ///
+    /// This is a cheap way for caches layered on top of this one to tell if anything changed since they last looked:
+    /// if the generation is the same, no file was stored or dropped in between.
+    /// Each change increments the generation exactly once, but not atomically with the change itself,
+    /// so another thread may see the new generation slightly before or after it sees the change.
+    /// Reading the generation again after reading the files tells if anything changed in between.
+    /// The counter is 64 bits, so it would take centuries of changes at millions per second to wrap around to 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let generation: u64 = cache.generation();
+    /// cache.get_source("www/index.html");
+    /// if cache.generation() != generation {
+    ///     println!("The cache changed, rebuild anything made from its files.");
+    /// }
+    /// ```
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Gets the generation of the cache at which the file was last stored or replaced, as returned by `generation()`.
+    ///
+    /// Returns None if the file isn't in the cache.
+    pub fn path_generation<P: AsRef<Path>>(&self, path: P) -> Option<u64> {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.path_generation(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.path_generation(target);
+        }
+        self.file_map.find(path.as_ref()).map(|file| file.get().generation)
+    }
+
+    /// Gets a handle to the file in the cache, without counting an access, or touching the filesystem.
+    ///
+    /// The handle shares the bytes of the file, so it can be kept after the file leaves the cache.
+    /// Soft purged files are returned as they are, without being checked against the filesystem.
+    ///
+    /// # Return
+    ///
+    /// Returns None if the file isn't in memory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// static SCRIPT: &'static [u8] = b"a";
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.seed_embedded(&[("www/app.js", SCRIPT)]);
+    /// let handle = cache.try_get_cached("www/app.js").unwrap();
+    /// assert_eq!(handle.size(), 1);
+    /// assert_eq!(handle.content_hash(), "af63dc4c8601ec8c");
+    /// assert!(cache.try_get_cached("www/index.html").is_none());
+    /// ```
+    pub fn try_get_cached<P: AsRef<Path>>(&self, path: P) -> Option<EntryHandle> {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.try_get_cached(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.try_get_cached(target);
+        }
+        self.file_map.find(path.as_ref()).map(|file| EntryHandle::new(path.as_ref(), file.get()))
+    }
+
+    /// Gets a handle to every file in memory, in no particular order.
+    ///
+    /// The handles are collected before they are returned, so no entry of the cache stays locked while they are used,
+    /// and files stored or removed meanwhile aren't reflected in them.
+    pub fn iter(&self) -> impl Iterator<Item = EntryHandle> {
+        let handles: Vec<EntryHandle> = self.file_map
+            .iter()
+            .map(|(path, file)| EntryHandle::new(path, file))
+            .collect();
+        handles.into_iter()
+    }
+
+    /// Gets the time the file in the cache was last modified.
+    /// This is the modification time of the file when it was read from the filesystem,
+    /// or the time its bytes were last replaced with `update_bytes()`.
+    ///
+    /// Returns None if the file isn't in the cache, or if it was embedded in the binary.
+    pub fn last_modified<P: AsRef<Path>>(&self, path: P) -> Option<SystemTime> {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.last_modified(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.last_modified(target);
+        }
+        self.file_map.find(path.as_ref()).and_then(|file| file.get().modified)
+    }
+
+    /// Gets the content hash of every file in the cache.
+    /// See `content_hash()` for how the hashes are computed.
+    pub fn content_hashes(&self) -> HashMap<PathBuf, String> {
+        self.file_map
+            .iter()
+            .map(|(path, file)| {
+                let hash: u64 = file.content_hash.unwrap_or_else(|| content_hash::hash(file.bytes.as_slice()));
+                (path.clone(), content_hash::to_hex(hash))
+            })
+            .collect()
+    }
+
+    /// Sets whether `content_hash()` should hash files that aren't in the cache by reading them
+    /// from the filesystem. Files hashed this way are not admitted into the cache.
+    /// This is disabled by default.
+    pub fn set_hash_uncached_files(&mut self, hash_uncached_files: bool) {
+        self.hash_uncached_files = hash_uncached_files;
+    }
+
+    /// Stores files under the hash of their content instead of their path, for immutable assets
+    /// whose paths change between deploys while their content doesn't.
+    ///
+    /// Every path points at the content it was read with, so paths with the same content share one copy of it,
+    /// as long as they have the same extension, which keeps the content type they are served with.
+    /// A path whose content is already stored isn't read again once its hash is known, from a sidecar file next to it,
+    /// so renaming a file doesn't empty the cache of it. Refreshing a path hashes it again,
+    /// and points it at its new content, which every other path sharing the old content keeps serving.
+    ///
+    /// Like with `add_alias()`, `remove()` called with a path removes the content it points at, for every path.
+    /// Files already in the cache stay under their paths, so this is best set before any files are stored.
+    ///
+    /// Hashes read from sidecar files are trusted without reading the file to check them, only its size is compared,
+    /// so a sidecar that is wrong makes the path serve the content of another file.
+    /// The hash isn't cryptographic, so files whose hash collides with stored content are compared with it,
+    /// and stored under their own path if they differ.
+    ///
+    /// Directory quotas, directory floors and boosts match the keys files are stored under,
+    /// which are content keys starting with `#content-hash/` in this mode, so they no longer match the files stored under
+    /// the directories they were set for. They should not be relied on together with content addressing.
+    /// Passing `None` turns content addressing off, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, ContentAddressedConfig};
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_content_addressed(Some(ContentAddressedConfig {
+    ///     sidecar_extension: Some(String::from("fnv")),
+    /// }));
+    /// ```
+    pub fn set_content_addressed(&mut self, config: Option<ContentAddressedConfig>) {
+        self.content_index = config.map(ContentIndex::new);
+    }
+
+    /// Gets a handle to the content with the hash, as returned by `content_hash()`, if it is stored in a content addressed cache.
+    /// The path of the handle is the key the content is stored under, not one of the paths pointing at it.
+    ///
+    /// Returns None if the content isn't stored, or if the cache isn't content addressed.
+    pub fn lookup_by_hash(&self, hash: &str) -> Option<EntryHandle> {
+        let content_index: &ContentIndex = self.content_index.as_ref()?;
+        let hash: u64 = content_hash::from_hex(hash)?;
+        content_index
+            .keys_with_hash(hash)
+            .into_iter()
+            .filter_map(|key| self.file_map.find(&key).map(|file| EntryHandle::new(&key, file.get())))
+            .next()
+    }
+
+    /// Alters the access count value of one file in the access_count_map.
+    /// # Arguments
+    ///
+    /// * `path` - The key to look up the file.
+    /// * `alter_count_function` - A function that determines how to alter the access_count for the file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{Cache, CacheBuilder};
+    /// use std::path::PathBuf;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let pathbuf = PathBuf::from("www/index.html");
+    /// cache.get_source(&pathbuf); // Add a file to the cache
+    /// cache.remove(&pathbuf); // Removing the file will not reset its access count.
+    /// cache.alter_access_count(&pathbuf, | x | { 0 }); // Set the access count to 0.
+    /// ```
+    ///
+    pub fn alter_access_count<P: AsRef<Path>>(&self, path: P, alter_count_function: fn(&usize) -> usize) -> bool {
+        let _operation: Operation = self.operation();
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.alter_access_count(key, alter_count_function);
+        }
+        if self.is_frozen() {
+            return false;
+        }
+        let new_count: usize;
+        {
+            match self.access_count_map.find(&path.as_ref().to_path_buf()) {
+                Some(access_count_entry) => {
+                    new_count = alter_count_function(&access_count_entry.get());
+                }
+                None => return false, // Can't update a file that isn't in the cache.
+            }
+        }
+        {
+            self.access_count_map.insert(
+                path.as_ref().to_path_buf(),
+                new_count,
+            );
+        }
+        self.update_stats(&path);
+        self.debug_check_invariants();
+        return true;
+    }
+
+    /// Alters the access count value of every file in the access_count_map.
+    /// This is useful for manually aging-out entries in the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `alter_count_function` - A function that determines how to alter the access_count for the file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{Cache, CacheBuilder};
+    /// use std::path::PathBuf;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let pathbuf = PathBuf::from("www/index.html");
+    /// let other_pathbuf = PathBuf::from("www/about.html");
+    /// cache.get_source(&pathbuf);
+    /// cache.get_source(&other_pathbuf);
+    /// // Reduce all access counts by half,
+    /// // allowing newer files to enter the cache more easily.
+    /// cache.alter_all_access_counts(| x | { x / 2 });
+    /// ```
+    ///
+    pub fn alter_all_access_counts(&self, alter_count_function: fn(&usize) -> usize) {
+        let _operation: Operation = self.operation();
+        let all_counts: Vec<PathBuf>;
+        {
+            all_counts = self.access_count_map
+                .iter()
+                .map(|x: (&PathBuf, &usize)| x.0.clone())
+                .collect();
+        }
+        for pathbuf in all_counts {
+            self.alter_access_count(&pathbuf, alter_count_function);
+        }
+
+    }
+
+    /// Gets the stats of the file stored in the cache under the path,
+    /// or None if the file isn't in the cache.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// static FAVICON: &'static [u8] = &[0, 0, 1, 0];
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.seed_embedded(&[("www/favicon.ico", FAVICON)]);
+    /// assert_eq!(cache.stats_for("www/favicon.ico").unwrap().size, FAVICON.len());
+    /// assert!(cache.stats_for("www/index.html").is_none());
+    /// ```
+    pub fn stats_for<P: AsRef<Path>>(&self, path: P) -> Option<FileStats> {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.stats_for(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.stats_for(target);
+        }
+        self.file_map.find(path.as_ref()).map(|file| file.get().stats.clone())
+    }
+
+    /// Forgets every path recorded by the classification filter set with `CacheBuilder::classification_filter()`,
+    /// so they are checked against the size bounds again.
+    ///
+    /// Refreshing or removing a single path can't take it out of the filter, so this should be called
+    /// after files that were outside of the size bounds may have changed, or the bounds were changed.
+    pub fn reset_classification_filter(&self) {
+        if let Some(ref filter) = self.classification_filter {
+            filter.reset();
+        }
+    }
+
+    /// Gets the paths the cache is keeping access counts for.
+    /// This includes files that were requested, but didn't make it into the cache.
+    pub fn tracked_paths(&self) -> Vec<PathBuf> {
+        self.access_count_map
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Gets the sum of the sizes of the files that are stored in the cache.
+    /// This includes space reserved for files that are being read into the cache by other threads,
+    /// and the space in the slabs of the small file arena that isn't used by any file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{Cache, CacheBuilder};
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// assert!(cache.used_bytes() == 0);
+    /// ```
+    pub fn used_bytes(&self) -> usize {
+        self.resident_bytes.load(Ordering::Acquire).saturating_add(self.unused_slab_bytes())
+    }
+
+    /// Gets the number of bytes of files that were evicted or removed from the cache,
+    /// but are still kept alive by responses or detached files that share them.
+    ///
+    /// These bytes aren't counted in the `used_bytes()`, so under heavy eviction, the cache can use more memory than that.
+    /// The bytes are no longer counted once the last response sharing them is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// println!("{} bytes are held beyond the used bytes.", cache.zombie_bytes());
+    /// ```
+    pub fn zombie_bytes(&self) -> usize {
+        self.zombie_bytes.bytes()
+    }
+
+    /// Gets the most bytes the cache holds, as it was set with `CacheBuilder::size_limit()`.
+    pub fn size_limit_bytes(&self) -> usize {
+        self.size_limit
+    }
+
+    /// Gets the size of the smallest files the cache stores.
+    pub fn min_file_size_bytes(&self) -> usize {
+        self.min_file_size
+    }
+
+    /// Gets the size of the largest files the cache stores.
+    pub fn max_file_size_bytes(&self) -> usize {
+        self.max_file_size
+    }
+
+    /// The space in the slabs of the small file arena that isn't used by any file.
+    fn unused_slab_bytes(&self) -> usize {
+        self.small_file_arena.as_ref().map_or(0, |arena| arena.unused_bytes())
+    }
+
+    /// Gets how the files with each extension use the cache, sorted by extension.
+    ///
+    /// Extensions are counted in lowercase, and files without an extension are counted under an empty string.
+    /// Only requests for files that were found are counted. Once 64 extensions are counted,
+    /// the files with any other extension are counted together under `*`.
+    /// The resident bytes and files are what the cache holds now,
+    /// the other counts are of everything that happened since the cache was created.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.get_source("www/app.js");
+    /// for (extension, stats) in cache.extension_stats() {
+    ///     println!("{}: {} bytes, {:.0}% hits", extension, stats.resident_bytes, stats.hit_rate() * 100.0);
+    /// }
+    /// ```
+    pub fn extension_stats(&self) -> Vec<(String, ExtensionStats)> {
+        let mut extensions: HashMap<String, ExtensionStats> = self.extension_counters.counts();
+        for (path, file) in self.file_map.iter() {
+            // Every stored file was counted, so an extension that wasn't is in the bucket of the other extensions.
+            let extension: String = extension_of(path);
+            let extension: String = if extensions.contains_key(&extension) {
+                extension
+            } else {
+                String::from(OTHER_EXTENSIONS)
+            };
+            let stats: &mut ExtensionStats = extensions.entry(extension).or_default();
+            stats.resident_bytes = stats.resident_bytes.saturating_add(file.stats.size);
+            stats.file_count += 1;
+        }
+        let mut extensions: Vec<(String, ExtensionStats)> = extensions.into_iter().collect();
+        extensions.sort_by(|a, b| a.0.cmp(&b.0));
+        extensions
+    }
+
+    /// Gets a summary of the state of the cache.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{Cache, CacheBuilder};
+    ///
+    /// let cache = CacheBuilder::new()
+    ///     .size_limit(1024)
+    ///     .build()
+    ///     .unwrap();
+    /// let summary = cache.summary();
+    /// assert_eq!(summary.size_limit, 1024);
+    /// assert_eq!(summary.used_bytes, 0);
+    /// ```
+    pub fn summary(&self) -> CacheSummary {
+        CacheSummary {
+            size_limit: self.size_limit,
+            used_bytes: self.used_bytes(),
+            file_count: self.file_map.iter().count(),
+            tracked_paths: self.access_count_map.iter().count(),
+            cold_tier_used_bytes: self.cold_tier.as_ref().map(|cold_tier| cold_tier.used_bytes()).unwrap_or(0),
+            cold_tier_file_count: self.cold_tier.as_ref().map(|cold_tier| cold_tier.len()).unwrap_or(0),
+            directory_floors: self.directory_floors.usage(&self.directory_floor_bytes()),
+            evictions_of_previously_resident: self.churn.evictions_of_previously_resident(),
+            bytes_served_from_cache: self.served_bytes.served_from_cache(),
+            bytes_served_from_fs: self.served_bytes.served_from_fs(),
+            bookkeeping_bytes: self.bookkeeping_bytes(),
+            size_mismatches: self.size_mismatches.load(Ordering::Relaxed),
+            checksum_mismatches: self.checksum_mismatches.load(Ordering::Relaxed),
+            degraded: self.is_degraded(),
+            small_file_slab_bytes: self.small_file_arena.as_ref().map_or(0, |arena| arena.slab_bytes()),
+            small_file_live_bytes: self.small_file_arena.as_ref().map_or(0, |arena| arena.live_bytes()),
+            extensions: self.extension_stats(),
+            ghost_hits: self.ghost_entries.as_ref().map_or(0, |ghost_entries| ghost_entries.hits()),
+        }
+    }
+
+    /// Takes a `CacheSnapshot` of the summary of the cache, and the keys and stats of its files,
+    /// which can be read after the cache changes, or after the lock around the cache is released.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{Cache, CacheBuilder, CacheSnapshot};
+    /// use std::sync::Mutex;
+    ///
+    /// let cache: Mutex<Cache> = Mutex::new(CacheBuilder::new().build().unwrap());
+    /// // The lock is only held while the snapshot is taken.
+    /// let snapshot: CacheSnapshot = cache.lock().unwrap().snapshot();
+    /// for &(ref path, ref stats) in snapshot.files() {
+    ///     println!("{:?}: {} bytes, {} accesses", path, stats.size, stats.access_count);
+    /// }
+    /// println!("{} of {} bytes used", snapshot.used_bytes(), snapshot.summary().size_limit);
+    /// ```
+    pub fn snapshot(&self) -> CacheSnapshot {
+        let files: Vec<(PathBuf, FileStats)> = self.file_map
+            .iter()
+            .map(|(path, file)| (path.clone(), file.stats.clone()))
+            .collect();
+        CacheSnapshot::new(self.summary(), files)
+    }
+
+    /// Estimates the bytes of memory the cache holds to keep track of paths, on top of the bytes of the files it caches.
+    ///
+    /// This counts the entries of the maps of files, access counts, aliases, keys, fallbacks, out of bounds files,
+    /// blocked and bypassed paths, churn, thrash protection, ghost entries and served bytes, the index of the cold tier,
+    /// and the decision log, from the number of entries, the lengths of their paths, and the sizes of their types.
+    /// It is a rough estimate, not a measurement: allocator overhead and the spare capacity of the maps aren't known.
+    ///
+    /// Every file that is ever requested is remembered with its access count,
+    /// so this grows with the number of distinct files requested, even when few of them are cached.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_decision_log(Some(100));
+    /// let before: usize = cache.bookkeeping_bytes();
+    /// cache.get_source("www/index.html");
+    /// assert!(cache.bookkeeping_bytes() > before);
+    /// ```
+    pub fn bookkeeping_bytes(&self) -> usize {
+        let files: usize = self.file_map
+            .iter()
+            .map(|(path, file)| {
+                // The key shared with served files is a separate allocation, with the two counts of its Arc.
+                let key: usize = file.key.as_ref().map_or(0, |key| key.as_os_str().len() + 2 * mem::size_of::<usize>());
+                path_entry_bytes::<InMemoryFile>(path) + key
+            })
+            .sum();
+        let access_counts: usize = self.access_count_map.iter().map(|(path, _)| path_entry_bytes::<usize>(path)).sum();
+        let aliases: usize = self.aliases
+            .iter()
+            .map(|(alias, target)| path_entry_bytes::<PathBuf>(alias) + target.as_os_str().len())
+            .sum();
+        let fs_paths: usize = self.fs_paths
+            .iter()
+            .map(|(key, fs_path)| path_entry_bytes::<PathBuf>(key) + fs_path.as_os_str().len())
+            .sum();
+        let out_of_bounds: usize = self.out_of_bounds.iter().map(|(path, _)| path_entry_bytes::<ServeClass>(path)).sum();
+        let bypassed: usize = self.bypassed.iter().map(|path| path_entry_bytes::<()>(path)).sum();
+
+        files
+            + access_counts
+            + aliases
+            + fs_paths
+            + out_of_bounds
+            + bypassed
+            + self.classification_filter.as_ref().map_or(0, |filter| filter.bookkeeping_bytes())
+            + self.fallback_resolutions.bookkeeping_bytes()
+            + self.tombstones.bookkeeping_bytes()
+            + self.churn.bookkeeping_bytes()
+            + self.served_bytes.bookkeeping_bytes()
+            + self.thrash_protection.as_ref().map_or(0, |thrash_protection| thrash_protection.bookkeeping_bytes())
+            + self.ghost_entries.as_ref().map_or(0, |ghost_entries| ghost_entries.bookkeeping_bytes())
+            + self.sibling_prefetch.as_ref().map_or(0, |sibling_prefetch| sibling_prefetch.bookkeeping_bytes())
+            + self.zombie_bytes.bookkeeping_bytes()
+            + self.cold_tier.as_ref().map_or(0, |cold_tier| cold_tier.bookkeeping_bytes())
+            + self.decision_log.as_ref().map_or(0, |decision_log| decision_log.bookkeeping_bytes())
+            + self.content_index.as_ref().map_or(0, |content_index| content_index.bookkeeping_bytes())
+    }
+
+    /// Gets what the cache knows about the path, including how many bytes were served for it,
+    /// if the bytes served for each path are counted, see `set_served_bytes_by_path()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.get_source("www/index.html");
+    /// let metrics = cache.path_metrics("www/index.html");
+    /// println!("Served {} bytes from memory", metrics.bytes_served_from_cache);
+    /// ```
+    pub fn path_metrics<P: AsRef<Path>>(&self, path: P) -> PathMetrics {
+        if let Some(key) = self.key_case.normalize(path.as_ref()) {
+            return self.path_metrics(key);
+        }
+        if let Some(target) = self.alias_target(&path) {
+            return self.path_metrics(target);
+        }
+        let access_count: usize = match self.access_count_map.find(path.as_ref()) {
+            Some(access_count) => *access_count.get(),
+            None => 0,
+        };
+        let (bytes_served_from_cache, bytes_served_from_fs) = self.served_bytes.path(path.as_ref());
+        PathMetrics {
+            access_count,
+            bytes_served_from_cache,
+            bytes_served_from_fs,
+        }
+    }
+
+    /// Resets the served bytes counters of the `summary()` and of `path_metrics()`.
+    pub fn reset_served_bytes(&self) {
+        self.served_bytes.reset();
+    }
+
+    /// Counts the bytes served for each path, as returned by `path_metrics()`, for up to `max_paths` paths.
+    ///
+    /// Paths are counted in the order they are first served. Once `max_paths` paths are counted,
+    /// the bytes served for other paths are only counted in the totals of the `summary()`.
+    /// Changing this forgets the bytes counted for every path. Passing `None` stops counting them, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// cache.set_served_bytes_by_path(Some(10_000));
+    /// ```
+    pub fn set_served_bytes_by_path(&mut self, max_paths: Option<usize>) {
+        self.served_bytes.set_max_paths(max_paths);
+    }
+
+    /// Lists the files that were evicted and inserted into the cache again at least `min_cycles` times
+    /// since the churn counters were last reset, along with how many times they did.
+    /// Files with the most cycles are listed first.
+    ///
+    /// Files that keep cycling in and out of the cache indicate that the files being requested don't fit
+    /// into it together, so the size limit may be too small.
+    /// The number of evictions of these files is in the `evictions_of_previously_resident` field of the `summary()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// for (path, cycles) in cache.churn_report(10) {
+    ///     println!("{:?} was evicted and inserted again {} times", path, cycles);
+    /// }
+    /// ```
+    pub fn churn_report(&self, min_cycles: usize) -> Vec<(PathBuf, usize)> {
+        self.churn.report(min_cycles)
+    }
+
+    /// Resets the counters behind `churn_report()` and the `evictions_of_previously_resident` summary field.
+    pub fn reset_churn(&self) {
+        self.churn.reset();
+    }
+
+    /// Refreshes the files listed in a manifest, like the list of changed files a deploy produces.
+    ///
+    /// The manifest lists one path per line, relative to the `root` if one is given.
+    /// Blank lines and lines starting with `#` are skipped, and lines that can't be used are reported with their line number.
+    /// Each file that is in the cache is refreshed, or removed if it no longer exists in the filesystem.
+    /// Files that aren't in the cache are inserted if `preload` is set, and are otherwise left alone.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    /// use std::io::Cursor;
+    /// use std::path::Path;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let manifest = Cursor::new("# Changed by the deploy\nindex.html\ncss/style.css\n");
+    /// let report = cache.refresh_from_manifest(manifest, Some(Path::new("www")), false);
+    /// assert_eq!(report.outcomes.len(), 2);
+    /// ```
+    pub fn refresh_from_manifest<R: BufRead>(&self, reader: R, root: Option<&Path>, preload: bool) -> ManifestReport {
+        self.refresh_from_manifest_with(reader, root, preload, &PreloadOptions::new())
+    }
+
+    /// Refreshes the files listed in a manifest like `refresh_from_manifest()`,
+    /// reporting its progress and stopping early if it is cancelled, as the options say.
+    ///
+    /// Files that were refreshed or preloaded count as inserted, the others as skipped.
+    pub fn refresh_from_manifest_with<R: BufRead>(&self, reader: R, root: Option<&Path>, preload: bool, options: &PreloadOptions) -> ManifestReport {
+        let (paths, errors) = manifest::read_manifest(reader, root);
+        for &(line, ref error) in &errors {
+            warn!("Skipped line {} of the manifest: {:?}", line, error);
+        }
+        let mut tracker: PreloadTracker = PreloadTracker::new(options);
+        let mut outcomes: Vec<(PathBuf, ManifestOutcome)> = Vec::with_capacity(paths.len());
+        let mut inserted: usize = 0;
+        let mut cancelled: bool = false;
+        for path in paths {
+            if !tracker.next(self.preload_progress(outcomes.len(), inserted, outcomes.len() - inserted)) {
+                debug!("Refreshing from the manifest was cancelled after {} paths.", outcomes.len());
+                cancelled = true;
+                break;
+            }
+            let outcome: ManifestOutcome = self.apply_manifest_entry(&path, preload);
+            if outcome == ManifestOutcome::Refreshed || outcome == ManifestOutcome::Preloaded {
+                inserted += 1;
+            }
+            outcomes.push((path, outcome));
+        }
+        tracker.finish(self.preload_progress(outcomes.len(), inserted, outcomes.len() - inserted));
+        ManifestReport { outcomes, errors, cancelled }
+    }
+
+    /// The progress of a preload that looked at, inserted and skipped the numbers of files.
+    fn preload_progress(&self, scanned: usize, inserted: usize, skipped: usize) -> PreloadProgress {
+        PreloadProgress {
+            scanned,
+            inserted,
+            skipped,
+            bytes_used: self.used_bytes(),
+        }
+    }
+
+    /// Refreshes, removes or preloads a file listed in a manifest.
+    fn apply_manifest_entry(&self, path: &Path, preload: bool) -> ManifestOutcome {
+        if self.is_frozen() {
+            return ManifestOutcome::Failed(CacheError::CacheFrozen);
+        }
+        if !self.contains_key(path) {
+            if !preload {
+                return ManifestOutcome::NotCached;
+            }
+            return match self.insert(path) {
+                Ok(_) => ManifestOutcome::Preloaded,
+                Err(error) => ManifestOutcome::Failed(error),
+            };
+        }
+        match self.refresh_source(path) {
+            FileSource::Cached(_) => ManifestOutcome::Refreshed,
+            FileSource::FileMissing => {
+                self.remove(path);
+                ManifestOutcome::Removed
+            }
+            _ => ManifestOutcome::Failed(CacheError::CouldNotReadFile),
+        }
+    }
+
+    /// Reads files that have been requested before, but aren't in the cache, back into the cache.
+    ///
+    /// Files with recorded accesses are ordered by the priority they would have, based on their
+    /// current access counts and sizes in the filesystem, and are inserted from the highest priority down
+    /// for as long as they fit into both the byte budget and the free space of the cache.
+    /// Prefetching never evicts files, and doesn't count as an access of the files it inserts.
+    ///
+    /// This is meant for maintenance tasks that warm the cache up again, for instance after it was
+    /// restarted with access counts that were saved before.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// // Spend at most 10 MB on warming up the cache.
+    /// let report = cache.prefetch_top(1024 * 1024 * 10);
+    /// println!("Prefetched {} files", report.inserted.len());
+    /// ```
+    pub fn prefetch_top(&self, byte_budget: usize) -> PrefetchReport {
+        self.prefetch_top_with(byte_budget, &PreloadOptions::new())
+    }
+
+    /// Prefetches files like `prefetch_top()`, reporting its progress and stopping early if it is cancelled,
+    /// as the options say.
+    ///
+    /// Every file with recorded accesses is scanned first, and the ones that may fit are then inserted,
+    /// so the number of scanned files stops growing once the first file is inserted.
+    /// A cancelled prefetch returns the files it inserted or skipped so far, with `cancelled` set.
+    pub fn prefetch_top_with(&self, byte_budget: usize, options: &PreloadOptions) -> PrefetchReport {
+        let _operation: Operation = self.operation();
+        let mut report: PrefetchReport = PrefetchReport {
+            inserted: vec![],
+            skipped: vec![],
+            inserted_bytes: 0,
+            cancelled: false,
+        };
+        let mut tracker: PreloadTracker = PreloadTracker::new(options);
+        let mut scanned: usize = 0;
+
+        let access_counts: Vec<(PathBuf, usize)> = self.access_count_map
+            .iter()
+            .map(|(path, access_count)| (path.clone(), *access_count))
+            .collect();
+        let mut candidates: Vec<(PathBuf, usize, usize)> = vec![];
+        for (path, access_count) in access_counts {
+            if !tracker.next(self.preload_progress(scanned, report.inserted.len(), report.skipped.len())) {
+                report.cancelled = true;
+                break;
+            }
+            scanned += 1;
+            // Content keys are only stored through the paths that point at them.
+            if self.file_map.find(&path).is_some()
+                || self.content_target(&path).is_some()
+                || content_index::hash_of_key(&path).is_some()
+            {
+                continue;
+            }
+            match self.get_file_size_from_metadata(&path) {
+                Ok(size) if size > self.max_file_size || size < self.min_file_size => {
+                    report.skipped.push((path, PrefetchSkipReason::Rejected(CacheError::FileSizeOutOfBounds)));
+                }
+                Ok(size) => {
+                    let priority: usize = self.priority(access_count, size);
+                    candidates.push((path, size, priority));
+                }
+                Err(error) => report.skipped.push((path, PrefetchSkipReason::Rejected(error))),
+            }
+        }
+        // Highest priority first, with the path breaking ties so prefetching is repeatable.
+        candidates.sort_by(|l, r| r.2.cmp(&l.2).then_with(|| l.0.cmp(&r.0)));
+        if report.cancelled {
+            candidates.clear();
+        }
+
+        for (path, size, _) in candidates {
+            if !tracker.next(self.preload_progress(scanned, report.inserted.len(), report.skipped.len())) {
+                debug!("Prefetching was cancelled after inserting {} files.", report.inserted.len());
+                report.cancelled = true;
+                break;
+            }
+            match self.prefetch_file(&path, size, byte_budget.saturating_sub(report.inserted_bytes)) {
+                Ok(stored_bytes) => {
+                    debug!("Prefetched {:?} into the cache.", path);
+                    report.inserted_bytes = usize::saturating_add(report.inserted_bytes, stored_bytes);
+                    report.inserted.push(path);
+                }
+                Err(reason) => report.skipped.push((path, reason)),
+            }
+        }
+        tracker.finish(self.preload_progress(scanned, report.inserted.len(), report.skipped.len()));
+        self.debug_check_invariants();
+        report
+    }
+
+    /// Writes the bytes and stats of every file in the cache to a spill file,
+    /// so a server restarting on the same machine can get them back with `restore_spill()`
+    /// instead of reading every file again.
+    ///
+    /// The spill file holds the raw bytes of the files, so it is as large as the cache, and is only meant
+    /// to be read back by this version of the crate. Embedded files aren't spilled, as they are seeded again at startup.
+    /// In content addressed mode, the content is spilled under every path pointing at it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate tempdir;
+    /// # extern crate rocket_file_cache;
+    /// # fn main() {
+    /// use rocket_file_cache::{CacheBuilder, RestoreOptions};
+    ///
+    /// # let temp_dir = tempdir::TempDir::new("spill").unwrap();
+    /// # let spill_path = temp_dir.path().join("cache.spill");
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// cache.spill(&spill_path).unwrap();
+    ///
+    /// // After a restart.
+    /// let mut cache = CacheBuilder::new().build().unwrap();
+    /// let summary = cache.restore_spill(&spill_path, RestoreOptions { verify_mtime: true }).unwrap();
+    /// # assert_eq!(summary.restored, 0);
+    /// # }
+    /// ```
+    pub fn spill<P: AsRef<Path>>(&self, path: P) -> io::Result<SpillSummary> {
+        // The bytes are shared rather than copied, so the shards aren't locked while the spill file is written.
+        let files: Vec<(PathBuf, usize, Option<SystemTime>, FileBytes)> = self.file_map
+            .iter()
+            .filter(|&(_, file)| !file.is_embedded())
+            .flat_map(|(key, file)| {
+                let paths: Vec<PathBuf> = match self.content_index {
+                    Some(ref content_index) if content_index::hash_of_key(key).is_some() => content_index.paths_of(key),
+                    _ => vec![key.clone()],
+                };
+                paths.into_iter().map(move |path| (path, file.stats.access_count, file.modified, file.bytes.clone()))
+            })
+            .collect();
+
+        let mut summary: SpillSummary = SpillSummary::default();
+        let mut writer = BufWriter::new(fs::File::create(path.as_ref())?);
+        spill::write_header(&mut writer)?;
+        for (path, access_count, modified, bytes) in files {
+            if spill::write_record(&mut writer, &path, access_count, modified, bytes.as_slice())? {
+                summary.entries += 1;
+                summary.bytes += bytes.as_slice().len();
+            } else {
+                warn!("{:?} can't be written to a spill file on this platform.", path);
+                summary.skipped += 1;
+            }
+        }
+        writer.flush()?;
+        debug!("Spilled {} files holding {} bytes to {:?}.", summary.entries, summary.bytes, path.as_ref());
+        Ok(summary)
+    }
+
+    /// Stores the files of a spill file written by `spill()` in the cache, with the access counts they had.
+    ///
+    /// Files are only stored while they fit into the free space of the cache, as restoring never evicts anything.
+    /// Files that are already in the cache, or are outside of its size bounds, are skipped.
+    /// With `verify_mtime`, each file is checked against the filesystem first,
+    /// and files that changed or disappeared since they were spilled are skipped as stale.
+    ///
+    /// A spill file that isn't one, has another version, or is corrupt is an `InvalidData` error.
+    /// The files read before a corrupt record stay in the cache.
+    pub fn restore_spill<P: AsRef<Path>>(&mut self, path: P, options: RestoreOptions) -> io::Result<RestoreSummary> {
+        let mut reader = BufReader::new(fs::File::open(path.as_ref())?);
+        spill::read_header(&mut reader)?;
+
+        let mut summary: RestoreSummary = RestoreSummary::default();
+        while let Some(record) = spill::read_record(&mut reader)? {
+            let SpillRecord { path, access_count, modified, bytes } = record;
+            if self.validate_key(&path).is_err() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the spill file has an invalid path: {:?}", path)));
+            }
+            let mut file: InMemoryFile = InMemoryFile::from_bytes(bytes);
+            file.modified = modified;
+            if options.verify_mtime {
+                match fs::metadata(self.fs_path(&path)) {
+                    Ok(ref metadata) if metadata.is_file()
+                        && metadata.len() == file.stats.size as u64
+                        && metadata.modified().ok() == modified
+                        && modified.is_some() => {
+                        file.identity = FileIdentity::of(metadata);
+                    }
+                    _ => {
+                        debug!("Not restoring {:?}, as it changed since it was spilled.", path);
+                        summary.stale += 1;
+                        continue;
+                    }
+                }
+            }
+
+            let size: usize = file.stats.size;
+            if self.contains_key(&path)
+                || size > self.max_file_size
+                || size < self.min_file_size
+                || size >= self.size_limit
+                || self.directory_quotas.overflow(&path, size).is_some()
+                || !self.reserve_space(size)
+            {
+                summary.skipped += 1;
+                continue;
+            }
+            if self.access_count_map.find(&path).is_none() {
+                self.access_count_map.insert(path.clone(), access_count);
+            }
+            let key: PathBuf = self.store_admitted(&path, file).0;
+            self.update_stats(&key);
+            summary.restored += 1;
+            summary.restored_bytes += size;
+        }
+        debug!("Restored {} files holding {} bytes from {:?}.", summary.restored, summary.restored_bytes, path.as_ref());
+        self.debug_check_invariants();
+        Ok(summary)
+    }
+
+    /// Checks every file in the cache against its file in the filesystem, and the cache's bookkeeping
+    /// against the files it holds, without changing anything.
+    ///
+    /// This stats every cached file, so it is meant to be run occasionally, like after startup.
+    /// Insertions and evictions that happen while the cache is being verified can show up as a drift
+    /// in the used bytes, so the cache should be quiet while it is verified.
+    /// The problems that were found can be fixed with `repair()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let report = cache.verify();
+    /// if !report.is_consistent() {
+    ///     cache.repair(&report);
+    /// }
+    /// ```
+    pub fn verify(&self) -> VerifyReport {
+        let entries: Vec<(PathBuf, usize, Option<SystemTime>, bool, bool)> = self.file_map
+            .iter()
+            .map(|(path, file)| (path.clone(), file.stats.size, file.modified, file.is_embedded(), file.pushed))
+            .collect();
+
+        let mut inconsistencies: Vec<Inconsistency> = vec![];
+        let mut stored_bytes: usize = 0;
+        for (path, cached_size, cached_modified, is_embedded, pushed) in entries {
+            stored_bytes = usize::saturating_add(stored_bytes, cached_size);
+            // Embedded files aren't backed by the filesystem, and aren't counted.
+            if is_embedded {
+                continue;
+            }
+            if self.access_count_map.find(&path).is_none() {
+                inconsistencies.push(Inconsistency::MissingAccessCount { path: path.clone() });
+            }
+            // Pushed bytes aren't expected to match the file in the filesystem.
+            if pushed {
+                continue;
+            }
+            match fs::metadata(self.fs_path(&path)) {
+                Ok(metadata) => {
+                    let file_size: usize = metadata.len() as usize;
+                    let modified_since_cached: bool = match (metadata.modified().ok(), cached_modified) {
+                        (Some(modified), Some(cached_modified)) => modified > cached_modified,
+                        _ => false,
+                    };
+                    if file_size != cached_size {
+                        inconsistencies.push(Inconsistency::SizeDiffers { path, cached_size, file_size });
+                    } else if modified_since_cached {
+                        inconsistencies.push(Inconsistency::ModifiedSinceCached { path });
+                    }
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                    inconsistencies.push(Inconsistency::FileMissing { path });
+                }
+                Err(error) => warn!("Could not verify {:?}: {}", path, error),
+            }
+        }
+
+        // The unused space in the slabs isn't held by any file.
+        let counted: usize = self.resident_bytes.load(Ordering::Acquire);
+        if counted != stored_bytes {
+            inconsistencies.push(Inconsistency::UsedBytesDrift { counted, actual: stored_bytes });
+        }
+        VerifyReport { inconsistencies }
+    }
+
+    /// Checks that the bookkeeping of the cache agrees with the files it holds, without looking at the filesystem.
+    ///
+    /// * The size in the stats of every file is the number of bytes it holds.
+    /// * `used_bytes()` is the sum of the sizes of the files.
+    /// * Every file read from the filesystem has an access count.
+    /// * If priorities are recalculated on every access, the priority of every file read from the filesystem
+    ///   is what the priority function gives for its access count and size.
+    ///   A file refreshed without recalculating its priority is reported until it is accessed again.
+    ///
+    /// Like `verify()`, this should only be called while the cache is quiet.
+    /// Debug builds check the invariants after every change, see `set_strict_invariants()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// assert_eq!(cache.check_invariants(), Ok(()));
+    /// ```
+    pub fn check_invariants(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut violations: Vec<InvariantViolation> = vec![];
+        let mut stored_bytes: usize = 0;
+        for (path, file) in self.file_map.iter() {
+            let bytes: usize = file.bytes.as_slice().len();
+            stored_bytes = usize::saturating_add(stored_bytes, file.stats.size);
+            if file.stats.size != bytes {
+                violations.push(InvariantViolation::SizeMismatch {
+                    path: path.clone(),
+                    stats_size: file.stats.size,
+                    bytes,
+                });
+            }
+            // Embedded files aren't counted, and keep the stats they were seeded with.
+            if file.is_embedded() {
+                continue;
+            }
+            if self.access_count_map.find(path).is_none() {
+                violations.push(InvariantViolation::MissingAccessCount { path: path.clone() });
+            }
+            if self.priority_recompute == Recompute::EveryAccess {
+                let expected: usize = self.priority(file.stats.access_count, file.stats.size);
+                if file.stats.priority != expected {
+                    violations.push(InvariantViolation::StalePriority {
+                        path: path.clone(),
+                        priority: file.stats.priority,
+                        expected,
+                    });
+                }
+            }
+        }
+
+        // The unused space in the slabs isn't held by any file.
+        let counted: usize = self.resident_bytes.load(Ordering::Acquire);
+        if counted != stored_bytes {
+            violations.push(InvariantViolation::UsedBytesDrift { counted, actual: stored_bytes });
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Panics if the invariants of the cache are broken, when it is in strict mode in a debug build.
+    ///
+    /// The check is skipped while another thread is in the middle of an operation started with `operation()`.
+    fn debug_check_invariants(&self) {
+        if !cfg!(debug_assertions) || !self.strict_invariants {
+            return;
+        }
+        let result: Option<Result<(), Vec<InvariantViolation>>> = self.operations.check_alone(|| {
+            self.check_invariants().or_else(|violations| {
+                // A file refreshed without recalculating its priority keeps its old priority on purpose.
+                let violations: Vec<InvariantViolation> = violations
+                    .into_iter()
+                    .filter(|violation| !matches!(*violation, InvariantViolation::StalePriority { .. }))
+                    .collect();
+                if violations.is_empty() {
+                    Ok(())
+                } else {
+                    Err(violations)
+                }
+            })
+        });
+        if let Some(result) = result {
+            debug_assert!(result.is_ok(), "The invariants of the cache are broken: {:?}", result);
+        }
+    }
+
+    /// Starts an operation that changes the cache, which ends when the returned guard is dropped.
+    /// While it is in progress, other threads don't check the invariants of the cache.
+    fn operation(&self) -> Operation {
+        self.operations.begin()
+    }
+
+    /// Fixes the problems found by `verify()`.
+    ///
+    /// Files that changed in the filesystem are refreshed, and files that no longer exist are removed.
+    /// Files without an access count have it restored from their stats,
+    /// and a drift in the used bytes is fixed by counting the sizes of the files in the cache again.
+    /// A frozen cache is left as it is.
+    pub fn repair(&self, report: &VerifyReport) {
+        let _operation: Operation = self.operation();
+        if self.is_frozen() {
+            warn!("Not repairing the cache, because it is frozen.");
+            return;
+        }
+        for inconsistency in report.inconsistencies.iter() {
+            match *inconsistency {
+                Inconsistency::SizeDiffers { ref path, .. } | Inconsistency::ModifiedSinceCached { ref path } => {
+                    debug!("Repairing {:?} by refreshing it.", path);
+                    self.refresh_source(path);
+                }
+                Inconsistency::FileMissing { ref path } => {
+                    debug!("Repairing {:?} by removing it.", path);
+                    self.remove(path);
+                }
+                Inconsistency::MissingAccessCount { ref path } => {
+                    let access_count: Option<usize> = self.file_map.find(path).map(|file| file.get().stats.access_count);
+                    if let Some(access_count) = access_count {
+                        debug!("Restoring the access count of {:?}.", path);
+                        self.access_count_map.upsert(path.clone(), access_count, &|_| {});
+                    }
+                }
+                Inconsistency::UsedBytesDrift { .. } => {}
+            }
+        }
+
+        // Files may have been refreshed or removed above, so the used bytes are counted last.
+        if report.inconsistencies.iter().any(|inconsistency| matches!(*inconsistency, Inconsistency::UsedBytesDrift { .. })) {
+            let stored_bytes: usize = self.file_map
+                .iter()
+                .fold(0, |stored_bytes, (_, file)| usize::saturating_add(stored_bytes, file.stats.size));
+            debug!("Recounting the used bytes of the cache as {}.", stored_bytes);
+            self.resident_bytes.store(stored_bytes, Ordering::Release);
+        }
+        self.debug_check_invariants();
+    }
+
+    /// Gets the size of the file from the file's metadata.
+    /// This avoids having to read the file into memory in order to get the file size.
+    fn get_file_size_from_metadata<P: AsRef<Path>>(&self, path: P) -> Result<usize, CacheError> {
+        self.metadata_reads.fetch_add(1, Ordering::Relaxed);
+        let fs_path: PathBuf = self.fs_path(&path);
+        let path_string: String = match fs_path.to_str() {
+            Some(s) => String::from(s),
+            None => return Err(CacheError::InvalidPath),
+        };
+        let metadata: Metadata = match fs::metadata(path_string.as_str()) {
+            Ok(m) => m,
+            Err(_) => return Err(CacheError::InvalidMetadata),
+        };
+        if metadata.is_dir() {
+            return Err(CacheError::IsDirectory);
+        }
+        let size: usize = metadata.len() as usize;
+        Ok(size)
+    }
+
+
+    /// Attempt to store the file in the cache, making sure that concurrent misses for the same path
+    /// only read the file from the filesystem once.
+    ///
+    /// The first thread to miss on a path reads the file and tries to insert it.
+    /// Any other thread that misses on the same path while that read is in progress waits for it to finish,
+    /// and then serves the freshly inserted entry.
+    /// If the file didn't make it into the cache, the waiting thread tries to insert it itself,
+    /// so it is served with the reason it was rejected for.
+    /// If the wait exceeds the `coalesce_timeout`, the waiting thread serves the file from the filesystem instead.
+    fn try_insert_coalesced<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> (FileSource, ServeClass) {
+        match self.in_flight_reads.join(&path) {
+            Flight::Leader(guard) => {
+                // Another leader may have finished inserting the file between the miss and registering this read.
+                if self.contains_key(&path) {
+                    drop(guard);
+                    return self.serve_key(path, options);
+                }
+                self.try_insert(path, options)
+                // The guard drops here, waking any threads waiting on this read.
+            }
+            Flight::Follower(in_flight_read) => {
+                debug!("Waiting for another thread to read file: {:?}", path.as_ref());
+                if !in_flight_read.wait(self.coalesce_timeout) {
+                    self.serve_unfinished_read(path, options)
+                } else if self.contains_key(&path) {
+                    self.serve_key(path, options)
+                } else {
+                    self.try_insert(path, options)
+                }
+            }
+        }
+    }
+
+    /// Attempt to store a given file in the the cache.
+    /// Storing will fail if the current files have more access attempts than the file being added.
+    /// If the provided file has more more access attempts than one of the files in the cache,
+    /// but the cache is full, a file will have to be removed from the cache to make room
+    /// for the new file.
+    ///
+    /// If the insertion works, the cache will update the priority score for the file being inserted.
+    /// The cached priority score requires the file in question to exist in the file map, so it will
+    /// have a size to use when calculating.
+    ///
+    /// It will get the size of the file to be inserted.
+    /// If will use this size to check if the file could be inserted.
+    /// If it can be inserted, it reads the file into memory, stores a copy of the in-memory
+    /// file behind a pointer, and constructs a FileSource to return.
+    ///
+    /// If the file can't be added, it will open the file in the filesystem and return that instead.
+    /// This means that it doesn't need to read the whole file into memory before reading through it
+    /// again to set the response body.
+    /// The lack of the need to read the whole file twice keeps performance of cache misses on par
+    /// with just normally reading the file without a cache.
+    ///
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file to be stored. Acts as a key for the file in the cache. Is used
+    /// look up the location of the file in the filesystem if the file is not in the cache.
+    /// * `options` - Limits on the work done to insert the file.
+    ///
+    ///
+    fn try_insert<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> (FileSource, ServeClass) {
+        self.try_insert_file(path, None, options)
+    }
+
+    /// Attempt to store a file in the cache, like `try_insert()`.
+    ///
+    /// If the contents of the file are already in memory, they can be provided as `preloaded`,
+    /// and they will be stored instead of reading the file from the filesystem.
+    /// If a preloaded file isn't accepted into the cache, it is returned to the cold tier.
+    fn try_insert_file<P: AsRef<Path>>(&self, path: P, mut preloaded: Option<InMemoryFile>, options: &GetOptions) -> (FileSource, ServeClass) {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        trace!("Trying to insert file {:?}", path);
+
+        match self.admit(&path, &mut preloaded, options) {
+            Ok(_) => {
+                let timing: Option<Instant> = if self.timing { Some(Instant::now()) } else { None };
+                let key: PathBuf = self.content_target(&path).unwrap_or_else(|| path.clone());
+                let cache_file_accessor = match self.file_map.find(&key) {
+                    Some(accessor_to_file) => accessor_to_file,
+                    None => {
+                        // If a concurrent remove operation removes the file before
+                        // it can be gotten via an accessor lock, recursively try to add
+                        // the file to the Cache until the lock can be attained.
+
+                        // Because this action takes place after room was made for
+                        // the new file in the cache, those files will be left out of the cache.
+                        warn!("Tried to add file to cache, but it was removed before it could be added. Attempting to insert file again.");
+                        // Because this recursion only occurs under extremely rare
+                        // circumstances due to concurrent removal of the file being
+                        // added between the insertion into the map, and locking an
+                        // accessor, a stack overflow is almost impossible. This would require
+                        // the file to be removed on every recursive attempt to re-insert it,
+                        // with the exact same timing required to invalidate the `find()` method,
+                        // for as many times as it takes to fill up the stack. It's not
+                        // going to happen.
+                        return self.try_insert(path, options);
+                    }
+                };
+
+                let named_in_memory_file: NamedInMemoryFile = NamedInMemoryFile::new(
+                    &path,
+                    cache_file_accessor
+                );
+                if let Some(start) = timing {
+                    self.latency.record(Phase::Response, start.elapsed());
+                }
+
+                self.record_decision(&path, Outcome::MissInserted);
+                self.record_fs_success();
+                (FileSource::from(named_in_memory_file), ServeClass::Inserted)
+            }
+            // Could not open file to read metadata, or the path can't be used.
+            Err(CacheError::InvalidMetadata) => {
+                self.record_fs_error();
+                self.record_decision(&path, Outcome::Error);
+                (FileSource::NotFound, ServeClass::FsError)
+            }
+            Err(CacheError::InvalidPath) | Err(CacheError::InvalidKey(_)) => {
+                self.record_decision(&path, Outcome::Error);
+                (FileSource::NotFound, ServeClass::FsError)
+            }
+            Err(CacheError::IsDirectory) => self.serve_index(&path, options),
+            Err(error) => {
+                debug!("The file was not accepted into the cache: {:?}", error);
+                if let (Some(file), Some(cold_tier)) = (preloaded, self.cold_tier.as_ref()) {
+                    cold_tier.demote(path.clone(), &file);
+                }
+                // The new file would not be accepted by the cache, so instead of reading the whole file
+                // into memory, and then copying it yet again when it is attached to the body of the
+                // response, serve the open file instead.
+                let counted: bool = error != CacheError::Bypassed;
+                let served: (FileSource, ServeClass) = self.serve_from_fs(&path, error, options);
+                match served.0 {
+                    FileSource::NotFound => {}
+                    _ if counted => self.count_miss(&path, CountPolicy::OnSuccess),
+                    _ => {}
+                }
+                served
+            }
+        }
+    }
+
+    /// Serves the index file of the directory the key names, for a request that found the directory.
+    ///
+    /// Only files are chosen as index files, so serving one doesn't find another directory,
+    /// unless the index file is replaced by one in the meantime, and even then each level goes one directory deeper.
+    fn serve_index(&self, key: &Path, options: &GetOptions) -> (FileSource, ServeClass) {
+        let directory: PathBuf = self.fs_path(key);
+        let index_file: Option<&String> = self.index_files.iter().find(|name| {
+            self.metadata_reads.fetch_add(1, Ordering::Relaxed);
+            fs::metadata(directory.join(name)).map(|metadata| metadata.is_file()).unwrap_or(false)
+        });
+        let name: &String = match index_file {
+            Some(name) => name,
+            None => {
+                debug!("{:?} is a directory without an index file.", key);
+                self.record_decision(key, Outcome::Error);
+                return (FileSource::NotFound, ServeClass::FsError);
+            }
+        };
+        match self.index_key {
+            IndexKey::File => {
+                let index_key: PathBuf = key.join(name);
+                let index_key: PathBuf = self.key_case.normalize(&index_key).unwrap_or(index_key);
+                self.serve_key(index_key, options)
+            }
+            IndexKey::Directory => {
+                // This request is already reading the key, so it inserts the index file itself instead of waiting for another one.
+                self.fs_paths.insert(key.to_path_buf(), directory.join(name));
+                self.try_insert_file(key, None, options)
+            }
+        }
+    }
+
+    /// Decides if the file should be stored in the cache, and if so, stores it,
+    /// evicting lower priority files if necessary.
+    ///
+    /// A preloaded file is taken out of the option once it is used. If the file isn't accepted,
+    /// the preloaded file is left in place for the caller to deal with.
+    fn admit(&self, path: &Path, preloaded: &mut Option<InMemoryFile>, options: &GetOptions) -> Result<CacheInsertionSuccess, CacheError> {
+        self.validate_key(path)?;
+        // Bypassed files aren't counted either.
+        if self.bypassed.contains(path) {
+            return Err(CacheError::Bypassed);
+        }
+
+        // If the FS can read metadata for a file, then the file exists, and it should be safe to increment
+        // the access_count and update.
+        let size: usize = match *preloaded {
+            Some(ref file) => file.stats.size,
+            None => self.timed(Phase::Metadata, || self.get_file_size_from_metadata(path))?,
+        };
+        self.count_miss(path, CountPolicy::OnRequest);
+
+        if let Err(error) = policy::check_size_bounds(size, self.min_file_size, self.max_file_size) {
+            // The file will never fit the size bounds, so later requests can skip straight to the filesystem.
+            debug!("Remembering that {:?} is outside of the size bounds of the cache.", path);
+            let bound: ServeClass = if size > self.max_file_size {
+                ServeClass::FsTooLarge
+            } else {
+                ServeClass::FsTooSmall
+            };
+            self.record_out_of_bounds(path, bound);
+            return Err(error);
+        }
+        if options.skip_insert_above.is_some_and(|threshold| size > threshold) {
+            debug!("Skipping insertion of {:?}, because it is larger than the request allows.", path);
+            return Err(CacheError::SkippedByRequest);
+        }
+        if self.is_thrashing(path) {
+            debug!("Skipping insertion of {:?}, because it is thrashing.", path);
+            return Err(CacheError::Thrashing);
+        }
+        if self.is_blocked(path) {
+            debug!("Skipping insertion of {:?}, because it is blocked.", path);
+            return Err(CacheError::Blocked);
+        }
+        if !self.within_memory_ceiling(size) {
+            debug!("Skipping insertion of {:?}, because it would go over the memory ceiling.", path);
+            return Err(CacheError::MemoryCeilingExceeded);
+        }
+
+        let quota_overflow: Option<(PathBuf, usize)> = self.directory_quotas.overflow(path, size);
+        if quota_overflow.is_none() && size < self.size_limit && self.reserve_space(size) {
+            debug!("Cache has room for the file.");
+            let file: InMemoryFile = match self.load_file(path, preloaded.take()) {
+                Ok(file) => file,
+                Err(_) => {
+                    self.release_space(size);
+                    return Err(CacheError::CouldNotReadFile);
+                }
+            };
+            // The file may have changed size since its metadata was read.
+            self.add_space(file.stats.size);
+            self.release_space(size);
+            self.count_miss(path, CountPolicy::OnSuccess);
+            let (key, replaced_existing): (PathBuf, bool) = self.store_admitted(path, file);
+            self.update_stats(&key);
+            self.schedule_overshoot_eviction();
+            self.remove_if_blocked(path)?;
+            if replaced_existing {
+                debug!("{:?} was stored by another insertion while it was read, replaced that copy.", path);
+                return Ok(CacheInsertionSuccess::ReplacedExistingEntry);
+            }
+            return Ok(CacheInsertionSuccess::SimpleAddition);
+        }
+
+        // The access_count should have incremented since the last time this was called, so the priority must be recalculated.
+        let mut new_file_access_count: usize = match self.access_count_map.find(path) {
+            Some(access_count) => *access_count.get(),
+            None => 0,
+        };
+        // Under CountPolicy::OnSuccess, this request isn't counted until the file is stored, but it still counts here.
+        if self.count_policy == CountPolicy::OnSuccess {
+            new_file_access_count = new_file_access_count.saturating_add(1);
+        }
+        let new_file_priority: usize = self.boosted_priority(path, self.priority(new_file_access_count, size));
+
+        let files_to_be_removed: Vec<(PathBuf, usize)> = self.timed(Phase::EvictionSelection, || match quota_overflow {
+            Some((prefix, quota_space)) => {
+                debug!("Trying to make room for the file under {:?}, which is at its quota", prefix);
+                self.make_room_within_quota(&prefix, quota_space, size, new_file_priority, options.max_eviction_candidates)
+            }
+            None => {
+                debug!("Trying to make room for the file");
+                // The number of bytes that need to be freed in order to make room for the new file.
+                let required_space_for_new_file: usize = policy::required_space(self.used_bytes(), size, self.effective_size_limit());
+                let batch_space: usize = self.batch_eviction_space(size);
+                let request = RoomRequest {
+                    required_space: required_space_for_new_file,
+                    batch_space,
+                    new_file_priority,
+                    max_candidates: options.max_eviction_candidates,
+                };
+                self.make_room_for_new_file(&request, None, None)
+            }
+        })?;
+        debug!("Made room for new file");
+        let file: InMemoryFile = self.load_file(path, preloaded.take()).map_err(|_| CacheError::CouldNotReadFile)?;
+        if self.contains_key(path) {
+            // The copy stored meanwhile already holds most of the space the file needs, so nothing is evicted for it.
+            return self.replace_existing(path, file);
+        }
+
+        // We have read a new file into memory, it is safe to
+        // remove the old files.
+        let evicted_entries: Vec<EvictedEntry> = self.evict_with_reservation(file.stats.size, &files_to_be_removed)?;
+        self.count_miss(path, CountPolicy::OnSuccess);
+        let (key, replaced_existing): (PathBuf, bool) = self.store_admitted(path, file);
+        self.update_stats(&key);
+        self.schedule_overshoot_eviction();
+        self.remove_if_blocked(path)?;
+        if replaced_existing {
+            return Ok(CacheInsertionSuccess::ReplacedExistingEntry);
+        }
+
+        Ok(CacheInsertionSuccess::ReplacedFile(evicted_entries))
+    }
+
+    /// Stores a file that another insertion stored while it was read, replacing that copy.
+    ///
+    /// Only the difference in their sizes is reserved, as the space of the replaced copy is given back.
+    /// If the file grew by more than the cache or the quotas of its directories allow, the copy already stored is kept.
+    fn replace_existing(&self, path: &Path, file: InMemoryFile) -> Result<CacheInsertionSuccess, CacheError> {
+        let key: PathBuf = self.content_target(path).unwrap_or_else(|| path.to_path_buf());
+        let existing_size: usize = self.file_map.find(&key).map_or(0, |existing| existing.get().stats.size);
+        let growth: usize = usize::saturating_sub(file.stats.size, existing_size);
+        if growth > 0 && (self.directory_quotas.overflow(path, growth).is_some() || !self.reserve_space(growth)) {
+            debug!("{:?} was stored by another insertion while it was read, and grew too much to replace that copy.", path);
+            return Err(CacheError::AlreadyInCache);
+        }
+        debug!("{:?} was stored by another insertion while it was read, replacing that copy.", path);
+        self.count_miss(path, CountPolicy::OnSuccess);
+        // The rest of the space is given back when the copy is replaced.
+        self.add_space(file.stats.size - growth);
+        let key: PathBuf = self.store_admitted(path, file).0;
+        self.update_stats(&key);
+        self.schedule_overshoot_eviction();
+        self.remove_if_blocked(path)?;
+        Ok(CacheInsertionSuccess::ReplacedExistingEntry)
+    }
+
+    /// Handles a request for a file of the size like `get()` would,
+    /// with zeroed bytes standing in for the file, so requests can be replayed or simulated without their files.
+    /// Returns where the file would have been served from, or `FsError` if it wasn't found.
+    pub(crate) fn replay_get(&self, path: &Path, size: Option<usize>) -> ServeClass {
+        let size: usize = match size {
+            Some(size) => size,
+            None => return ServeClass::FsError,
+        };
+        if self.is_disabled() {
+            return ServeClass::FsDisabled;
+        }
+        if let Some(bound) = self.out_of_bounds_class(path) {
+            return bound;
+        }
+        self.evict_overshoot();
+        if self.contains_key(path) {
+            self.increment_access_count(path);
+            self.update_stats_on_access(path);
+            return ServeClass::Hit;
+        }
+        let mut preloaded: Option<InMemoryFile> = Some(InMemoryFile::from_bytes(vec![0; size]));
+        match self.admit(path, &mut preloaded, &GetOptions::new()) {
+            Ok(_) => ServeClass::Inserted,
+            Err(error) => {
+                // The file would have been served from the filesystem, which can't fail without the file.
+                if error != CacheError::Bypassed {
+                    self.count_miss(path, CountPolicy::OnSuccess);
+                }
+                self.classify_rejection(path, &error)
+            }
+        }
+    }
+
+    /// Handles a recorded refresh of a file to the size like `refresh()` would, with zeroed bytes standing in for the file.
+    pub(crate) fn replay_refresh(&self, path: &Path, size: Option<usize>) {
+        self.out_of_bounds.remove(path);
+        if !self.contains_key(path) {
+            return;
+        }
+        match size {
+            Some(size) => self.store_refreshed(path, InMemoryFile::from_bytes(vec![0; size]), &RefreshOptions::new()),
+            None if self.on_missing == OnMissing::Remove => {
+                self.remove(path);
+            }
+            None => {}
+        }
+    }
+
+    /// Serves the file while the cache is frozen, without changing anything about the cache.
+    fn get_frozen(&self, key: &Path, fs_path: PathBuf, options: &GetOptions) -> (FileSource, ServeClass) {
+        if self.file_map.find(key).is_some() {
+            return (self.get_from_cache(key), ServeClass::Hit);
+        }
+        match self.open_for_response(fs_path, options) {
+            Ok(file) => (file, ServeClass::FsManual),
+            Err(_) => (FileSource::NotFound, ServeClass::FsError),
+        }
+    }
+
+    /// Serves the file from memory if the cache holds it, and from the filesystem otherwise,
+    /// for a cache in manual mode, without recording anything about it.
+    fn serve_manual(&self, key: &Path, options: &GetOptions) -> (FileSource, ServeClass) {
+        if self.file_map.find(key).is_some() {
+            return (self.get_from_cache(key), ServeClass::Hit);
+        }
+        self.pass_through(self.fs_path(key), ServeClass::FsManual, options)
+    }
+
+    /// Checks if the cache has a size limit of 0, which makes it pass every request through to the filesystem.
+    fn is_disabled(&self) -> bool {
+        self.size_limit == 0
+    }
+
+    /// Serves the file from the filesystem without recording anything about it,
+    /// for a cache that is disabled, or a path that is bypassed.
+    fn pass_through<P: AsRef<Path>>(&self, fs_path: P, class: ServeClass, options: &GetOptions) -> (FileSource, ServeClass) {
+        match self.open_for_response(fs_path, options) {
+            Ok(file) => (file, class),
+            Err(_) => (FileSource::NotFound, ServeClass::FsError),
+        }
+    }
+
+    /// Serves a file from the filesystem, because another thread was reading it into the cache
+    /// and didn't finish within the `coalesce_timeout`.
+    fn serve_unfinished_read<P: AsRef<Path>>(&self, path: P, options: &GetOptions) -> (FileSource, ServeClass) {
+        debug!("Another thread didn't finish reading {:?} in time.", path.as_ref());
+        match self.timed(Phase::Response, || self.open_for_response(self.fs_path(&path), options)) {
+            Ok(file) => {
+                self.record_decision(&path, Outcome::MissServedFromFs(CacheError::ConcurrentReadNotFinished));
+                self.increment_access_count(path);
+                (file, ServeClass::FsError)
+            }
+            Err(_) => {
+                self.record_decision(&path, Outcome::Error);
+                (FileSource::NotFound, ServeClass::FsError)
+            }
+        }
+    }
+
+    /// Serves a file that won't be inserted into the cache from the filesystem,
+    /// recording the reason it wasn't inserted.
+    fn serve_from_fs<P: AsRef<Path>>(&self, path: P, reason: CacheError, options: &GetOptions) -> (FileSource, ServeClass) {
+        if reason == CacheError::CouldNotReadFile {
+            self.record_fs_error();
+        }
+        match self.timed(Phase::Response, || self.open_for_response(self.fs_path(&path), options)) {
+            Ok(file) => {
+                if reason != CacheError::CouldNotReadFile {
+                    self.record_fs_success();
+                }
+                let class: ServeClass = self.classify_rejection(&path, &reason);
+                self.record_decision(&path, Outcome::MissServedFromFs(reason));
+                (file, class)
+            }
+            Err(_) => {
+                self.record_fs_error();
+                self.record_decision(&path, Outcome::Error);
+                (FileSource::NotFound, ServeClass::FsError)
+            }
+        }
+    }
+
+    /// Opens the file to serve it from the filesystem.
+    ///
+    /// Files resolved with `resolve()` are opened by its caller instead, so they are returned as `Deferred`.
+    fn open_for_response<P: AsRef<Path>>(&self, fs_path: P, options: &GetOptions) -> io::Result<FileSource> {
+        if options.defer_open {
+            return Ok(FileSource::Deferred(fs_path.as_ref().to_path_buf()));
+        }
+        self.fs_opens.fetch_add(1, Ordering::Relaxed);
+        FileSource::open(fs_path)
+    }
+
+    /// Checks if a miss may use the filesystem, or has to fail fast because the cache is degraded.
+    fn allows_miss(&self) -> bool {
+        match self.fs_health {
+            Some(ref fs_health) => fs_health.allows_miss((self.clock)()),
+            None => true,
+        }
+    }
+
+    /// Fails a miss while the cache is degraded, without touching the filesystem.
+    fn fail_degraded_miss<P: AsRef<Path>>(&self, path: P) -> (FileSource, ServeClass) {
+        debug!("Not serving {:?}, because the filesystem keeps failing.", path.as_ref());
+        self.record_decision(&path, Outcome::Error);
+        (FileSource::NotFound, ServeClass::FsError)
+    }
+
+    fn record_fs_error(&self) {
+        if let Some(ref fs_health) = self.fs_health {
+            fs_health.on_error((self.clock)());
+        }
+    }
+
+    fn record_fs_success(&self) {
+        if let Some(ref fs_health) = self.fs_health {
+            fs_health.on_success();
+        }
+    }
+
+    /// Remembers that the path is outside of the size bounds, so later requests for it skip reading its metadata.
+    fn record_out_of_bounds(&self, path: &Path, bound: ServeClass) {
+        match self.classification_filter {
+            Some(ref filter) => filter.insert(path, bound_marker(bound)),
+            None => {
+                self.out_of_bounds.insert(path.to_path_buf(), bound);
+            }
+        }
+    }
+
+    /// Gets which of the size bounds the path was found to be outside of, if it was.
+    ///
+    /// With a classification filter, a path can be mistaken for one outside of the bounds,
+    /// which only means it is served from the filesystem.
+    fn out_of_bounds_class(&self, path: &Path) -> Option<ServeClass> {
+        if let Some(bound) = self.out_of_bounds.find(path) {
+            return Some(*bound.get());
+        }
+        let filter: &ClassificationFilter = self.classification_filter.as_ref()?;
+        [ServeClass::FsTooLarge, ServeClass::FsTooSmall]
+            .iter()
+            .cloned()
+            .find(|&bound| filter.contains(path, bound_marker(bound)))
+    }
+
+    /// Serves the file, failing with the reason it couldn't be served if it isn't found.
+    fn serve_result<P: AsRef<Path>>(&self, path: P) -> Result<(FileSource, ServeClass), CacheError> {
+        self.validate_key(&path)?;
+        match self.serve(&path, &GetOptions::new()) {
+            (FileSource::NotFound, _) | (FileSource::FileMissing, _) => Err(self.why_not_found(path.as_ref())),
+            served => Ok(served),
+        }
+    }
+
+    /// Reads the metadata of a file that wasn't found once more, to tell why it couldn't be served.
+    fn why_not_found(&self, path: &Path) -> CacheError {
+        let key: PathBuf = self.key_case.normalize(path).unwrap_or(path.to_path_buf());
+        match fs::metadata(self.fs_path(&key)) {
+            Ok(ref metadata) if metadata.is_dir() => CacheError::IsDirectory,
+            Ok(_) => CacheError::CouldNotReadFile,
+            Err(ref error) if error.kind() == io::ErrorKind::PermissionDenied => CacheError::PermissionDenied,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => CacheError::NotFound,
+            Err(_) => CacheError::CouldNotReadFile,
+        }
+    }
+
+    /// Classifies why a file that wasn't inserted into the cache was served from the filesystem.
+    fn classify_rejection<P: AsRef<Path>>(&self, path: P, reason: &CacheError) -> ServeClass {
+        match *reason {
+            // Which of the bounds the file was outside of was recorded when it was rejected.
+            CacheError::FileSizeOutOfBounds => self.out_of_bounds_class(path.as_ref()).unwrap_or(ServeClass::FsTooLarge),
+            CacheError::NoMoreFilesToRemove => ServeClass::FsTooLarge,
+            CacheError::SkippedByRequest => ServeClass::FsSkipped,
+            CacheError::DirectoryQuotaExceeded
+            | CacheError::AllCandidatesVetoed
+            | CacheError::MemoryCeilingExceeded => ServeClass::FsRejected,
+            CacheError::CacheFrozen | CacheError::CacheFull => ServeClass::FsManual,
+            CacheError::NewPriorityIsNotHighEnough => ServeClass::FsPriorityTooLow,
+            CacheError::TooManyEvictionCandidates => ServeClass::FsEvictionBudget,
+            CacheError::Thrashing => ServeClass::FsThrashing,
+            CacheError::Blocked => ServeClass::FsBlocked,
+            CacheError::CacheDisabled => ServeClass::FsDisabled,
+            CacheError::Bypassed => ServeClass::FsBypassed,
+            _ => ServeClass::FsError,
+        }
+    }
+
+    /// Checks if the path was blocked with `remove_and_block()`.
+    fn is_blocked(&self, path: &Path) -> bool {
+        self.tombstones.is_blocked(path, (self.clock)())
+    }
+
+    /// Removes a file that was just stored if its path was blocked while the file was being read,
+    /// so a file being inserted while it is removed and blocked doesn't stay in the cache.
+    ///
+    /// `remove_and_block()` blocks the path before it removes the file, so either it removes the stored file,
+    /// or the block is seen here.
+    fn remove_if_blocked(&self, path: &Path) -> Result<(), CacheError> {
+        if self.is_blocked(path) {
+            debug!("{:?} was blocked while it was inserted, removing it again.", path);
+            self.remove(path);
+            return Err(CacheError::Blocked);
+        }
+        Ok(())
+    }
+
+    /// Runs the phase, recording how long it took if timing is enabled.
+    fn timed<T, F: FnOnce() -> T>(&self, phase: Phase, run: F) -> T {
+        if !self.timing {
+            return run();
+        }
+        let start: Instant = Instant::now();
+        let result: T = run();
+        self.latency.record(phase, start.elapsed());
+        result
+    }
+
+    /// Checks if thrash protection is keeping the file out of the cache.
+    fn is_thrashing(&self, path: &Path) -> bool {
+        match self.thrash_protection {
+            Some(ref thrash_protection) => thrash_protection.is_penalized(path, (self.clock)()),
+            None => false,
+        }
+    }
+
+    /// Adds the decision to the decision log, if there is one.
+    fn record_decision<P: AsRef<Path>>(&self, path: P, outcome: Outcome) {
+        if let Some(ref decision_log) = self.decision_log {
+            decision_log.record(path.as_ref(), outcome);
+        }
+    }
+
+    /// Remembers the spelling of the path a key lowercased by `KeyCase::Insensitive` was first requested with,
+    /// so its file is read from that spelling on case-sensitive filesystems.
+    /// Only spellings of files that exist are remembered, so requests for made up spellings don't take any memory.
+    fn remember_spelling(&self, key: &Path, path: &Path) {
+        if self.key_case != KeyCase::Insensitive || self.fs_paths.find(key).is_some() {
+            return;
+        }
+        let spelling: PathBuf = KeyCase::Sensitive.normalize(path).unwrap_or_else(|| path.to_path_buf());
+        if spelling.as_path() == key {
+            return;
+        }
+        self.metadata_reads.fetch_add(1, Ordering::Relaxed);
+        if fs::metadata(&spelling).is_ok() {
+            self.fs_paths.insert(key.to_path_buf(), spelling);
+        }
+    }
+
+    /// Gets the path in the filesystem of the file stored under the key.
+    /// Unless the file was added with `get_keyed()`, the key is the path.
+    fn fs_path<P: AsRef<Path>>(&self, key: P) -> PathBuf {
+        match self.fs_paths.find(key.as_ref()) {
+            Some(fs_path) => fs_path.get().clone(),
+            None => key.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Reads the file at the path into memory.
+    ///
+    /// Every read of a file into the cache goes through this function, so the number of reads can be tracked.
+    /// If a read deadline is set, the read happens on another thread, and this fails with `TimedOut`
+    /// if the file isn't read in time. The read permit moves to that thread, so it is only given back once the read ends.
+    fn read_file<P: AsRef<Path>>(&self, path: P, read_permit: Option<ReadPermit>) -> io::Result<InMemoryFile> {
+        self.file_reads.fetch_add(1, Ordering::Relaxed);
+        let fs_path: PathBuf = self.fs_path(path);
+        let read_deadline: Duration = match (self.read_deadline, self.latency.budget(Phase::FileRead)) {
+            (Some(read_deadline), Some(read_budget)) => cmp::min(read_deadline, read_budget),
+            (Some(read_deadline), None) | (None, Some(read_deadline)) => read_deadline,
+            (None, None) => return InMemoryFile::open_with(fs_path, self.storage),
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let reader_path: PathBuf = fs_path.clone();
+        let storage: Storage = self.storage;
+        thread::spawn(move || {
+            let _read_permit: Option<ReadPermit> = read_permit;
+            // If the deadline has passed, nobody is listening anymore and the file is dropped.
+            let _ = sender.send(InMemoryFile::open_with(reader_path, storage));
+        });
+        match receiver.recv_timeout(read_deadline) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("Reading {:?} took longer than the read deadline of {:?}.", fs_path, read_deadline);
+                Err(io::Error::new(io::ErrorKind::TimedOut, "the file could not be read before the read deadline"))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(io::Error::other("the thread reading the file stopped without a result"))
+            }
+        }
+    }
+
+    /// Refreshes a file while serving a request for it.
+    ///
+    /// If the file no longer exists in the filesystem, the stale copy is served if the cache is configured
+    /// to keep it, otherwise the file is not found.
+    fn refresh_on_access<P: AsRef<Path>>(&self, path: P) -> FileSource {
+        if !self.allows_refresh() {
+            debug!("Too many files are being refreshed, serving the cached copy of {:?}", path.as_ref());
+            return self.get_from_cache(&path);
+        }
+        match self.refresh_source(&path) {
+            FileSource::FileMissing => match self.on_missing {
+                OnMissing::ServeStale => self.get_from_cache(&path),
+                OnMissing::Remove => FileSource::NotFound,
+            },
+            cached_file => cached_file,
+        }
+    }
+
+    /// Checks a soft purged file against the filesystem while serving a request for it.
+    ///
+    /// The file is served as it is if it hasn't changed, and refreshed if it has.
+    /// If it can't be checked or read, it is handled according to `set_soft_purge_on_missing()`.
+    fn revalidate_soft_purged(&self, path: &Path) -> FileSource {
+        match self.matches_filesystem(path) {
+            Some(true) => {
+                debug!("{:?} hasn't changed since it was soft purged, serving it again.", path);
+                if let Some(mut file) = self.file_map.find_mut(path) {
+                    file.get().stale = false;
+                }
+                self.get_from_cache(path)
+            }
+            Some(false) if !self.allows_refresh() => {
+                debug!("Too many files are being refreshed, serving the stale copy of {:?}", path);
+                self.get_from_cache(path)
+            }
+            Some(false) => match self.refresh_source(path) {
+                FileSource::Cached(cached_file) => FileSource::Cached(cached_file),
+                _ if self.contains_key(path) => self.serve_soft_purged_without_revalidating(path),
+                not_found => not_found,
+            },
+            None => self.serve_soft_purged_without_revalidating(path),
+        }
+    }
+
+    /// Takes a token for a refresh triggered by serving a request, if the refresh rate is limited.
+    fn allows_refresh(&self) -> bool {
+        match self.refresh_limiter {
+            Some(ref refresh_limiter) => refresh_limiter.try_take((self.clock)()),
+            None => true,
+        }
+    }
+
+    /// Handles a soft purged file that couldn't be checked against the filesystem, or couldn't be read again.
+    fn serve_soft_purged_without_revalidating(&self, path: &Path) -> FileSource {
+        match self.soft_purge_on_missing {
+            OnMissing::ServeStale => {
+                warn!("Could not revalidate the soft purged {:?}, serving the stale copy.", path);
+                self.get_from_cache(path)
+            }
+            OnMissing::Remove => {
+                warn!("Could not revalidate the soft purged {:?}, removing it from the cache.", path);
+                self.remove(path);
+                FileSource::NotFound
+            }
+        }
+    }
+
+    /// Checks the bytes of the cached file against the hash stored for them.
+    /// Files without a stored hash are hashed now, and match.
+    fn checksum_matches(&self, path: &Path) -> bool {
+        let (hash, stored_hash, generation): (u64, Option<u64>, u64) = match self.file_map.find(path) {
+            Some(file) => {
+                let file: &InMemoryFile = file.get();
+                (content_hash::hash(file.bytes.as_slice()), file.content_hash, file.generation)
+            }
+            None => return true,
+        };
+        match stored_hash {
+            Some(stored_hash) => stored_hash == hash,
+            None => {
+                self.memoize_content_hash(path, generation, hash);
+                true
+            }
+        }
+    }
+
+    /// Reads a cached file whose bytes were corrupted from the filesystem again.
+    /// If it no longer exists, the corrupted copy is removed instead of being served.
+    fn replace_corrupted(&self, path: &Path) -> FileSource {
+        match self.refresh_source(path) {
+            FileSource::FileMissing => {
+                self.remove(path);
+                FileSource::NotFound
+            }
+            file => file,
+        }
+    }
+
+    /// Compares the size and modification time of the cached file with the file in the filesystem.
+    ///
+    /// Returns None if the file no longer exists in the filesystem.
+    /// Files without a known modification time never match, so they are refreshed to be safe.
+    fn matches_filesystem<P: AsRef<Path>>(&self, path: P) -> Option<bool> {
+        self.metadata_reads.fetch_add(1, Ordering::Relaxed);
+        let metadata: Metadata = match fs::metadata(self.fs_path(&path)) {
+            Ok(metadata) => metadata,
+            Err(_) => return None,
+        };
+        let modified: Option<SystemTime> = metadata.modified().ok();
+        match self.file_map.find(path.as_ref()) {
+            Some(file) => {
+                let file: &InMemoryFile = file.get();
+                let identity_matches: bool = match (self.revalidation, file.identity, FileIdentity::of(&metadata)) {
+                    (Revalidation::ByIdentity, Some(identity), Some(current_identity)) => identity == current_identity,
+                    // Without an identity to compare, only the size and modification time can tell if the file changed.
+                    _ => true,
+                };
+                Some(identity_matches && file.stats.size == metadata.len() as usize && file.modified.is_some() && file.modified == modified)
+            }
+            None => Some(true),
+        }
+    }
+
+    /// Checks if the file stored under the path was read from the filesystem, see `InMemoryFile::is_backed_by_fs()`.
+    fn is_backed_by_fs<P: AsRef<Path>>(&self, path: P) -> bool {
+        match self.file_map.find(path.as_ref()) {
+            Some(file) => file.get().is_backed_by_fs(),
+            None => false,
+        }
+    }
+
+    /// Checks if the file stored under the path was embedded in the binary.
+    fn is_embedded<P: AsRef<Path>>(&self, path: P) -> bool {
+        match self.file_map.find(path.as_ref()) {
+            Some(file) => file.get().is_embedded(),
+            None => false,
+        }
+    }
+
+    /// Uses the preloaded file if there is one, otherwise reads the file from the filesystem.
+    ///
+    /// Fails with `WouldBlock` if the maximum number of concurrent reads are already in progress.
+    fn load_file<P: AsRef<Path>>(&self, path: P, preloaded: Option<InMemoryFile>) -> io::Result<InMemoryFile> {
+        match preloaded {
+            Some(file) => Ok(file),
+            None => {
+                let read_permit: ReadPermit = match self.read_limiter.try_acquire() {
+                    Some(read_permit) => read_permit,
+                    None => {
+                        debug!("Too many files are being read into the cache, not reading {:?}", path.as_ref());
+                        return Err(io::Error::new(io::ErrorKind::WouldBlock, "too many concurrent reads"));
+                    }
+                };
+                self.timed(Phase::FileRead, || self.read_file(path, Some(read_permit)))
+            }
+        }
+    }
+
+    /// Removes the file from the cache to make room for other files.
+    /// If the cache has a cold tier, the file is demoted into it instead of being dropped.
+    ///
+    /// Returns the evicted entry, or None if the file was no longer in the cache.
+    fn evict(&self, path: &Path) -> Option<EvictedEntry> {
+        let evicted_entry: EvictedEntry = self.evict_holding_space(path)?;
+        self.release_space(evicted_entry.size);
+        Some(evicted_entry)
+    }
+
+    /// Evicts the file like `evict()`, but keeps the space it held reserved for the caller.
+    fn evict_holding_space(&self, path: &Path) -> Option<EvictedEntry> {
+        let file: InMemoryFile = self.file_map.remove(path)?;
+        self.next_generation();
+        self.directory_quotas.on_removed(path, file.stats.size);
+        self.zombie_bytes.on_removed(&file.bytes);
+        self.churn.on_evicted(path);
+        self.extension_counters.record_eviction(path);
+        if let Some(ref ghost_entries) = self.ghost_entries {
+            ghost_entries.on_evicted(path, file.stats.size);
+        }
+        if let Some(ref cold_tier) = self.cold_tier {
+            cold_tier.demote(path.to_path_buf(), &file);
+        }
+        let evicted_entry = EvictedEntry {
+            path: path.to_path_buf(),
+            size: file.stats.size,
+            access_count: file.stats.access_count,
+        };
+        if let Some(ref observer) = self.observer {
+            observer.on_evict(&evicted_entry);
+        }
+        Some(evicted_entry)
+    }
+
+    /// Stores a file that was admitted under the path, returning the key it was stored under,
+    /// and whether it replaced a file already stored under that key.
+    ///
+    /// In content addressed mode, the file is stored under the key of its content, and the path is pointed at it.
+    /// If the content is already stored, the file shares it, and the space accounted for the file is given back.
+    /// A file whose hash collides with stored content that differs from it is stored under its path instead.
+    fn store_admitted(&self, path: &Path, mut file: InMemoryFile) -> (PathBuf, bool) {
+        if self.content_index.is_none() {
+            return (path.to_path_buf(), self.store_file(path.to_path_buf(), file));
+        }
+        let hash: u64 = match file.content_hash {
+            Some(hash) => hash,
+            None => content_hash::hash(file.bytes.as_slice()),
+        };
+        file.content_hash = Some(hash);
+        let key: PathBuf = content_index::content_key(hash, path);
+        let modified: Option<SystemTime> = file.modified;
+        let access_count: usize = self.access_count_map.find(path).map_or(0, |access_count| *access_count.get());
+        let same_content: Option<bool> = self.file_map
+            .find(&key)
+            .map(|stored| stored.get().bytes.as_slice() == file.bytes.as_slice());
+        if same_content == Some(false) {
+            warn!("{:?} has the content hash of {:?}, but not its content, storing it under its path.", path, key);
+            self.unpoint_content(path);
+            return (path.to_path_buf(), self.store_file(path.to_path_buf(), file));
+        }
+        let replaced_existing: bool = if same_content == Some(true) {
+            debug!("{:?} has the same content as {:?}, sharing it.", path, key);
+            self.release_space(file.stats.size);
+            false
+        } else {
+            self.store_file(key.clone(), file)
+        };
+        self.access_count_map.upsert(key.clone(), access_count, &|count| *count = count.saturating_add(access_count));
+        self.point_to_content(path, key.clone(), modified);
+        (key, replaced_existing)
+    }
+
+    /// Stores the file in the file_map, releasing the space held by any file it replaces.
+    /// The space for the new file must already have been accounted for.
+    ///
+    /// Returns whether the file replaced one that was already stored under the path.
+    fn store_file(&self, path: PathBuf, mut file: InMemoryFile) -> bool {
+        file.stats.last_access = self.next_access_tick();
+        if self.victim_comparator.is_some() {
+            file.last_accessed = Some((self.clock)());
+        }
+        file.key = Some(Arc::from(path.as_path()));
+        // A file that replaces one already in the cache keeps the time the path was first stored.
+        let inserted_at: Option<Instant> = self.file_map.find(&path).and_then(|existing| existing.get().inserted_at);
+        file.inserted_at = Some(inserted_at.unwrap_or_else(self.clock));
+        file.generation = self.next_generation();
+        // Only bytes owned by the file are moved into the slabs, embedded and mapped files stay where they are.
+        let is_owned: bool = matches!(file.bytes, FileBytes::Owned(_));
+        if let (true, Some(arena)) = (is_owned, self.small_file_arena.as_ref()) {
+            if let Some(bytes) = arena.store(file.bytes.as_slice()) {
+                file.bytes = bytes;
+            }
+        }
+        if self.verify_checksums && file.content_hash.is_none() && !file.is_embedded() {
+            file.content_hash = Some(content_hash::hash(file.bytes.as_slice()));
+        }
+        self.directory_quotas.on_stored(&path, file.stats.size);
+        if let Some(ref ghost_entries) = self.ghost_entries {
+            ghost_entries.on_stored(&path);
+        }
+        if self.churn.on_stored(&path) {
+            if let Some(ref thrash_protection) = self.thrash_protection {
+                thrash_protection.on_cycle(&path, (self.clock)());
+            }
+        }
+        match self.file_map.insert(path.clone(), file) {
+            Some(replaced) => {
+                self.release_space(replaced.stats.size);
+                self.directory_quotas.on_removed(&path, replaced.stats.size);
+                self.zombie_bytes.on_removed(&replaced.bytes);
+                true
+            }
+            None => {
+                self.extension_counters.record_insertion(&path);
+                false
+            }
+        }
+    }
+
+    /// Atomically reserves space for a file that is about to be inserted,
+    /// so concurrent insertions can't together push the cache past its size limit.
+    /// Returns false without reserving anything if the file would not fit.
+    fn reserve_space(&self, size: usize) -> bool {
+        if !self.within_memory_ceiling(size) {
+            return false;
+        }
+        let limit: usize = self.effective_size_limit().saturating_sub(self.unused_slab_bytes());
+        let mut used_bytes: usize = self.resident_bytes.load(Ordering::Acquire);
+        loop {
+            let reserved: usize = match used_bytes.checked_add(size) {
+                Some(reserved) if reserved <= limit => reserved,
+                _ => return false,
+            };
+            match self.resident_bytes.compare_exchange_weak(used_bytes, reserved, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(actual) => used_bytes = actual,
+            }
+        }
+    }
+
+    /// Reserves as much of the space as is free, up to the size, returning how much was reserved.
+    fn reserve_free_space(&self, size: usize) -> usize {
+        let limit: usize = self.effective_size_limit().saturating_sub(self.unused_slab_bytes());
+        let mut used_bytes: usize = self.resident_bytes.load(Ordering::Acquire);
+        loop {
+            let reserved: usize = cmp::min(size, limit.saturating_sub(used_bytes));
+            match self.resident_bytes.compare_exchange_weak(used_bytes, used_bytes + reserved, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return reserved,
+                Err(actual) => used_bytes = actual,
+            }
+        }
+    }
+
+    /// Evicts the files chosen to make room for a file of the size, reserving the space for the file,
+    /// and returns the entries that were evicted.
+    ///
+    /// The free space is reserved before anything is evicted, and the space of the evicted files is kept reserved,
+    /// so concurrent insertions can't take the space made for the file.
+    /// If the files were removed by another thread in the meantime, the space they held may have been taken,
+    /// in which case nothing is reserved.
+    fn evict_with_reservation(&self, size: usize, victims: &[(PathBuf, usize)]) -> Result<Vec<EvictedEntry>, CacheError> {
+        let victim_bytes: usize = victims.iter().map(|&(_, victim_size)| victim_size).sum();
+        if !self.within_memory_ceiling(size.saturating_sub(victim_bytes)) {
+            return Err(CacheError::MemoryCeilingExceeded);
+        }
+        let mut reserved: usize = self.reserve_free_space(size);
+        let evicted_entries: Vec<EvictedEntry> = victims
+            .iter()
+            .filter_map(|(victim, _)| {
+                // The file was accessed with this key earlier when sorting priorities, which should make removal safe.
+                let evicted_entry: Option<EvictedEntry> = self.evict_holding_space(victim);
+                if evicted_entry.is_none() {
+                    warn!("Likely due to concurrent mutations, a file being removed from the cache was not found because another thread removed it first.")
+                }
+                evicted_entry
+            })
+            .collect();
+        reserved += evicted_entries.iter().map(|evicted_entry| evicted_entry.size).sum::<usize>();
+        if reserved >= size {
+            self.release_space(reserved - size);
+            return Ok(evicted_entries);
+        }
+        if !self.reserve_space(size - reserved) {
+            self.release_space(reserved);
+            debug!("The space freed for the file was taken by a concurrent insertion.");
+            return Err(CacheError::SpaceTakenByConcurrentInsertion);
+        }
+        Ok(evicted_entries)
+    }
+
+    /// Accounts for bytes stored without a reservation, regardless of the size limit.
+    fn add_space(&self, size: usize) {
+        self.resident_bytes.fetch_add(size, Ordering::AcqRel);
+    }
+
+    /// Gives back space that was reserved or held by a file that left the cache.
+    fn release_space(&self, size: usize) {
+        let mut used_bytes: usize = self.resident_bytes.load(Ordering::Acquire);
+        loop {
+            let released: usize = usize::saturating_sub(used_bytes, size);
+            match self.resident_bytes.compare_exchange_weak(used_bytes, released, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(actual) => used_bytes = actual,
+            }
+        }
+    }
+
+    /// Takes the file out of the cold tier, if the cache has one and the file is stored there.
+    fn take_from_cold_tier<P: AsRef<Path>>(&self, path: P) -> Option<InMemoryFile> {
+        match self.cold_tier {
+            Some(ref cold_tier) => cold_tier.take(path),
+            None => None,
+        }
+    }
+
+    /// Checks if storing a file of the size keeps the used bytes and the zombie bytes under the memory ceiling, if one is set.
+    fn within_memory_ceiling(&self, size: usize) -> bool {
+        match self.memory_ceiling {
+            Some(ceiling) => {
+                let memory: usize = self.used_bytes().saturating_add(self.zombie_bytes.bytes());
+                memory.saturating_add(size) <= ceiling
+            }
+            None => true,
+        }
+    }
+
+    /// The number of bytes insertions are allowed to fill the cache up to without evicting files.
+    fn effective_size_limit(&self) -> usize {
+        // Nothing evicts the overshoot of a cache in manual mode, so it never goes over its limit.
+        if self.mode == Mode::Manual {
+            return self.high_watermark;
+        }
+        match self.overshoot {
+            Some(overshoot) => {
+                let allowed_overshoot: usize = (self.high_watermark as f64 * overshoot.fraction) as usize;
+                usize::saturating_add(self.high_watermark, allowed_overshoot)
+            }
+            None => self.high_watermark,
+        }
+    }
+
+    /// The number of bytes that should be freed to make room for a file of the given size,
+    /// so that the cache ends up at its low watermark once the file is inserted.
+    fn batch_eviction_space(&self, size: usize) -> usize {
+        policy::required_space(self.used_bytes(), size, self.low_watermark)
+    }
+
+    /// Marks that the cache may be over its size limit after an insertion,
+    /// so the next cache operation will evict files.
+    fn schedule_overshoot_eviction(&self) {
+        if self.overshoot.is_some() {
+            self.overshoot_eviction_pending.store(true, Ordering::Release);
+        }
+    }
+
+    /// Moves the files out of the slabs of the small file arena that are mostly empty, so the slabs can be freed,
+    /// once the unused space in the slabs is worth it.
+    fn compact_small_file_arena(&self) {
+        let arena: &SmallFileArena = match self.small_file_arena {
+            Some(ref arena) => arena,
+            None => return,
+        };
+        let sparse_slabs: HashSet<usize> = arena.sparse_slabs();
+        if sparse_slabs.is_empty() {
+            return;
+        }
+        let keys: Vec<PathBuf> = self.file_map
+            .iter()
+            .filter(|&(_, file)| file.bytes.slab_id().is_some_and(|slab_id| sparse_slabs.contains(&slab_id)))
+            .map(|(key, _)| key.clone())
+            .collect();
+        debug!("Moving {} files out of {} sparse slabs.", keys.len(), sparse_slabs.len());
+        for key in keys {
+            if let Some(mut file) = self.file_map.find_mut(&key) {
+                let file: &mut InMemoryFile = file.get();
+                if let Some(bytes) = arena.store(file.bytes.as_slice()) {
+                    file.bytes = bytes;
+                }
+            }
+        }
+    }
+
+    /// Inserts the file of the size into the free space of the cache, if it fits into the bytes left in the budget,
+    /// without evicting anything. Returns the number of bytes that were stored.
+    ///
+    /// A file that has never been requested is stored with an access count of 0, so prefetching it doesn't raise its priority.
+    fn prefetch_file(&self, path: &Path, size: usize, budget_left: usize) -> Result<usize, PrefetchSkipReason> {
+        if self.is_frozen() {
+            return Err(PrefetchSkipReason::Rejected(CacheError::CacheFrozen));
+        }
+        if size > budget_left {
+            return Err(PrefetchSkipReason::OverBudget);
+        }
+        if self.is_thrashing(path) {
+            return Err(PrefetchSkipReason::Rejected(CacheError::Thrashing));
+        }
+        if self.is_blocked(path) {
+            return Err(PrefetchSkipReason::Rejected(CacheError::Blocked));
+        }
+        if self.bypassed.contains(path) {
+            return Err(PrefetchSkipReason::Rejected(CacheError::Bypassed));
+        }
+        if self.directory_quotas.overflow(path, size).is_some() {
+            return Err(PrefetchSkipReason::Rejected(CacheError::DirectoryQuotaExceeded));
+        }
+        if size >= self.size_limit || !self.reserve_space(size) {
+            return Err(PrefetchSkipReason::CacheFull);
+        }
+        let preloaded: Option<InMemoryFile> = self.take_from_cold_tier(path);
+        let file: InMemoryFile = match self.load_file(path, preloaded) {
+            Ok(file) => file,
+            Err(_) => {
+                self.release_space(size);
+                return Err(PrefetchSkipReason::Rejected(CacheError::CouldNotReadFile));
+            }
+        };
+        // The file may have changed size since its metadata was read.
+        self.add_space(file.stats.size);
+        self.release_space(size);
+        let stored_bytes: usize = file.stats.size;
+        if self.access_count_map.find(path).is_none() {
+            self.access_count_map.insert(path.to_path_buf(), 0);
+        }
+        let key: PathBuf = self.store_admitted(path, file).0;
+        self.update_stats(&key);
+        self.schedule_overshoot_eviction();
+        self.remove_if_blocked(path).map_err(PrefetchSkipReason::Rejected)?;
+        Ok(stored_bytes)
+    }
+
+    /// Inserts the siblings of the file that has waited longest for them to be prefetched, if sibling prefetching is on.
+    ///
+    /// The siblings are listed from the directory the file was read from, and are stored under keys in the directory of its key.
+    fn prefetch_siblings(&self) {
+        let sibling_prefetch: &SiblingPrefetch = match self.sibling_prefetch {
+            Some(ref sibling_prefetch) => sibling_prefetch,
+            None => return,
+        };
+        let path: PathBuf = match sibling_prefetch.pop() {
+            Some(path) => path,
+            None => return,
+        };
+        let config: PrefetchConfig = sibling_prefetch.config();
+        let mut prefetched_files: usize = 0;
+        let mut prefetched_bytes: usize = 0;
+        for sibling in prefetch::sibling_files(&self.fs_path(&path)) {
+            if prefetched_files >= config.max_siblings {
+                break;
+            }
+            let sibling: PathBuf = match self.key_case.normalize(&sibling) {
+                Some(key) => {
+                    self.remember_spelling(&key, &sibling);
+                    key
+                }
+                None => sibling,
+            };
+            if self.contains_key(&sibling) || self.out_of_bounds_class(&sibling).is_some() {
+                continue;
+            }
+            let size: usize = match self.get_file_size_from_metadata(&sibling) {
+                Ok(size) => size,
+                Err(_) => continue,
+            };
+            if policy::check_size_bounds(size, self.min_file_size, self.max_file_size).is_err() {
+                continue;
+            }
+            match self.prefetch_file(&sibling, size, config.byte_budget.saturating_sub(prefetched_bytes)) {
+                Ok(stored_bytes) => {
+                    debug!("Prefetched {:?}, a sibling of {:?}.", sibling, path);
+                    prefetched_files += 1;
+                    prefetched_bytes = usize::saturating_add(prefetched_bytes, stored_bytes);
+                }
+                Err(reason) => debug!("Didn't prefetch {:?}: {:?}", sibling, reason),
+            }
+        }
+    }
+
+    /// Evicts some of the lowest priority files if an insertion left the cache over its size limit.
+    ///
+    /// At most `evictions_per_operation` files will be evicted per call.
+    /// If the cache is still over its limit afterwards, the next call will continue evicting.
+    fn evict_overshoot(&self) {
+        let evictions_per_operation: usize = match self.overshoot {
+            Some(overshoot) => overshoot.evictions_per_operation,
+            None => return,
+        };
+        if !self.overshoot_eviction_pending.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let mut used_bytes: usize = self.used_bytes();
+        let mut candidates: Vec<CandidateFile> = self.sorted_priorities();
+        let mut floor_bytes: Vec<usize> = self.directory_floor_bytes();
+        let mut evicted: usize = 0;
+        while used_bytes > self.high_watermark && evicted < evictions_per_operation {
+            match candidates.pop() {
+                Some(lowest) => {
+                    if !self.directory_floors.allows_eviction(&mut floor_bytes, &lowest.key, lowest.stats.size) {
+                        continue;
+                    }
+                    if self.evict(&lowest.key).is_some() {
+                        debug!("Evicted {:?} to bring the cache back under its size limit.", lowest.key);
+                        used_bytes = usize::saturating_sub(used_bytes, lowest.stats.size);
+                    }
+                    evicted += 1;
+                }
+                None => break,
+            }
+        }
+
+        if used_bytes > self.high_watermark {
+            self.overshoot_eviction_pending.store(true, Ordering::Release);
+        }
+    }
+
+    /// Checks that the decision recorded in the token still holds:
+    /// the file is still missing from the cache and has the same size,
+    /// the files chosen for eviction are still cached with the same sizes,
+    /// and evicting them still makes enough room for the file.
+    fn token_is_current(&self, token: &AdmissionToken) -> bool {
+        if self.contains_key(&token.path) {
+            return false;
+        }
+        match self.get_file_size_from_metadata(&token.path) {
+            Ok(size) if size == token.size => {}
+            _ => return false,
+        }
+        let victims_are_unchanged: bool = token.victims.iter().all(|&(ref victim, size)| {
+            match self.file_map.find(victim) {
+                Some(file) => file.get().stats.size == size,
+                None => false,
+            }
+        });
+        let freed_space: usize = token.victims.iter().map(|&(_, size)| size).sum();
+        victims_are_unchanged
+            && usize::saturating_add(self.used_bytes().saturating_sub(freed_space), token.size) <= self.effective_size_limit()
+    }
+
+    /// The number of bytes in the cache accounted to each directory floor.
+    fn directory_floor_bytes(&self) -> Vec<usize> {
+        self.directory_floors.resident_bytes(self.file_map.iter().map(|(path, file)| (path.as_path(), file.stats.size)))
+    }
+
+    /// Chooses files under the prefix to evict, to make room for a file that would otherwise take
+    /// the prefix over its quota.
+    ///
+    /// `quota_space` is the number of bytes that have to be freed under the prefix.
+    /// If the cache as a whole also needs room for the file, that room has to be made under the prefix as well,
+    /// so files outside of the prefix are never evicted.
+    fn make_room_within_quota(&self, prefix: &Path, quota_space: usize, size: usize, new_file_priority: usize, max_candidates: Option<usize>) -> Result<Vec<(PathBuf, usize)>, CacheError> {
+        let cache_space: usize = policy::required_space(self.used_bytes(), size, self.effective_size_limit());
+        let required_space: usize = usize::max(quota_space, cache_space);
+        let request = RoomRequest {
+            required_space,
+            batch_space: required_space,
+            new_file_priority,
+            max_candidates,
+        };
+        self.make_room_for_new_file(&request, None, Some(prefix))
+            .map_err(|error| match error {
+                CacheError::NoMoreFilesToRemove => CacheError::DirectoryQuotaExceeded,
+                error => error,
+            })
+    }
+
+    /// Remove the n lowest priority files to make room for a file with a size: required_space.
+    ///
+    /// If this returns an OK, this function has removed the required file space from the file_map.
+    /// If this returns an Err, then either not enough space could be freed, or the priority of
+    /// files that would need to be freed to make room for the new file is greater than the
+    /// new file's priority, and as result no memory was freed.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The number of bytes that must be freed to make room for the new file, its priority,
+    ///   and how many files may be considered for removal. See `RoomRequest`.
+    /// * `exclude` - A file that is already in the cache and is making room for itself to grow.
+    ///   It is never chosen for removal.
+    /// * `within` - If set, only files under this directory are considered for removal.
+    ///
+    /// The files are chosen by `policy::select_victims()`, from the files that aren't protected by a directory floor.
+    fn make_room_for_new_file(&self, request: &RoomRequest, exclude: Option<&Path>, within: Option<&Path>) -> Result<Vec<(PathBuf, usize)>, CacheError> {
+        if self.mode == Mode::Manual {
+            return Err(CacheError::CacheFull);
+        }
+        // The files requested together with `get_source_many()` share the candidates, which are only sorted once.
+        let victims = match self.batch_plan.candidates(|| self.sorted_priorities()) {
+            Some(candidates) => self.choose_victims(candidates, request, exclude, within),
+            None => self.choose_victims(self.sorted_priorities(), request, exclude, within),
+        };
+        if let Ok(ref victims) = victims {
+            self.batch_plan.on_chosen(victims);
+        }
+        victims
+    }
+
+    /// Chooses the files to evict from the candidates, for `make_room_for_new_file()`.
+    fn choose_victims(&self, mut candidates: Vec<CandidateFile>, request: &RoomRequest, exclude: Option<&Path>, within: Option<&Path>) -> Result<Vec<(PathBuf, usize)>, CacheError> {
+        if let Some(exclude) = exclude {
+            candidates.retain(|candidate| &*candidate.key != exclude);
+        }
+        if let Some(within) = within {
+            candidates.retain(|candidate| candidate.key.starts_with(within));
+        }
+        let candidates: Vec<CandidateFile> = self.filter_candidates(candidates)?;
+        // Files that were only just stored are spared if the settled files are enough to make room.
+        // If they aren't, the new file may still evict them, so the cache never refuses a file only to protect young ones.
+        if let Some(min_residency) = self.min_residency {
+            let settled: Vec<CandidateFile> = policy::settled(&candidates, (self.clock)(), min_residency);
+            if settled.len() < candidates.len() {
+                let mut floor_bytes: Vec<usize> = self.directory_floor_bytes();
+                let victims = policy::select_victims(settled, request, |key, size| self.directory_floors.allows_eviction(&mut floor_bytes, key, size));
+                if victims.is_ok() {
+                    return victims;
+                }
+            }
+        }
+        let mut floor_bytes: Vec<usize> = self.directory_floor_bytes();
+        policy::select_victims(candidates, request, |key, size| self.directory_floors.allows_eviction(&mut floor_bytes, key, size))
+    }
+
+    /// Asks the eviction filter about the candidates, dropping the ones it denies,
+    /// and moving the ones it prefers to the end, where they are evicted first.
+    fn filter_candidates(&self, candidates: Vec<CandidateFile>) -> Result<Vec<CandidateFile>, CacheError> {
+        let eviction_filter = match self.eviction_filter {
+            Some(ref eviction_filter) => eviction_filter,
+            None => return Ok(candidates),
+        };
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        let mut allowed: Vec<CandidateFile> = Vec::with_capacity(candidates.len());
+        let mut preferred: Vec<CandidateFile> = vec![];
+        for candidate in candidates {
+            match eviction_filter(&candidate.key, &candidate.stats) {
+                EvictionVote::Allow => allowed.push(candidate),
+                EvictionVote::Prefer => preferred.push(candidate),
+                EvictionVote::Deny => {}
+            }
+        }
+        if allowed.is_empty() && preferred.is_empty() {
+            return Err(CacheError::AllCandidatesVetoed);
+        }
+        allowed.extend(preferred);
+        Ok(allowed)
+    }
+
+    ///Helper function that gets the file from the cache if it exists there.
+    fn get_from_cache<P: AsRef<Path>>(&self, path: P) -> FileSource {
+        match self.file_map.find(path.as_ref()) {
+            Some(in_memory_file) => {
+                trace!("Found file: {:?} in cache.", path.as_ref());
+                if in_memory_file.get().has_size_mismatch() {
+                    self.size_mismatches.fetch_add(1, Ordering::Relaxed);
+                    warn!("The declared size of {:?} disagrees with its bytes.", path.as_ref());
+                }
+                FileSource::from(NamedInMemoryFile::new(
+                    path.as_ref(),
+                    in_memory_file,
+                ))
+            }
+            None => FileSource::NotFound,
+        }
+
+    }
+
+    /// Counts a request for a file that isn't in the cache as an access, if the count policy counts it at this point.
+    fn count_miss<P: AsRef<Path>>(&self, path: P, when: CountPolicy) {
+        if self.count_policy == when {
+            self.increment_access_count(path);
+        }
+    }
+
+    /// Helper function for incrementing the access count for a given file name.
+    ///
+    /// This should only be used in cases where the file is known to exist, to avoid bloating the access count map with useless values.
+    fn increment_access_count<P: AsRef<Path>>(&self, path: P) {
+        // Files that are already counted are incremented in place, so counting an access doesn't copy the path.
+        if let Some(mut access_count) = self.access_count_map.find_mut(path.as_ref()) {
+            let access_count: &mut usize = access_count.get();
+            *access_count = usize::saturating_add(*access_count, 1);
+            return;
+        }
+        self.access_count_map.upsert(
+            path.as_ref().to_path_buf(),
+            1, // insert 1 if nothing at key. The closure will not execute.
+            &|access_count| {
+                *access_count = match usize::checked_add(access_count.clone(), 1) {
+                    Some(v) => v, // return the incremented value
+                    None => usize::MAX, // If the access count bumps up against the usize max, keep the value the same.
+                }
+            },
+        );
+    }
+
+
+    /// Update the stats associated with a file that was accessed in the cache.
+    ///
+    /// The access count is always updated, but the priority is only recalculated as often as
+    /// the cache's `Recompute` setting allows.
+    fn update_stats_on_access<P: AsRef<Path>>(&self, path: P) {
+        let last_access: usize = self.next_access_tick();
+        let last_accessed: Option<Instant> = if self.victim_comparator.is_some() { Some((self.clock)()) } else { None };
+        let recompute_every: usize = match self.priority_recompute {
+            Recompute::EveryAccess => 1,
+            Recompute::EveryN(n) => n,
+            Recompute::OnInsertOnly => usize::MAX,
+        };
+
+        let access_count: usize = match self.access_count_map.find(path.as_ref()) {
+            Some(access_count) => *access_count.get(),
+            None => 0,
+        };
+        if let Some(mut file_entry) = self.file_map.find_mut(path.as_ref()) {
+            let file_entry: &mut InMemoryFile = file_entry.get();
+            file_entry.stats.access_count = access_count;
+            file_entry.stats.last_access = last_access;
+            if last_accessed.is_some() {
+                file_entry.last_accessed = last_accessed;
+            }
+            file_entry.accesses_since_priority_update = usize::saturating_add(file_entry.accesses_since_priority_update, 1);
+            if file_entry.accesses_since_priority_update >= recompute_every {
+                file_entry.accesses_since_priority_update = 0;
+                file_entry.stats.priority = self.priority(file_entry.stats.access_count, file_entry.stats.size);
+            }
+        }
+    }
+
+    /// Increments the generation of the cache, returning the new generation.
+    fn next_generation(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::AcqRel).wrapping_add(1)
+    }
+
+    /// Gets the next value of the access clock, which is larger than any value handed out before it.
+    fn next_access_tick(&self) -> usize {
+        self.access_clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Update the stats associated with this file.
+    ///
+    /// The current access count is copied into the file's stats before its priority is recalculated,
+    /// so files gain priority as they keep being accessed.
+    /// A file that was never accessed has an access count of 0.
+    fn update_stats<P: AsRef<Path>>(&self, path: P) {
+
+        let access_count: usize = match self.access_count_map.find(path.as_ref()) {
+            Some(access_count) => access_count.get().clone(),
+            None => 0,
+        };
+
+        // Only files that are in the cache have stats to update.
+        // Creating a placeholder entry here would hold space that was never reserved.
+        if let Some(mut file_entry) = self.file_map.find_mut(path.as_ref()) {
+            let file_entry: &mut InMemoryFile = file_entry.get();
+            file_entry.stats.access_count = access_count;
+            file_entry.accesses_since_priority_update = 0;
+            file_entry.stats.priority = self.priority(file_entry.stats.access_count, file_entry.stats.size); // update the priority score.
+        }
+    }
+
+
+
+
+
+    /// Runs the priority function, clamping its result to `MAX_PRIORITY`.
+    fn priority(&self, access_count: usize, size: usize) -> usize {
+        cmp::min((self.priority_function)(access_count, size), MAX_PRIORITY)
+    }
+
+    /// Multiplies the priority of the file by the boosts it is under.
+    fn boosted_priority(&self, path: &Path, priority: usize) -> usize {
+        if self.boosts.is_empty() {
+            return priority;
+        }
+        self.boosts.apply(path, priority, (self.clock)())
+    }
+
+    /// The priority a file is evicted by.
+    ///
+    /// Stored priorities may be out of date if they aren't recalculated on every access,
+    /// so they are recalculated to base evictions on the current access counts.
+    fn eviction_priority(&self, stats: &FileStats) -> usize {
+        match self.priority_recompute {
+            Recompute::EveryAccess => stats.priority,
+            _ => self.priority(stats.access_count, stats.size),
+        }
+    }
+
+    /// Gets every file in the file_map that can be evicted, with the priority it is evicted by.
+    ///
+    /// The vector is sorted from highest to lowest priority, with ties ordered by the cache's tie breaker.
+    /// This allows the assumption that the last element to be popped from the vector will have the
+    /// lowest priority, and therefore is the most eligible candidate for elimination from the
+    /// cache.
+    ///
+    fn sorted_priorities(&self) -> Vec<CandidateFile> {
+        self.candidate_sorts.fetch_add(1, Ordering::Relaxed);
+        // The clock is read once for every file, and only if there are boosts.
+        let now: Option<Instant> = if self.boosts.is_empty() { None } else { Some((self.clock)()) };
+        let mut candidates: Vec<CandidateFile> = self.file_map
+            .iter()
+            .filter(|&(_, file)| !file.is_embedded()) // Embedded files can't be evicted.
+            .map(|(path, file)| {
+                let mut stats: FileStats = file.stats.clone();
+                stats.priority = self.eviction_priority(&stats);
+                if let Some(now) = now {
+                    stats.priority = self.boosts.apply(path, stats.priority, now);
+                }
+                // Stored files share their key, so it is only copied for a file that somehow has none.
+                let key: Arc<Path> = file.key.clone().unwrap_or_else(|| Arc::from(path.as_path()));
+                CandidateFile { key, stats, inserted_at: file.inserted_at, last_accessed: file.last_accessed }
+            })
+            .collect();
+        match self.victim_comparator {
+            Some(ref comparator) => policy::order_by_comparator(&mut candidates, comparator),
+            None => policy::order_for_eviction(&mut candidates, self.tie_breaker),
+        }
+        candidates
+    }
+}
+
+
+
+/// The marker a bound is recorded with in the classification filter.
+fn bound_marker(bound: ServeClass) -> u8 {
+    match bound {
+        ServeClass::FsTooSmall => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+
+    use self::tempdir::TempDir;
+    use std::io::{Write, BufWriter};
+    use std::fs::File;
+    use std::io::Read;
+    use std::sync::Arc;
+    use cache_builder::CacheBuilder;
+    use cold_tier::Compression;
+    use std::sync::{Barrier, Mutex};
+    use std::cell::RefCell;
+    use observer::CacheObserver;
+    use directory_floors::DirectoryFloorUsage;
+    use policy::VictimCandidate;
+    use serve_class::ServeClass;
+    use priority_function::{default_priority_function, default_tie_breaker};
+    use std::thread;
+    use alloc_counter::allocations_during;
+    use testing::{create_sized_file, read_all};
+    use replay::ReplayReport;
+    use manifest::ManifestError;
+
+
+    const MEG1: usize = 1024 * 1024;
+    const MEG2: usize = MEG1 * 2;
+    const MEG5: usize = MEG1 * 5;
+    const MEG10: usize = MEG1 * 10;
+
+    const DIR_TEST: &'static str = "test1";
+    const FILE_MEG1: &'static str = "meg1.txt";
+    const FILE_MEG2: &'static str = "meg2.txt";
+    const FILE_MEG5: &'static str = "meg5.txt";
+    const FILE_MEG10: &'static str = "meg10.txt";
+
+    // Helper function that creates a highly compressible test file.
+    fn create_compressible_test_file(temp_dir: &TempDir, size: usize, name: &str) -> PathBuf {
+        let path = temp_dir.path().join(name);
+        let tmp_file = File::create(path.clone()).unwrap();
+        let data: Vec<u8> = (0..size).map(|i| (i % 16) as u8).collect();
+        let mut buffer = BufWriter::new(tmp_file);
+        buffer.write_all(&data).unwrap();
+        path
+    }
+
+    // Gets the bytes the cache serves for the path, or None if the file isn't found.
+    fn served_bytes(cache: &Cache, path: &Path) -> Option<Vec<u8>> {
+        match cache.get_source(path) {
+            FileSource::Cached(c) => Some(c.file.get().bytes.as_slice().to_vec()),
+            FileSource::Fs(..) | FileSource::Detached(_) | FileSource::Deferred(_) => panic!("Expected the file to be served from the cache"),
+            FileSource::NotFound | FileSource::FileMissing => None,
+        }
+    }
+
+    // Standardize the way a file is used in these tests.
+    impl<'a> FileSource<'a> {
+        fn get_in_memory_file(self) -> NamedInMemoryFile<'a> {
+            match self {
+                FileSource::Cached(n) => n,
+                _ => panic!("tried to get cached file for named file"),
+
+            }
+        }
+
+        fn get_fs_file(self) -> File {
+            match self {
+                FileSource::Fs(file, _, _) => file,
+                _ =>  panic!("tried to get cached file for named file"),
+            }
+        }
+    }
+
+    /// Benchmarks need the unstable `test` crate, so they only run with the `nightly-bench` feature on nightly.
+    #[cfg(feature = "nightly-bench")]
+    mod benches {
+        extern crate test;
+
+        use super::*;
+        use self::test::Bencher;
+        use std::mem;
+
+        /// A priority function that is expensive to run.
+        fn slow_priority_function(access_count: usize, size: usize) -> usize {
+            (0..10_000).fold(0usize, |acc, i| test::black_box(acc.wrapping_add(i))) % 2 + access_count * size
+        }
+
+        fn bench_slow_priority_hits(b: &mut Bencher, priority_recompute: Recompute) {
+            let mut cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .priority_function(slow_priority_function)
+                .build()
+                .unwrap();
+            cache.set_priority_recompute(priority_recompute);
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+            cache.get_source(&path_1m);
+
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_1m);
+                test::black_box(&cached_file);
+            });
+        }
+
+        #[bench]
+        fn cache_hit_slow_priority_every_access(b: &mut Bencher) {
+            bench_slow_priority_hits(b, Recompute::EveryAccess);
+        }
+
+        #[bench]
+        fn cache_hit_slow_priority_every_100(b: &mut Bencher) {
+            bench_slow_priority_hits(b, Recompute::EveryN(100));
+        }
+
+        #[bench]
+        fn cache_get_10mb(b: &mut Bencher) {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .build()
+                .unwrap();
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_10m = create_sized_file(temp_dir.path(), MEG10, FILE_MEG10);
+            cache.get_source(&path_10m); // add the 10 mb file to the cache
+
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_10m);
+                read_all(cached_file)
+            });
+        }
+
+        #[bench]
+        fn cache_hit_without_reading(b: &mut Bencher) {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .build()
+                .unwrap();
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+            cache.get_source(&path_1m);
+
+            b.iter(|| {
+                cache.get_source(&path_1m);
+            });
+        }
+
+        #[bench]
+        fn cache_miss_10mb(b: &mut Bencher) {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .build()
+                .unwrap();
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_10m = create_sized_file(temp_dir.path(), MEG10, FILE_MEG10);
+
+            // Every file is read from the filesystem and inserted, then cleared so the next one misses too.
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_10m);
+                let bytes: Vec<u8> = read_all(cached_file);
+                assert_eq!(cache.clear(), 1);
+                bytes
+            });
+        }
+
+        #[bench]
+        fn named_file_read_10mb(b: &mut Bencher) {
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_10m = create_sized_file(temp_dir.path(), MEG10, FILE_MEG10);
+            b.iter(|| {
+                let named_file = FileSource::open(&path_10m).unwrap();
+                read_all(named_file)
+            });
+        }
+
+        #[bench]
+        fn cache_get_1mb(b: &mut Bencher) {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .build()
+                .unwrap(); //Cache can hold 20Mb
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+            cache.get_source(&path_1m); // add the 10 mb file to the cache
+
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_1m);
+                read_all(cached_file)
+            });
+        }
+
+        #[bench]
+        fn cache_miss_1mb(b: &mut Bencher) {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .build()
+                .unwrap();
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+
+            // Every file is read from the filesystem and inserted, then cleared so the next one misses too.
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_1m);
+                let bytes: Vec<u8> = read_all(cached_file);
+                assert_eq!(cache.clear(), 1);
+                bytes
+            });
+        }
+
+        #[bench]
+        fn named_file_read_1mb(b: &mut Bencher) {
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+
+            b.iter(|| {
+                let named_file = FileSource::open(&path_1m).unwrap();
+                read_all(named_file)
+            });
+        }
+
+
+
+        #[bench]
+        fn cache_get_5mb(b: &mut Bencher) {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .build()
+                .unwrap();
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+            cache.get_source(&path_5m); // add the 10 mb file to the cache
+
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_5m);
+                read_all(cached_file)
+            });
+        }
+
+        #[bench]
+        fn cache_miss_5mb(b: &mut Bencher) {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .build()
+                .unwrap();
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+
+            // Every file is read from the filesystem and inserted, then cleared so the next one misses too.
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_5m);
+                let bytes: Vec<u8> = read_all(cached_file);
+                assert_eq!(cache.clear(), 1);
+                bytes
+            });
+        }
+
+        #[bench]
+        fn named_file_read_5mb(b: &mut Bencher) {
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+
+            b.iter(|| {
+                let named_file = FileSource::open(&path_5m).unwrap();
+                read_all(named_file)
+            });
+        }
+
+
+
+        // Constant time access regardless of size.
+        #[bench]
+        fn cache_get_1mb_from_1000_entry_cache(b: &mut Bencher) {
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1 * 3)
+                .build()
+                .unwrap();
+            cache.get_source(&path_1m); // add the file to the cache
+
+            // Add 1024 1kib files to the cache.
+            for i in 0..1024 {
+                let path = create_sized_file(temp_dir.path(), 1024, format!("{}_1kib.txt", i).as_str());
+                cache.get_source(&path);
+            }
+            // make sure that the file has a high priority.
+            cache.alter_all_access_counts(|x| x + 100000);
+
+            assert_eq!(cache.used_bytes(), MEG1 * 2);
+
+            let named_file = FileSource::open(&path_1m).unwrap();
+
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_1m);
+                assert!(mem::discriminant(&cached_file) != mem::discriminant(&named_file));
+                read_all(cached_file)
+            });
+        }
+
+        // There is a penalty for missing the cache.
+        #[bench]
+        fn cache_miss_1mb_from_1000_entry_cache(b: &mut Bencher) {
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1)
+                .build()
+                .unwrap();
+
+            // Add 1024 1kib files to the cache.
+            for i in 0..1024 {
+                let path = create_sized_file(temp_dir.path(), 1024, format!("{}_1kib.txt", i).as_str());
+                cache.get_source(&path);
+            }
+            // make sure that the file has a high priority.
+            cache.alter_all_access_counts(|x| x + 100_000_000_000_000_000);
+            let named_file = FileSource::open(&path_1m).unwrap();
+
+            b.iter(|| {
+                let cached_file = cache.get_source(&path_1m);
+                assert!(mem::discriminant(&cached_file) == mem::discriminant(&named_file)); // get() in this case should only return files in the FS
+                read_all(cached_file)
+            });
+        }
+
+        // This is pretty much a worst-case scenario, where every file would try to be removed to make room for the new file.
+        // There is a penalty for missing the cache.
+        #[bench]
+        fn cache_miss_5mb_from_1000_entry_cache(b: &mut Bencher) {
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG1);
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(MEG5)
+                .build()
+                .unwrap();
+
+            // Add 1024 5kib files to the cache.
+            for i in 0..1024 {
+                let path = create_sized_file(temp_dir.path(), 1024 * 5, format!("{}_5kib.txt", i).as_str());
+                cache.get_source(&path);
+            }
+            // make sure that the file has a high priority.
+            cache.alter_all_access_counts(|x| x + 100_000_000_000_000_000);
+            let named_file = FileSource::open(&path_5m).unwrap();
+
+            b.iter(|| {
+                let cached_file: FileSource = cache.get_source(&path_5m);
+                // Mimic what is done when the response body is set.
+                assert!(mem::discriminant(&cached_file) == mem::discriminant(&named_file));  // get() in this case should only return files in the FS
+                read_all(cached_file)
+            });
+        }
+
+
+        #[bench]
+        fn in_memory_file_read_10mb(b: &mut Bencher) {
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path_10m = create_sized_file(temp_dir.path(), MEG10, FILE_MEG10);
+
+            b.iter(|| {
+                let in_memory_file: InMemoryFile = InMemoryFile::open(path_10m.clone()).unwrap();
+                let mut v: Vec<u8> = Vec::new();
+                in_memory_file.bytes.as_slice().read_to_end(&mut v).unwrap();
+                v
+            });
+        }
+    }
+
+
+    #[test]
+    fn file_exceeds_size_limit() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 8) // Cache can hold only 8Mb
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_10m = create_sized_file(temp_dir.path(), MEG10, FILE_MEG10);
+
+        let named_file = FileSource::open(path_10m.clone()).unwrap();
+
+        // expect the cache to get the item from the FS.
+        assert_eq!(cache.try_insert(path_10m, &GetOptions::new()).0, named_file);
+    }
+
+
+    #[test]
+    fn file_replaces_other_file() {
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+
+        let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+
+        let named_file_1m = FileSource::open(path_1m.clone()).unwrap();
+        let named_file_1m_2 = FileSource::open(path_1m.clone()).unwrap();
+
+
+        let mut imf_5m = InMemoryFile::open(path_5m.clone()).unwrap();
+        let mut imf_1m = InMemoryFile::open(path_1m.clone()).unwrap();
+
+        // set expected stats for 5m
+        imf_5m.stats.access_count = 1;
+        imf_5m.stats.priority = 2289;
+        imf_5m.stats.last_access = 1;
+
+
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(5500000) //Cache can hold only about 5.5Mib
+            .build()
+            .unwrap();
+
+        println!("0:\n{:#?}", cache);
+
+        assert_eq!(
+            cache
+                .try_insert(path_5m.clone(), &GetOptions::new()).0
+                .get_in_memory_file()
+                .file
+                .get(),
+            &imf_5m
+        );
+        println!("1:\n{:#?}", cache);
+        assert_eq!(
+            cache.try_insert(path_1m.clone(), &GetOptions::new()).0,
+            named_file_1m
+        );
+        println!("2:\n{:#?}", cache);
+        assert_eq!(
+            cache.try_insert(path_1m.clone(), &GetOptions::new()).0,
+            named_file_1m_2
+        );
+        println!("3:\n{:#?}", cache);
+
+        // set the expected stats for 1m
+        imf_1m.stats.access_count = 3;
+        imf_1m.stats.priority = 3072;
+        imf_1m.stats.last_access = 2;
+
+        assert_eq!(
+            cache
+                .try_insert(path_1m.clone(), &GetOptions::new()).0
+                .get_in_memory_file()
+                .file
+                .get(),
+            &imf_1m
+        );
+        println!("4:\n{:#?}", cache);
+    }
+
+
+
+
+    #[test]
+    fn new_file_replaces_lowest_priority_file() {
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+
+
+        #[allow(unused_variables)]
+        let named_file_1m = FileSource::open(path_1m.clone()).unwrap();
+
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 7 + 2000) // cache can hold a little more than 7MB
+            .build()
+            .unwrap();
+
+        println!("1:\n{:#?}", cache);
+        let mut imf_5m: InMemoryFile = InMemoryFile::open(path_5m.clone()).unwrap();
+        imf_5m.stats.priority = 2289;
+        imf_5m.stats.access_count = 1;
+        imf_5m.stats.last_access = 1;
+
+        assert_eq!(
+            cache.get_source(&path_5m)
+               .get_in_memory_file()
+               .file
+               .get(),
+            &imf_5m
+        );
+
+        println!("2:\n{:#?}", cache);
+        let mut imf_2m: InMemoryFile = InMemoryFile::open(path_2m.clone()).unwrap();
+        imf_2m.stats.priority = 1448;
+        imf_2m.stats.access_count = 1;
+        imf_2m.stats.last_access = 2;
+        assert_eq!(
+            cache.get_source(&path_2m)
+                .get_in_memory_file()
+                .file
+                .get(),
+            &imf_2m
+        );
+
+
+        println!("3:\n{:#?}", cache);
+        let mut named_1m = File::open(path_1m.clone()).unwrap();
+        let mut v: Vec<u8> = Vec::new();
+        let _ = cache
+            .get_source(&path_1m)
+            .get_fs_file()
+            .read_to_end(&mut v)
+            .unwrap();
+
+        let mut file_vec: Vec<u8> = Vec::new();
+        let _ = named_1m.read_to_end(&mut file_vec);
+        assert_eq!(
+            v,
+            file_vec
+        );
+
+
+        println!("4:\n{:#?}", cache);
+        let mut imf_1m: InMemoryFile = InMemoryFile::open(path_1m.clone()).unwrap();
+        imf_1m.stats.priority = 2048; // This priority is higher than the in memory file - 2m's 1448, and therefore will replace it now
+        imf_1m.stats.access_count = 1;
+
+        // The cache will now accept the 1 meg file because (sqrt(2)_size * 1_access) for the old
+        // file is less than (sqrt(1)_size * 2_access) for the new file.
+        assert_eq!(
+            cache.get_source(&path_1m)
+                .get_in_memory_file()
+                .file
+                .get()
+                .bytes,
+            imf_1m.bytes
+        );
+        println!("5:\n{:#?}", cache);
+
+
+        if let FileSource::NotFound = cache.get_from_cache(&path_1m) {
+            panic!("Expected 1m file to be in the cache");
+        }
+
+        // Check if the 5m file is still in the cache
+        if let FileSource::NotFound = cache.get_from_cache(&path_5m) {
+            panic!("Expected 5m file to be in the cache");
+        }
+
+        //
+        if let FileSource::Cached(_) = cache.get_from_cache(&path_2m) {
+            panic!("Expected 2m file to not be in the cache");
+        }
+
+        drop(cache);
+    }
+
+
+
+
+    #[test]
+    fn remove_file() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+
+        let mut imf: InMemoryFile = InMemoryFile::open(path_5m.clone()).unwrap();
+
+        // Set the expected values for the stats in IMF.
+        imf.stats.priority = 2289;
+        imf.stats.access_count = 1;
+        imf.stats.last_access = 1;
+
+        // expect the cache to get the item from the FS.
+        assert_eq!(
+            cache
+                .get_source(&path_5m)
+                .get_in_memory_file()
+                .file
+                .get(),
+            &imf
+        );
+
+        cache.remove(&path_5m);
+
+        assert_eq!(cache.contains_key(&path_5m.clone()), false);
+    }
+
+    #[test]
+    fn refresh_file() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+
+
+        assert_eq!(
+            match cache.get_source(&path_5m) {
+                FileSource::Cached(c) => c.file.get().stats.size,
+                FileSource::Fs(..) | FileSource::Detached(_) | FileSource::Deferred(_) => unreachable!(),
+                FileSource::NotFound | FileSource::FileMissing => unreachable!()
+            },
+            MEG5
+        );
+
+        let path_of_file_with_10mb_but_path_name_5m = create_sized_file(temp_dir.path(), MEG10, FILE_MEG5);
+
+
+        cache.refresh_source(&path_5m);
+
+        assert_eq!(
+            match cache.get_source(&path_of_file_with_10mb_but_path_name_5m) {
+                FileSource::Cached(c) => c.file.get().stats.size,
+                FileSource::Fs(..) | FileSource::Detached(_) | FileSource::Deferred(_) => unreachable!(),
+                FileSource::NotFound | FileSource::FileMissing => unreachable!()
+            },
+            MEG10
+        );
+
+        drop(cache);
+    }
+
+
+    #[test]
+    fn refresh_preserves_or_resets_access_count() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 20)
+            .build()
+            .unwrap();
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        for _ in 0..3 {
+            cache.get_source(&path_1m);
+            cache.get_source(&path_2m);
+        }
+        let access_count: usize = cache.stats_for(&path_1m).unwrap().access_count;
+        assert_eq!(cache.stats_for(&path_2m).unwrap().access_count, access_count);
+
+        // Both files grow on disk.
+        create_sized_file(temp_dir.path(), MEG5, FILE_MEG1);
+        create_sized_file(temp_dir.path(), MEG5, FILE_MEG2);
+
+        cache.refresh_source(&path_1m);
+        let stats: FileStats = cache.stats_for(&path_1m).unwrap();
+        assert_eq!(stats.size, MEG5);
+        assert_eq!(stats.access_count, access_count);
+        assert_eq!(stats.priority, (cache.priority_function)(access_count, MEG5));
+
+        cache.refresh_source_with(&path_2m, RefreshOptions::new().reset_access_count(true));
+        let stats: FileStats = cache.stats_for(&path_2m).unwrap();
+        assert_eq!(stats.size, MEG5);
+        assert_eq!(stats.access_count, 0);
+        assert_eq!(stats.priority, (cache.priority_function)(0, MEG5));
+
+        // The reset count is what later accesses build on.
+        cache.get_source(&path_2m);
+        assert_eq!(cache.stats_for(&path_2m).unwrap().access_count, 1);
+    }
+
+    #[test]
+    fn refresh_can_keep_the_old_priority() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 20)
+            .build()
+            .unwrap();
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        cache.get_source(&path_1m);
+        cache.get_source(&path_1m);
+        let old_stats: FileStats = cache.stats_for(&path_1m).unwrap();
+
+        create_sized_file(temp_dir.path(), MEG5, FILE_MEG1);
+        cache.refresh_source_with(&path_1m, RefreshOptions::new().recompute_priority(false));
+        let stats: FileStats = cache.stats_for(&path_1m).unwrap();
+        assert_eq!(stats.size, MEG5);
+        assert_eq!(stats.access_count, old_stats.access_count);
+        assert_eq!(stats.priority, old_stats.priority);
+    }
+
+
+    #[test]
+    fn updated_bytes_replace_the_cached_file() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_hot = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        let path_cold = create_sized_file(temp_dir.path(), MEG2, FILE_MEG1);
+        let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+        for _ in 0..10 {
+            cache.get_source(&path_hot);
+        }
+        cache.get_source(&path_cold);
+        cache.get_source(&path_5m);
+        assert_eq!(cache.used_bytes(), MEG1 * 9);
+
+        let hash: Option<String> = cache.content_hash(&path_hot);
+        let modified: Option<SystemTime> = cache.last_modified(&path_hot);
+        assert!(modified.is_some());
+
+        // Growing by 2 MB with 1 MB free evicts the coldest file.
+        assert_eq!(cache.update_bytes(&path_hot, vec![1; MEG1 * 4]), Ok(()));
+        assert!(!cache.contains_key(&path_cold));
+        assert!(cache.contains_key(&path_5m));
+        assert_eq!(cache.used_bytes(), MEG1 * 9);
+        let stats: FileStats = cache.stats_for(&path_hot).unwrap();
+        assert_eq!(stats.size, MEG1 * 4);
+        assert_eq!(stats.access_count, 10);
+        assert_eq!(stats.priority, (cache.priority_function)(10, MEG1 * 4));
+        assert_ne!(cache.content_hash(&path_hot), hash);
+        assert_ne!(cache.last_modified(&path_hot), modified);
+        match cache.get_source(&path_hot) {
+            FileSource::Cached(c) => assert_eq!(c.file.get().bytes.as_slice(), &vec![1; MEG1 * 4][..]),
+            _ => unreachable!(),
+        }
+
+        // Making room for the cold file to grow would evict the hot file, so the update is rejected.
+        assert_eq!(cache.update_bytes(&path_5m, vec![1; MEG1 * 7]), Err(CacheError::NewPriorityIsNotHighEnough));
+        assert_eq!(cache.stats_for(&path_5m).unwrap().size, MEG5);
+        assert_eq!(cache.stats_for(&path_hot).unwrap().size, MEG1 * 4);
+        assert_eq!(cache.used_bytes(), MEG1 * 9);
+
+        // Shrinking always fits.
+        assert_eq!(cache.update_bytes(&path_5m, vec![1; MEG1]), Ok(()));
+        assert_eq!(cache.used_bytes(), MEG1 * 5);
+
+        assert_eq!(cache.update_bytes(&path_cold, vec![1; MEG1]), Err(CacheError::NotCached));
+        assert!(!cache.contains_key(&path_cold));
+        assert_eq!(cache.used_bytes(), MEG1 * 5);
+    }
+
+    #[test]
+    fn updated_bytes_are_not_revalidated_against_the_filesystem() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .accesses_per_refresh(2)
+            .build()
+            .unwrap();
+        cache.set_consistency(Consistency::Paranoid);
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        cache.get_source(&path);
+
+        assert_eq!(cache.update_bytes(&path, vec![1; 1000]), Ok(()));
+        assert!(!cache.soft_purge(&path));
+        for _ in 0..4 {
+            match cache.get_source(&path) {
+                FileSource::Cached(c) => assert_eq!(c.file.get().bytes.as_slice(), &vec![1; 1000][..]),
+                _ => panic!("Expected the pushed bytes to be served from the cache."),
+            }
+        }
+        assert!(cache.verify().is_consistent());
+
+        // Refreshing the file explicitly reads it from the filesystem again.
+        cache.refresh_source(&path);
+        assert_eq!(cache.stats_for(&path).unwrap().size, MEG1);
+        assert!(cache.soft_purge(&path));
+    }
+
+
+    #[test]
+    fn directory_floors_keep_files_in_the_cache() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        fs::create_dir_all(temp_dir.path().join("critical/inner")).unwrap();
+        let critical: PathBuf = temp_dir.path().join("critical");
+        let inner: PathBuf = critical.join("inner");
+        cache.set_directory_floor(critical.clone(), MEG1);
+        cache.set_directory_floor(inner.clone(), MEG1);
+
+        let outer_files: Vec<PathBuf> = vec![
+            create_sized_file(temp_dir.path(), MEG1, "critical/a.txt"),
+            create_sized_file(temp_dir.path(), MEG1, "critical/b.txt"),
+        ];
+        let inner_files: Vec<PathBuf> = vec![
+            create_sized_file(temp_dir.path(), MEG1, "critical/inner/c.txt"),
+            create_sized_file(temp_dir.path(), MEG1, "critical/inner/d.txt"),
+        ];
+        for path in outer_files.iter().chain(inner_files.iter()) {
+            cache.get_source(path);
+        }
+
+        // Flood the cache with files that are accessed more often than the protected ones.
+        for i in 0..10 {
+            let path = create_sized_file(temp_dir.path(), MEG1, &format!("other{}.txt", i));
+            for _ in 0..3 {
+                cache.get_source(&path);
+            }
+        }
+
+        // The files under the inner floor don't count towards the outer one, so each floor keeps one file.
+        assert_eq!(outer_files.iter().filter(|path| cache.contains_key(path)).count(), 1);
+        assert_eq!(inner_files.iter().filter(|path| cache.contains_key(path)).count(), 1);
+        assert_eq!(cache.used_bytes(), MEG1 * 10);
+
+        let floors: Vec<DirectoryFloorUsage> = cache.summary().directory_floors;
+        assert_eq!(
+            floors,
+            vec![
+                DirectoryFloorUsage { prefix: critical, min_bytes: MEG1, resident_bytes: MEG1, protected_bytes: MEG1 },
+                DirectoryFloorUsage { prefix: inner, min_bytes: MEG1, resident_bytes: MEG1, protected_bytes: MEG1 },
+            ]
+        );
+    }
+
+
+    #[test]
+    fn directory_quotas_only_displace_siblings() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        fs::create_dir_all(temp_dir.path().join("thumbnails")).unwrap();
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+        cache.get_source(&path_2m);
+        cache.get_source(&path_5m);
+        cache.set_directory_quota(temp_dir.path().join("thumbnails"), MEG2);
+
+        let thumbnails: Vec<PathBuf> = (0..4)
+            .map(|i| create_sized_file(temp_dir.path(), MEG1, &format!("thumbnails/{}.png", i)))
+            .collect();
+        for _ in 0..3 {
+            cache.get_source(&thumbnails[0]);
+            cache.get_source(&thumbnails[1]);
+        }
+        assert_eq!(cache.used_bytes(), MEG1 * 9);
+
+        // The cache has room, but the quota doesn't, and the siblings are accessed more often.
+        match cache.get_source(&thumbnails[2]) {
+            FileSource::Fs(..) => {}
+            _ => panic!("The file should have been served from the filesystem."),
+        }
+        match cache.check(&thumbnails[2]) {
+            AdmissionPrediction::Rejected(error) => assert_eq!(error, CacheError::NewPriorityIsNotHighEnough),
+            _ => panic!("The file should not be admitted."),
+        }
+        cache.get_source(&thumbnails[2]);
+        cache.get_source(&thumbnails[2]);
+        assert!(cache.contains_key(&thumbnails[2]));
+        assert_eq!(thumbnails.iter().filter(|path| cache.contains_key(path)).count(), 2);
+
+        // Files outside of the quota are never evicted for files under it.
+        for _ in 0..10 {
+            cache.get_source(&thumbnails[3]);
+        }
+        assert!(cache.contains_key(&thumbnails[3]));
+        assert_eq!(thumbnails.iter().filter(|path| cache.contains_key(path)).count(), 2);
+        assert!(cache.contains_key(&path_2m));
+        assert!(cache.contains_key(&path_5m));
+        assert_eq!(cache.used_bytes(), MEG1 * 9);
+
+        // A file that doesn't fit into its quota at all is never stored.
+        fs::create_dir_all(temp_dir.path().join("icons")).unwrap();
+        cache.set_directory_quota(temp_dir.path().join("icons"), MEG1 / 2);
+        let icon = create_sized_file(temp_dir.path(), MEG1, "icons/0.png");
+        assert_eq!(cache.insert(&icon), Err(CacheError::DirectoryQuotaExceeded));
+        assert_eq!(cache.used_bytes(), MEG1 * 9);
+    }
+
+
+    #[test]
+    fn served_files_are_classified() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 6)
+            .min_file_size(1024)
+            .max_file_size(MEG5)
+            .build()
+            .unwrap();
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_5m = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        let path_10m = create_sized_file(temp_dir.path(), MEG10, FILE_MEG10);
+        let path_small = create_sized_file(temp_dir.path(), 10, "small.txt");
+
+        let classify = |path: &PathBuf, options: &GetOptions| cache.get_source_classified_with(path, options).map(|(_, class)| class);
+        let no_options: GetOptions = GetOptions::new();
+
+        assert_eq!(classify(&path_5m, &no_options), Some(ServeClass::Inserted));
+        assert_eq!(classify(&path_5m, &no_options), Some(ServeClass::Hit));
+        assert_eq!(classify(&path_10m, &no_options), Some(ServeClass::FsTooLarge));
+        // Files remembered to be out of bounds keep their class.
+        assert_eq!(classify(&path_10m, &no_options), Some(ServeClass::FsTooLarge));
+        assert_eq!(classify(&path_small, &no_options), Some(ServeClass::FsTooSmall));
+        assert_eq!(classify(&path_small, &no_options), Some(ServeClass::FsTooSmall));
+        assert_eq!(classify(&path_2m, GetOptions::new().skip_insert_above(MEG1)), Some(ServeClass::FsSkipped));
+        assert_eq!(classify(&path_2m, GetOptions::new().max_eviction_candidates(0)), Some(ServeClass::FsEvictionBudget));
+        // The 2 MB file now has 3 accesses, still fewer than the 5 MB file is worth.
+        assert_eq!(classify(&path_2m, &no_options), Some(ServeClass::FsPriorityTooLow));
+        assert_eq!(classify(&temp_dir.path().join("missing.txt"), &no_options), None);
+
+        cache.set_frozen(true);
+        assert_eq!(classify(&path_2m, &no_options), Some(ServeClass::FsManual));
+        assert_eq!(classify(&path_5m, &no_options), Some(ServeClass::Hit));
+    }
+
+    #[test]
+    fn files_rejected_by_a_limit_or_in_manual_mode_have_their_own_class() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG5)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        fs::create_dir_all(temp_dir.path().join("icons")).unwrap();
+        cache.set_directory_quota(temp_dir.path().join("icons"), MEG1 / 2);
+        let icon = create_sized_file(temp_dir.path(), MEG1, "icons/0.png");
+        let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+
+        assert_eq!(cache.get_source_classified(&icon).map(|(_, class)| class), Some(ServeClass::FsRejected));
+
+        cache.set_mode(Mode::Manual);
+        assert_eq!(cache.get_source_classified(&path_1m).map(|(_, class)| class), Some(ServeClass::FsManual));
+        assert!(!cache.contains_key(&path_1m));
+    }
+
+
+    #[test]
+    fn debug_output_elides_file_contents() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG10)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = temp_dir.path().join(FILE_MEG1);
+        fs::write(&path, vec![b'z'; MEG1]).unwrap();
+        cache.get_source(&path);
+
+        let debug: String = format!("{:#?}", cache);
+        assert!(debug.len() < 1024, "The debug output is {} bytes long", debug.len());
+        assert!(debug.contains(&MEG1.to_string()));
+        assert!(debug.contains(FILE_MEG1));
+        assert!(!debug.contains("zzzz"));
+    }
+
+    #[test]
+    fn churn_is_reported_for_files_that_thrash() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = temp_dir.path().join("a.txt");
+        let path_b = temp_dir.path().join("b.txt");
+        fs::write(&path_a, vec![b'a'; MEG1]).unwrap();
+        fs::write(&path_b, vec![b'b'; MEG1]).unwrap();
+
+        // Only one of the files fits, so each one displaces the other once it has been requested more often.
+        let request_until_cached = |path: &PathBuf| {
+            for _ in 0..10 {
+                if cache.contains_key(path) {
+                    return;
+                }
+                cache.get_source(path);
+            }
+            panic!("{:?} was never inserted", path);
+        };
+        for path in &[&path_a, &path_b, &path_a, &path_b, &path_a] {
+            request_until_cached(path);
+        }
+
+        assert_eq!(cache.churn_report(1), vec![(path_a.clone(), 2), (path_b.clone(), 1)]);
+        assert_eq!(cache.churn_report(2), vec![(path_a.clone(), 2)]);
+        // The first eviction of each file doesn't count, as the file had only been inserted once.
+        assert_eq!(cache.summary().evictions_of_previously_resident, 2);
+
+        cache.reset_churn();
+        assert!(cache.churn_report(0).is_empty());
+        assert_eq!(cache.summary().evictions_of_previously_resident, 0);
+    }
+
+    thread_local! {
+        static FAKE_NOW: ::std::cell::Cell<Option<Instant>> = const { ::std::cell::Cell::new(None) };
+    }
+
+    /// A clock that only moves when the test moves it.
+    fn fake_clock() -> Instant {
+        FAKE_NOW.with(|now| now.get()).expect("The fake clock wasn't set")
+    }
+
+    #[test]
+    fn thrashing_files_are_kept_out_until_the_cooldown_passes() {
+        let start: Instant = Instant::now();
+        FAKE_NOW.with(|now| now.set(Some(start)));
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        cache.clock = fake_clock;
+        cache.set_thrash_protection(Some(ThrashConfig {
+            cycles: 2,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(300),
+        }));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = temp_dir.path().join("a.txt");
+        let path_b = temp_dir.path().join("b.txt");
+        fs::write(&path_a, vec![b'a'; MEG1]).unwrap();
+        fs::write(&path_b, vec![b'b'; MEG1]).unwrap();
+
+        let request_until_cached = |path: &PathBuf| {
+            for _ in 0..10 {
+                if cache.contains_key(path) {
+                    return;
+                }
+                cache.get_source(path);
+            }
+            panic!("{:?} was never inserted", path);
+        };
+        // The file is penalized once it comes back for the second time, but it isn't evicted for it.
+        for path in &[&path_a, &path_b, &path_a, &path_b, &path_a] {
+            request_until_cached(path);
+        }
+        assert_eq!(cache.penalized_paths(), vec![(path_a.clone(), Duration::from_secs(300))]);
+
+        // Once evicted, the penalized file is served from the filesystem for the whole cooldown.
+        request_until_cached(&path_b);
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(299))));
+        for _ in 0..5 {
+            let class: Option<ServeClass> = cache.get_source_classified(&path_a).map(|(_, class)| class);
+            assert_eq!(class, Some(ServeClass::FsThrashing));
+        }
+        assert!(!cache.contains_key(&path_a));
+        assert_eq!(cache.check(&path_a), AdmissionPrediction::Rejected(CacheError::Thrashing));
+
+        // After the cooldown, the file can be inserted again.
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(300))));
+        assert!(cache.penalized_paths().is_empty());
+        request_until_cached(&path_a);
+    }
+
+    #[test]
+    fn the_generation_changes_once_for_every_change_to_the_files() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(2500)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        let path_b = create_sized_file(temp_dir.path(), 1000, "b.txt");
+        let path_c = create_sized_file(temp_dir.path(), 1000, "c.txt");
+        assert_eq!(cache.generation(), 0);
+
+        cache.get_source(&path_a);
+        assert_eq!(cache.generation(), 1);
+        assert_eq!(cache.path_generation(&path_a), Some(1));
+        // Hits don't change anything.
+        cache.get_source(&path_a);
+        cache.get_source(&path_a);
+        assert_eq!(cache.generation(), 1);
+
+        cache.get_source(&path_b);
+        assert_eq!(cache.generation(), 2);
+        // Inserting the third file evicts the second, which changes the generation twice.
+        cache.get_source(&path_c);
+        assert!(!cache.contains_key(&path_b));
+        assert_eq!(cache.generation(), 4);
+        assert_eq!(cache.path_generation(&path_c), Some(4));
+
+        cache.refresh_source(&path_a);
+        assert_eq!(cache.generation(), 5);
+        assert_eq!(cache.path_generation(&path_a), Some(5));
+        assert!(cache.remove(&path_c));
+        assert!(!cache.remove(&path_c));
+        assert_eq!(cache.generation(), 6);
+        assert_eq!(cache.clear(), 1);
+        assert_eq!(cache.generation(), 7);
+        assert_eq!(cache.path_generation(&path_a), None);
+    }
+
+    #[test]
+    fn removed_and_blocked_files_are_kept_out_until_the_block_ends() {
+        let start: Instant = Instant::now();
+        FAKE_NOW.with(|now| now.set(Some(start)));
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        cache.clock = fake_clock;
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        let path_b = create_sized_file(temp_dir.path(), 1000, "b.txt");
+        cache.get_source(&path_a);
+        cache.get_source(&path_b);
+
+        // A get right after the purge is served from the filesystem, and doesn't put the file back.
+        assert!(cache.remove_and_block(&path_a, Duration::from_secs(10)));
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(9))));
+        for _ in 0..3 {
+            let class: Option<ServeClass> = cache.get_source_classified(&path_a).map(|(_, class)| class);
+            assert_eq!(class, Some(ServeClass::FsBlocked));
+        }
+        assert!(!cache.contains_key(&path_a));
+        assert_eq!(cache.check(&path_a), AdmissionPrediction::Rejected(CacheError::Blocked));
+
+        // Once the block ends, the file is cached again.
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(10))));
+        cache.get_source(&path_a);
+        assert!(cache.contains_key(&path_a));
+
+        // Blocks can be ended early.
+        assert!(!cache.unblock(&path_a));
+        assert!(!cache.remove_and_block(temp_dir.path().join("missing.txt"), Duration::from_secs(10)));
+        cache.remove_and_block(&path_b, Duration::from_secs(10));
+        assert!(cache.unblock(&path_b));
+        cache.get_source(&path_b);
+        assert!(cache.contains_key(&path_b));
+
+        // A block too long to be represented never ends.
+        assert!(cache.remove_and_block(&path_b, Duration::from_secs(u64::MAX)));
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(60 * 60 * 24 * 365 * 100))));
+        assert_eq!(cache.get_source_classified(&path_b).map(|(_, class)| class), Some(ServeClass::FsBlocked));
+    }
+
+    #[test]
+    fn files_are_spared_from_eviction_until_they_have_been_resident_long_enough() {
+        let start: Instant = Instant::now();
+        FAKE_NOW.with(|now| now.set(Some(start)));
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(2200)
+            .build()
+            .unwrap();
+        cache.clock = fake_clock;
+        cache.set_min_residency(Some(Duration::from_secs(30)));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        let path_b = create_sized_file(temp_dir.path(), 400, "b.txt");
+        let path_c = create_sized_file(temp_dir.path(), 1000, "c.txt");
+        let path_d = create_sized_file(temp_dir.path(), 1000, "d.txt");
+        cache.get_source(&path_a);
+
+        // The new file evicts the settled file, even though the file that was only just inserted has a lower priority.
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(60))));
+        cache.get_source(&path_b);
+        cache.get_source(&path_c);
+        assert!(cache.contains_key(&path_b));
+        assert!(cache.contains_key(&path_c));
+        assert!(!cache.contains_key(&path_a));
+
+        // Once it has been resident long enough, it is evicted like any other file.
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(90))));
+        cache.get_source(&path_d);
+        assert!(!cache.contains_key(&path_b));
+        assert!(cache.contains_key(&path_c));
+        assert!(cache.contains_key(&path_d));
+    }
+
+    #[test]
+    fn misses_fail_fast_without_evicting_while_the_filesystem_keeps_failing() {
+        let start: Instant = Instant::now();
+        FAKE_NOW.with(|now| now.set(Some(start)));
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(1500)
+            .build()
+            .unwrap();
+        cache.clock = fake_clock;
+        cache.set_degraded_mode(Some(DegradedModeConfig {
+            errors: 3,
+            backoff: Duration::from_secs(10),
+        }));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let healthy_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        let path_b = create_sized_file(healthy_dir.path(), 1000, "b.txt");
+        cache.get_source(&path_a);
+        fs::remove_dir_all(temp_dir.path()).unwrap();
+
+        for name in &["x.txt", "y.txt", "z.txt"] {
+            cache.get_source(temp_dir.path().join(name));
+        }
+        assert!(cache.is_degraded());
+        assert!(cache.summary().degraded);
+
+        // Misses neither read anything nor evict anything, but the cached file is still served.
+        let metadata_reads: usize = cache.metadata_reads.load(Ordering::Relaxed);
+        let class: Option<ServeClass> = cache.get_source_classified(&path_b).map(|(_, class)| class);
+        assert_eq!(class, None);
+        assert_eq!(cache.metadata_reads.load(Ordering::Relaxed), metadata_reads);
+        assert!(cache.contains_key(&path_a));
+        match cache.get_source(&path_a) {
+            FileSource::Cached(_) => {}
+            _ => panic!("Expected the cached file to be served while degraded."),
+        }
+
+        // A failed probe backs off again, and a successful one ends the degraded state.
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(10))));
+        cache.get_source(temp_dir.path().join("x.txt"));
+        assert!(cache.is_degraded());
+        cache.get_source(&path_b);
+        assert!(!cache.contains_key(&path_b));
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(20))));
+        cache.get_source(&path_b);
+        assert!(!cache.is_degraded());
+        // The cached file was hit while degraded, so the new file needs another access to replace it.
+        cache.get_source(&path_b);
+        assert!(cache.contains_key(&path_b));
+        assert!(!cache.contains_key(&path_a));
+    }
+
+    #[test]
+    fn files_requested_together_share_one_sort_of_the_eviction_candidates() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(3000)
+            .max_file_size(1500)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_hit = create_sized_file(temp_dir.path(), 1000, "hit.txt");
+        let path_old_1 = create_sized_file(temp_dir.path(), 1000, "old_1.txt");
+        let path_old_2 = create_sized_file(temp_dir.path(), 1000, "old_2.txt");
+        let path_new_1 = create_sized_file(temp_dir.path(), 1000, "new_1.txt");
+        let path_too_large = create_sized_file(temp_dir.path(), 2000, "too_large.txt");
+        let path_new_2 = create_sized_file(temp_dir.path(), 1000, "new_2.txt");
+        for _ in 0..3 {
+            cache.get_source(&path_hit);
+        }
+        cache.get_source(&path_old_1);
+        cache.get_source(&path_old_2);
+        let sorts: usize = cache.candidate_sorts.load(Ordering::Relaxed);
+
+        let paths: Vec<PathBuf> = vec![path_hit.clone(), path_new_1.clone(), path_too_large.clone(), path_new_2.clone()];
+        {
+            let files: Vec<FileSource> = cache.get_source_many(&paths);
+            match files[0] {
+                FileSource::Cached(ref file) => assert_eq!(&*file.path, path_hit.as_path()),
+                _ => panic!("Expected the hit to be served from the cache."),
+            }
+            match files[1] {
+                FileSource::Cached(ref file) => assert_eq!(&*file.path, path_new_1.as_path()),
+                _ => panic!("Expected the new file to be inserted."),
+            }
+            match files[2] {
+                FileSource::Fs(_, ref path, _) => assert_eq!(path, &path_too_large),
+                _ => panic!("Expected the large file to be served from the filesystem."),
+            }
+            match files[3] {
+                FileSource::Cached(ref file) => assert_eq!(&*file.path, path_new_2.as_path()),
+                _ => panic!("Expected the new file to be inserted."),
+            }
+        }
+
+        // Both new files evicted a file, but the candidates were only sorted once.
+        assert_eq!(cache.candidate_sorts.load(Ordering::Relaxed), sorts + 1);
+        assert!(!cache.contains_key(&path_old_1));
+        assert!(!cache.contains_key(&path_old_2));
+
+        // Outside of a batch, every file that needs room sorts the candidates again.
+        cache.get_source(&path_old_1);
+        cache.get_source(&path_old_1);
+        assert_eq!(cache.candidate_sorts.load(Ordering::Relaxed), sorts + 2);
+    }
+
+    #[test]
+    fn small_file_slabs_dont_grow_while_small_files_come_and_go() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        cache.set_small_file_arena(Some(SmallFileArenaConfig {
+            threshold: 1000,
+            slab_size: 2000,
+        }));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let mut kept: Vec<PathBuf> = vec![];
+        for round in 0..30 {
+            let paths: Vec<PathBuf> = (0..5)
+                .map(|i| create_sized_file(temp_dir.path(), 400, &format!("{}_{}.txt", round, i)))
+                .collect();
+            for path in &paths {
+                cache.get_source(path);
+                assert_eq!(cache.get_source(path).bytes(), Some(fs::read(path).unwrap().as_slice()));
+            }
+            // One file of every round stays, so every slab would stay alive if files weren't moved out of sparse slabs.
+            for path in &paths[1..] {
+                cache.remove(path);
+            }
+            kept.push(paths[0].clone());
+
+            let summary: CacheSummary = cache.summary();
+            assert_eq!(summary.small_file_live_bytes, kept.len() * 400);
+            assert!(summary.small_file_slab_bytes <= 2 * summary.small_file_live_bytes + 3 * 2000);
+            assert_eq!(cache.used_bytes(), summary.used_bytes);
+        }
+
+        // The files that were moved are still served whole.
+        for path in &kept {
+            assert_eq!(cache.get_source(path).bytes(), Some(fs::read(path).unwrap().as_slice()));
+        }
+        assert!(cache.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn served_bytes_are_counted_by_where_they_were_served_from() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(2500)
+            .build()
+            .unwrap();
+        cache.set_served_bytes_by_path(Some(3));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        let path_b = create_sized_file(temp_dir.path(), 1000, "b.txt");
+        let path_large = create_sized_file(temp_dir.path(), 5000, "large.txt");
+
+        cache.get_source(&path_a); // Inserted, and served from the cache.
+        cache.get_source(&path_a); // Hit.
+        cache.get_source(&path_large); // Too large for the cache.
+        cache.get_source_classified(&path_large);
+        cache.get_source(&path_b);
+        cache.get_source(temp_dir.path().join("missing.txt"));
+
+        let summary: CacheSummary = cache.summary();
+        assert_eq!(summary.bytes_served_from_cache, 3000);
+        assert_eq!(summary.bytes_served_from_fs, 10_000);
+        assert_eq!(cache.path_metrics(&path_a), PathMetrics {
+            access_count: 2,
+            bytes_served_from_cache: 2000,
+            bytes_served_from_fs: 0,
+        });
+        assert_eq!(cache.path_metrics(&path_large).bytes_served_from_fs, 10_000);
+        assert_eq!(cache.path_metrics(temp_dir.path().join("missing.txt")).bytes_served_from_cache, 0);
+
+        // Once the limit is reached, other paths are only counted in the totals.
+        let path_c = create_sized_file(temp_dir.path(), 1000, "c.txt");
+        cache.get_source(&path_c);
+        assert_eq!(cache.path_metrics(&path_c).bytes_served_from_fs + cache.path_metrics(&path_c).bytes_served_from_cache, 0);
+        assert_eq!(cache.summary().bytes_served_from_cache + cache.summary().bytes_served_from_fs, 14_000);
+
+        cache.reset_served_bytes();
+        assert_eq!(cache.summary().bytes_served_from_cache, 0);
+        assert_eq!(cache.summary().bytes_served_from_fs, 0);
+        assert_eq!(cache.path_metrics(&path_a).bytes_served_from_cache, 0);
+        assert_eq!(cache.path_metrics(&path_a).access_count, 2);
+    }
+
+    #[test]
+    fn zero_size_caches_pass_requests_through() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(0)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        let missing = temp_dir.path().join("missing.txt");
+        let roots: Vec<PathBuf> = vec![temp_dir.path().to_path_buf()];
+
+        for i in 0..1000 {
+            match i % 4 {
+                0 => assert_eq!(read_all(cache.get_source(&path)), fs::read(&path).unwrap()),
+                1 => assert_eq!(cache.get_source_classified(&path).map(|(_, class)| class), Some(ServeClass::FsDisabled)),
+                2 => assert_eq!(read_all(cache.get_source_keyed(format!("/key_{}", i), &path)), fs::read(&path).unwrap()),
+                _ => assert_eq!(read_all(cache.get_source_with_fallbacks("a.txt", &roots)), fs::read(&path).unwrap()),
+            }
+            assert_eq!(cache.get_source(&missing), FileSource::NotFound);
+        }
+
+        assert_eq!(cache.insert(&path), Err(CacheError::CacheDisabled));
+        assert_eq!(cache.check(&path), AdmissionPrediction::Rejected(CacheError::CacheDisabled));
+        assert_eq!(cache.used_bytes(), 0);
+        assert_eq!(cache.file_map.iter().count(), 0);
+        assert_eq!(cache.access_count_map.iter().count(), 0);
+        assert_eq!(cache.out_of_bounds.iter().count(), 0);
+        assert_eq!(cache.fs_paths.iter().count(), 0);
+        assert_eq!(cache.fallback_resolutions.get(Path::new("a.txt")), None);
+        assert!(cache.recent_decisions().is_empty());
+        assert_eq!(cache.path_metrics(&path), PathMetrics {
+            access_count: 0,
+            bytes_served_from_cache: 0,
+            bytes_served_from_fs: 0,
+        });
+    }
+
+    #[test]
+    fn bypassed_paths_are_evicted_and_never_admitted_again() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let version = create_sized_file(temp_dir.path(), 100, "version.json");
+        let index = create_sized_file(temp_dir.path(), 100, "index.html");
+        cache.get_source(&version);
+        cache.get_source(&index);
+        assert!(cache.contains_key(&version));
+
+        cache.always_bypass(version.clone());
+        assert!(!cache.contains_key(&version));
+        assert_eq!(cache.used_bytes(), 100);
+        assert_eq!(cache.bypassed_paths(), vec![version.clone()]);
+
+        for _ in 0..10 {
+            let class: Option<ServeClass> = cache.get_source_classified(&version).map(|(_, class)| class);
+            assert_eq!(class, Some(ServeClass::FsBypassed));
+        }
+        assert_eq!(read_all(cache.get_source(&version)), fs::read(&version).unwrap());
+        assert_eq!(cache.insert(&version), Err(CacheError::Bypassed));
+        assert!(cache.prefetch_top(MEG1).inserted.is_empty());
+        assert!(!cache.contains_key(&version));
+        assert_eq!(cache.path_metrics(&version).access_count, 0);
+
+        assert!(cache.remove_bypass(&version));
+        assert!(!cache.remove_bypass(&version));
+        assert!(cache.bypassed_paths().is_empty());
+        cache.get_source(&version);
+        assert!(cache.contains_key(&version));
+    }
+
+    #[test]
+    fn bookkeeping_bytes_grow_with_tracked_paths_and_shrink_when_cleared() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        cache.set_served_bytes_by_path(Some(100));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        assert_eq!(cache.bookkeeping_bytes(), 0);
+
+        // Every cached file has an entry in the map of files, its access count, and the bytes served for it.
+        let paths: Vec<PathBuf> = (0..50).map(|i| create_sized_file(temp_dir.path(), 10, &format!("{:02}.txt", i))).collect();
+        for path in &paths {
+            cache.get_source(path);
+        }
+        assert_eq!(cache.summary().file_count, 50);
+        let with_files: usize = cache.bookkeeping_bytes();
+        let per_path: usize = with_files / paths.len();
+        let path_bytes: usize = paths[0].as_os_str().len() + mem::size_of::<PathBuf>();
+        assert!(per_path >= 3 * path_bytes + mem::size_of::<InMemoryFile>(), "{} bytes per path", per_path);
+        assert!(per_path <= 4 * path_bytes + mem::size_of::<InMemoryFile>() + 128, "{} bytes per path", per_path);
+        assert_eq!(cache.summary().bookkeeping_bytes, with_files);
+
+        // Clearing drops the entries of the files, but keeps their access counts and served bytes.
+        assert_eq!(cache.clear(), 50);
+        let cleared: usize = cache.bookkeeping_bytes();
+        assert!(cleared < with_files / 2, "{} of {}", cleared, with_files);
+
+        // Resetting the served bytes forgets them for every path, which is about half of what is left.
+        cache.reset_served_bytes();
+        let reset: usize = cache.bookkeeping_bytes();
+        assert!(reset < cleared * 3 / 4 && reset > cleared / 4, "{} of {}", reset, cleared);
+    }
+
+    fn max_priority_function(_: usize, _: usize) -> usize {
+        usize::MAX
+    }
+
+    #[test]
+    fn the_highest_priorities_are_clamped_and_evictions_still_terminate() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(3000)
+            .priority_function(max_priority_function)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let paths: Vec<PathBuf> = (0..3).map(|i| create_sized_file(temp_dir.path(), 1000, &format!("{}.txt", i))).collect();
+        for path in &paths {
+            cache.get_source(path);
+            assert_eq!(cache.stats_for(path).unwrap().priority, MAX_PRIORITY);
+        }
+
+        // Making room for a large file adds up the priorities of two files, which would overflow without clamping.
+        let large = create_sized_file(temp_dir.path(), 2000, "large.txt");
+        assert_eq!(cache.insert(&large), Err(CacheError::NewPriorityIsNotHighEnough));
+        assert_eq!(cache.used_bytes(), 3000);
+
+        // A file with the same priority replaces a single file.
+        let small = create_sized_file(temp_dir.path(), 1000, "small.txt");
+        match cache.insert(&small) {
+            Ok(CacheInsertionSuccess::ReplacedFile(evicted)) => assert_eq!(evicted.len(), 1),
+            result => panic!("Expected the file to replace another one, got {:?}.", result),
+        }
+        assert!(cache.contains_key(&small));
+        assert_eq!(cache.used_bytes(), 3000);
+        assert!(cache.would_admit_priority(usize::MAX, 1000));
+    }
+
+    fn decreasing_priority_function(access_count: usize, size: usize) -> usize {
+        size / access_count
+    }
+
+    #[test]
+    fn validating_priority_functions_reports_constant_and_decreasing_priorities() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let report: PriorityFunctionReport = cache.validate_priority_function(10);
+        assert_eq!(report.samples, 100);
+        assert!(!report.constant);
+        assert!(report.decreasing_with_accesses.is_empty());
+        assert_eq!(report.clamped, 0);
+
+        let cache: Cache = CacheBuilder::new()
+            .priority_function(max_priority_function)
+            .build()
+            .unwrap();
+        let report: PriorityFunctionReport = cache.validate_priority_function(10);
+        assert!(report.constant);
+        assert_eq!(report.clamped, 100);
+
+        // Sizes stop at the largest file the cache accepts.
+        let cache: Cache = CacheBuilder::new()
+            .priority_function(decreasing_priority_function)
+            .max_file_size(16)
+            .build()
+            .unwrap();
+        let report: PriorityFunctionReport = cache.validate_priority_function(3);
+        assert_eq!(report.samples, 9);
+        assert!(!report.constant);
+        assert_eq!(report.decreasing_with_accesses, vec![(2, 1), (2, 4), (3, 4), (2, 16), (3, 16)]);
+    }
+
+    #[test]
+    fn replaying_a_trace_reproduces_the_recorded_cache() {
+        let builder: CacheBuilder = {
+            let mut builder = CacheBuilder::new();
+            builder.size_limit(3000);
+            builder
+        };
+        let mut cache: Cache = builder.build().unwrap();
+        let observed: Arc<Mutex<Vec<EvictedEntry>>> = Arc::new(Mutex::new(vec![]));
+        cache.set_observer(Some(Box::new(RecordingObserver { evicted_entries: observed.clone() })));
+        cache.record_trace(true);
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        let path_b = create_sized_file(temp_dir.path(), 1000, "b.txt");
+        let path_c = create_sized_file(temp_dir.path(), 1000, "c.txt");
+        let path_d = create_sized_file(temp_dir.path(), 1000, "d.txt");
+        let path_large = create_sized_file(temp_dir.path(), 2500, "large.txt");
+        let path_missing = temp_dir.path().join("missing.txt");
+
+        let mut hits: usize = 0;
+        let mut requests: usize = 0;
+        {
+            let mut get = |path: &PathBuf| {
+                requests += 1;
+                if let Some((_, ServeClass::Hit)) = cache.get_source_classified(path) {
+                    hits += 1;
+                }
+            };
+            for path in &[&path_a, &path_a, &path_b, &path_c, &path_a, &path_d, &path_d, &path_d, &path_large, &path_missing, &path_b] {
+                get(path);
+            }
+        }
+        cache.remove(&path_a);
+        create_sized_file(temp_dir.path(), 1500, "d.txt");
+        cache.refresh_source(&path_d);
+        for _ in 0..3 {
+            requests += 1;
+            if let Some((_, ServeClass::Hit)) = cache.get_source_classified(&path_a) {
+                hits += 1;
+            }
+        }
+
+        let trace: Trace = cache.trace();
+        assert_eq!(trace.ops.len(), 16);
+        let mut saved: Vec<u8> = vec![];
+        trace.save(&mut saved).unwrap();
+        let loaded: Trace = Trace::load(&saved[..]).unwrap();
+        assert_eq!(loaded, trace);
+
+        let report: ReplayReport = loaded.replay_against(&builder).unwrap();
+        let mut resident: Vec<PathBuf> = cache.file_map.iter().map(|(path, _)| path.clone()).collect();
+        resident.sort();
+        assert_eq!(report.resident, resident);
+        assert_eq!(report.used_bytes, cache.used_bytes());
+        assert_eq!(report.requests, requests);
+        assert_eq!(report.hits, hits);
+        let evictions: Vec<PathBuf> = observed.lock().unwrap().iter().map(|evicted| evicted.path.clone()).collect();
+        assert!(!evictions.is_empty());
+        assert_eq!(report.evictions, evictions);
+
+        // Replaying is deterministic, so it can be used to compare configurations.
+        assert_eq!(loaded.replay_against(&builder).unwrap(), report);
+        cache.record_trace(false);
+        assert!(cache.trace().ops.is_empty());
+    }
+
+    #[test]
+    fn fallbacks_are_remembered_until_refreshed_or_removed() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let site = temp_dir.path().join("site");
+        let default = temp_dir.path().join("default");
+        fs::create_dir(&site).unwrap();
+        fs::create_dir(&default).unwrap();
+        let roots: Vec<PathBuf> = vec![site.clone(), default.clone()];
+        fs::write(default.join("index.html"), b"default").unwrap();
+
+        match cache.get_source_with_fallbacks("index.html", &roots) {
+            FileSource::Cached(ref file) => assert_eq!(file.file.get().bytes.as_slice(), b"default"),
+            _ => panic!("Expected the file to be cached from the second root."),
+        }
+        assert!(cache.contains_key(default.join("index.html")));
+
+        // The root that had the file is used until the file is refreshed.
+        fs::write(site.join("index.html"), b"site").unwrap();
+        assert_eq!(cache.get_source_with_fallbacks("index.html", &roots).path(), Some(default.join("index.html").as_path()));
+        cache.refresh_source(default.join("index.html"));
+        match cache.get_source_with_fallbacks("index.html", &roots) {
+            FileSource::Cached(ref file) => assert_eq!(file.file.get().bytes.as_slice(), b"site"),
+            _ => panic!("Expected the file to be cached from the first root."),
+        }
+
+        // Removing the file from the cache makes the roots be searched again as well.
+        fs::remove_file(site.join("index.html")).unwrap();
+        cache.remove(site.join("index.html"));
+        assert_eq!(cache.get_source_with_fallbacks("index.html", &roots).path(), Some(default.join("index.html").as_path()));
+
+        assert_eq!(cache.get_source_with_fallbacks("../default/index.html", &roots), FileSource::NotFound);
+        assert_eq!(cache.get_source_with_fallbacks(default.join("index.html"), &roots), FileSource::NotFound);
+    }
+
+    #[test]
+    fn latency_report_counts_the_phases_of_each_request() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = temp_dir.path().join("a.txt");
+        let path_b = temp_dir.path().join("b.txt");
+        fs::write(&path_a, vec![b'a'; MEG1 / 2 + 1]).unwrap();
+        fs::write(&path_b, vec![b'b'; MEG1 / 2 + 1]).unwrap();
+
+        // Inserted: looked up, metadata read, file read, responded with.
+        cache.get_source(&path_a);
+        // Hit: looked up, responded with.
+        cache.get_source(&path_a);
+        // Doesn't fit next to the more popular file: looked up, metadata read, evictions selected, responded with from the filesystem.
+        match cache.get_source(&path_b) {
+            FileSource::Fs(..) => {}
+            _ => panic!("Expected the file to be served from the filesystem."),
+        }
+        // Missing: looked up, metadata read.
+        cache.get_source(temp_dir.path().join("missing.txt"));
+
+        let counts = |cache: &Cache| -> Vec<(Phase, usize)> {
+            cache.latency_report().phases.iter().map(|latency| (latency.phase, latency.count())).collect()
+        };
+        let expected: Vec<(Phase, usize)> = vec![
+            (Phase::Lookup, 4),
+            (Phase::Metadata, 3),
+            (Phase::EvictionSelection, 1),
+            (Phase::FileRead, 1),
+            (Phase::Response, 3),
+        ];
+        assert_eq!(counts(&cache), expected);
+        let report: LatencyReport = cache.latency_report();
+        assert_eq!(report.phase(Phase::Lookup).counts.len(), report.bucket_bounds.len() + 1);
+        assert_eq!(report.phase(Phase::Lookup).over_budget, 0);
+
+        cache.set_timing(false);
+        cache.get_source(&path_a);
+        assert_eq!(counts(&cache), expected);
+    }
+
+    /// Every storage the cache can keep files in.
+    fn storages() -> Vec<Storage> {
+        #[cfg(all(unix, feature = "mmap"))]
+        return vec![Storage::Heap, Storage::Mmap];
+        #[cfg(not(all(unix, feature = "mmap")))]
+        return vec![Storage::Heap];
+    }
+
+    #[test]
+    fn storages_serve_refresh_and_hash_files_alike() {
+        let mut content_hashes: Vec<Option<String>> = vec![];
+        for storage in storages() {
+            let mut cache: Cache = CacheBuilder::new()
+                .size_limit(MEG1)
+                .build()
+                .unwrap();
+            cache.set_storage(storage);
+            let temp_dir = TempDir::new(DIR_TEST).unwrap();
+            let path = temp_dir.path().join("file.txt");
+            let empty_path = temp_dir.path().join("empty.txt");
+            fs::write(&path, b"first").unwrap();
+            fs::write(&empty_path, b"").unwrap();
+
+            assert_eq!(cache.get_source(&path).bytes(), Some(&b"first"[..]), "{:?}", storage);
+            assert_eq!(cache.get_source(&empty_path).bytes(), Some(&b""[..]), "{:?}", storage);
+            assert_eq!(cache.used_bytes(), 5);
+            content_hashes.push(cache.content_hash(&path));
+
+            // Files are replaced rather than modified in place, which mapped files require.
+            let replacement = temp_dir.path().join("replacement.txt");
+            fs::write(&replacement, b"second!").unwrap();
+            fs::rename(&replacement, &path).unwrap();
+            assert_eq!(cache.refresh_source(&path).bytes(), Some(&b"second!"[..]), "{:?}", storage);
+            assert_eq!(cache.used_bytes(), 7);
+
+            let detached: FileSource<'static> = cache.get_source(&path).detach();
+            drop(cache);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(detached.bytes(), Some(&b"second!"[..]), "{:?}", storage);
+        }
+        // The contents, and so the hashes, don't depend on where the bytes are kept.
+        assert!(content_hashes.iter().all(|hash| hash.is_some() && *hash == content_hashes[0]));
+    }
+
+    #[test]
+    fn hits_do_not_allocate() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG10)
+            .build()
+            .unwrap();
+        // Checking the invariants allocates.
+        cache.set_strict_invariants(false);
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        cache.get_source(&path);
+        cache.get_source(&path);
+
+        let (served_from_cache, allocations) = allocations_during(|| {
+            matches!(cache.get_source(&path), FileSource::Cached(_))
+        });
+        assert!(served_from_cache);
+        assert_eq!(allocations, 0);
+
+        #[cfg(feature = "rocket")]
+        {
+            let (served_from_cache, allocations) = allocations_during(|| {
+                matches!(cache.get(&path), CachedFile::InMemory(_))
+            });
+            assert!(served_from_cache);
+            assert_eq!(allocations, 0);
+        }
+    }
+
+    #[test]
+    fn detached_files_outlive_the_cache() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let small_path = temp_dir.path().join("small.txt");
+        let large_path = temp_dir.path().join("large.txt");
+        fs::write(&small_path, b"small").unwrap();
+        fs::write(&large_path, vec![b'l'; MEG2]).unwrap();
+
+        let small = cache.get_source(&small_path).detach();
+        let large = cache.get_source(&large_path).detach();
+        match large {
+            FileSource::Fs(..) => {}
+            _ => panic!("Expected the file to be served from the filesystem."),
+        }
+        drop(cache);
+        fs::remove_file(&small_path).unwrap();
+        fs::remove_file(&large_path).unwrap();
+
+        assert_eq!(small.bytes(), Some(&b"small"[..]));
+        // The file is read from the handle that was opened while it existed.
+        let mut contents: Vec<u8> = vec![];
+        large.into_reader().unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents.len(), MEG2);
+    }
+
+    #[test]
+    fn file_sources_can_be_read_without_rocket() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let small_path = temp_dir.path().join("small.txt");
+        let large_path = temp_dir.path().join("large.txt");
+        fs::write(&small_path, b"small").unwrap();
+        fs::write(&large_path, vec![b'l'; MEG2]).unwrap();
+
+        let small = cache.get_source(&small_path);
+        assert_eq!(small.bytes(), Some(&b"small"[..]));
+        assert_eq!(small.path(), Some(small_path.as_path()));
+        let mut contents: Vec<u8> = vec![];
+        small.into_reader().unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"small");
+
+        let large = cache.get_source(&large_path);
+        assert!(large.bytes().is_none());
+        let mut contents: Vec<u8> = vec![];
+        large.into_reader().unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents.len(), MEG2);
+
+        assert!(cache.get_source(temp_dir.path().join("missing.txt")).into_reader().is_none());
+    }
+
+
+    #[test]
+    fn concurrent_misses_read_file_once() {
+        let cache: Arc<Cache> = Arc::new(
+            CacheBuilder::new()
+                .size_limit(MEG1 * 20)
+                .build()
+                .unwrap()
+        );
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_10m = create_sized_file(temp_dir.path(), MEG10, FILE_MEG10);
+
+        let mut file_bytes: Vec<u8> = Vec::new();
+        let _ = File::open(&path_10m).unwrap().read_to_end(&mut file_bytes).unwrap();
+
+        const THREADS: usize = 10;
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let handles: Vec<thread::JoinHandle<Vec<u8>>> = (0..THREADS)
+            .map(|_| {
+                let cache = cache.clone();
+                let barrier = barrier.clone();
+                let path = path_10m.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    match cache.get_source(&path) {
+                        FileSource::Cached(c) => c.file.get().bytes.as_slice().to_vec(),
+                        FileSource::Fs(..) | FileSource::Detached(_) | FileSource::Deferred(_) => panic!("Expected the file to be served from the cache"),
+                        FileSource::NotFound | FileSource::FileMissing => panic!("Expected the file to be found"),
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), file_bytes);
+        }
+        assert_eq!(cache.file_reads.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn concurrent_insertions_never_exceed_size_limit() {
+        let cache: Arc<Cache> = Arc::new(
+            CacheBuilder::new()
+                .size_limit(MEG1 * 4)
+                .build()
+                .unwrap()
+        );
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+
+        let threads: usize = 8;
+        let files_per_thread: usize = 6;
+        let barrier = Arc::new(Barrier::new(threads));
+        let handles: Vec<thread::JoinHandle<()>> = (0..threads)
+            .map(|thread_index| {
+                let paths: Vec<PathBuf> = (0..files_per_thread)
+                    .map(|file_index| create_sized_file(temp_dir.path(), MEG1, &format!("{}_{}.txt", thread_index, file_index)))
+                    .collect();
+                let cache = cache.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for path in paths {
+                        // Check the invariant around every operation, while the FileSource isn't held.
+                        assert!(cache.used_bytes() <= MEG1 * 4);
+                        let cached_file = cache.get_source(&path);
+                        drop(cached_file);
+                        assert!(cache.used_bytes() <= MEG1 * 4);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let resident_bytes: usize = cache.file_map.iter().fold(0, |size, (_, file)| size + file.stats.size);
+        assert_eq!(cache.used_bytes(), resident_bytes);
+        assert!(resident_bytes <= MEG1 * 4);
+    }
+
+    #[test]
+    fn space_made_for_a_file_stays_reserved_for_it() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 4)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let paths: Vec<PathBuf> = (0..3)
+            .map(|i| create_sized_file(temp_dir.path(), MEG1, &format!("reserved_{}.txt", i)))
+            .collect();
+        for path in paths.iter() {
+            cache.insert(path).unwrap();
+        }
+
+        // The free space and the space of the evicted file are reserved together, so nothing else fits in the meantime.
+        let evicted: Vec<EvictedEntry> = cache.evict_with_reservation(MEG2, &[(paths[0].clone(), MEG1)]).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(cache.used_bytes(), MEG1 * 4);
+        assert!(!cache.reserve_space(1));
+        cache.release_space(MEG2);
+
+        // Files removed by another thread in the meantime give their space to whoever takes it first.
+        cache.remove(&paths[1]);
+        assert!(cache.reserve_space(MEG2));
+        assert_eq!(cache.evict_with_reservation(MEG2, &[(paths[1].clone(), MEG1)]), Err(CacheError::SpaceTakenByConcurrentInsertion));
+        assert_eq!(cache.used_bytes(), MEG1 * 3);
+        assert!(cache.contains_key(&paths[2]));
+    }
+
+    #[test]
+    fn concurrent_reads_are_limited() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 100)
+            .build()
+            .unwrap();
+        cache.set_max_concurrent_reads(Some(2));
+        let cache: Arc<Cache> = Arc::new(cache);
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+
+        let threads: usize = 16;
+        let barrier = Arc::new(Barrier::new(threads));
+        let handles: Vec<thread::JoinHandle<bool>> = (0..threads)
+            .map(|thread_index| {
+                let path: PathBuf = create_sized_file(temp_dir.path(), MEG1, &format!("{}.txt", thread_index));
+                let cache = cache.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    match cache.get_source(&path) {
+                        FileSource::Cached(_) | FileSource::Fs(..) | FileSource::Detached(_) | FileSource::Deferred(_) => true,
+                        FileSource::NotFound | FileSource::FileMissing => false,
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap(), "Every file should be served from somewhere.");
+        }
+
+        assert!(cache.read_limiter.peak_reads() >= 1);
+        assert!(cache.read_limiter.peak_reads() <= 2);
+    }
+
+    #[test]
+    fn overshoot_is_bounded_and_converges() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+        cache.set_overshoot(Some(OvershootConfig {
+            fraction: 0.2,
+            evictions_per_operation: 1,
+        }));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+
+        let mut max_used_bytes: usize = 0;
+        for i in 0..30 {
+            let path = create_sized_file(temp_dir.path(), MEG1, format!("{}_1mib.txt", i).as_str());
+            cache.get_source(&path);
+            let used_bytes = cache.used_bytes();
+            assert!(used_bytes <= MEG1 * 12, "Cache exceeded its overshoot: {}", used_bytes);
+            max_used_bytes = usize::max(max_used_bytes, used_bytes);
+        }
+        assert!(max_used_bytes > MEG1 * 10, "The overshoot should have been used by the burst of inserts.");
+
+        // Later operations bring the cache back under its size limit.
+        let missing_path = temp_dir.path().join("missing.txt");
+        for _ in 0..5 {
+            cache.get_source(&missing_path);
+        }
+        assert!(cache.used_bytes() <= MEG1 * 10);
+    }
+
+    #[test]
+    fn evicted_file_is_promoted_from_cold_tier() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 6)
+            .build()
+            .unwrap();
+        cache.set_cold_tier(Some(ColdTierConfig {
+            size_limit: MEG1,
+            compression: Compression::Fast,
+        }));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_compressible_test_file(&temp_dir, MEG5, "a.txt");
+        let path_b = create_compressible_test_file(&temp_dir, MEG5, "b.txt");
+        let file_a: InMemoryFile = InMemoryFile::open(&path_a).unwrap();
+
+        cache.get_source(&path_a).get_in_memory_file();
+        // b has the same priority as a, so it replaces a, which is demoted into the cold tier.
+        cache.get_source(&path_b).get_in_memory_file();
+        assert!(!cache.contains_key(&path_a));
+        assert_eq!(cache.summary().cold_tier_file_count, 1);
+
+        // a has been accessed twice now, so it is promoted back into the cache and b is demoted.
+        assert_eq!(
+            cache.get_source(&path_a)
+                .get_in_memory_file()
+                .file
+                .get()
+                .bytes,
+            file_a.bytes
+        );
+        assert!(cache.contains_key(&path_a));
+        assert!(!cache.contains_key(&path_b));
+        // a was not read from the filesystem again.
+        assert_eq!(cache.file_reads.load(Ordering::Relaxed), 2);
+
+        let summary = cache.summary();
+        assert_eq!(summary.cold_tier_file_count, 1);
+        assert!(summary.used_bytes <= MEG1 * 6);
+        assert!(summary.cold_tier_used_bytes <= MEG1);
+    }
+
+    #[test]
+    fn embedded_files_are_served_without_copying() {
+        static EMBEDDED_HTML: &[u8] = b"<html></html>";
+        static EMBEDDED_CSS: &[u8] = b"body {}";
+
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        cache.seed_embedded(&[("www/index.html", EMBEDDED_HTML), ("www/style.css", EMBEDDED_CSS)]);
+
+        for &(path, bytes) in [("www/index.html", EMBEDDED_HTML), ("www/style.css", EMBEDDED_CSS)].iter() {
+            let named_in_memory_file = cache.get_source(path).get_in_memory_file();
+            let served: &[u8] = named_in_memory_file.file.get().bytes.as_slice();
+            assert_eq!(served, bytes);
+            assert_eq!(served.as_ptr(), bytes.as_ptr());
+        }
+        assert_eq!(cache.used_bytes(), EMBEDDED_HTML.len() + EMBEDDED_CSS.len());
+
+        // Refreshing an embedded file leaves it in place, as there is no file in the filesystem.
+        assert!(cache.refresh_source("www/index.html") != FileSource::NotFound);
+        assert!(cache.sorted_priorities().is_empty());
+    }
+
+    #[test]
+    fn files_whose_declared_size_disagrees_with_their_bytes_are_counted_and_served_whole() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        // The declared size disagreeing with the bytes is the broken invariant under test.
+        cache.set_strict_invariants(false);
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = temp_dir.path().join("truncated.txt");
+        cache.store_file(path.clone(), InMemoryFile::with_declared_size(vec![b'a'; 100], 150));
+
+        match cache.get_source(&path) {
+            FileSource::Cached(cached_file) => assert_eq!(cached_file.file.get().response_size(), 100),
+            _ => panic!("Expected the file to be served from the cache."),
+        }
+        assert_eq!(cache.summary().size_mismatches, 1);
+
+        cache.store_file(path.clone(), InMemoryFile::from_bytes(vec![b'a'; 100]));
+        cache.get_source(&path);
+        assert_eq!(cache.summary().size_mismatches, 1);
+    }
+
+    #[test]
+    fn corrupted_files_are_read_again_when_checksums_are_verified() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1)
+            .build()
+            .unwrap();
+        cache.set_verify_checksums(true);
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        cache.get_source(&path);
+        assert!(cache.file_map.find(&path).unwrap().get().content_hash.is_some());
+
+        cache.file_map.find_mut(&path).unwrap().get().bytes = FileBytes::Owned(Arc::new(vec![0; 1000]));
+        let mut bytes: Vec<u8> = vec![];
+        cache.get_source(&path).into_reader().unwrap().read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, fs::read(&path).unwrap());
+        assert_eq!(cache.summary().checksum_mismatches, 1);
+
+        // The file that was read again matches its new checksum.
+        cache.get_source(&path);
+        assert_eq!(cache.summary().checksum_mismatches, 1);
+    }
+
+    #[test]
+    fn content_hash_is_stable_and_changes_on_refresh() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        assert_eq!(cache.content_hash(&path), None);
+
+        cache.set_hash_uncached_files(true);
+        let uncached_hash: Option<String> = cache.content_hash(&path);
+        assert!(uncached_hash.is_some());
+        assert!(!cache.contains_key(&path));
+
+        cache.get_source(&path);
+        let hash: Option<String> = cache.content_hash(&path);
+        assert_eq!(hash, uncached_hash);
+        assert_eq!(cache.content_hash(&path), hash);
+        assert_eq!(cache.content_hashes().get(&path), hash.as_ref());
+
+        create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        cache.refresh_source(&path);
+        assert_ne!(cache.content_hash(&path), hash);
+    }
+
+    #[test]
+    fn hashes_of_replaced_files_are_not_memoized() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = temp_dir.path().join("hashed.txt");
+        fs::write(&path, b"old").unwrap();
+        cache.insert(&path).unwrap();
+        let generation: u64 = cache.file_map.find(&path).unwrap().get().generation;
+
+        // A refresh replaces the file between hashing its old bytes and memoizing their hash.
+        fs::write(&path, b"new").unwrap();
+        cache.refresh_source(&path);
+        cache.memoize_content_hash(&path, generation, content_hash::hash(b"old"));
+        assert_eq!(cache.content_hash(&path), Some(content_hash::to_hex(content_hash::hash(b"new"))));
+    }
+
+    #[test]
+    fn get_with_skips_insertion_above_threshold() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+
+        match cache.get_source_with(&path, GetOptions::new().skip_insert_above(MEG1 - 1)) {
+            FileSource::Fs(..) => {}
+            _ => panic!("Expected the file to be served from the filesystem."),
+        }
+        assert!(!cache.contains_key(&path));
+        assert_eq!(cache.used_bytes(), 0);
+
+        let cached_file = cache.get_source_with(&path, GetOptions::new().skip_insert_above(MEG1));
+        match cached_file {
+            FileSource::Cached(_) => {}
+            _ => panic!("Expected the file to be inserted."),
+        }
+    }
+
+    #[test]
+    fn would_admit_priority_agrees_with_insertions() {
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let residents: Vec<PathBuf> = (0..4)
+            .map(|i| create_sized_file(temp_dir.path(), 1000, &format!("resident_{}.txt", i)))
+            .collect();
+        let full_cache = || {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(4200)
+                .build()
+                .unwrap();
+            // The residents are accessed once, twice, three and four times.
+            for (i, path) in residents.iter().enumerate() {
+                for _ in 0..i + 1 {
+                    cache.get_source(path);
+                }
+            }
+            cache
+        };
+        assert_eq!(full_cache().current_floor_priority(), None);
+
+        let mut admitted: usize = 0;
+        for &size in &[100, 500, 1500, 2500, 4100] {
+            let path = create_sized_file(temp_dir.path(), size, &format!("new_{}.txt", size));
+            for previous_accesses in 0..12 {
+                let cache: Cache = full_cache();
+                // Requests that skip insertion still count as accesses.
+                for _ in 0..previous_accesses {
+                    cache.get_source_with(&path, GetOptions::new().skip_insert_above(0));
+                }
+                let priority: usize = (cache.priority_function)(previous_accesses + 1, size);
+                let would_admit: bool = cache.would_admit_priority(priority, size);
+                let inserted: bool = cache.insert(&path).is_ok();
+                assert_eq!(would_admit, inserted, "size: {}, priority: {}", size, priority);
+                if inserted {
+                    admitted += 1;
+                }
+            }
+        }
+        // The grid covers both outcomes.
+        assert!(admitted > 0 && admitted < 60);
+
+        let cache: Cache = full_cache();
+        cache.get_source(create_sized_file(temp_dir.path(), 200, "filler.txt"));
+        assert_eq!(cache.current_floor_priority(), Some((cache.priority_function)(1, 200)));
+    }
+
+    #[test]
+    fn get_with_limits_eviction_candidates() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 3)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let small_paths: Vec<PathBuf> = (0..3)
+            .map(|i| create_sized_file(temp_dir.path(), MEG1, &format!("small_{}.txt", i)))
+            .collect();
+        for path in small_paths.iter() {
+            cache.get_source(path);
+        }
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+
+        // Making room for the new file requires evicting two files, which exceeds the limit.
+        for _ in 0..5 {
+            match cache.get_source_with(&path_2m, GetOptions::new().max_eviction_candidates(1)) {
+                FileSource::Fs(..) => {}
+                _ => panic!("Expected the file to be served from the filesystem."),
+            }
+            assert_eq!(cache.used_bytes(), MEG1 * 3);
+            assert!(small_paths.iter().all(|path| cache.contains_key(path)));
+        }
+
+        let cached_file = cache.get_source_with(&path_2m, GetOptions::new().max_eviction_candidates(2));
+        match cached_file {
+            FileSource::Cached(_) => {}
+            _ => panic!("Expected the file to replace the smaller files."),
+        }
+    }
+
+    #[test]
+    fn case_insensitive_keys_share_one_entry() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+        cache.set_key_case(KeyCase::Insensitive);
+
+        // The file is read from the spelling it was first requested with, even on a case-sensitive filesystem.
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = create_sized_file(temp_dir.path(), MEG1, "Logo.PNG");
+        let lowercase_path: PathBuf = cache.key_case.normalize(&path).unwrap();
+        let uppercase_path: PathBuf = temp_dir.path().join("LOGO.png");
+
+        // A spelling that doesn't exist isn't remembered.
+        let made_up_path: PathBuf = temp_dir.path().join("Missing.PNG");
+        assert!(cache.get_source_classified(&made_up_path).is_none());
+        assert_eq!(cache.fs_paths.iter().count(), 0);
+
+        match cache.get_source_classified(&path) {
+            Some((_, ServeClass::Inserted)) => {}
+            other => panic!("Expected the file to be inserted, got {:?}", other.map(|(_, class)| class)),
+        }
+        for spelling in &[&lowercase_path, &uppercase_path, &path] {
+            match cache.get_source(spelling) {
+                FileSource::Cached(_) => {}
+                _ => panic!("Expected {:?} to hit the cached file.", spelling),
+            }
+        }
+        assert_eq!(cache.file_map.iter().count(), 1);
+        assert_eq!(cache.used_bytes(), MEG1);
+
+        assert!(cache.remove(&uppercase_path));
+        assert!(!cache.contains_key(&path));
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected_without_touching_state() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+        cache.set_reject_parent_directories(true);
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let parent_path: PathBuf = temp_dir.path().join("..").join(temp_dir.path().file_name().unwrap()).join(FILE_MEG1);
+        let nul_path: PathBuf = temp_dir.path().join("meg1\0.txt");
+
+        let rejections: Vec<(PathBuf, InvalidKeyReason)> = vec![
+            (PathBuf::new(), InvalidKeyReason::Empty),
+            (nul_path, InvalidKeyReason::ContainsNul),
+            (parent_path.clone(), InvalidKeyReason::ContainsParentDirectory),
+        ];
+        for (rejected_path, reason) in rejections {
+            assert_eq!(cache.validate_key(&rejected_path), Err(CacheError::InvalidKey(reason)));
+            assert_eq!(cache.get_source(&rejected_path), FileSource::NotFound);
+        }
+        assert!(cache.tracked_paths().is_empty());
+        assert_eq!(cache.used_bytes(), 0);
+
+        cache.set_reject_parent_directories(false);
+        assert_eq!(cache.validate_key(&parent_path), Ok(()));
+        assert_eq!(cache.validate_key(&path), Ok(()));
+    }
+
+    #[test]
+    fn out_of_bounds_files_stop_being_tracked() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .max_file_size(MEG1)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+
+        for _ in 0..1000 {
+            match cache.get_source(&path) {
+                FileSource::Fs(..) => {}
+                _ => panic!("Expected the file to be served from the filesystem."),
+            }
+        }
+        assert_eq!(cache.metadata_reads.load(Ordering::Relaxed), 1);
+        assert_eq!(cache.tracked_paths(), vec![path.clone()]);
+        assert_eq!(*cache.access_count_map.find(&path).unwrap().get(), 1);
+
+        // Refreshing the path forgets the classification, so the file is checked again.
+        cache.refresh_source(&path);
+        cache.get_source(&path);
+        assert_eq!(cache.metadata_reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn out_of_bounds_files_can_be_recorded_in_a_filter() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .min_file_size(1024)
+            .max_file_size(MEG1)
+            .classification_filter(1000)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let large_path = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        let small_path = create_sized_file(temp_dir.path(), 10, "small.txt");
+
+        let classify = |path: &PathBuf| cache.get_source_classified_with(path, &GetOptions::new()).map(|(_, class)| class);
+        for _ in 0..10 {
+            assert_eq!(classify(&large_path), Some(ServeClass::FsTooLarge));
+            assert_eq!(classify(&small_path), Some(ServeClass::FsTooSmall));
+            assert_eq!(read_all(cache.get_source(&small_path)).len(), 10);
+        }
+        assert_eq!(cache.metadata_reads.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.out_of_bounds.iter().count(), 0);
+
+        // A path mistaken for one outside of the bounds is still served, just never cached.
+        let path = create_sized_file(temp_dir.path(), MEG1 / 2, FILE_MEG1);
+        cache.classification_filter.as_ref().unwrap().insert(&path, bound_marker(ServeClass::FsTooLarge));
+        assert_eq!(read_all(cache.get_source(&path)).len(), MEG1 / 2);
+        assert!(!cache.contains_key(&path));
+        assert_eq!(cache.metadata_reads.load(Ordering::Relaxed), 2);
+
+        cache.reset_classification_filter();
+        cache.get_source(&path).get_in_memory_file();
+        assert_eq!(classify(&large_path), Some(ServeClass::FsTooLarge));
+        assert_eq!(cache.metadata_reads.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn keys_are_separate_from_paths() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+
+        cache.get_source_keyed("/first.txt", &path_1m);
+        cache.get_source_keyed("/second.txt", &path_1m);
+        cache.get_source_keyed("/other.txt", &path_2m);
+        assert!(cache.contains_key("/first.txt"));
+        assert!(cache.contains_key("/second.txt"));
+        assert!(!cache.contains_key(&path_1m));
+        assert_eq!(cache.used_bytes(), MEG1 * 2 + MEG2);
+
+        // The entries for the same file are independent.
+        assert!(cache.remove("/first.txt"));
+        assert!(cache.contains_key("/second.txt"));
+
+        // Refreshing by key re-reads the file the key was stored with.
+        create_sized_file(temp_dir.path(), MEG5, FILE_MEG2);
+        cache.refresh_source("/other.txt");
+        assert_eq!(cache.file_map.find(Path::new("/other.txt")).unwrap().get().stats.size, MEG5);
+        assert_eq!(cache.file_map.find(Path::new("/second.txt")).unwrap().get().stats.size, MEG1);
+    }
+
+    #[test]
+    fn keys_of_missing_files_are_not_remembered() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let bookkeeping_bytes: usize = cache.bookkeeping_bytes();
+
+        for i in 0..100 {
+            let missing: PathBuf = temp_dir.path().join(format!("missing_{}.txt", i));
+            assert_eq!(cache.get_source_keyed(format!("/missing_{}.txt", i), &missing), FileSource::NotFound);
+        }
+        assert_eq!(cache.fs_paths.iter().count(), 0);
+        assert_eq!(cache.bookkeeping_bytes(), bookkeeping_bytes);
+
+        // A key whose file is gone is forgotten once the file is.
+        let path: PathBuf = temp_dir.path().join("page.html");
+        fs::write(&path, b"contents").unwrap();
+        cache.get_source_keyed("/page.html", &path);
+        assert_eq!(cache.fs_paths.iter().count(), 1);
+        cache.remove("/page.html");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cache.get_source_keyed("/page.html", &path), FileSource::NotFound);
+        assert_eq!(cache.fs_paths.iter().count(), 0);
+    }
+
+    struct RecordingObserver {
+        evicted_entries: Arc<Mutex<Vec<EvictedEntry>>>,
+    }
+
+    impl CacheObserver for RecordingObserver {
+        fn on_evict(&self, evicted_entry: &EvictedEntry) {
+            self.evicted_entries.lock().unwrap().push(evicted_entry.clone());
+        }
+    }
+
+    #[test]
+    fn insertion_reports_evicted_entries() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 3)
+            .build()
+            .unwrap();
+        let observed: Arc<Mutex<Vec<EvictedEntry>>> = Arc::new(Mutex::new(Vec::new()));
+        cache.set_observer(Some(Box::new(RecordingObserver { evicted_entries: observed.clone() })));
+
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let small_paths: Vec<PathBuf> = (0..3)
+            .map(|i| create_sized_file(temp_dir.path(), MEG1, &format!("small_{}.txt", i)))
+            .collect();
+        for path in small_paths.iter() {
+            assert_eq!(cache.insert(path), Ok(CacheInsertionSuccess::SimpleAddition));
+        }
+        assert_eq!(cache.insert(&small_paths[0]), Err(CacheError::AlreadyInCache));
+
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        assert_eq!(cache.insert(&path_2m), Err(CacheError::NewPriorityIsNotHighEnough));
+        let evicted_entries: Vec<EvictedEntry> = match cache.insert(&path_2m) {
+            Ok(CacheInsertionSuccess::ReplacedFile(evicted_entries)) => evicted_entries,
+            other => panic!("Expected files to be replaced, got {:?}", other),
+        };
+
+        assert_eq!(evicted_entries.len(), 2);
+        for evicted_entry in evicted_entries.iter() {
+            assert!(!cache.contains_key(&evicted_entry.path));
+            assert_eq!(evicted_entry.size, MEG1);
+            assert_eq!(evicted_entry.access_count, 1);
+        }
+        let remaining: usize = small_paths.iter().filter(|path| cache.contains_key(path)).count();
+        assert_eq!(remaining, 1);
+        assert!(cache.contains_key(&path_2m));
+        assert_eq!(*observed.lock().unwrap(), evicted_entries);
+    }
+
+    #[test]
+    fn committed_insertion_evicts_the_checked_victims() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 3)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let small_paths: Vec<PathBuf> = (0..3)
+            .map(|i| create_sized_file(temp_dir.path(), MEG1, &format!("small_{}.txt", i)))
+            .collect();
+        for path in small_paths.iter() {
+            cache.insert(path).unwrap();
+        }
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        assert_eq!(cache.check(&path_2m), AdmissionPrediction::Rejected(CacheError::NewPriorityIsNotHighEnough));
+        cache.get_source(&path_2m);
+
+        let token: AdmissionToken = match cache.check(&path_2m) {
+            AdmissionPrediction::Admit(token) => token,
+            other => panic!("Expected the file to be admitted, got {:?}", other),
+        };
+        let victims: Vec<PathBuf> = token.victims().iter().map(|victim| victim.to_path_buf()).collect();
+        assert_eq!(victims.len(), 2);
+        // Checking doesn't change the cache.
+        assert_eq!(cache.used_bytes(), MEG1 * 3);
+        assert!(victims.iter().all(|victim| cache.contains_key(victim)));
+
+        let cached_file = cache.commit_insert_source(&path_2m, token).unwrap();
+        match cached_file {
+            FileSource::Cached(_) => {}
+            _ => panic!("Expected the file to be in memory"),
+        }
+        assert!(victims.iter().all(|victim| !cache.contains_key(victim)));
+        assert_eq!(small_paths.iter().filter(|path| cache.contains_key(path)).count(), 1);
+        assert_eq!(cache.used_bytes(), MEG1 * 3);
+        assert_eq!(cache.check(&path_2m), AdmissionPrediction::AlreadyInCache);
+    }
+
+    #[test]
+    fn stale_admission_token_is_checked_again() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 3)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let small_paths: Vec<PathBuf> = (0..3)
+            .map(|i| create_sized_file(temp_dir.path(), MEG1, &format!("small_{}.txt", i)))
+            .collect();
+        for path in small_paths.iter() {
+            cache.insert(path).unwrap();
+        }
+        let path_2m = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        cache.get_source(&path_2m);
+        let token: AdmissionToken = match cache.check(&path_2m) {
+            AdmissionPrediction::Admit(token) => token,
+            other => panic!("Expected the file to be admitted, got {:?}", other),
+        };
+
+        // Another thread removes one of the chosen victims before the insertion is committed.
+        let removed_victim: PathBuf = token.victims()[0].to_path_buf();
+        cache.remove(&removed_victim);
+
+        let cached_file = cache.commit_insert_source(&path_2m, token).unwrap();
+        match cached_file {
+            FileSource::Cached(_) => {}
+            _ => panic!("Expected the file to be in memory"),
+        }
+        // Only one more file had to be evicted once the removal was taken into account.
+        assert_eq!(small_paths.iter().filter(|path| cache.contains_key(path)).count(), 1);
+        let stored_bytes: usize = cache.file_map.iter().map(|(_, file)| file.stats.size).sum();
+        assert_eq!(cache.used_bytes(), stored_bytes);
+        assert_eq!(cache.used_bytes(), MEG1 * 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_deadline_abandons_slow_reads() {
+        use std::process::Command;
+        use std::time::Instant;
+
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG10)
+            .build()
+            .unwrap();
+        cache.set_read_deadline(Some(Duration::from_millis(100)));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+
+        // Opening a FIFO for reading blocks until something opens it for writing, like a hung network filesystem.
+        let fifo_path: PathBuf = temp_dir.path().join("slow.fifo");
+        let status = Command::new("mkfifo").arg(&fifo_path).status().unwrap();
+        assert!(status.success());
+
+        let start: Instant = Instant::now();
+        assert_eq!(cache.insert(&fifo_path), Err(CacheError::CouldNotReadFile));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(cache.used_bytes(), 0);
+
+        // Let the abandoned read finish. Its result must not end up in the cache.
+        {
+            let mut writer = File::create(&fifo_path).unwrap();
+            writer.write_all(&[1, 2, 3, 4]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert!(!cache.contains_key(&fifo_path));
+        assert_eq!(cache.used_bytes(), 0);
+
+        // Files that can be read in time are still inserted.
+        let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let cached_file = cache.get_source(&path_1m);
+        match cached_file {
+            FileSource::Cached(_) => {}
+            _ => panic!("Expected the file to be in memory"),
+        }
+        assert_eq!(cache.used_bytes(), MEG1);
+    }
+
+    #[test]
+    fn abandoned_reads_keep_their_read_permit() {
+        use std::process::Command;
+
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG10)
+            .build()
+            .unwrap();
+        cache.set_read_deadline(Some(Duration::from_millis(100)));
+        cache.set_max_concurrent_reads(Some(1));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let fifo_path: PathBuf = temp_dir.path().join("hung.fifo");
+        assert!(Command::new("mkfifo").arg(&fifo_path).status().unwrap().success());
+        let path_1m = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
 
-                let cached_file: NamedInMemoryFile = NamedInMemoryFile::new(
-                    path.as_ref().to_path_buf(),
-                    cache_file_accessor
-                );
+        assert_eq!(cache.insert(&fifo_path), Err(CacheError::CouldNotReadFile));
+        // The hung read still holds the only permit.
+        assert_eq!(cache.insert(&path_1m), Err(CacheError::CouldNotReadFile));
 
-                return CachedFile::from(cached_file);
-            }
-            Err(_) => return CachedFile::NotFound,
+        {
+            let mut writer = File::create(&fifo_path).unwrap();
+            writer.write_all(&[1, 2, 3, 4]).unwrap();
         }
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.insert(&path_1m), Ok(CacheInsertionSuccess::SimpleAddition));
+        assert_eq!(cache.read_limiter.peak_reads(), 1);
     }
 
+    #[test]
+    fn paranoid_consistency_serves_changes_on_disk() {
+        let mut fast_cache: Cache = CacheBuilder::new().build().unwrap();
+        fast_cache.set_consistency(Consistency::Fast);
+        let mut paranoid_cache: Cache = CacheBuilder::new().build().unwrap();
+        paranoid_cache.set_consistency(Consistency::Paranoid);
 
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = temp_dir.path().join("page.html");
+        fs::write(&path, b"old contents").unwrap();
+        assert_eq!(served_bytes(&fast_cache, &path), Some(b"old contents".to_vec()));
+        assert_eq!(served_bytes(&paranoid_cache, &path), Some(b"old contents".to_vec()));
+
+        fs::write(&path, b"new, longer contents").unwrap();
+        assert_eq!(served_bytes(&fast_cache, &path), Some(b"old contents".to_vec()));
+        assert_eq!(served_bytes(&paranoid_cache, &path), Some(b"new, longer contents".to_vec()));
+        assert_eq!(paranoid_cache.used_bytes(), b"new, longer contents".len());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(served_bytes(&fast_cache, &path), Some(b"old contents".to_vec()));
+        assert_eq!(served_bytes(&paranoid_cache, &path), None);
+        assert!(!paranoid_cache.contains_key(&path));
+        assert_eq!(paranoid_cache.used_bytes(), 0);
+    }
 
-    /// Remove the n lowest priority files to make room for a file with a size: required_space.
-    ///
-    /// If this returns an OK, this function has removed the required file space from the file_map.
-    /// If this returns an Err, then either not enough space could be freed, or the priority of
-    /// files that would need to be freed to make room for the new file is greater than the
-    /// new file's priority, and as result no memory was freed.
-    ///
-    /// # Arguments
-    ///
-    /// * `required_space` - A `usize` representing the number of bytes that must be freed to make room for a new file.
-    /// * `new_file_priority` - A `usize` representing the priority of the new file to be added. If the priority of the files possibly being removed
-    /// is greater than this value, then the files won't be removed.
-    fn make_room_for_new_file(&self, required_space: usize, new_file_priority: usize) -> Result<Vec<PathBuf>, CacheError> {
-        let mut possibly_freed_space: usize = 0;
-        let mut priority_score_to_free: usize = 0;
-        let mut file_paths_to_remove: Vec<PathBuf> = vec![];
-
-        let mut stats: Vec<(PathBuf, FileStats)> = self.sorted_priorities();
-        while possibly_freed_space < required_space {
-            // pop the priority group with the lowest priority off of the vector
-            match stats.pop() {
-                Some(lowest) => {
-                    let (lowest_key, lowest_stats) = lowest;
-
-                    possibly_freed_space += lowest_stats.size;
-                    priority_score_to_free += lowest_stats.priority;
-                    file_paths_to_remove.push(lowest_key.clone());
-
-                    // Check if total priority to free is greater than the new file's priority,
-                    // If it is, then don't free the files, as they in aggregate, are more important
-                    // than the new file.
-                    if priority_score_to_free > new_file_priority {
-                        return Err(CacheError::NewPriorityIsNotHighEnough);
-                    }
-                }
-                None => return Err(CacheError::NoMoreFilesToRemove),
-            };
+    #[test]
+    fn missing_files_are_handled_as_configured() {
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = temp_dir.path().join("deleted.html");
+
+        let mut removing_cache: Cache = CacheBuilder::new().build().unwrap();
+        removing_cache.set_on_missing(OnMissing::Remove);
+        let mut stale_cache: Cache = CacheBuilder::new().build().unwrap();
+        stale_cache.set_on_missing(OnMissing::ServeStale);
+        stale_cache.set_consistency(Consistency::Paranoid);
+
+        fs::write(&path, b"contents").unwrap();
+        removing_cache.insert(&path).unwrap();
+        stale_cache.insert(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(removing_cache.refresh_source(&path), FileSource::FileMissing);
+        assert!(!removing_cache.contains_key(&path));
+        assert_eq!(removing_cache.used_bytes(), 0);
+        assert_eq!(removing_cache.get_source(&path), FileSource::NotFound);
+
+        assert_eq!(stale_cache.refresh_source(&path), FileSource::FileMissing);
+        assert!(stale_cache.contains_key(&path));
+        // Paranoid hits find the file missing, but keep serving the stale copy.
+        let cached_file = stale_cache.get_source(&path);
+        match cached_file {
+            FileSource::Cached(c) => assert_eq!(c.file.get().bytes.as_slice(), b"contents"),
+            _ => panic!("Expected the stale file to be served from the cache"),
         }
-        Ok(file_paths_to_remove)
+        assert_eq!(stale_cache.used_bytes(), b"contents".len());
+    }
 
+    #[test]
+    fn soft_purged_files_are_revalidated_on_the_next_request() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let unchanged: PathBuf = temp_dir.path().join("unchanged.html");
+        let changed: PathBuf = temp_dir.path().join("changed.html");
+        fs::write(&unchanged, b"unchanged").unwrap();
+        fs::write(&changed, b"old contents").unwrap();
+        cache.insert(&unchanged).unwrap();
+        cache.insert(&changed).unwrap();
+        assert!(!cache.soft_purge(temp_dir.path().join("missing.html")));
+        assert_eq!(cache.soft_purge_prefix(temp_dir.path()), 2);
+        assert_eq!(cache.used_bytes(), 21);
+
+        // An unchanged file costs one metadata read, and is no longer stale.
+        let metadata_reads: usize = cache.metadata_reads.load(Ordering::Relaxed);
+        let file_reads: usize = cache.file_reads.load(Ordering::Relaxed);
+        assert_eq!(served_bytes(&cache, &unchanged), Some(b"unchanged".to_vec()));
+        assert_eq!(cache.metadata_reads.load(Ordering::Relaxed), metadata_reads + 1);
+        assert_eq!(cache.file_reads.load(Ordering::Relaxed), file_reads);
+        assert!(!cache.is_soft_purged(&unchanged));
+        served_bytes(&cache, &unchanged);
+        assert_eq!(cache.metadata_reads.load(Ordering::Relaxed), metadata_reads + 1);
+
+        fs::write(&changed, b"new, longer contents").unwrap();
+        assert_eq!(served_bytes(&cache, &changed), Some(b"new, longer contents".to_vec()));
+        assert!(!cache.is_soft_purged(&changed));
+        assert_eq!(cache.used_bytes(), 29);
     }
 
-    ///Helper function that gets the file from the cache if it exists there.
-    fn get_from_cache<P: AsRef<Path>>(&self, path: P) -> CachedFile {
-        match self.file_map.find(&path.as_ref().to_path_buf()) {
-            Some(in_memory_file) => {
-                trace!("Found file: {:?} in cache.", path.as_ref());
-                CachedFile::from(NamedInMemoryFile::new(
-                    path.as_ref().to_path_buf(),
-                    in_memory_file,
-                ))
-            }
-            None => CachedFile::NotFound,
+    #[test]
+    fn soft_purged_files_that_cant_be_revalidated_are_handled_as_configured() {
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = temp_dir.path().join("deleted.html");
+        fs::write(&path, b"contents").unwrap();
+
+        let stale_cache: Cache = CacheBuilder::new().build().unwrap();
+        let mut removing_cache: Cache = CacheBuilder::new().build().unwrap();
+        removing_cache.set_soft_purge_on_missing(OnMissing::Remove);
+        stale_cache.insert(&path).unwrap();
+        removing_cache.insert(&path).unwrap();
+        assert!(stale_cache.soft_purge(&path));
+        assert!(removing_cache.soft_purge(&path));
+        fs::remove_file(&path).unwrap();
+
+        match stale_cache.get_source(&path) {
+            FileSource::Cached(c) => assert_eq!(c.file.get().bytes.as_slice(), b"contents"),
+            _ => panic!("Expected the stale file to be served from the cache"),
         }
+        // The file stays stale, so it is checked again on the next request.
+        assert!(stale_cache.is_soft_purged(&path));
 
+        assert_eq!(removing_cache.get_source(&path), FileSource::NotFound);
+        assert!(!removing_cache.contains_key(&path));
+        assert_eq!(removing_cache.used_bytes(), 0);
     }
 
-    /// Helper function for incrementing the access count for a given file name.
-    ///
-    /// This should only be used in cases where the file is known to exist, to avoid bloating the access count map with useless values.
-    fn increment_access_count<P: AsRef<Path>>(&self, path: P) {
-        self.access_count_map.upsert(
-            path.as_ref().to_path_buf(),
-            1, // insert 1 if nothing at key. The closure will not execute.
-            &|access_count| {
-                *access_count = match usize::checked_add(access_count.clone(), 1) {
-                    Some(v) => v, // return the incremented value
-                    None => usize::MAX, // If the access count bumps up against the usize max, keep the value the same.
-                }
-            },
+    #[test]
+    fn manifests_refresh_remove_and_preload_the_files_they_list() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let root: &Path = temp_dir.path();
+        fs::write(root.join("changed.css"), b"old").unwrap();
+        fs::write(root.join("deleted.js"), b"deleted").unwrap();
+        cache.insert(root.join("changed.css")).unwrap();
+        cache.insert(root.join("deleted.js")).unwrap();
+        fs::write(root.join("changed.css"), b"new contents").unwrap();
+        fs::remove_file(root.join("deleted.js")).unwrap();
+        fs::write(root.join("new.html"), b"new page").unwrap();
+
+        let mut manifest: Vec<u8> = b"# Changed by the deploy\n\nchanged.css\n  deleted.js\r\n/etc/passwd\n".to_vec();
+        manifest.extend_from_slice(b"\xff\xfe\nnew.html\nmissing.html\n");
+        let report: ManifestReport = cache.refresh_from_manifest(io::Cursor::new(manifest.clone()), Some(root), true);
+        assert_eq!(
+            report.outcomes,
+            vec![
+                (root.join("changed.css"), ManifestOutcome::Refreshed),
+                (root.join("deleted.js"), ManifestOutcome::Removed),
+                (root.join("new.html"), ManifestOutcome::Preloaded),
+                (root.join("missing.html"), ManifestOutcome::Failed(CacheError::InvalidMetadata)),
+            ]
         );
+        assert_eq!(report.errors, vec![(5, ManifestError::NotRelative), (6, ManifestError::InvalidUtf8)]);
+        assert_eq!(cache.used_bytes(), 20);
+        assert_eq!(read_all(cache.get_source(root.join("changed.css"))), b"new contents".to_vec());
+        assert!(!cache.contains_key(root.join("deleted.js")));
+        assert!(cache.contains_key(root.join("new.html")));
+
+        // Without preloading, files that aren't in the cache are left alone.
+        cache.remove(root.join("new.html"));
+        let report: ManifestReport = cache.refresh_from_manifest(io::Cursor::new(manifest), Some(root), false);
+        assert_eq!(report.outcomes[2], (root.join("new.html"), ManifestOutcome::NotCached));
+        assert_eq!(report.outcomes[3], (root.join("missing.html"), ManifestOutcome::NotCached));
+        assert!(!cache.contains_key(root.join("new.html")));
     }
 
+    #[test]
+    fn resolve_leaves_passed_through_files_for_the_caller_to_open() {
+        fn reason(resolution: Resolution) -> Option<ServeClass> {
+            match resolution {
+                Resolution::PassThrough { reason, .. } => Some(reason),
+                _ => None,
+            }
+        }
 
-    /// Update the stats associated with this file.
-    fn update_stats<P: AsRef<Path>>(&self, path: P) {
-
-        let access_count: usize = match self.access_count_map.find(&path.as_ref().to_path_buf()) {
-            Some(access_count) => access_count.get().clone(),
-            None => 1,
-        };
-
-        self.file_map.upsert(
-            // Key
-            path.as_ref().to_path_buf(),
-            // Default Value
-            InMemoryFile {
-                bytes: Vec::new(),
-                stats: FileStats {
-                    size: 0,
-                    access_count: 0,
-                    priority: 0,
-                },
-            },
-            // Update Function
-            &|file_entry| {
-                // If the size is initialized to 0, then try to get the actual size from the filesystem
-                if file_entry.stats.size == 0 {
-                    file_entry.stats.size = Cache::get_file_size_from_metadata(&path.as_ref().to_path_buf()).unwrap_or(0);
-                }
-                file_entry.stats.access_count = access_count;
-                file_entry.stats.priority = (self.priority_function)(file_entry.stats.access_count, file_entry.stats.size); // update the priority score.
-            },
-        );
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(1000)
+            .min_file_size(10)
+            .max_file_size(1000)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let resident = create_sized_file(temp_dir.path(), 1000, "resident.txt");
+        let newcomer = create_sized_file(temp_dir.path(), 1000, "newcomer.txt");
+        let small = create_sized_file(temp_dir.path(), 5, "small.txt");
+        let large = create_sized_file(temp_dir.path(), 2000, "large.txt");
+        let bypassed = create_sized_file(temp_dir.path(), 100, "bypassed.txt");
+        let blocked = create_sized_file(temp_dir.path(), 100, "blocked.txt");
+        cache.always_bypass(bypassed.clone());
+        cache.remove_and_block(&blocked, Duration::from_secs(60));
+
+        for _ in 0..3 {
+            match cache.resolve(&resident) {
+                Resolution::Cached(cached_file) => assert_eq!(cached_file.file.get().stats.size, 1000),
+                _ => panic!("Expected the file to be served from the cache."),
+            }
+        }
+        match cache.resolve(&large) {
+            Resolution::PassThrough { path, reason } => {
+                assert_eq!(path, large);
+                assert_eq!(reason, ServeClass::FsTooLarge);
+            }
+            _ => panic!("Expected the file to be passed through."),
+        }
+        assert_eq!(reason(cache.resolve(&small)), Some(ServeClass::FsTooSmall));
+        assert_eq!(reason(cache.resolve(&newcomer)), Some(ServeClass::FsPriorityTooLow));
+        assert_eq!(reason(cache.resolve(&bypassed)), Some(ServeClass::FsBypassed));
+        assert_eq!(reason(cache.resolve(&blocked)), Some(ServeClass::FsBlocked));
+        cache.set_frozen(true);
+        assert_eq!(reason(cache.resolve(&newcomer)), Some(ServeClass::FsManual));
+        cache.set_frozen(false);
+        match cache.resolve(temp_dir.path().join("missing.txt")) {
+            Resolution::NotFound => {}
+            _ => panic!("Expected the missing file not to be found."),
+        }
+        assert_eq!(cache.fs_opens.load(Ordering::Relaxed), 0);
 
+        let disabled_cache: Cache = CacheBuilder::new().size_limit(0).build().unwrap();
+        assert_eq!(reason(disabled_cache.resolve(&resident)), Some(ServeClass::FsDisabled));
+        assert_eq!(disabled_cache.fs_opens.load(Ordering::Relaxed), 0);
 
+        // Getting the file opens it for the response.
+        match cache.get_source(&large) {
+            FileSource::Fs(..) => {}
+            _ => panic!("Expected the file to be served from the filesystem."),
+        }
+        assert_eq!(cache.fs_opens.load(Ordering::Relaxed), 1);
     }
 
+    #[test]
+    fn periodic_refresh_removes_missing_files() {
+        let cache: Cache = CacheBuilder::new()
+            .accesses_per_refresh(2)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = temp_dir.path().join("deleted.html");
+        fs::write(&path, b"contents").unwrap();
+
+        cache.insert(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // The second access triggers a refresh, which finds the file missing.
+        assert_eq!(cache.get_source(&path), FileSource::NotFound);
+        assert!(!cache.contains_key(&path));
+    }
 
+    #[test]
+    fn watermarks_evict_in_batches() {
+        const KB1: usize = 1024;
 
+        fn eviction_sizes(cache: &Cache, paths: &[PathBuf]) -> Vec<usize> {
+            paths
+                .iter()
+                .filter_map(|path| {
+                    let evicted_files: Option<usize> = match cache.insert(path) {
+                        Ok(CacheInsertionSuccess::SimpleAddition) => None,
+                        Ok(CacheInsertionSuccess::ReplacedFile(evicted_entries)) => Some(evicted_entries.len()),
+                        Ok(CacheInsertionSuccess::ReplacedExistingEntry) => panic!("{:?} was inserted twice", path),
+                        Err(error) => panic!("Expected {:?} to be inserted, got {:?}", path, error),
+                    };
+                    assert!(cache.used_bytes() <= KB1 * 10);
+                    evicted_files
+                })
+                .collect()
+        }
 
-
-    /// Gets a vector of tuples containing the Path, priority score, and size in bytes of all items
-    /// in the file_map.
-    ///
-    /// The vector is sorted from highest to lowest priority.
-    /// This allows the assumption that the last element to be popped from the vector will have the
-    /// lowest priority, and therefore is the most eligible candidate for elimination from the
-    /// cache.
-    ///
-    fn sorted_priorities(&self) -> Vec<(PathBuf, FileStats)> {
-
-        let mut priorities: Vec<(PathBuf, FileStats)> = self.file_map
-            .iter()
-            .map(|x| (x.0.clone(), x.1.stats.clone()))
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let paths: Vec<PathBuf> = (0..30)
+            .map(|i| create_sized_file(temp_dir.path(), KB1, &format!("stream_{}.txt", i)))
             .collect();
 
-        // Sort the priorities from highest priority to lowest, so when they are pop()ed later,
-        // the last element will have the lowest priority.
-        priorities.sort_by(|l, r| r.1.priority.cmp(&l.1.priority));
-        priorities
+        let default_cache: Cache = CacheBuilder::new()
+            .size_limit(KB1 * 10)
+            .build()
+            .unwrap();
+        let mut batched_cache: Cache = CacheBuilder::new()
+            .size_limit(KB1 * 10)
+            .build()
+            .unwrap();
+        batched_cache.set_watermarks(KB1 * 5, KB1 * 10);
+
+        // Once full, every insertion evicts exactly one file.
+        assert_eq!(eviction_sizes(&default_cache, &paths), vec![1; 20]);
+        // Crossing the high watermark evicts down to the low watermark, leaving room for the next 5 files.
+        assert_eq!(eviction_sizes(&batched_cache, &paths), vec![6; 4]);
+        assert_eq!(batched_cache.used_bytes(), KB1 * 6);
     }
-}
 
+    #[test]
+    fn ties_are_broken_deterministically() {
+        const KB1: usize = 1024;
 
+        fn newest_first(path: &Path, stats: &FileStats, other_path: &Path, other_stats: &FileStats) -> cmp::Ordering {
+            default_tie_breaker(other_path, other_stats, path, stats)
+        }
 
-#[cfg(test)]
-mod tests {
-    extern crate test;
-    extern crate tempdir;
-    extern crate rand;
+        fn victim(cache: &Cache, paths: &[PathBuf]) -> PathBuf {
+            cache.insert(&paths[0]).unwrap();
+            cache.insert(&paths[1]).unwrap();
+            match cache.insert(&paths[2]) {
+                Ok(CacheInsertionSuccess::ReplacedFile(ref evicted_entries)) if evicted_entries.len() == 1 => {
+                    evicted_entries[0].path.clone()
+                }
+                other => panic!("Expected exactly one file to be evicted, got {:?}", other),
+            }
+        }
 
-    use super::*;
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        // The files have the same size and access count, so they all have the same priority.
+        // The path of the older file sorts last, so only its last access decides that it is evicted.
+        let paths: Vec<PathBuf> = vec![
+            create_sized_file(temp_dir.path(), KB1, "b_older.txt"),
+            create_sized_file(temp_dir.path(), KB1, "a_newer.txt"),
+            create_sized_file(temp_dir.path(), KB1, "c_new.txt"),
+        ];
+
+        for _ in 0..20 {
+            let cache: Cache = CacheBuilder::new()
+                .size_limit(KB1 * 2)
+                .build()
+                .unwrap();
+            assert_eq!(victim(&cache, &paths), paths[0]);
+
+            let mut cache: Cache = CacheBuilder::new()
+                .size_limit(KB1 * 2)
+                .build()
+                .unwrap();
+            cache.set_tie_breaker(newest_first);
+            assert_eq!(victim(&cache, &paths), paths[1]);
+        }
 
-    use self::tempdir::TempDir;
-    use self::test::Bencher;
-    use self::rand::rngs::StdRng;
-    use std::io::{Write, BufWriter};
-    use std::fs::File;
-    use rocket::response::NamedFile;
-    use std::io::Read;
-    use in_memory_file::InMemoryFile;
-    use concurrent_hashmap::Accessor;
-    use std::sync::Arc;
-    use std::mem;
-    use cache_builder::CacheBuilder;
-    use self::rand::FromEntropy;
-    use self::rand::RngCore;
+        let stats = |size: usize| FileStats {
+            size,
+            access_count: 1,
+            priority: 1,
+            last_access: 1,
+        };
+        // With the same last access, the larger file goes first, then the path that sorts first.
+        assert_eq!(default_tie_breaker(Path::new("b"), &stats(2), Path::new("a"), &stats(1)), cmp::Ordering::Less);
+        assert_eq!(default_tie_breaker(Path::new("a"), &stats(1), Path::new("b"), &stats(1)), cmp::Ordering::Less);
+    }
 
+    #[test]
+    fn frozen_cache_does_not_change() {
+        fn access_counts(cache: &Cache) -> Vec<(PathBuf, usize)> {
+            let mut access_counts: Vec<(PathBuf, usize)> = cache.access_count_map
+                .iter()
+                .map(|(path, access_count)| (path.clone(), *access_count))
+                .collect();
+            access_counts.sort();
+            access_counts
+        }
 
-    const MEG1: usize = 1024 * 1024;
-    const MEG2: usize = MEG1 * 2;
-    const MEG5: usize = MEG1 * 5;
-    const MEG10: usize = MEG1 * 10;
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 10)
+            .build()
+            .unwrap();
+        cache.set_decision_log(Some(10));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let cached_path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let uncached_path = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        cache.insert(&cached_path).unwrap();
+
+        cache.set_frozen(true);
+        cache.clear_decisions();
+        let counts_before: Vec<(PathBuf, usize)> = access_counts(&cache);
+        let stats_before: Option<FileStats> = cache.stats_for(&cached_path);
+
+        match cache.get_source(&cached_path) {
+            FileSource::Cached(_) => {}
+            _ => panic!("Expected hits to be served from the cache"),
+        }
+        match cache.get_source(&uncached_path) {
+            FileSource::Fs(..) => {}
+            _ => panic!("Expected misses to be served from the filesystem"),
+        }
+        assert_eq!(cache.insert(&uncached_path), Err(CacheError::CacheFrozen));
+        assert!(!cache.remove(&cached_path));
+        assert!(!cache.alter_access_count(&cached_path, |_| 100));
+        cache.refresh_source(&cached_path);
+
+        assert_eq!(access_counts(&cache), counts_before);
+        assert_eq!(cache.stats_for(&cached_path), stats_before);
+        assert_eq!(cache.tracked_paths(), vec![cached_path.clone()]);
+        assert_eq!(cache.used_bytes(), MEG1);
+        assert_eq!(cache.file_reads.load(Ordering::Relaxed), 1);
+        assert!(cache.recent_decisions().is_empty());
+
+        cache.set_frozen(false);
+        match cache.get_source(&uncached_path) {
+            FileSource::Cached(_) => {}
+            _ => panic!("Expected the file to be inserted once the cache is unfrozen"),
+        }
+        assert_eq!(cache.used_bytes(), MEG1 + MEG2);
+        assert!(cache.remove(&cached_path));
+    }
 
-    const DIR_TEST: &'static str = "test1";
-    const FILE_MEG1: &'static str = "meg1.txt";
-    const FILE_MEG2: &'static str = "meg2.txt";
-    const FILE_MEG5: &'static str = "meg5.txt";
-    const FILE_MEG10: &'static str = "meg10.txt";
+    #[test]
+    fn check_invariants_catches_each_kind_of_violation() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_sized_file(temp_dir.path(), 100, "a.txt");
+        let path_b = create_sized_file(temp_dir.path(), 100, "b.txt");
+        cache.get_source(&path_a);
+        cache.get_source(&path_b);
+        cache.get_source(&path_b);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        let violations = |cache: &Cache| cache.check_invariants().unwrap_err();
+
+        cache.file_map.find_mut(&path_a).unwrap().get().stats.size = 90;
+        let found: Vec<InvariantViolation> = violations(&cache);
+        assert!(found.contains(&InvariantViolation::SizeMismatch { path: path_a.clone(), stats_size: 90, bytes: 100 }), "{:?}", found);
+        cache.file_map.find_mut(&path_a).unwrap().get().stats.size = 100;
+
+        cache.add_space(5);
+        assert_eq!(violations(&cache), vec![InvariantViolation::UsedBytesDrift { counted: 205, actual: 200 }]);
+        cache.release_space(5);
+
+        cache.access_count_map.remove(&path_b);
+        assert_eq!(violations(&cache), vec![InvariantViolation::MissingAccessCount { path: path_b.clone() }]);
+        cache.access_count_map.insert(path_b.clone(), 2);
+
+        let priority: usize = cache.stats_for(&path_b).unwrap().priority;
+        cache.file_map.find_mut(&path_b).unwrap().get().stats.priority = priority + 1;
+        assert_eq!(violations(&cache), vec![InvariantViolation::StalePriority { path: path_b.clone(), priority: priority + 1, expected: priority }]);
+        cache.file_map.find_mut(&path_b).unwrap().get().stats.priority = priority;
+
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 
-    // Helper function that creates test files in a directory that is cleaned up after the test runs.
-    fn create_test_file(temp_dir: &TempDir, size: usize, name: &str) -> PathBuf {
-        let path = temp_dir.path().join(name);
-        let tmp_file = File::create(path.clone()).unwrap();
-        let mut rand_data: Vec<u8> = vec![0u8; size];
-        StdRng::from_entropy().fill_bytes(rand_data.as_mut());
-        let mut buffer = BufWriter::new(tmp_file);
-        buffer.write(&rand_data).unwrap();
-        path
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "The invariants of the cache are broken")]
+    fn strict_mode_panics_on_broken_invariants() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_a = create_sized_file(temp_dir.path(), 100, "a.txt");
+        let path_b = create_sized_file(temp_dir.path(), 100, "b.txt");
+        cache.get_source(&path_a);
+        cache.get_source(&path_b);
+        cache.add_space(5);
+        cache.remove(&path_a);
     }
 
+    #[test]
+    fn verify_detects_and_repair_fixes_inconsistencies() {
+        let mut cache: Cache = CacheBuilder::new().build().unwrap();
+        // The inconsistencies are made on purpose, to be found by `verify()`.
+        cache.set_strict_invariants(false);
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let resized_path: PathBuf = temp_dir.path().join("resized.txt");
+        let rewritten_path: PathBuf = temp_dir.path().join("rewritten.txt");
+        let deleted_path: PathBuf = temp_dir.path().join("deleted.txt");
+        let uncounted_path: PathBuf = temp_dir.path().join("uncounted.txt");
+        for path in &[&resized_path, &rewritten_path, &deleted_path, &uncounted_path] {
+            fs::write(path, b"original").unwrap();
+            cache.insert(path).unwrap();
+        }
+        assert!(cache.verify().is_consistent());
+
+        thread::sleep(Duration::from_millis(20));
+        fs::write(&resized_path, b"longer contents").unwrap();
+        fs::write(&rewritten_path, b"modified").unwrap();
+        fs::remove_file(&deleted_path).unwrap();
+        cache.access_count_map.remove(&uncounted_path);
+        cache.add_space(5);
+
+        let report: VerifyReport = cache.verify();
+        let mut expected: Vec<Inconsistency> = vec![
+            Inconsistency::SizeDiffers { path: resized_path.clone(), cached_size: 8, file_size: 15 },
+            Inconsistency::ModifiedSinceCached { path: rewritten_path.clone() },
+            Inconsistency::FileMissing { path: deleted_path.clone() },
+            Inconsistency::MissingAccessCount { path: uncounted_path.clone() },
+            Inconsistency::UsedBytesDrift { counted: 8 * 4 + 5, actual: 8 * 4 },
+        ];
+        for inconsistency in expected.drain(..) {
+            assert!(report.inconsistencies.contains(&inconsistency), "{:?} was not reported", inconsistency);
+        }
+        assert_eq!(report.inconsistencies.len(), 5);
+        // Verifying doesn't change anything.
+        assert_eq!(cache.verify(), report);
+        assert_eq!(cache.used_bytes(), 8 * 4 + 5);
+
+        cache.repair(&report);
+        assert!(cache.verify().is_consistent());
+        assert!(!cache.contains_key(&deleted_path));
+        assert_eq!(cache.stats_for(&resized_path).unwrap().size, 15);
+        assert_eq!(*cache.access_count_map.find(&uncounted_path).unwrap().get(), 1);
+        match cache.get_source(&rewritten_path) {
+            FileSource::Cached(c) => assert_eq!(c.file.get().bytes.as_slice(), b"modified"),
+            _ => panic!("Expected the file to be in memory"),
+        }
+        assert_eq!(cache.used_bytes(), 8 * 2 + 15);
+    }
 
-    // Standardize the way a file is used in these tests.
-    impl<'a> CachedFile<'a> {
-        fn dummy_write(self) {
-            match self {
-                CachedFile::InMemory(cached_file) => unsafe {
-                    let file: *const Accessor<'a, PathBuf, InMemoryFile> = Arc::into_raw(cached_file.file);
-                    let mut v: Vec<u8> = Vec::new();
-                    let _ = (*file).get().bytes.as_slice().read_to_end(&mut v).unwrap();
-                    let _ = Arc::from_raw(file); // To prevent a memory leak, an Arc needs to be reconstructed from the raw pointer.
-                },
-                CachedFile::FileSystem(mut named_file) => {
-                    let mut v: Vec<u8> = Vec::new();
-                    let _ = named_file.read_to_end(&mut v).unwrap();
-                }
-                CachedFile::NotFound => {
-                    panic!("tried to write using a non-existent file")
-                }
-            }
+    #[test]
+    fn decision_log_keeps_recent_decisions() {
+        fn outcomes(cache: &Cache) -> Vec<(PathBuf, Outcome)> {
+            cache.recent_decisions()
+                .into_iter()
+                .map(|decision| (decision.path.to_path_buf(), decision.outcome))
+                .collect()
         }
 
-        fn get_in_memory_file(self) -> NamedInMemoryFile<'a> {
-            match self {
-                CachedFile::InMemory(n) => n,
-                _ => panic!("tried to get cached file for named file"),
+        let mut cache: Cache = CacheBuilder::new()
+            .max_file_size(MEG1)
+            .build()
+            .unwrap();
+        cache.set_decision_log(Some(4));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let small_path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let large_path = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        let missing_path: PathBuf = temp_dir.path().join("missing.txt");
+
+        cache.get_source(&small_path);
+        assert_eq!(outcomes(&cache), vec![(small_path.clone(), Outcome::MissInserted)]);
+
+        cache.get_source(&small_path);
+        cache.get_source(&missing_path);
+        cache.get_source(&large_path);
+        cache.get_source(&large_path);
+        // The first decision was overwritten once the log was full.
+        assert_eq!(
+            outcomes(&cache),
+            vec![
+                (small_path.clone(), Outcome::Hit),
+                (missing_path.clone(), Outcome::Error),
+                (large_path.clone(), Outcome::MissServedFromFs(CacheError::FileSizeOutOfBounds)),
+                (large_path.clone(), Outcome::MissServedFromFs(CacheError::FileSizeOutOfBounds)),
+            ]
+        );
+        let decisions: Vec<Decision> = cache.recent_decisions();
+        assert!(decisions.windows(2).all(|pair| pair[0].time <= pair[1].time));
 
-            }
-        }
+        cache.clear_decisions();
+        assert!(cache.recent_decisions().is_empty());
+        cache.get_source(&small_path);
+        assert_eq!(outcomes(&cache), vec![(small_path.clone(), Outcome::Hit)]);
 
-        fn get_named_file(self) -> NamedFile {
-            match self {
-                CachedFile::FileSystem(n) => n,
-                _ =>  panic!("tried to get cached file for named file"),
-            }
-        }
+        cache.set_decision_log(None);
+        cache.get_source(&small_path);
+        assert!(cache.recent_decisions().is_empty());
     }
 
-    #[bench]
-    fn cache_get_10mb(b: &mut Bencher) {
+    #[test]
+    fn prefetch_inserts_the_highest_priority_files_within_budget() {
         let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1 * 20)
+            .size_limit(MEG10)
             .build()
             .unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_10m = create_test_file(&temp_dir, MEG10, FILE_MEG10);
-        cache.get(&path_10m); // add the 10 mb file to the cache
-
-        b.iter(|| {
-            let cached_file = cache.get(&path_10m);
-            cached_file.dummy_write()
-        });
+        let paths: Vec<PathBuf> = (0..3)
+            .map(|i| create_sized_file(temp_dir.path(), MEG1, &format!("prefetch_{}.txt", i)))
+            .collect();
+        // The first file is the most popular, and the last file the least popular.
+        for (i, path) in paths.iter().enumerate() {
+            for _ in 0..(3 - i) {
+                cache.get_source(path);
+            }
+            cache.remove(path);
+        }
+        assert_eq!(cache.used_bytes(), 0);
+
+        let report: PrefetchReport = cache.prefetch_top(MEG2);
+        assert_eq!(report.inserted, vec![paths[0].clone(), paths[1].clone()]);
+        assert_eq!(report.skipped, vec![(paths[2].clone(), PrefetchSkipReason::OverBudget)]);
+        assert_eq!(report.inserted_bytes, MEG2);
+        assert!(cache.contains_key(&paths[0]));
+        assert!(cache.contains_key(&paths[1]));
+        assert!(!cache.contains_key(&paths[2]));
+        assert_eq!(cache.used_bytes(), MEG2);
+        // Prefetching doesn't count as an access.
+        assert_eq!(cache.stats_for(&paths[0]).unwrap().access_count, 3);
     }
 
-    #[bench]
-    fn cache_miss_10mb(b: &mut Bencher) {
+    #[test]
+    fn priority_grows_with_access_count() {
         let cache: Cache = CacheBuilder::new()
-            .size_limit(0)
+            .size_limit(MEG1 * 10)
             .build()
             .unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_10m = create_test_file(&temp_dir, MEG10, FILE_MEG10);
+        let popular_path = create_sized_file(temp_dir.path(), MEG1, "popular.txt");
+        let unpopular_path = create_sized_file(temp_dir.path(), MEG1, "unpopular.txt");
 
-        b.iter(|| {
-            let cached_file = cache.get(&path_10m);
-            cached_file.dummy_write()
-        });
-    }
+        cache.get_source(&unpopular_path);
+        for _ in 0..100 {
+            cache.get_source(&popular_path);
+        }
 
-    #[bench]
-    fn named_file_read_10mb(b: &mut Bencher) {
-        let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_10m = create_test_file(&temp_dir, MEG10, FILE_MEG10);
-        b.iter(|| {
-            let named_file = CachedFile::from(NamedFile::open(&path_10m).unwrap());
-            named_file.dummy_write()
-        });
+        let popular_stats: FileStats = cache.stats_for(&popular_path).unwrap();
+        let unpopular_stats: FileStats = cache.stats_for(&unpopular_path).unwrap();
+        assert_eq!(popular_stats.access_count, 100);
+        assert_eq!(unpopular_stats.access_count, 1);
+        assert!(popular_stats.priority > unpopular_stats.priority);
     }
 
-    #[bench]
-    fn cache_get_1mb(b: &mut Bencher) {
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1 * 20)
+    #[test]
+    fn evictions_use_current_counts_when_priorities_are_stale() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG1 * 2)
             .build()
-            .unwrap(); //Cache can hold 20Mb
+            .unwrap();
+        cache.set_priority_recompute(Recompute::OnInsertOnly);
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_1m = create_test_file(&temp_dir, MEG1, FILE_MEG1);
-        cache.get(&path_1m); // add the 10 mb file to the cache
+        let popular_path = create_sized_file(temp_dir.path(), MEG1, "popular.txt");
+        let unpopular_path = create_sized_file(temp_dir.path(), MEG1, "unpopular.txt");
+        let new_path = create_sized_file(temp_dir.path(), MEG1, "new.txt");
+
+        cache.get_source(&popular_path);
+        cache.get_source(&unpopular_path);
+        for _ in 0..100 {
+            cache.get_source(&popular_path);
+        }
+        // The stored priority is only calculated on insertion.
+        let popular_stats: FileStats = cache.stats_for(&popular_path).unwrap();
+        assert_eq!(popular_stats.access_count, 101);
+        assert_eq!(popular_stats.priority, default_priority_function(1, MEG1));
 
-        b.iter(|| {
-            let cached_file = cache.get(&path_1m);
-            cached_file.dummy_write()
-        });
+        for _ in 0..3 {
+            cache.get_source(&new_path);
+        }
+        assert!(cache.contains_key(&new_path));
+        assert!(cache.contains_key(&popular_path));
+        assert!(!cache.contains_key(&unpopular_path));
     }
 
-    #[bench]
-    fn cache_miss_1mb(b: &mut Bencher) {
+    #[test]
+    fn alias_shares_entry_with_target() {
         let cache: Cache = CacheBuilder::new()
-            .size_limit(0)
+            .size_limit(MEG1 * 10)
             .build()
             .unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_1m = create_test_file(&temp_dir, MEG1, FILE_MEG1);
+        let path = create_sized_file(temp_dir.path(), MEG1, "app.js");
+        let alias = temp_dir.path().join("app.3f9c.js");
+
+        assert!(cache.add_alias(alias.clone(), path.clone()));
+        assert!(!cache.add_alias(path.clone(), alias.clone()));
+        assert!(!cache.add_alias(alias.clone(), alias.clone()));
+
+        cache.get_source(&path).get_in_memory_file();
+        cache.get_source(&alias).get_in_memory_file();
+        assert!(cache.contains_key(&alias));
+        assert_eq!(cache.summary().file_count, 1);
+        assert_eq!(cache.used_bytes(), MEG1);
+
+        // Refreshing the alias updates the bytes served under the target.
+        let new_path = create_sized_file(temp_dir.path(), MEG2, "app.js");
+        let new_file: InMemoryFile = InMemoryFile::open(&new_path).unwrap();
+        cache.refresh_source(&alias);
+        assert_eq!(
+            cache.get_source(&path)
+                .get_in_memory_file()
+                .file
+                .get()
+                .bytes,
+            new_file.bytes
+        );
 
-        b.iter(|| {
-            let cached_file = cache.get(&path_1m);
-            cached_file.dummy_write()
-        });
+        assert!(cache.remove_alias(&alias));
+        assert!(!cache.contains_key(&alias));
+        assert!(cache.contains_key(&path));
     }
 
-    #[bench]
-    fn named_file_read_1mb(b: &mut Bencher) {
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_files_are_only_counted_on_request() {
+        use std::os::unix::net::UnixListener;
+
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_1m = create_test_file(&temp_dir, MEG1, FILE_MEG1);
+        // The metadata of a socket can be read, but it can't be opened, even by root.
+        let socket_path = temp_dir.path().join("socket");
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+
+        let mut cache: Cache = CacheBuilder::new().size_limit(MEG1 * 2).build().unwrap();
+        cache.set_count_policy(CountPolicy::OnSuccess);
+        for _ in 0..3 {
+            assert!(cache.get_source(&socket_path) == FileSource::NotFound);
+        }
+        assert!(cache.access_count_map.find(&socket_path).is_none());
+        cache.get_source(&path).get_in_memory_file();
+        assert_eq!(cache.stats_for(&path).unwrap().access_count, 1);
 
-        b.iter(|| {
-            let named_file = CachedFile::from(NamedFile::open(&path_1m).unwrap());
-            named_file.dummy_write()
-        });
+        cache.set_count_policy(CountPolicy::OnRequest);
+        for _ in 0..3 {
+            assert!(cache.get_source(&socket_path) == FileSource::NotFound);
+        }
+        assert_eq!(*cache.access_count_map.find(&socket_path).unwrap().get(), 3);
     }
 
-
-
-    #[bench]
-    fn cache_get_5mb(b: &mut Bencher) {
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1 * 20)
-            .build()
-            .unwrap();
+    #[cfg(unix)]
+    #[test]
+    fn replaced_files_are_only_detected_by_identity() {
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_5m = create_test_file(&temp_dir, MEG5, FILE_MEG5);
-        cache.get(&path_5m); // add the 10 mb file to the cache
-
-        b.iter(|| {
-            let cached_file = cache.get(&path_5m);
-            cached_file.dummy_write()
-        });
+        let path = temp_dir.path().join("app.js");
+        let replacement = temp_dir.path().join("app.js.new");
+        fs::write(&path, b"old").unwrap();
+
+        let mut cache: Cache = CacheBuilder::new().size_limit(MEG1).build().unwrap();
+        cache.set_consistency(Consistency::Paranoid);
+        assert_eq!(read_all(cache.get_source(&path)), b"old".to_vec());
+
+        // The replacement has the same size and modification time, so only its inode differs.
+        fs::write(&replacement, b"new").unwrap();
+        let modified: SystemTime = fs::metadata(&path).unwrap().modified().unwrap();
+        File::options().write(true).open(&replacement).unwrap().set_modified(modified).unwrap();
+        fs::rename(&replacement, &path).unwrap();
+
+        assert_eq!(read_all(cache.get_source(&path)), b"old".to_vec());
+        cache.set_revalidation(Revalidation::ByIdentity);
+        assert_eq!(read_all(cache.get_source(&path)), b"new".to_vec());
+        assert_eq!(read_all(cache.get_source(&path)), b"new".to_vec());
     }
 
-    #[bench]
-    fn cache_miss_5mb(b: &mut Bencher) {
+    #[test]
+    fn stats_are_kept_for_each_extension() {
         let cache: Cache = CacheBuilder::new()
-            .size_limit(0)
+            .size_limit(MEG2)
             .build()
             .unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_5m = create_test_file(&temp_dir, MEG5, FILE_MEG5);
+        let script = create_sized_file(temp_dir.path(), MEG1 / 2, "app.js");
+        let image = create_sized_file(temp_dir.path(), MEG1 / 2, "logo.PNG");
+        let license = create_sized_file(temp_dir.path(), MEG1, "LICENSE");
+        let bundle = create_sized_file(temp_dir.path(), MEG1, "bundle.JS");
 
-        b.iter(|| {
-            let cached_file = cache.get(&path_5m);
-            cached_file.dummy_write()
-        });
+        for _ in 0..3 {
+            cache.get_source(&script);
+        }
+        cache.get_source(&image);
+        cache.get_source(&image);
+        cache.get_source(&license);
+        // The license has the lowest priority, so it makes room for the bundle.
+        cache.get_source(&bundle);
+        assert!(!cache.contains_key(&license));
+
+        let stats: Vec<(String, ExtensionStats)> = cache.extension_stats();
+        assert_eq!(
+            stats,
+            vec![
+                (String::new(), ExtensionStats { resident_bytes: 0, file_count: 0, hits: 0, misses: 1, insertions: 1, evictions: 1 }),
+                (String::from("js"), ExtensionStats { resident_bytes: MEG1 + MEG1 / 2, file_count: 2, hits: 2, misses: 2, insertions: 2, evictions: 0 }),
+                (String::from("png"), ExtensionStats { resident_bytes: MEG1 / 2, file_count: 1, hits: 1, misses: 1, insertions: 1, evictions: 0 }),
+            ]
+        );
+        assert_eq!(stats[1].1.hit_rate(), 0.5);
+        assert!(cache.summary().to_json().contains(&format!(
+            "\"extensions\":{{\"\":{{\"resident_bytes\":0,\"file_count\":0,\"hits\":0,\"misses\":1,\"insertions\":1,\"evictions\":1}},\"js\":{{\"resident_bytes\":{},",
+            MEG1 + MEG1 / 2
+        )));
     }
 
-    #[bench]
-    fn named_file_read_5mb(b: &mut Bencher) {
+    #[test]
+    fn eviction_filter_can_keep_files_in_the_cache() {
+        let mut cache: Cache = CacheBuilder::new().size_limit(3000).build().unwrap();
+        cache.set_eviction_filter(Some(Box::new(|path: &Path, _stats: &FileStats| match path.extension() {
+            Some(extension) if extension == "woff2" => EvictionVote::Deny,
+            Some(extension) if extension == "txt" => EvictionVote::Prefer,
+            _ => EvictionVote::Allow,
+        })));
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_5m = create_test_file(&temp_dir, MEG5, FILE_MEG5);
+        let path_font = create_sized_file(temp_dir.path(), 1000, "font.woff2");
+        let path_style = create_sized_file(temp_dir.path(), 1000, "style.css");
+        let path_notes = create_sized_file(temp_dir.path(), 1000, "notes.txt");
+        let path_print = create_sized_file(temp_dir.path(), 1000, "print.css");
+        let path_bold = create_sized_file(temp_dir.path(), 1000, "bold.woff2");
+        let path_italic = create_sized_file(temp_dir.path(), 1000, "italic.woff2");
+        let path_theme = create_sized_file(temp_dir.path(), 1000, "theme.css");
+
+        for path in &[&path_font, &path_style, &path_notes] {
+            assert_eq!(cache.insert(path), Ok(CacheInsertionSuccess::SimpleAddition));
+        }
+        // The preferred file goes first, even though the stylesheet was used longer ago.
+        let evicted = |result: Result<CacheInsertionSuccess, CacheError>| match result {
+            Ok(CacheInsertionSuccess::ReplacedFile(evicted_entries)) => evicted_entries.into_iter().map(|entry| entry.path).collect::<Vec<PathBuf>>(),
+            result => panic!("Expected files to be evicted, got {:?}", result),
+        };
+        assert_eq!(evicted(cache.insert(&path_print)), vec![path_notes.clone()]);
+        // The font is skipped, so the stylesheets are evicted instead.
+        assert_eq!(evicted(cache.insert(&path_bold)), vec![path_style.clone()]);
+        assert_eq!(evicted(cache.insert(&path_italic)), vec![path_print.clone()]);
+
+        // Only fonts are left, so nothing can make room for another file.
+        assert_eq!(cache.insert(&path_theme), Err(CacheError::AllCandidatesVetoed));
+        assert_eq!(cache.used_bytes(), 3000);
+        assert!(cache.contains_key(&path_font) && cache.contains_key(&path_bold) && cache.contains_key(&path_italic));
+        match cache.get_source_classified(&path_theme) {
+            Some((FileSource::Fs(..), ServeClass::FsRejected)) => {}
+            other => panic!("Expected the file to be served from the filesystem, got {:?}", other.map(|(_, class)| class)),
+        }
 
-        b.iter(|| {
-            let named_file = CachedFile::from(NamedFile::open(&path_5m).unwrap());
-            named_file.dummy_write()
-        });
+        cache.set_eviction_filter(None);
+        assert!(cache.insert(&path_theme).is_ok());
     }
 
+    #[test]
+    fn ghost_hits_estimate_the_gain_of_a_larger_cache() {
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let paths: Vec<PathBuf> = (0..4).map(|i| create_sized_file(temp_dir.path(), 1000, &format!("{}.txt", i))).collect();
+        let ghost_hits = |files: usize| {
+            let mut cache: Cache = CacheBuilder::new().size_limit(3000).build().unwrap();
+            cache.set_shadow_limit(Some(6000));
+            for _ in 0..10 {
+                for path in &paths[..files] {
+                    cache.get_source(path);
+                }
+            }
+            cache.summary().ghost_hits
+        };
 
+        // A working set slightly larger than the cache keeps missing files that a cache twice its size would hold.
+        let hits: usize = ghost_hits(4);
+        assert!(hits >= 9, "{} ghost hits", hits);
+        // A working set that fits has nothing to gain.
+        assert_eq!(ghost_hits(3), 0);
+    }
 
-    // Constant time access regardless of size.
-    #[bench]
-    fn cache_get_1mb_from_1000_entry_cache(b: &mut Bencher) {
+    #[test]
+    fn spellings_of_a_path_share_one_entry() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_1m = create_test_file(&temp_dir, MEG1, FILE_MEG1);
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1 * 3)
-            .build()
-            .unwrap();
-        cache.get(&path_1m); // add the file to the cache
-
-        // Add 1024 1kib files to the cache.
-        for i in 0..1024 {
-            let path = create_test_file(&temp_dir, 1024, format!("{}_1kib.txt", i).as_str());
-            cache.get(&path);
+        fs::create_dir(temp_dir.path().join("css")).unwrap();
+        let path: PathBuf = create_sized_file(temp_dir.path().join("css"), 1000, "app.css");
+        let dir: String = temp_dir.path().to_string_lossy().into_owned();
+        let doubled: PathBuf = PathBuf::from(format!("{}/css//app.css", dir));
+        let trailing: PathBuf = PathBuf::from(format!("{}/css/app.css/", dir));
+        let dotted: PathBuf = PathBuf::from(format!("{}/./css/app.css", dir));
+
+        match cache.get_source_classified(&doubled) {
+            Some((_, ServeClass::Inserted)) => {}
+            other => panic!("Expected the file to be inserted, got {:?}", other.map(|(_, class)| class)),
         }
-        // make sure that the file has a high priority.
-        cache.alter_all_access_counts(|x| x + 1 * 100000);
-
-        assert_eq!(cache.used_bytes(), MEG1 * 2);
-
-        let named_file = CachedFile::from(NamedFile::open(&path_1m).unwrap());
+        for spelling in &[&path, &doubled, &trailing, &dotted] {
+            assert!(cache.contains_key(spelling), "{:?}", spelling);
+            match cache.get_source_classified(spelling) {
+                Some((_, ServeClass::Hit)) => {}
+                other => panic!("Expected {:?} to be a hit, got {:?}", spelling, other.map(|(_, class)| class)),
+            }
+        }
+        assert_eq!(cache.summary().file_count, 1);
+        assert_eq!(cache.used_bytes(), 1000);
+        assert_eq!(cache.stats_for(&trailing).map(|stats| stats.access_count), Some(5));
 
-        b.iter(|| {
-            let cached_file = cache.get(&path_1m);
-            assert!(mem::discriminant(&cached_file) != mem::discriminant(&named_file));
-            cached_file.dummy_write()
-        });
+        assert!(cache.remove(&trailing));
+        assert!(!cache.contains_key(&path));
     }
 
-    // There is a penalty for missing the cache.
-    #[bench]
-    fn cache_miss_1mb_from_1000_entry_cache(b: &mut Bencher) {
+    #[test]
+    fn misses_in_a_full_cache_do_not_copy_every_key() {
+        const ENTRIES: usize = 500;
+        let mut cache: Cache = CacheBuilder::new().size_limit(ENTRIES * 100).build().unwrap();
+        // Checking the invariants allocates.
+        cache.set_strict_invariants(false);
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_1m = create_test_file(&temp_dir, MEG1, FILE_MEG1);
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1)
-            .build()
-            .unwrap();
-
-        // Add 1024 1kib files to the cache.
-        for i in 0..1024 {
-            let path = create_test_file(&temp_dir, 1024, format!("{}_1kib.txt", i).as_str());
-            cache.get(&path);
+        for i in 0..ENTRIES {
+            let path = create_sized_file(temp_dir.path(), 100, &format!("{}.txt", i));
+            cache.get_source(&path);
         }
-        // make sure that the file has a high priority.
-        cache.alter_all_access_counts(|x| x + 1 * 100_000_000_000_000_000);
-        let named_file = CachedFile::from(NamedFile::open(&path_1m).unwrap());
+        assert_eq!(cache.used_bytes(), ENTRIES * 100);
+        let path_new = create_sized_file(temp_dir.path(), 100, "new.txt");
 
-        b.iter(|| {
-            let cached_file = cache.get(&path_1m);
-            assert!(mem::discriminant(&cached_file) == mem::discriminant(&named_file)); // get() in this case should only return files in the FS
-            cached_file.dummy_write()
-        });
+        let (evicted, allocations) = allocations_during(|| cache.insert(&path_new));
+        match evicted {
+            Ok(CacheInsertionSuccess::ReplacedFile(ref evicted_entries)) => assert_eq!(evicted_entries.len(), 1),
+            ref other => panic!("Expected a file to be evicted, got {:?}", other),
+        }
+        // Reading the file and evicting one takes a handful of allocations, however many files the cache holds.
+        assert!(allocations < 50, "{} allocations", allocations);
     }
 
-    // This is pretty much a worst-case scenario, where every file would try to be removed to make room for the new file.
-    // There is a penalty for missing the cache.
-    #[bench]
-    fn cache_miss_5mb_from_1000_entry_cache(b: &mut Bencher) {
+    #[test]
+    fn manual_mode_only_holds_files_inserted_explicitly() {
+        let mut cache: Cache = CacheBuilder::new().size_limit(2500).build().unwrap();
+        cache.set_mode(Mode::Manual);
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_5m = create_test_file(&temp_dir, MEG5, FILE_MEG1);
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG5)
-            .build()
-            .unwrap();
-
-        // Add 1024 5kib files to the cache.
-        for i in 0..1024 {
-            let path = create_test_file(&temp_dir, 1024 * 5, format!("{}_5kib.txt", i).as_str());
-            cache.get(&path);
+        let path_a = create_sized_file(temp_dir.path(), 1000, "a.txt");
+        let path_b = create_sized_file(temp_dir.path(), 1000, "b.txt");
+        let path_c = create_sized_file(temp_dir.path(), 1000, "c.txt");
+        let path_missing = temp_dir.path().join("missing.txt");
+
+        assert_eq!(cache.insert(&path_a), Ok(CacheInsertionSuccess::SimpleAddition));
+        assert_eq!(cache.insert(&path_b), Ok(CacheInsertionSuccess::SimpleAddition));
+        // Inserting beyond the size limit fails instead of evicting anything.
+        assert_eq!(cache.insert(&path_c), Err(CacheError::CacheFull));
+        assert_eq!(cache.used_bytes(), 2000);
+
+        let tracked_paths: usize = cache.summary().tracked_paths;
+        let stats: Option<FileStats> = cache.stats_for(&path_a);
+        let bookkeeping_bytes: usize = cache.bookkeeping_bytes();
+        for _ in 0..3 {
+            match cache.get_source_classified(&path_a) {
+                Some((FileSource::Cached(_), ServeClass::Hit)) => {}
+                other => panic!("Expected a hit, got {:?}", other.map(|(_, class)| class)),
+            }
+            match cache.get_source_classified(&path_c) {
+                Some((FileSource::Fs(..), ServeClass::FsManual)) => {}
+                other => panic!("Expected the file to be served from the filesystem, got {:?}", other.map(|(_, class)| class)),
+            }
+            assert_eq!(cache.get_source(&path_missing), FileSource::NotFound);
         }
-        // make sure that the file has a high priority.
-        cache.alter_all_access_counts(|x| x + 1 * 100_000_000_000_000_000);
-        let named_file = CachedFile::from(NamedFile::open(&path_5m).unwrap());
-
-        b.iter(|| {
-            let cached_file: CachedFile = cache.get(&path_5m);
-            // Mimic what is done when the response body is set.
-            assert!(mem::discriminant(&cached_file) == mem::discriminant(&named_file));  // get() in this case should only return files in the FS
-            cached_file.dummy_write()
-        });
+        assert!(!cache.contains_key(&path_c));
+        assert_eq!(cache.summary().tracked_paths, tracked_paths);
+        assert_eq!(cache.stats_for(&path_a), stats);
+        assert_eq!(cache.bookkeeping_bytes(), bookkeeping_bytes);
+        let txt: ExtensionStats = cache.extension_stats().into_iter().find(|(extension, _)| extension == "txt").unwrap().1;
+        // The requests for the missing file aren't counted.
+        assert_eq!((txt.hits, txt.misses), (3, 3));
+
+        // Removing a file makes room for another one.
+        assert!(cache.remove(&path_b));
+        assert_eq!(cache.insert(&path_c), Ok(CacheInsertionSuccess::SimpleAddition));
     }
 
+    #[test]
+    fn directories_serve_their_index_file() {
+        let mut cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let docs: PathBuf = temp_dir.path().join("docs");
+        let empty: PathBuf = temp_dir.path().join("empty");
+        let nested: PathBuf = temp_dir.path().join("nested");
+        fs::create_dir(&docs).unwrap();
+        fs::create_dir(&empty).unwrap();
+        fs::create_dir_all(nested.join("index.html")).unwrap();
+        let index: PathBuf = create_sized_file(&docs, 1000, "index.html");
+        let class = |cache: &Cache, path: &Path| cache.get_source_classified(path).map(|(file, class)| (file.size(), class));
+
+        // The directory and its index file share one entry.
+        assert_eq!(class(&cache, &docs), Some((Some(1000), ServeClass::Inserted)));
+        assert!(cache.contains_key(&index));
+        assert_eq!(class(&cache, &index), Some((Some(1000), ServeClass::Hit)));
+        assert_eq!(class(&cache, &PathBuf::from(format!("{}/", docs.display()))), Some((Some(1000), ServeClass::Hit)));
+        assert_eq!(cache.summary().file_count, 1);
+
+        // Directories without an index file, even one whose index is a directory, aren't found.
+        assert_eq!(cache.get_source(&empty), FileSource::NotFound);
+        assert_eq!(cache.get_source(&nested), FileSource::NotFound);
+        assert_eq!(cache.insert(&empty), Err(CacheError::IsDirectory));
+
+        // The other index files are tried in order.
+        let default: PathBuf = create_sized_file(&empty, 500, "default.htm");
+        cache.set_index_files(vec![String::from("index.html"), String::from("default.htm")]);
+        assert_eq!(class(&cache, &empty), Some((Some(500), ServeClass::Inserted)));
+        assert!(cache.contains_key(&default));
+
+        // The index file can be cached under the directory instead.
+        cache.set_index_key(IndexKey::Directory);
+        cache.remove(&index);
+        assert_eq!(class(&cache, &docs), Some((Some(1000), ServeClass::Inserted)));
+        assert!(cache.contains_key(&docs));
+        assert!(!cache.contains_key(&index));
+        assert_eq!(class(&cache, &docs), Some((Some(1000), ServeClass::Hit)));
+    }
 
-    #[bench]
-    fn in_memory_file_read_10mb(b: &mut Bencher) {
+    #[test]
+    fn max_insert_size_only_applies_to_its_call() {
+        let mut builder = CacheBuilder::new();
+        builder.size_limit(MEG10).classification_filter(100);
+        let cache: Cache = builder.build().unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_10m = create_test_file(&temp_dir, MEG10, FILE_MEG10);
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
 
-        b.iter(|| {
-            let in_memory_file = Arc::new(InMemoryFile::open(path_10m.clone()).unwrap());
-            let file: *const InMemoryFile = Arc::into_raw(in_memory_file);
-            unsafe {
-                let _ = (*file).bytes.clone();
-                let _ = Arc::from_raw(file);
+        for _ in 0..2 {
+            match cache.get_source_classified_with(&path, GetOptions::new().max_insert_size(Some(MEG1 / 2))) {
+                Some((FileSource::Fs(..), ServeClass::FsSkipped)) => {}
+                other => panic!("Expected the file to be served from the filesystem, got {:?}", other.map(|(_, class)| class)),
             }
-        });
-    }
+        }
+        assert!(!cache.contains_key(&path));
 
+        match cache.get_source_classified_with(&path, GetOptions::new().max_insert_size(None)) {
+            Some((FileSource::Cached(_), ServeClass::Inserted)) => {}
+            other => panic!("Expected the file to be inserted, got {:?}", other.map(|(_, class)| class)),
+        }
+        assert!(cache.contains_key(&path));
+    }
 
     #[test]
-    fn file_exceeds_size_limit() {
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1 * 8) // Cache can hold only 8Mb
+    fn resizing_evicts_files_that_became_too_large() {
+        let mut cache: Cache = CacheBuilder::new()
+            .size_limit(MEG10)
+            .max_file_fraction(0.25)
             .build()
             .unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_10m = create_test_file(&temp_dir, MEG10, FILE_MEG10);
-
-        let named_file = NamedFile::open(path_10m.clone()).unwrap();
-
-        // expect the cache to get the item from the FS.
-        assert_eq!(cache.try_insert(path_10m), CachedFile::from(named_file));
+        // 30% of the cache once it is resized to 5 MB, but only 15% of it before.
+        let large = create_sized_file(temp_dir.path(), MEG1 + MEG1 / 2, "large.txt");
+        let small = create_sized_file(temp_dir.path(), MEG1 / 2, "small.txt");
+        cache.insert(&large).unwrap();
+        cache.insert(&small).unwrap();
+
+        let evicted: Vec<PathBuf> = cache.resize(MEG5).into_iter().map(|entry| entry.path).collect();
+        assert_eq!(evicted, vec![large.clone()]);
+        assert_eq!(cache.max_file_size_bytes(), MEG5 / 4);
+        assert!(cache.contains_key(&small));
+        assert_eq!(cache.insert(&large), Err(CacheError::FileSizeOutOfBounds));
+
+        // Growing the cache again lets the file back in.
+        assert!(cache.resize(MEG10).is_empty());
+        assert!(cache.insert(&large).is_ok());
     }
 
-
     #[test]
-    fn file_replaces_other_file() {
+    fn resizing_evicts_down_to_the_new_limit() {
+        let mut cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let paths: Vec<PathBuf> = (0..4).map(|i| create_sized_file(temp_dir.path(), MEG2, &format!("{}.txt", i))).collect();
+        for path in &paths {
+            cache.insert(path).unwrap();
+        }
 
-        let path_1m = create_test_file(&temp_dir, MEG1, FILE_MEG1);
-        let path_5m = create_test_file(&temp_dir, MEG5, FILE_MEG5);
+        assert_eq!(cache.resize(MEG5).len(), 2);
+        assert_eq!(cache.used_bytes(), MEG2 * 2);
+        assert_eq!(cache.size_limit_bytes(), MEG5);
+        // Without a fraction, the max_file_size stays where it was.
+        assert_eq!(cache.max_file_size_bytes(), usize::MAX);
+    }
 
-        let named_file_1m = NamedFile::open(path_1m.clone()).unwrap();
-        let named_file_1m_2 = NamedFile::open(path_1m.clone()).unwrap();
+    #[test]
+    fn siblings_of_a_miss_are_prefetched_within_the_budget() {
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let page = create_sized_file(temp_dir.path(), 1000, "page.html");
+        let script = create_sized_file(temp_dir.path(), 1000, "app.js");
+        let style = create_sized_file(temp_dir.path(), 1000, "style.css");
+        fs::create_dir(temp_dir.path().join("images")).unwrap();
+
+        let prefetched = |config: Option<PrefetchConfig>| {
+            let mut cache: Cache = CacheBuilder::new().size_limit(MEG1).build().unwrap();
+            cache.set_sibling_prefetch(config);
+            assert_eq!(cache.get_source_classified(&page).map(|(_, class)| class), Some(ServeClass::Inserted));
+            // The siblings are prefetched by the next request.
+            assert_eq!(cache.get_source_classified(&page).map(|(_, class)| class), Some(ServeClass::Hit));
+            (cache.contains_key(&script), cache.contains_key(&style))
+        };
 
+        let config = PrefetchConfig {
+            max_siblings: 8,
+            byte_budget: 2000,
+            queue_capacity: 4,
+        };
+        assert_eq!(prefetched(Some(config)), (true, true));
+        assert_eq!(prefetched(Some(PrefetchConfig { byte_budget: 1500, ..config })), (true, false));
+        assert_eq!(prefetched(Some(PrefetchConfig { max_siblings: 1, ..config })), (true, false));
+        assert_eq!(prefetched(None), (false, false));
+    }
 
-        let mut imf_5m = InMemoryFile::open(path_5m.clone()).unwrap();
-        let mut imf_1m = InMemoryFile::open(path_1m.clone()).unwrap();
+    #[test]
+    fn siblings_are_listed_where_the_file_was_read_and_are_not_counted_as_accessed() {
+        let mut cache: Cache = CacheBuilder::new().size_limit(MEG1).build().unwrap();
+        cache.set_key_case(KeyCase::Insensitive);
+        cache.set_sibling_prefetch(Some(PrefetchConfig {
+            max_siblings: 8,
+            byte_budget: MEG1,
+            queue_capacity: 4,
+        }));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let directory: PathBuf = temp_dir.path().join("Assets");
+        fs::create_dir(&directory).unwrap();
+        let page = create_sized_file(&directory, 1000, "Page.html");
+        let script = create_sized_file(&directory, 1000, "App.js");
+
+        cache.get_source(&page);
+        cache.get_source(&page);
+        let script_key: PathBuf = cache.key_case.normalize(&script).unwrap();
+        assert!(cache.contains_key(&script));
+        assert_eq!(cache.stats_for(&script_key).map(|stats| stats.access_count), Some(0));
+        assert_eq!(cache.access_count_map.find(&script_key).map(|access_count| *access_count.get()), Some(0));
+    }
 
-        // set expected stats for 5m
-        imf_5m.stats.access_count = 1;
-        imf_5m.stats.priority = 2289;
+    #[test]
+    fn failed_refreshes_leave_the_cached_file_intact() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        cache.insert(&path).unwrap();
+        for _ in 0..3 {
+            cache.get_source(&path);
+        }
+        let stats: FileStats = cache.stats_for(&path).unwrap();
+        let bytes: Vec<u8> = fs::read(&path).unwrap();
+        let is_intact = |cache: &Cache| {
+            cache.stats_for(&path) == Some(stats.clone())
+                && match cache.get_from_cache(&path) {
+                    FileSource::Cached(file) => file.file.get().bytes.as_slice() == bytes.as_slice(),
+                    _ => false,
+                }
+        };
 
+        // The file is deleted after it passed the check, but before it is read again.
+        assert_eq!(cache.check_refreshable(&path), Ok(()));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cache.reread(&path, &RefreshOptions::new()), RefreshOutcome::OpenFailed);
+        assert!(is_intact(&cache));
+
+        fs::create_dir(&path).unwrap();
+        assert_eq!(cache.try_refresh(&path, &RefreshOptions::new()), RefreshOutcome::NotAFile);
+        assert!(is_intact(&cache));
+        match cache.refresh_source(&path) {
+            FileSource::NotFound => {}
+            _ => panic!("A file that wasn't refreshed shouldn't be reported as refreshed."),
+        }
 
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(5500000) //Cache can hold only about 5.5Mib
-            .build()
-            .unwrap();
+        fs::remove_dir(&path).unwrap();
+        create_sized_file(temp_dir.path(), MEG2, FILE_MEG1);
+        assert_eq!(cache.try_refresh(&path, &RefreshOptions::new()), RefreshOutcome::Refreshed);
+        assert_eq!(cache.stats_for(&path).map(|stats| stats.size), Some(MEG2));
+    }
 
-        println!("0:\n{:#?}", cache);
+    #[test]
+    fn preloads_report_their_progress_and_can_be_cancelled() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let paths: Vec<PathBuf> = (0..10)
+            .map(|i| create_sized_file(temp_dir.path(), 1000, &format!("warm_{}.txt", i)))
+            .collect();
+        for path in &paths {
+            cache.get_source(path);
+            cache.remove(path);
+        }
 
+        let progress: RefCell<Vec<PreloadProgress>> = RefCell::new(vec![]);
+        let report: PrefetchReport = cache.prefetch_top_with(MEG1, PreloadOptions::new().progress(3, |p| progress.borrow_mut().push(p)));
+        assert!(!report.cancelled);
+        assert_eq!(report.inserted.len(), 10);
+        let progress: Vec<PreloadProgress> = progress.into_inner();
+        assert!(progress.len() >= 6, "{:?}", progress);
+        assert!(progress.windows(2).all(|pair| {
+            pair[0].scanned <= pair[1].scanned && pair[0].inserted <= pair[1].inserted && pair[0].bytes_used <= pair[1].bytes_used
+        }));
         assert_eq!(
-            cache
-                .try_insert(path_5m.clone())
-                .get_in_memory_file()
-                .file
-                .as_ref()
-                .get(),
-            &imf_5m
-        );
-        println!("1:\n{:#?}", cache);
-        assert_eq!(
-            cache.try_insert(path_1m.clone()),
-            CachedFile::from(named_file_1m)
-        );
-        println!("2:\n{:#?}", cache);
-        assert_eq!(
-            cache.try_insert(path_1m.clone()),
-            CachedFile::from(named_file_1m_2)
+            progress.last(),
+            Some(&PreloadProgress { scanned: 10, inserted: 10, skipped: 0, bytes_used: 10 * 1000 })
         );
-        println!("3:\n{:#?}", cache);
-
-        // set the expected stats for 1m
-        imf_1m.stats.access_count = 3;
-        imf_1m.stats.priority = 3072;
 
-        assert_eq!(
-            cache
-                .try_insert(path_1m.clone())
-                .get_in_memory_file()
-                .file
-                .as_ref()
-                .get(),
-            &imf_1m
+        for path in &paths {
+            cache.remove(path);
+        }
+        let cancel: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        let cancel_after_four = |p: PreloadProgress| if p.inserted >= 4 {
+            cancel.store(true, Ordering::Release);
+        };
+        let report: PrefetchReport = cache.prefetch_top_with(
+            MEG1,
+            PreloadOptions::new().progress(1, cancel_after_four).cancel_flag(cancel.clone()),
         );
-        println!("4:\n{:#?}", cache);
+        assert!(report.cancelled);
+        assert_eq!(report.inserted.len(), 4);
+        assert_eq!(cache.used_bytes(), 4 * 1000);
+
+        // A manifest stops at the path it was cancelled before.
+        let manifest: String = (0..10).map(|i| format!("warm_{}.txt\n", i)).collect();
+        let report: ManifestReport = cache.refresh_from_manifest_with(
+            manifest.as_bytes(),
+            Some(temp_dir.path()),
+            true,
+            PreloadOptions::new().cancel_flag(cancel.clone()),
+        );
+        assert!(report.cancelled);
+        assert!(report.outcomes.is_empty());
     }
 
-
-
-
     #[test]
-    fn new_file_replaces_lowest_priority_file() {
+    fn boosted_files_displace_warmer_ones_until_the_boost_ends() {
+        let start: Instant = Instant::now();
+        FAKE_NOW.with(|now| now.set(Some(start)));
+        let mut cache: Cache = CacheBuilder::new().size_limit(MEG2).build().unwrap();
+        cache.clock = fake_clock;
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_1m = create_test_file(&temp_dir, MEG1, FILE_MEG1);
-        let path_2m = create_test_file(&temp_dir, MEG2, FILE_MEG2);
-        let path_5m = create_test_file(&temp_dir, MEG5, FILE_MEG5);
-
-
-        #[allow(unused_variables)]
-        let named_file_1m = NamedFile::open(path_1m.clone()).unwrap();
+        let landing: PathBuf = temp_dir.path().join("landing");
+        fs::create_dir(&landing).unwrap();
+        let warm = create_sized_file(temp_dir.path(), MEG1 + MEG1 / 2, "warm.txt");
+        let cold = create_sized_file(&landing, MEG1 + MEG1 / 2, "cold.txt");
+        for _ in 0..3 {
+            cache.get_source(&warm);
+        }
+        assert!(cache.contains_key(&warm));
+
+        assert!(!cache.boost(&landing, f32::NAN, Duration::from_secs(60)));
+        assert!(!cache.boost(&landing, -2.0, Duration::from_secs(60)));
+        assert!(cache.active_boosts().is_empty());
+        assert!(cache.boost(&landing, 10.0, Duration::from_secs(60)));
+        assert_eq!(cache.active_boosts().len(), 1);
+        assert!(cache.insert(&cold).is_ok());
+        assert!(!cache.contains_key(&warm));
+
+        // Once the boost ends, the warm file displaces the cold one again.
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(61))));
+        assert!(cache.active_boosts().is_empty());
+        for _ in 0..3 {
+            cache.get_source(&warm);
+        }
+        assert!(cache.contains_key(&warm));
+        assert!(!cache.contains_key(&cold));
+    }
 
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1 * 7 + 2000) // cache can hold a little more than 7MB
-            .build()
-            .unwrap();
+    #[test]
+    fn files_being_refreshed_never_miss() {
+        let cache: Arc<Cache> = Arc::new(CacheBuilder::new().size_limit(MEG10).build().unwrap());
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        cache.insert(&path).unwrap();
+        let refreshing: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+
+        let readers: Vec<thread::JoinHandle<Vec<ServeClass>>> = (0..4)
+            .map(|_| {
+                let cache: Arc<Cache> = cache.clone();
+                let path: PathBuf = path.clone();
+                let refreshing: Arc<AtomicBool> = refreshing.clone();
+                thread::spawn(move || {
+                    let mut misses: Vec<ServeClass> = vec![];
+                    while refreshing.load(Ordering::Acquire) {
+                        match cache.get_source_classified(&path) {
+                            Some((FileSource::Cached(ref file), ServeClass::Hit)) if file.file.get().stats.access_count > 0 => {}
+                            other => misses.push(other.map_or(ServeClass::FsError, |(_, class)| class)),
+                        }
+                    }
+                    misses
+                })
+            })
+            .collect();
+        for _ in 0..200 {
+            assert_eq!(cache.try_refresh(&path, &RefreshOptions::new()), RefreshOutcome::Refreshed);
+        }
+        refreshing.store(false, Ordering::Release);
 
-        println!("1:\n{:#?}", cache);
-        let mut imf_5m: InMemoryFile = InMemoryFile::open(path_5m.clone()).unwrap();
-        imf_5m.stats.priority = 2289;
-        imf_5m.stats.access_count = 1;
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), vec![]);
+        }
+        assert_eq!(cache.used_bytes(), MEG1);
+        assert_eq!(cache.file_reads.load(Ordering::Relaxed), 201);
+    }
 
-        assert_eq!(
-            cache.get(&path_5m)
-               .get_in_memory_file()
-               .file
-               .as_ref()
-               .get(),
-            &imf_5m
-        );
+    #[test]
+    fn spilled_files_are_restored_with_their_stats() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_1 = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let path_2 = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        cache.insert(&path_1).unwrap();
+        cache.insert(&path_2).unwrap();
+        for _ in 0..3 {
+            cache.get_source(&path_2);
+        }
+        let spill_path = temp_dir.path().join("cache.spill");
+        assert_eq!(cache.spill(&spill_path).unwrap(), SpillSummary { entries: 2, bytes: MEG1 + MEG2, skipped: 0 });
+
+        let mut restored: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let summary: RestoreSummary = restored.restore_spill(&spill_path, RestoreOptions { verify_mtime: true }).unwrap();
+        assert_eq!(summary, RestoreSummary { restored: 2, restored_bytes: MEG1 + MEG2, stale: 0, skipped: 0 });
+        assert_eq!(restored.used_bytes(), MEG1 + MEG2);
+        assert_eq!(restored.stats_for(&path_2).map(|stats| stats.access_count), cache.stats_for(&path_2).map(|stats| stats.access_count));
+        assert_eq!(restored.stats_for(&path_2).map(|stats| stats.priority), cache.stats_for(&path_2).map(|stats| stats.priority));
+        assert_eq!(restored.get_source_classified(&path_1).map(|(_, class)| class), Some(ServeClass::Hit));
+        assert_eq!(read_all(restored.get_source(&path_2)), read_all(cache.get_source(&path_2)));
+        assert_eq!(restored.file_reads.load(Ordering::Relaxed), 0);
+
+        // Restoring again skips the files that are already in the cache.
+        let summary: RestoreSummary = restored.restore_spill(&spill_path, RestoreOptions::default()).unwrap();
+        assert_eq!(summary, RestoreSummary { restored: 0, restored_bytes: 0, stale: 0, skipped: 2 });
+    }
 
-        println!("2:\n{:#?}", cache);
-        let mut imf_2m: InMemoryFile = InMemoryFile::open(path_2m.clone()).unwrap();
-        imf_2m.stats.priority = 1448;
-        imf_2m.stats.access_count = 1;
-        assert_eq!(
-            cache.get(&path_2m)
-                .get_in_memory_file()
-                .file
-                .as_ref()
-                .get(),
-            &imf_2m
-        );
+    #[test]
+    fn corrupt_spill_files_are_errors() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        cache.insert(&path).unwrap();
+        let spill_path = temp_dir.path().join("cache.spill");
+        cache.spill(&spill_path).unwrap();
+        let spilled: Vec<u8> = fs::read(&spill_path).unwrap();
+
+        let restore = |bytes: &[u8]| {
+            fs::write(&spill_path, bytes).unwrap();
+            let mut restored: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+            let result: io::Result<RestoreSummary> = restored.restore_spill(&spill_path, RestoreOptions::default());
+            if result.is_err() {
+                assert_eq!(restored.used_bytes(), 0);
+            }
+            result.map_err(|error| error.kind())
+        };
+        assert_eq!(restore(&spilled[..spilled.len() - 1]), Err(io::ErrorKind::InvalidData));
+        assert_eq!(restore(&spilled[..20]), Err(io::ErrorKind::InvalidData));
+        assert_eq!(restore(b"<html></html>"), Err(io::ErrorKind::InvalidData));
+        let mut other_version: Vec<u8> = spilled.clone();
+        other_version[8] = 2;
+        assert_eq!(restore(&other_version), Err(io::ErrorKind::InvalidData));
+        assert!(restore(&spilled).is_ok());
+    }
 
+    #[test]
+    fn stale_spilled_files_are_skipped_when_verified() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_1 = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let path_2 = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        cache.insert(&path_1).unwrap();
+        cache.insert(&path_2).unwrap();
+        let spill_path = temp_dir.path().join("cache.spill");
+        cache.spill(&spill_path).unwrap();
+        fs::write(&path_1, b"changed").unwrap();
+        fs::remove_file(&path_2).unwrap();
+
+        let mut verified: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let summary: RestoreSummary = verified.restore_spill(&spill_path, RestoreOptions { verify_mtime: true }).unwrap();
+        assert_eq!(summary, RestoreSummary { restored: 0, restored_bytes: 0, stale: 2, skipped: 0 });
+        assert_eq!(read_all(verified.get_source(&path_1)), b"changed".to_vec());
+
+        let mut unverified: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let summary: RestoreSummary = unverified.restore_spill(&spill_path, RestoreOptions::default()).unwrap();
+        assert_eq!(summary.restored, 2);
+        assert_eq!(unverified.used_bytes(), MEG1 + MEG2);
+    }
 
-        println!("3:\n{:#?}", cache);
-        let mut named_1m = NamedFile::open(path_1m.clone()).unwrap();
-        let mut v: Vec<u8> = Vec::new();
-        let _ = cache
-            .get(&path_1m)
-            .get_named_file()
-            .read_to_end(&mut v)
-            .unwrap();
+    #[test]
+    fn evicted_files_that_are_still_served_are_zombie_bytes() {
+        let mut cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_1 = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let path_2 = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        cache.insert(&path_1).unwrap();
+        let response: FileSource<'static> = cache.get_source(&path_1).detach();
+        assert_eq!(cache.zombie_bytes(), 0);
+
+        assert!(cache.evict(&path_1).is_some());
+        assert_eq!(cache.used_bytes(), 0);
+        assert_eq!(cache.zombie_bytes(), MEG1);
+
+        cache.set_memory_ceiling(Some(MEG2));
+        assert_eq!(cache.insert(&path_2), Err(CacheError::MemoryCeilingExceeded));
+        assert_eq!(cache.check(&path_2), AdmissionPrediction::Rejected(CacheError::MemoryCeilingExceeded));
+        assert_eq!(cache.get_source_classified(&path_2).map(|(_, class)| class), Some(ServeClass::FsRejected));
+
+        drop(response);
+        assert_eq!(cache.zombie_bytes(), 0);
+        assert_eq!(cache.insert(&path_2), Ok(CacheInsertionSuccess::SimpleAddition));
+        // Bytes replaced in place are still served by the responses that share them.
+        let response: FileSource<'static> = cache.get_source(&path_2).detach();
+        cache.update_bytes(&path_2, vec![0; MEG1]).unwrap();
+        assert_eq!(cache.zombie_bytes(), MEG2);
+        drop(response);
+        // Nothing shares the bytes of the file, so they are dropped when it is evicted.
+        assert!(cache.evict(&path_2).is_some());
+        assert_eq!(cache.zombie_bytes(), 0);
+    }
 
-        let mut file_vec: Vec<u8> = Vec::new();
-        let _ = named_1m.read_to_end(&mut file_vec);
-        assert_eq!(
-            v,
-            file_vec
-        );
+    #[test]
+    fn snapshots_keep_the_state_they_were_taken_in() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path_1 = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let path_2 = create_sized_file(temp_dir.path(), MEG2, FILE_MEG2);
+        cache.insert(&path_1).unwrap();
+        cache.insert(&path_2).unwrap();
+        cache.get_source(&path_2);
+
+        let snapshot: CacheSnapshot = cache.snapshot();
+        assert_eq!(snapshot.summary(), &cache.summary());
+        assert_eq!(snapshot.used_bytes(), cache.used_bytes());
+        assert_eq!(snapshot.file_count(), 2);
+        let mut keys: Vec<PathBuf> = cache.file_map.iter().map(|(path, _)| path.clone()).collect();
+        keys.sort();
+        assert_eq!(snapshot.keys(), keys.iter().map(PathBuf::as_path).collect::<Vec<&Path>>());
+        assert_eq!(snapshot.stats_for(&path_2), cache.stats_for(&path_2));
+        let copy: CacheSnapshot = snapshot.clone();
+
+        cache.remove(&path_1);
+        cache.get_source(&path_2);
+        let path_5 = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+        cache.insert(&path_5).unwrap();
+
+        assert_eq!(snapshot, copy);
+        assert!(snapshot.contains_key(&path_1));
+        assert!(!snapshot.contains_key(&path_5));
+        assert_eq!(snapshot.used_bytes(), MEG1 + MEG2);
+        assert_ne!(snapshot.stats_for(&path_2), cache.stats_for(&path_2));
+        assert_ne!(snapshot, cache.snapshot());
+    }
 
+    #[test]
+    fn victim_comparators_replace_the_eviction_order() {
+        const KB1: usize = 1024;
 
-        println!("4:\n{:#?}", cache);
-        let mut imf_1m: InMemoryFile = InMemoryFile::open(path_1m.clone()).unwrap();
-        imf_1m.stats.priority = 2048; // This priority is higher than the in memory file - 2m's 1448, and therefore will replace it now
-        imf_1m.stats.access_count = 1;
+        fn same_priority(_access_count: usize, _size: usize) -> usize {
+            1
+        }
 
-        // The cache will now accept the 1 meg file because (sqrt(2)_size * 1_access) for the old
-        // file is less than (sqrt(1)_size * 2_access) for the new file.
-        assert_eq!(
-            cache.get(&path_1m)
-                .get_in_memory_file()
-                .file
-                .as_ref()
-                .get()
-                .bytes,
-            imf_1m.bytes
-        );
-        println!("5:\n{:#?}", cache);
+        fn victim(cache: &Cache, small: &PathBuf, large: &PathBuf, new: &PathBuf) -> PathBuf {
+            cache.insert(small).unwrap();
+            cache.insert(large).unwrap();
+            match cache.insert(new) {
+                Ok(CacheInsertionSuccess::ReplacedFile(ref evicted_entries)) if evicted_entries.len() == 1 => {
+                    evicted_entries[0].path.clone()
+                }
+                other => panic!("Expected exactly one file to be evicted, got {:?}", other),
+            }
+        }
 
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let small = create_sized_file(temp_dir.path(), KB1, "small.txt");
+        let large = create_sized_file(temp_dir.path(), KB1 * 2, "large.txt");
+        let new = create_sized_file(temp_dir.path(), KB1 * 2, "new.txt");
+        let cache = || CacheBuilder::new().size_limit(KB1 * 4).priority_function(same_priority).build().unwrap();
+
+        // Every file has the same priority, so the tie breaker evicts the file that was used longest ago.
+        assert_eq!(victim(&cache(), &small, &large, &new), small);
+
+        let mut largest_first: Cache = cache();
+        let compared: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let counter: Arc<AtomicUsize> = compared.clone();
+        largest_first.set_victim_comparator(Some(Box::new(move |l: &VictimCandidate, r: &VictimCandidate| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            assert!(l.last_accessed.is_some() && l.inserted_at.is_some());
+            r.stats.size.cmp(&l.stats.size)
+        })));
+        assert_eq!(victim(&largest_first, &small, &large, &new), large);
+        assert!(compared.load(Ordering::Relaxed) > 0);
+
+        largest_first.set_victim_comparator(None);
+        largest_first.clear();
+        assert_eq!(victim(&largest_first, &small, &large, &new), small);
+    }
 
-        if let CachedFile::NotFound = cache.get_from_cache(&path_1m) {
-            panic!("Expected 1m file to be in the cache");
+    #[test]
+    fn refreshed_files_grow_by_evicting_other_files_but_never_themselves() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let growing = create_sized_file(temp_dir.path(), MEG5, "growing.txt");
+        let other = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+        cache.insert(&growing).unwrap();
+        cache.insert(&other).unwrap();
+        for _ in 0..20 {
+            cache.get_source(&growing);
         }
+        assert_eq!(cache.used_bytes(), MEG10);
+
+        // The file grows to exactly fill the cache, so the other file is evicted to make room for it.
+        create_sized_file(temp_dir.path(), MEG10, "growing.txt");
+        assert_eq!(cache.try_refresh(&growing, &RefreshOptions::new()), RefreshOutcome::Refreshed);
+        assert!(!cache.contains_key(&other));
+        assert_eq!(cache.stats_for(&growing).map(|stats| stats.size), Some(MEG10));
+        assert_eq!(cache.used_bytes(), MEG10);
+    }
 
-        // Check if the 5m file is still in the cache
-        if let CachedFile::NotFound = cache.get_from_cache(&path_5m) {
-            panic!("Expected 5m file to be in the cache");
+    #[test]
+    fn refreshed_files_that_outgrow_the_cache_are_dropped_or_kept() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG10).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let growing = create_sized_file(temp_dir.path(), MEG5, "growing.txt");
+        let other = create_sized_file(temp_dir.path(), MEG5, FILE_MEG5);
+        cache.insert(&growing).unwrap();
+        cache.insert(&other).unwrap();
+        for _ in 0..20 {
+            cache.get_source(&growing);
         }
-
-        //
-        if let CachedFile::InMemory(_) = cache.get_from_cache(&path_2m) {
-            panic!("Expected 2m file to not be in the cache");
+        let stats: FileStats = cache.stats_for(&growing).unwrap();
+
+        // Evicting every other file wouldn't make room, so nothing is evicted for it.
+        create_sized_file(temp_dir.path(), MEG10 + MEG1, "growing.txt");
+        assert_eq!(cache.try_refresh(&growing, RefreshOptions::new().keep_when_too_large(true)), RefreshOutcome::TooLarge);
+        assert_eq!(cache.stats_for(&growing), Some(stats));
+        assert!(cache.contains_key(&other));
+        assert_eq!(cache.used_bytes(), MEG10);
+
+        assert_eq!(cache.try_refresh(&growing, &RefreshOptions::new()), RefreshOutcome::Evicted);
+        assert!(!cache.contains_key(&growing));
+        assert!(cache.contains_key(&other));
+        assert_eq!(cache.used_bytes(), MEG5);
+
+        // A refreshed file that was dropped is served from the filesystem.
+        create_sized_file(temp_dir.path(), MEG5, "growing.txt");
+        cache.insert(&growing).unwrap();
+        create_sized_file(temp_dir.path(), MEG10 + MEG1, "growing.txt");
+        match cache.refresh_source(&growing) {
+            FileSource::Fs(_, _, metadata) => assert_eq!(metadata.len() as usize, MEG10 + MEG1),
+            _ => panic!("A file that outgrew the cache should be served from the filesystem."),
         }
-
-        drop(cache);
+        assert_eq!(cache.used_bytes(), MEG5);
     }
 
-
-
-
     #[test]
-    fn remove_file() {
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1 * 10)
-            .build()
-            .unwrap();
+    fn refreshes_on_access_are_deferred_while_the_rate_limit_is_exhausted() {
+        let start: Instant = Instant::now();
+        FAKE_NOW.with(|now| now.set(Some(start)));
+        let mut cache: Cache = CacheBuilder::new().build().unwrap();
+        cache.clock = fake_clock;
+        cache.set_refresh_rate_limit(Some(RateLimit { per_second: 1, burst: 2 }));
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_5m = create_test_file(&temp_dir, MEG5, FILE_MEG5);
-
-        let mut imf: InMemoryFile = InMemoryFile::open(path_5m.clone()).unwrap();
-
-        // Set the expected values for the stats in IMF.
-        imf.stats.priority = 2289;
-        imf.stats.access_count = 1;
+        let paths: Vec<PathBuf> = (0..4).map(|i| temp_dir.path().join(format!("{}.html", i))).collect();
+        for path in paths.iter() {
+            fs::write(path, b"old").unwrap();
+            cache.insert(path).unwrap();
+        }
+        // A deploy changes every file.
+        for path in paths.iter() {
+            fs::write(path, b"deployed").unwrap();
+        }
+        assert_eq!(cache.soft_purge_prefix(temp_dir.path()), 4);
+        let serve_all = |cache: &Cache| -> Vec<Vec<u8>> {
+            paths.iter().map(|path| cache.get_source(path).bytes().unwrap().to_vec()).collect()
+        };
 
-        // expect the cache to get the item from the FS.
-        assert_eq!(
-            cache
-                .get(&path_5m)
-                .get_in_memory_file()
-                .file
-                .as_ref()
-                .get(),
-            &imf
-        );
+        // The burst is refreshed at once, and the rest are served stale.
+        assert_eq!(serve_all(&cache), vec![b"deployed".to_vec(), b"deployed".to_vec(), b"old".to_vec(), b"old".to_vec()]);
+        assert!(cache.is_soft_purged(&paths[2]) && cache.is_soft_purged(&paths[3]));
 
-        cache.remove(&path_5m);
+        // Explicit refreshes aren't limited.
+        assert_eq!(cache.try_refresh(&paths[3], &RefreshOptions::new()), RefreshOutcome::Refreshed);
 
-        assert_eq!(cache.contains_key(&path_5m.clone()), false);
+        // The deferred file is refreshed once a token refills.
+        assert_eq!(cache.get_source(&paths[2]).bytes(), Some(&b"old"[..]));
+        FAKE_NOW.with(|now| now.set(Some(start + Duration::from_secs(1))));
+        assert_eq!(serve_all(&cache), vec![b"deployed".to_vec(); 4]);
+        assert!(paths.iter().all(|path| !cache.is_soft_purged(path)));
     }
 
     #[test]
-    fn refresh_file() {
-        let cache: Cache = CacheBuilder::new()
-            .size_limit(MEG1 * 10)
-            .build()
-            .unwrap();
-
+    fn entry_handles_outlive_their_entries() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
         let temp_dir = TempDir::new(DIR_TEST).unwrap();
-        let path_5m = create_test_file(&temp_dir, MEG5, FILE_MEG5);
+        let index: PathBuf = temp_dir.path().join("index.html");
+        let style: PathBuf = temp_dir.path().join("style.css");
+        fs::write(&index, b"<html></html>").unwrap();
+        fs::write(&style, b"body {}").unwrap();
+        cache.insert(&index).unwrap();
+        cache.insert(&style).unwrap();
+        let access_count: usize = cache.stats_for(&index).unwrap().access_count;
+
+        let handle: EntryHandle = cache.try_get_cached(&index).unwrap();
+        assert_eq!(handle.bytes(), b"<html></html>");
+        assert_eq!(handle.size(), 13);
+        assert_eq!(handle.path(), index.as_path());
+        assert_eq!(Some(handle.content_hash()), cache.content_hash(&index));
+        assert_eq!(handle.last_modified(), fs::metadata(&index).unwrap().modified().ok());
+        assert_eq!(cache.stats_for(&index).unwrap().access_count, access_count);
+        assert!(cache.try_get_cached(temp_dir.path().join("missing.html")).is_none());
+
+        let mut paths: Vec<PathBuf> = cache.iter().map(|handle| handle.path().to_path_buf()).collect();
+        paths.sort();
+        assert_eq!(paths, vec![index.clone(), style.clone()]);
+
+        let taken: EntryHandle = cache.take(&index).unwrap();
+        assert_eq!(taken, handle);
+        assert!(!cache.contains_key(&index));
+        assert!(cache.take(&index).is_none());
+        assert_eq!(cache.used_bytes(), 7);
+        // The handles keep the bytes alive after the file left the cache.
+        assert_eq!(cache.zombie_bytes(), 13);
+        let clone: EntryHandle = taken.clone();
+        drop(handle);
+        drop(taken);
+        assert_eq!(clone.bytes(), b"<html></html>");
+        drop(clone);
+        assert_eq!(cache.zombie_bytes(), 0);
+    }
 
+    #[test]
+    fn duplicate_insertions_replace_the_existing_entry() {
+        let cache: Cache = CacheBuilder::new().size_limit(MEG2).build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path = create_sized_file(temp_dir.path(), MEG1, FILE_MEG1);
+        let other = create_sized_file(temp_dir.path(), MEG1, "other.txt");
+
+        // Both insertions found the file missing before either of them stored it.
+        assert_eq!(cache.admit(&path, &mut None, &GetOptions::new()), Ok(CacheInsertionSuccess::SimpleAddition));
+        assert_eq!(cache.admit(&path, &mut None, &GetOptions::new()), Ok(CacheInsertionSuccess::ReplacedExistingEntry));
+        assert_eq!(cache.used_bytes(), MEG1);
+
+        // A full cache doesn't evict anything for a duplicate.
+        cache.insert(&other).unwrap();
+        assert_eq!(cache.used_bytes(), MEG2);
+        assert_eq!(cache.admit(&path, &mut None, &GetOptions::new()), Ok(CacheInsertionSuccess::ReplacedExistingEntry));
+        assert!(cache.contains_key(&other));
+        assert_eq!(cache.used_bytes(), MEG2);
+        assert_eq!(cache.get_source(&path).bytes().map(|bytes| bytes.len()), Some(MEG1));
+
+        // A duplicate that grew past the free space keeps the copy already stored.
+        let grown: InMemoryFile = InMemoryFile::from_bytes(vec![0; MEG1 + 1]);
+        assert_eq!(cache.replace_existing(&path, grown), Err(CacheError::AlreadyInCache));
+        assert_eq!(cache.used_bytes(), MEG2);
+        assert_eq!(cache.get_source(&path).bytes().map(|bytes| bytes.len()), Some(MEG1));
+    }
 
-        assert_eq!(
-            match cache.get(&path_5m) {
-                CachedFile::InMemory(c) => c.file.get().stats.size,
-                CachedFile::FileSystem(_) => unreachable!(),
-                CachedFile::NotFound => unreachable!()
-            },
-            MEG5
-        );
+    #[test]
+    fn content_addressed_paths_share_their_content() {
+        let mut cache: Cache = CacheBuilder::new().build().unwrap();
+        cache.set_content_addressed(Some(ContentAddressedConfig {
+            sidecar_extension: Some(String::from("fnv")),
+        }));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let v1: PathBuf = temp_dir.path().join("v1.js");
+        let copy: PathBuf = temp_dir.path().join("copy.js");
+        fs::write(&v1, b"let app = 1;").unwrap();
+        fs::write(&copy, b"let app = 1;").unwrap();
+        cache.insert(&v1).unwrap();
+        cache.insert(&copy).unwrap();
+        // Both paths were read, but only one copy of their content is stored.
+        assert_eq!(cache.used_bytes(), 12);
+        let hash: String = cache.content_hash(&v1).unwrap();
+        assert_eq!(cache.content_hash(&copy), Some(hash.clone()));
+        let handle: EntryHandle = cache.lookup_by_hash(&hash).unwrap();
+        assert_eq!(handle.bytes(), b"let app = 1;");
+        assert!(cache.lookup_by_hash("0000000000000000").is_none());
+
+        // A deploy renames the file, and writes the sidecar with its hash.
+        let v2: PathBuf = temp_dir.path().join("v2.js");
+        fs::rename(&v1, &v2).unwrap();
+        fs::write(temp_dir.path().join("v2.js.fnv"), format!("{}\n", hash)).unwrap();
+        let file_reads: usize = cache.file_reads.load(Ordering::Relaxed);
+        assert_eq!(cache.get_source(&v2).bytes(), Some(&b"let app = 1;"[..]));
+        assert_eq!(cache.get_source_classified(&v2).map(|(_, class)| class), Some(ServeClass::Hit));
+        assert_eq!(cache.file_reads.load(Ordering::Relaxed), file_reads);
+        assert!(cache.contains_key(&v2));
+        assert_eq!(cache.used_bytes(), 12);
+
+        // Refreshing a changed path points it at its new content, and the other paths keep the old one.
+        fs::write(&copy, b"let app = 2;;").unwrap();
+        assert_eq!(cache.try_refresh(&copy, &RefreshOptions::new()), RefreshOutcome::Refreshed);
+        assert_eq!(cache.get_source(&copy).bytes(), Some(&b"let app = 2;;"[..]));
+        assert_eq!(cache.get_source(&v2).bytes(), Some(&b"let app = 1;"[..]));
+        assert_eq!(cache.used_bytes(), 25);
+        assert!(cache.content_hash(&copy) != Some(hash.clone()));
+
+        // Content nothing points at anymore leaves the cache.
+        fs::write(&v2, b"let app = 2;;").unwrap();
+        match cache.refresh_source(&v2) {
+            FileSource::Cached(file) => assert_eq!(file.file.get().bytes.as_slice(), b"let app = 2;;"),
+            _ => panic!("Expected the refreshed file to be served from the cache"),
+        }
+        assert!(cache.lookup_by_hash(&hash).is_some());
+        assert_eq!(cache.try_refresh(&v1, &RefreshOptions::new()), RefreshOutcome::FileMissing);
+        assert!(cache.lookup_by_hash(&hash).is_none());
+        assert_eq!(cache.used_bytes(), 13);
+        assert!(cache.verify().is_consistent());
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 
-        let path_of_file_with_10mb_but_path_name_5m = create_test_file(&temp_dir, MEG10, FILE_MEG5);
+    #[test]
+    fn committed_insertions_share_stored_content() {
+        let mut cache: Cache = CacheBuilder::new().build().unwrap();
+        cache.set_content_addressed(Some(ContentAddressedConfig::default()));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let first: PathBuf = temp_dir.path().join("first.css");
+        let second: PathBuf = temp_dir.path().join("second.css");
+        fs::write(&first, b"body {}").unwrap();
+        fs::write(&second, b"body {}").unwrap();
+        cache.insert(&first).unwrap();
+
+        let token: AdmissionToken = match cache.check(&second) {
+            AdmissionPrediction::Admit(token) => token,
+            other => panic!("Expected the file to be admitted, got {:?}", other),
+        };
+        assert_eq!(read_all(cache.commit_insert_source(&second, token).unwrap()), b"body {}".to_vec());
+        assert!(cache.contains_key(&second));
+        assert_eq!(cache.used_bytes(), 7);
+        assert!(cache.lookup_by_hash(&cache.content_hash(&second).unwrap()).is_some());
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 
+    #[test]
+    fn prefetched_files_share_stored_content() {
+        let mut cache: Cache = CacheBuilder::new().build().unwrap();
+        cache.set_content_addressed(Some(ContentAddressedConfig::default()));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let first: PathBuf = temp_dir.path().join("first.css");
+        let second: PathBuf = temp_dir.path().join("second.css");
+        fs::write(&first, b"body {}").unwrap();
+        fs::write(&second, b"body {}").unwrap();
+        cache.get_source(&first);
+        cache.get_source(&second);
+        let hash: String = cache.content_hash(&first).unwrap();
+        cache.remove(&first);
+        assert_eq!(cache.used_bytes(), 0);
+
+        let report: PrefetchReport = cache.prefetch_top(MEG1);
+        assert_eq!(report.inserted, vec![first.clone(), second.clone()]);
+        assert!(report.skipped.is_empty());
+        assert_eq!(cache.used_bytes(), 7);
+        assert!(cache.lookup_by_hash(&hash).is_some());
+        assert_eq!(cache.get_source_classified(&second).map(|(_, class)| class), Some(ServeClass::Hit));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 
-        cache.refresh(&path_5m);
+    #[test]
+    fn restored_files_share_stored_content() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let first: PathBuf = temp_dir.path().join("first.css");
+        let second: PathBuf = temp_dir.path().join("second.css");
+        fs::write(&first, b"body {}").unwrap();
+        fs::write(&second, b"body {}").unwrap();
+        cache.insert(&first).unwrap();
+        cache.insert(&second).unwrap();
+        let spill_path = temp_dir.path().join("cache.spill");
+        cache.spill(&spill_path).unwrap();
+
+        let mut restored: Cache = CacheBuilder::new().build().unwrap();
+        restored.set_content_addressed(Some(ContentAddressedConfig::default()));
+        let summary: RestoreSummary = restored.restore_spill(&spill_path, RestoreOptions { verify_mtime: true }).unwrap();
+        assert_eq!(summary.restored, 2);
+        assert_eq!(restored.used_bytes(), 7);
+        assert!(restored.lookup_by_hash(&cache.content_hash(&first).unwrap()).is_some());
+        assert_eq!(restored.get_source_classified(&second).map(|(_, class)| class), Some(ServeClass::Hit));
+        assert_eq!(restored.file_reads.load(Ordering::Relaxed), 0);
+
+        // Content is spilled under every path pointing at it, so restoring it again keeps both paths.
+        let respill_path = temp_dir.path().join("content.spill");
+        assert_eq!(restored.spill(&respill_path).unwrap().entries, 2);
+        let mut respilled: Cache = CacheBuilder::new().build().unwrap();
+        respilled.set_content_addressed(Some(ContentAddressedConfig::default()));
+        assert_eq!(respilled.restore_spill(&respill_path, RestoreOptions::default()).unwrap().restored, 2);
+        assert!(respilled.contains_key(&first));
+        assert!(respilled.contains_key(&second));
+        assert_eq!(respilled.used_bytes(), 7);
+    }
 
-        assert_eq!(
-            match cache.get(&path_of_file_with_10mb_but_path_name_5m) {
-                CachedFile::InMemory(c) => c.file.get().stats.size,
-                CachedFile::FileSystem(_) => unreachable!(),
-                CachedFile::NotFound => unreachable!()
-            },
-            MEG10
-        );
+    #[test]
+    fn get_source_result_tells_why_a_file_is_not_served() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let path: PathBuf = temp_dir.path().join("file.txt");
+        fs::write(&path, b"file").unwrap();
+        fs::create_dir(temp_dir.path().join("dir")).unwrap();
 
-        drop(cache);
+        match cache.get_source_result(&path) {
+            Ok(FileSource::Cached(_)) => {}
+            _ => panic!("Expected the file to be served from the cache"),
+        }
+        assert!(matches!(cache.get_source_result(temp_dir.path().join("missing.txt")), Err(CacheError::NotFound)));
+        assert!(matches!(cache.get_source_result(temp_dir.path().join("dir")), Err(CacheError::IsDirectory)));
+        assert!(matches!(cache.get_source_result(""), Err(CacheError::InvalidKey(_))));
     }
 
+    #[test]
+    fn colliding_content_hashes_do_not_share_content() {
+        let mut cache: Cache = CacheBuilder::new().build().unwrap();
+        cache.set_content_addressed(Some(ContentAddressedConfig::default()));
+        let temp_dir = TempDir::new(DIR_TEST).unwrap();
+        let first: PathBuf = temp_dir.path().join("first.js");
+        let second: PathBuf = temp_dir.path().join("second.js");
+        fs::write(&first, b"let first = 1;").unwrap();
+        fs::write(&second, b"let second = 2;").unwrap();
+        cache.insert(&first).unwrap();
+
+        // Moves the content of the first file to the key the content of the second file hashes to, as if their hashes collided.
+        let first_key: PathBuf = content_index::content_key(content_hash::hash(b"let first = 1;"), &first);
+        let colliding_key: PathBuf = content_index::content_key(content_hash::hash(b"let second = 2;"), &second);
+        let file: InMemoryFile = cache.file_map.remove(&first_key).unwrap();
+        cache.file_map.insert(colliding_key.clone(), file);
+        let access_count: usize = cache.access_count_map.remove(&first_key).unwrap();
+        cache.access_count_map.insert(colliding_key.clone(), access_count);
+        cache.point_to_content(&first, colliding_key.clone(), None);
+
+        assert_eq!(cache.get_source(&second).bytes(), Some(&b"let second = 2;"[..]));
+        assert_eq!(cache.get_source_classified(&second).map(|(_, class)| class), Some(ServeClass::Hit));
+        assert!(cache.file_map.find(&second).is_some());
+        assert_eq!(cache.get_source(&first).bytes(), Some(&b"let first = 1;"[..]));
+        assert_eq!(cache.used_bytes(), b"let first = 1;".len() + b"let second = 2;".len());
+
+        // Refreshing a path whose new content collides with stored content forgets the path, instead of sharing the content.
+        let third: PathBuf = temp_dir.path().join("third.js");
+        fs::write(&third, b"let third = 3;").unwrap();
+        cache.insert(&third).unwrap();
+        fs::write(&third, b"let second = 2;").unwrap();
+        assert_eq!(cache.try_refresh(&third, &RefreshOptions::new()), RefreshOutcome::Evicted);
+        assert_eq!(cache.get_source(&first).bytes(), Some(&b"let first = 1;"[..]));
+        assert_eq!(cache.get_source(&third).bytes(), Some(&b"let second = 2;"[..]));
+        assert!(cache.file_map.find(&third).is_some());
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 }
@@ -1,16 +1,44 @@
-use cache::Cache;
+use cache::{Cache, Recompute, Consistency, Revalidation, OnMissing, CountPolicy, Mode, IndexKey};
 
-use priority_function::default_priority_function;
+use priority_function::{default_priority_function, default_tie_breaker};
+use key_case::KeyCase;
+use policy;
 use std::usize;
 
 use concurrent_hashmap::{ConcHashMap, Options};
 use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, AtomicU64, AtomicBool};
+use in_flight::InFlightReads;
+use read_limiter::ReadLimiter;
+use directory_floors::DirectoryFloors;
+use directory_quotas::DirectoryQuotas;
+use boosts::Boosts;
+use churn::ChurnTracker;
+use fallbacks::FallbackResolutions;
+use latency::LatencyRecorder;
+use storage::Storage;
+use tombstones::Tombstones;
+use served_bytes::ServedBytes;
+use batch_plan::BatchPlan;
+use classification_filter::ClassificationFilter;
+use extension_stats::ExtensionCounters;
+use zombie_bytes::ZombieBytes;
+use operations::Operations;
+use size::{parse_size, ParseSizeError};
+#[cfg(feature = "rocket")]
+use rocket_config::{self, ConfigError};
 
 
 /// Error types that can be encountered when a cache is built.
 #[derive(Debug, PartialEq)]
 pub enum CacheBuildError {
     MinFileSizeIsLargerThanMaxFileSize,
+    /// Both `max_file_size()` and `max_file_fraction()` were set, but only one of them can bound the file size.
+    MaxFileSizeAndFractionAreBothSet,
+    /// The fraction given to `max_file_fraction()` isn't more than 0 and at most 1.
+    MaxFileFractionOutOfRange,
 }
 
 /// A builder for Caches.
@@ -22,6 +50,9 @@ pub struct CacheBuilder {
     priority_function: Option<fn(usize, usize) -> usize>,
     min_file_size: Option<usize>,
     max_file_size: Option<usize>,
+    max_file_fraction: Option<f32>,
+    coalesce_timeout: Option<Duration>,
+    classification_filter: Option<usize>,
 }
 
 
@@ -37,13 +68,57 @@ impl CacheBuilder {
             priority_function: None,
             min_file_size: None,
             max_file_size: None,
+            max_file_fraction: None,
+            coalesce_timeout: None,
+            classification_filter: None,
         }
     }
 
+    /// Creates a CacheBuilder with the settings in a named table of the `cache` extra of the Rocket config,
+    /// so differently configured caches can be created from the same `Rocket.toml`.
+    ///
+    /// The table can set `size_limit`, `min_file_size` and `max_file_size`, as sizes like `"100MiB"`
+    /// (see `parse_size()`) or as numbers of bytes, and `concurrency`, `accesses_per_refresh` and
+    /// `coalesce_timeout_ms` as numbers. Settings that aren't in the table keep their defaults,
+    /// and the builder can change any of them before the cache is built.
+    ///
+    /// ```toml
+    /// [global.cache.images]
+    /// size_limit = "100MiB"
+    /// max_file_size = "8MiB"
+    /// ```
+    ///
+    /// Missing tables, unknown keys, and values that can't be used are errors, which name the key that caused them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate rocket;
+    /// # extern crate rocket_file_cache;
+    /// use rocket::Config;
+    /// use rocket_file_cache::{Cache, CacheBuilder, ConfigError};
+    ///
+    /// # fn main() {
+    /// fn image_cache(config: &Config) -> Result<Cache, ConfigError> {
+    ///     let cache: Cache = CacheBuilder::from_rocket_config(config, "images")?
+    ///         .build()
+    ///         .expect("The minimum file size is larger than the maximum.");
+    ///     Ok(cache)
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "rocket")]
+    pub fn from_rocket_config(config: &::rocket::Config, table_name: &str) -> Result<CacheBuilder, ConfigError> {
+        rocket_config::builder_from_config(config, table_name)
+    }
+
     /// Sets the maximum number of bytes (as they exist in the FS) that the cache can hold.
     /// The cache will take up more space in memory due to the backing concurrent HashMap it uses.
     /// The memory overhead can be controlled by setting the concurrency parameter.
     ///
+    /// A size limit of 0 disables the cache. Every file is served straight from the filesystem,
+    /// and nothing about the requests is recorded, so the cache never grows.
+    ///
     /// # Arguments
     /// * size_limit - The number of bytes the cache will be able to hold.
     ///
@@ -52,6 +127,35 @@ impl CacheBuilder {
         self
     }
 
+    /// Sets the size limit from a human friendly size, like `"30MiB"`. See `parse_size()` for the sizes it accepts.
+    ///
+    /// `KB`, `MB` and `GB` are powers of 1000, while `KiB`, `MiB` and `GiB` are powers of 1024.
+    /// Sizes that can't be parsed, or don't fit into a `usize`, are errors, and leave the size limit as it was.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{Cache, CacheBuilder, ParseSizeError};
+    ///
+    /// let cache: Cache = CacheBuilder::new()
+    ///     .size_limit_str("30MiB").unwrap()
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(cache.size_limit_bytes(), 30 * 1024 * 1024);
+    ///
+    /// assert_eq!(
+    ///     CacheBuilder::new().size_limit_str("30 megs").unwrap_err(),
+    ///     ParseSizeError::UnknownUnit(String::from("megs"))
+    /// );
+    /// ```
+    pub fn size_limit_str(&mut self, size_limit: &str) -> Result<&mut Self, ParseSizeError> {
+        let size_limit: u64 = parse_size(size_limit)?;
+        if size_limit > usize::MAX as u64 {
+            return Err(ParseSizeError::TooLarge);
+        }
+        Ok(self.size_limit(size_limit as usize))
+    }
+
     /// Sets the concurrency setting of the concurrent hashmap backing the cache.
     /// A higher concurrency setting allows more threads to access the hashmap at the expense of more memory use.
     /// The default is 16.
@@ -97,6 +201,8 @@ impl CacheBuilder {
     ///
     /// The priority function should be kept simple, as it is calculated on every file in the cache
     /// every time a new file is attempted to be added.
+    /// Priorities above `MAX_PRIORITY` are clamped to it,
+    /// and `Cache::validate_priority_function()` can check that the function makes evictions meaningful.
     ///
     /// # Example
     ///
@@ -127,6 +233,42 @@ impl CacheBuilder {
         self
     }
 
+    /// Set the maximum size of files that can be stored in the cache as a fraction of its size limit,
+    /// so no single file takes more than that share of the cache.
+    ///
+    /// The size follows the limit when it is changed with `Cache::resize()`.
+    /// The fraction must be more than 0 and at most 1, and can't be combined with `max_file_size()`.
+    pub fn max_file_fraction(&mut self, fraction: f32) -> &mut Self {
+        self.max_file_fraction = Some(fraction);
+        self
+    }
+
+    /// Sets how long a request will wait for another request that is already reading the same file into the cache.
+    ///
+    /// When many requests miss on the same file at once, only the first one reads the file from the filesystem.
+    /// The others wait for that read to finish and then serve the cached copy.
+    /// If the read takes longer than this timeout, the waiting requests serve the file from the filesystem instead.
+    /// The default is 1 second.
+    pub fn coalesce_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.coalesce_timeout = Some(timeout);
+        self
+    }
+
+    /// Records the paths of files that are outside of the min_file_size and max_file_size bounds in a bloom filter,
+    /// sized for the expected number of such paths, instead of remembering each of them.
+    ///
+    /// Like the paths that are remembered, paths in the filter are served from the filesystem without reading
+    /// their metadata or counting their accesses. The filter takes about 10 bits per expected path,
+    /// no matter how many paths are recorded, so a site with millions of distinct oversized paths
+    /// doesn't grow the bookkeeping of the cache.
+    /// In exchange, about 1% of other paths are mistaken for recorded ones, and those are only ever served
+    /// from the filesystem, more often once the filter holds more paths than it was sized for.
+    /// Paths can only be forgotten all at once, with `Cache::reset_classification_filter()`.
+    pub fn classification_filter(&mut self, expected_paths: usize) -> &mut Self {
+        self.classification_filter = Some(expected_paths);
+        self
+    }
+
     /// Finalize the cache.
     ///
     /// # Example
@@ -157,8 +299,15 @@ impl CacheBuilder {
             None => default_priority_function,
         };
 
+        let max_file_size: Option<usize> = match (self.max_file_size, self.max_file_fraction) {
+            (Some(_), Some(_)) => return Err(CacheBuildError::MaxFileSizeAndFractionAreBothSet),
+            (None, Some(fraction)) if !(fraction > 0.0 && fraction <= 1.0) => return Err(CacheBuildError::MaxFileFractionOutOfRange),
+            (None, Some(fraction)) => Some(policy::max_file_size_for_fraction(size_limit, fraction)),
+            (max_file_size, None) => max_file_size,
+        };
+
         if let Some(min_file_size) = self.min_file_size {
-            if let Some(max_file_size) = self.max_file_size {
+            if let Some(max_file_size) = max_file_size {
                 if min_file_size > max_file_size {
                     return Err(CacheBuildError::MinFileSizeIsLargerThanMaxFileSize);
                 }
@@ -170,7 +319,7 @@ impl CacheBuilder {
             None => 0,
         };
 
-        let max_file_size: usize = match self.max_file_size {
+        let max_file_size: usize = match max_file_size {
             Some(max) => max,
             None => usize::MAX,
         };
@@ -190,10 +339,80 @@ impl CacheBuilder {
             size_limit: size_limit,
             min_file_size,
             max_file_size,
+            max_file_fraction: self.max_file_fraction,
             priority_function,
             accesses_per_refresh: self.accesses_per_refresh,
             file_map: ConcHashMap::with_options(options_files_map),
             access_count_map: ConcHashMap::with_options(options_access_map),
+            in_flight_reads: InFlightReads::new(),
+            coalesce_timeout: self.coalesce_timeout.unwrap_or(Duration::from_secs(1)),
+            file_reads: AtomicUsize::new(0),
+            fs_opens: AtomicUsize::new(0),
+            overshoot: None,
+            overshoot_eviction_pending: AtomicBool::new(false),
+            cold_tier: None,
+            aliases: ConcHashMap::with_options(Options::default()),
+            hash_uncached_files: false,
+            resident_bytes: AtomicUsize::new(0),
+            key_case: KeyCase::Sensitive,
+            reject_parent_directories: false,
+            read_limiter: ReadLimiter::new(None),
+            out_of_bounds: ConcHashMap::with_options(Options::default()),
+            classification_filter: self.classification_filter.map(ClassificationFilter::new),
+            extension_counters: ExtensionCounters::new(),
+            metadata_reads: AtomicUsize::new(0),
+            fs_paths: ConcHashMap::with_options(Options::default()),
+            observer: None,
+            eviction_filter: None,
+            victim_comparator: None,
+            priority_recompute: Recompute::EveryAccess,
+            read_deadline: None,
+            high_watermark: size_limit,
+            low_watermark: size_limit,
+            tie_breaker: default_tie_breaker,
+            access_clock: AtomicUsize::new(0),
+            frozen: AtomicBool::new(false),
+            decision_log: None,
+            directory_floors: DirectoryFloors::new(),
+            directory_quotas: DirectoryQuotas::new(),
+            boosts: Boosts::new(),
+            churn: ChurnTracker::new(),
+            fallback_resolutions: FallbackResolutions::new(),
+            served_bytes: ServedBytes::new(),
+            tombstones: Tombstones::new(),
+            bypassed: HashSet::new(),
+            thrash_protection: None,
+            ghost_entries: None,
+            sibling_prefetch: None,
+            zombie_bytes: ZombieBytes::new(),
+            memory_ceiling: None,
+            clock: Instant::now,
+            storage: Storage::Heap,
+            timing: true,
+            strict_invariants: true,
+            operations: Operations::new(),
+            latency: LatencyRecorder::new(),
+            trace: None,
+            min_residency: None,
+            verify_checksums: false,
+            size_mismatches: AtomicUsize::new(0),
+            checksum_mismatches: AtomicUsize::new(0),
+            fs_health: None,
+            refresh_limiter: None,
+            content_index: None,
+            batch_plan: BatchPlan::new(),
+            candidate_sorts: AtomicUsize::new(0),
+            small_file_arena: None,
+            generation: AtomicU64::new(0),
+            labeled_metrics: None,
+            consistency: Consistency::Fast,
+            revalidation: Revalidation::SizeAndModified,
+            on_missing: OnMissing::Remove,
+            soft_purge_on_missing: OnMissing::ServeStale,
+            count_policy: CountPolicy::OnRequest,
+            mode: Mode::Automatic,
+            index_files: vec![String::from("index.html")],
+            index_key: IndexKey::File,
         })
 
     }
@@ -214,6 +433,23 @@ mod tests {
         assert_eq!(CacheBuildError::MinFileSizeIsLargerThanMaxFileSize, e);
     }
 
+    #[test]
+    fn max_file_fraction_is_validated() {
+        let e = |builder: &CacheBuilder| builder.build().unwrap_err();
+        assert_eq!(
+            e(CacheBuilder::new().max_file_size(1024).max_file_fraction(0.5)),
+            CacheBuildError::MaxFileSizeAndFractionAreBothSet
+        );
+        assert_eq!(e(CacheBuilder::new().max_file_fraction(0.0)), CacheBuildError::MaxFileFractionOutOfRange);
+        assert_eq!(e(CacheBuilder::new().max_file_fraction(1.5)), CacheBuildError::MaxFileFractionOutOfRange);
+        assert_eq!(
+            e(CacheBuilder::new().size_limit(1000).min_file_size(300).max_file_fraction(0.25)),
+            CacheBuildError::MinFileSizeIsLargerThanMaxFileSize
+        );
+        let cache: Cache = CacheBuilder::new().size_limit(1000).max_file_fraction(1.0).build().unwrap();
+        assert_eq!(cache.max_file_size_bytes(), 1000);
+    }
+
     #[test]
     fn all_options_used_in_build() {
         let _: Cache = CacheBuilder::new()
@@ -223,8 +459,23 @@ mod tests {
             .min_file_size(1024 * 10)
             .concurrency(20)
             .accesses_per_refresh(1000)
+            .coalesce_timeout(Duration::from_millis(500))
             .build()
             .unwrap();
     }
 
+    #[test]
+    fn size_limits_can_be_decimal_or_binary() {
+        let decimal: Cache = CacheBuilder::new().size_limit_str("64KB").unwrap().build().unwrap();
+        let binary: Cache = CacheBuilder::new().size_limit_str("64KiB").unwrap().build().unwrap();
+        assert_eq!(decimal.size_limit_bytes(), 64_000);
+        assert_eq!(binary.size_limit_bytes(), 65_536);
+
+        let mut builder: CacheBuilder = CacheBuilder::new();
+        builder.size_limit(1024);
+        assert_eq!(builder.size_limit_str("1.5MB").unwrap_err(), ParseSizeError::InvalidNumber(String::from("1.5MB")));
+        assert_eq!(builder.size_limit_str("").unwrap_err(), ParseSizeError::Empty);
+        assert_eq!(builder.build().unwrap().size_limit_bytes(), 1024);
+    }
+
 }
@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use rocket::{Request, State};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+
+use cache::Cache;
+use cached_file::CachedFile;
+
+
+/// A request guard for the `Cache` managed by Rocket, which counts the hits and misses of the files it gets
+/// under a label, so the effectiveness of the cache can be measured separately for different parts of a site.
+///
+/// The label is the base the route of the request is mounted at, like `img` for a route mounted at `/img`,
+/// unless another one is given with `with_label()`. The counts of a label are returned by `Cache::labeled_summary()`.
+/// Since labels come from the mounted routes rather than from the requested paths, clients can't make up new ones.
+/// A guard used outside of a route doesn't make a label.
+///
+/// Labels are only counted once they are turned on with `Cache::set_labeled_metrics()`.
+/// Until then, the guard doesn't make a label and `get()` is the same as `Cache::get()`.
+/// A request for a cache that isn't managed by Rocket fails with 500.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::{CacheHandle, CachedFile};
+/// use std::path::{Path, PathBuf};
+///
+/// // Mounted as `#[get("/img/<file..>")]`, so its hits and misses are counted under `img`.
+/// fn images<'r>(file: PathBuf, cache: CacheHandle<'r>) -> CachedFile<'r> {
+///     cache.get(Path::new("www/img/").join(file))
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CacheHandle<'r> {
+    cache: &'r Cache,
+    label: Option<String>,
+}
+
+impl<'r> CacheHandle<'r> {
+    /// Counts the files got with the handle under the label, instead of the one derived from the request.
+    pub fn with_label<S: Into<String>>(self, label: S) -> CacheHandle<'r> {
+        let label: Option<String> = self.cache.labeled_metrics.as_ref().map(|_| label.into());
+        CacheHandle {
+            cache: self.cache,
+            label,
+        }
+    }
+
+    /// The label the files got with the handle are counted under, if labels are counted.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// The cache the handle gets files from.
+    pub fn cache(&self) -> &'r Cache {
+        self.cache
+    }
+
+    /// Gets the file like `Cache::get()`, counting whether it was a hit under the label of the handle.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> CachedFile<'r> {
+        match self.label {
+            Some(ref label) => self.cache.get_labeled(path, label),
+            None => self.cache.get(path),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for CacheHandle<'r> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<CacheHandle<'r>, ()> {
+        let cache: &'r Cache = match request.guard::<State<Cache>>() {
+            request::Outcome::Success(state) => state.inner(),
+            _ => {
+                error!("The cache requested with a CacheHandle is not managed by Rocket.");
+                return request::Outcome::Failure((Status::InternalServerError, ()));
+            }
+        };
+        let label: Option<String> = match cache.labeled_metrics {
+            Some(_) => request.route().map(|route| route_label(route.base.path()).to_string()),
+            None => None,
+        };
+        request::Outcome::Success(CacheHandle { cache, label })
+    }
+}
+
+/// Gets the label of the base a route is mounted at.
+fn route_label(base: &str) -> &str {
+    base.trim_matches('/')
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use self::tempdir::TempDir;
+    use cache_builder::CacheBuilder;
+    use labeled_metrics::LabeledSummary;
+    use rocket::{Route, Data};
+    use rocket::handler::{Handler, Outcome};
+    use rocket::http::Method;
+    use rocket::local::Client;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[derive(Clone)]
+    struct Serve {
+        path: PathBuf,
+        tag: Option<&'static str>,
+    }
+
+    impl Handler for Serve {
+        fn handle<'r>(&self, request: &'r Request, data: Data) -> Outcome<'r> {
+            let handle: CacheHandle = match request.guard::<CacheHandle>() {
+                request::Outcome::Success(handle) => handle,
+                request::Outcome::Failure((status, _)) => return Outcome::failure(status),
+                request::Outcome::Forward(()) => return Outcome::forward(data),
+            };
+            let handle: CacheHandle = match self.tag {
+                Some(tag) => handle.with_label(tag),
+                None => handle,
+            };
+            Outcome::from(request, handle.get(&self.path))
+        }
+    }
+
+    fn client(cache: Cache, img: &Path, js: &Path) -> Client {
+        let serve_img = Serve { path: img.to_path_buf(), tag: None };
+        let serve_js = Serve { path: js.to_path_buf(), tag: None };
+        let serve_tagged = Serve { path: js.to_path_buf(), tag: Some("scripts") };
+        let rocket = ::rocket::ignite()
+            .manage(cache)
+            .mount("/img", vec![Route::new(Method::Get, "/logo.png", serve_img)])
+            .mount("/js", vec![Route::new(Method::Get, "/app.js", serve_js)])
+            .mount("/tagged", vec![Route::new(Method::Get, "/app.js", serve_tagged)]);
+        Client::new(rocket).unwrap()
+    }
+
+    fn managed_cache(client: &Client) -> &Cache {
+        let request = client.get("/");
+        let cache: &Cache = match request.inner().guard::<State<Cache>>() {
+            request::Outcome::Success(state) => state.inner(),
+            _ => panic!("Expected the cache to be managed."),
+        };
+        cache
+    }
+
+    #[test]
+    fn hits_and_misses_are_counted_by_label() {
+        let temp_dir = TempDir::new("cache_handle").unwrap();
+        let img = temp_dir.path().join("logo.png");
+        let js = temp_dir.path().join("app.js");
+        fs::write(&img, b"png").unwrap();
+        fs::write(&js, b"javascript").unwrap();
+
+        let mut cache: Cache = CacheBuilder::new().size_limit(1024).build().unwrap();
+        cache.set_labeled_metrics(true);
+        let client = client(cache, &img, &js);
+
+        for _ in 0..3 {
+            assert_eq!(client.get("/img/logo.png").dispatch().status(), Status::Ok);
+        }
+        let mut response = client.get("/js/app.js").dispatch();
+        assert_eq!(response.body_string(), Some(String::from("javascript")));
+        assert_eq!(client.get("/tagged/app.js").dispatch().status(), Status::Ok);
+        // Guards that aren't used in a route don't make a label from the requested path.
+        match client.get("/made-up/logo.png").inner().guard::<CacheHandle>() {
+            request::Outcome::Success(handle) => assert_eq!(handle.label(), None),
+            _ => panic!("Expected the cache to be managed."),
+        }
+
+        let cache: &Cache = managed_cache(&client);
+        assert_eq!(cache.labeled_summary("img"), LabeledSummary { hits: 2, misses: 1 });
+        assert_eq!(cache.labeled_summary("js"), LabeledSummary { hits: 0, misses: 1 });
+        assert_eq!(cache.labeled_summary("scripts"), LabeledSummary { hits: 1, misses: 0 });
+        assert_eq!(cache.labeled_summary("tagged"), LabeledSummary::default());
+        assert_eq!(cache.labeled_summary("made-up"), LabeledSummary::default());
+    }
+
+    #[test]
+    fn nothing_is_counted_until_labels_are_turned_on() {
+        let temp_dir = TempDir::new("cache_handle").unwrap();
+        let img = temp_dir.path().join("logo.png");
+        let js = temp_dir.path().join("app.js");
+        fs::write(&img, b"png").unwrap();
+        fs::write(&js, b"javascript").unwrap();
+
+        let client = client(CacheBuilder::new().size_limit(1024).build().unwrap(), &img, &js);
+        let request = client.get("/img/logo.png");
+        match request.inner().guard::<CacheHandle>() {
+            request::Outcome::Success(handle) => assert_eq!(handle.with_label("images").label(), None),
+            _ => panic!("Expected the cache to be managed."),
+        }
+        assert_eq!(request.dispatch().status(), Status::Ok);
+
+        let cache: &Cache = managed_cache(&client);
+        assert!(cache.contains_key(&img));
+        assert_eq!(cache.labeled_summary("img"), LabeledSummary::default());
+    }
+
+    #[test]
+    fn labels_are_the_base_of_the_route() {
+        assert_eq!(route_label("/img"), "img");
+        assert_eq!(route_label("/static/js/"), "static/js");
+        assert_eq!(route_label("/"), "");
+    }
+}
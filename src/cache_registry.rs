@@ -0,0 +1,193 @@
+use std::path::{PathBuf, Path};
+
+use cache::Cache;
+#[cfg(feature = "rocket")]
+use cached_file::CachedFile;
+use file_source::FileSource;
+use summary::CacheSummary;
+
+
+/// Holds multiple caches, each with its own configuration and size limit,
+/// and routes requests for files to the cache responsible for the file's path.
+///
+/// Each cache is registered under a path prefix.
+/// A request is routed to the cache with the longest prefix that the requested path starts with.
+/// If no prefix matches, the request goes to the default cache, if one is set,
+/// or straight to the filesystem otherwise.
+///
+/// This allows a single object to be managed by Rocket, while still keeping files in one directory
+/// from evicting the files in another directory.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::{CacheBuilder, CacheRegistry};
+///
+/// let mut registry = CacheRegistry::new();
+/// registry
+///     .add("www/images/", CacheBuilder::new().size_limit(1024 * 1024 * 100).build().unwrap())
+///     .add("www/fonts/", CacheBuilder::new().size_limit(1024 * 1024 * 10).build().unwrap())
+///     .set_default(CacheBuilder::new().size_limit(1024 * 1024 * 20).build().unwrap());
+///
+/// assert!(registry.cache_for("www/images/logo.png").is_some());
+/// ```
+#[derive(Debug)]
+pub struct CacheRegistry {
+    caches: Vec<(PathBuf, Cache)>,
+    default: Option<Cache>,
+}
+
+impl Default for CacheRegistry {
+    fn default() -> CacheRegistry {
+        CacheRegistry::new()
+    }
+}
+
+impl CacheRegistry {
+    /// Creates an empty registry.
+    /// Until caches are added, every file will be served from the filesystem.
+    pub fn new() -> CacheRegistry {
+        CacheRegistry {
+            caches: Vec::new(),
+            default: None,
+        }
+    }
+
+    /// Registers a cache for files whose paths start with the prefix.
+    /// Registering a cache for a prefix that is already registered replaces the old cache.
+    pub fn add<P: Into<PathBuf>>(&mut self, prefix: P, cache: Cache) -> &mut Self {
+        let prefix: PathBuf = prefix.into();
+        self.caches.retain(|(registered_prefix, _)| *registered_prefix != prefix);
+        self.caches.push((prefix, cache));
+        self
+    }
+
+    /// Sets the cache used for files that don't match any registered prefix.
+    pub fn set_default(&mut self, cache: Cache) -> &mut Self {
+        self.default = Some(cache);
+        self
+    }
+
+    /// Gets the cache responsible for the path, by longest prefix match.
+    /// Returns the default cache if no prefix matches, or None if there is no default cache either.
+    pub fn cache_for<P: AsRef<Path>>(&self, path: P) -> Option<&Cache> {
+        self.caches
+            .iter()
+            .filter(|&(prefix, _)| path.as_ref().starts_with(prefix))
+            .max_by_key(|&(prefix, _)| prefix.components().count())
+            .map(|(_, cache)| cache)
+            .or(self.default.as_ref())
+    }
+
+    /// Gets the file from the cache responsible for the path.
+    /// If no cache is responsible for the path, the file is served from the filesystem.
+    pub fn get_source<P: AsRef<Path>>(&self, path: P) -> FileSource {
+        match self.cache_for(&path) {
+            Some(cache) => cache.get_source(path),
+            None => {
+                match FileSource::open(path.as_ref()) {
+                    Ok(file) => file,
+                    Err(_) => FileSource::NotFound,
+                }
+            }
+        }
+    }
+
+    /// Gets the file like `get_source()`, as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> CachedFile {
+        self.get_source(path).into()
+    }
+
+    /// Removes the file from the cache responsible for the path.
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> bool {
+        match self.cache_for(&path) {
+            Some(cache) => cache.remove(path),
+            None => false,
+        }
+    }
+
+    /// Removes every file from every cache in the registry, returning the number of files removed.
+    pub fn clear(&self) -> usize {
+        self.caches
+            .iter()
+            .map(|(_, cache)| cache)
+            .chain(self.default.iter())
+            .map(|cache| cache.clear())
+            .sum()
+    }
+
+    /// Refreshes the file in the cache responsible for the path.
+    pub fn refresh_source<P: AsRef<Path>>(&self, path: P) -> FileSource {
+        match self.cache_for(&path) {
+            Some(cache) => cache.refresh_source(path),
+            None => FileSource::NotFound,
+        }
+    }
+
+    /// Refreshes the file like `refresh_source()`, returning it as a `CachedFile`.
+    #[cfg(feature = "rocket")]
+    pub fn refresh<P: AsRef<Path>>(&self, path: P) -> CachedFile {
+        self.refresh_source(path).into()
+    }
+
+    /// Gets a summary combining the state of every cache in the registry.
+    pub fn summary(&self) -> CacheSummary {
+        self.caches
+            .iter()
+            .map(|(_, cache)| cache)
+            .chain(self.default.iter())
+            .fold(CacheSummary::empty(), |combined, cache| combined.combine(&cache.summary()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use self::tempdir::TempDir;
+    use cache_builder::CacheBuilder;
+    use testing::create_sized_file;
+    use std::fs;
+
+    const MEG1: usize = 1024 * 1024;
+
+    #[test]
+    fn files_are_routed_to_their_caches() {
+        let temp_dir = TempDir::new("registry").unwrap();
+        let images_dir = temp_dir.path().join("images");
+        let fonts_dir = temp_dir.path().join("fonts");
+        fs::create_dir(&images_dir).unwrap();
+        fs::create_dir(&fonts_dir).unwrap();
+
+        let mut registry = CacheRegistry::new();
+        registry
+            .add(images_dir.clone(), CacheBuilder::new().size_limit(MEG1 * 2 + 1).build().unwrap())
+            .add(fonts_dir.clone(), CacheBuilder::new().size_limit(MEG1 * 2 + 1).build().unwrap());
+
+        let font = create_sized_file(&fonts_dir, MEG1, "font.woff2");
+        registry.get_source(&font);
+
+        for i in 0..5 {
+            let image = create_sized_file(&images_dir, MEG1, format!("{}.png", i).as_str());
+            registry.get_source(&image);
+        }
+
+        let images_cache = registry.cache_for(images_dir.join("0.png")).unwrap();
+        let fonts_cache = registry.cache_for(&font).unwrap();
+        assert_eq!(images_cache.used_bytes(), MEG1 * 2);
+        assert_eq!(fonts_cache.used_bytes(), MEG1);
+        assert!(fonts_cache.contains_key(&font));
+        assert_eq!(registry.summary().used_bytes, MEG1 * 3);
+
+        // Files outside of every prefix are served from the filesystem.
+        let other = create_sized_file(temp_dir.path(), 10, "other.txt");
+        let file = registry.get_source(&other);
+        match file {
+            FileSource::Fs(..) => {}
+            _ => panic!("Expected the file to be served from the filesystem."),
+        }
+    }
+}
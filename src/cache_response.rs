@@ -0,0 +1,144 @@
+use rocket::http::Status;
+use rocket::response::{Response, Responder, status};
+use rocket::request::Request;
+
+use cache::CacheError;
+use cached_file::CachedFile;
+
+
+/// A Rocket responder for the result of `Cache::get_result()`.
+///
+/// A file that was found is served like a `CachedFile`. An error responds with a status that fits it,
+/// and a short plain text body naming the status, instead of leaving every failure to Rocket's 404 catcher.
+///
+/// By default, the statuses are:
+///
+/// * `NotFound` - 404 Not Found
+/// * `PermissionDenied` - 403 Forbidden
+/// * `IsDirectory` and `InvalidKey` - 400 Bad Request
+/// * Any other error - 500 Internal Server Error
+///
+/// Use `status_with()` to pick the statuses yourself, like answering 404 to files the server may not read,
+/// so they can't be told apart from missing ones.
+pub struct CacheResponse<'a> {
+    result: Result<CachedFile<'a>, CacheError>,
+    status_of: fn(&CacheError) -> Status,
+}
+
+impl<'a> CacheResponse<'a> {
+    /// Responds to errors with the status the function picks for them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate rocket;
+    /// # extern crate rocket_file_cache;
+    /// # fn main() {
+    /// use rocket::http::Status;
+    /// use rocket_file_cache::{CacheBuilder, CacheError, CacheResponse};
+    ///
+    /// fn hide_forbidden_files(error: &CacheError) -> Status {
+    ///     match *error {
+    ///         CacheError::PermissionDenied => Status::NotFound,
+    ///         ref error => CacheResponse::default_status(error),
+    ///     }
+    /// }
+    ///
+    /// let cache = CacheBuilder::new().build().unwrap();
+    /// let response = CacheResponse::from(cache.get_result("www/index.html")).status_with(hide_forbidden_files);
+    /// # }
+    /// ```
+    pub fn status_with(mut self, status_of: fn(&CacheError) -> Status) -> CacheResponse<'a> {
+        self.status_of = status_of;
+        self
+    }
+
+    /// The status an error responds with, unless `status_with()` picks another one.
+    pub fn default_status(error: &CacheError) -> Status {
+        match *error {
+            CacheError::NotFound => Status::NotFound,
+            CacheError::PermissionDenied => Status::Forbidden,
+            CacheError::IsDirectory | CacheError::InvalidKey(_) => Status::BadRequest,
+            _ => Status::InternalServerError,
+        }
+    }
+}
+
+impl<'a> From<Result<CachedFile<'a>, CacheError>> for CacheResponse<'a> {
+    fn from(result: Result<CachedFile<'a>, CacheError>) -> CacheResponse<'a> {
+        CacheResponse {
+            result,
+            status_of: CacheResponse::default_status,
+        }
+    }
+}
+
+impl<'a> Responder<'a> for CacheResponse<'a> {
+    fn respond_to(self, request: &Request) -> Result<Response<'a>, Status> {
+        match self.result {
+            Ok(cached_file) => cached_file.respond_to(request),
+            Err(error) => {
+                let status: Status = (self.status_of)(&error);
+                debug!("Responding with {} to {:?}.", status.code, error);
+                status::Custom(status, status.reason.to_string()).respond_to(request)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use self::tempdir::TempDir;
+    use cache::{Cache, InvalidKeyReason};
+    use cache_builder::CacheBuilder;
+    use rocket::local::Client;
+    use std::fs;
+
+    fn respond(response: CacheResponse) -> (u16, Option<String>) {
+        let client = Client::new(::rocket::ignite()).unwrap();
+        let request = client.get("/");
+        let mut response = response.respond_to(request.inner()).unwrap();
+        (response.status().code, response.body_string())
+    }
+
+    #[test]
+    fn errors_respond_with_a_status_that_fits_them() {
+        let cache: Cache = CacheBuilder::new().build().unwrap();
+        let temp_dir = TempDir::new("cache_response").unwrap();
+        let path = temp_dir.path().join("file.txt");
+        fs::write(&path, b"file").unwrap();
+        fs::create_dir(temp_dir.path().join("dir")).unwrap();
+
+        assert_eq!(respond(CacheResponse::from(cache.get_result(&path))), (200, Some(String::from("file"))));
+        assert_eq!(
+            respond(CacheResponse::from(cache.get_result(temp_dir.path().join("missing.txt")))),
+            (404, Some(String::from("Not Found")))
+        );
+        assert_eq!(cache.get_result(temp_dir.path().join("dir")), Err(CacheError::IsDirectory));
+        assert_eq!(respond(CacheResponse::from(cache.get_result(temp_dir.path().join("dir")))).0, 400);
+        assert_eq!(cache.get_result(""), Err(CacheError::InvalidKey(InvalidKeyReason::Empty)));
+        assert_eq!(respond(CacheResponse::from(cache.get_result(""))).0, 400);
+        // The server may read every file when the tests run as root, so the error is made up.
+        assert_eq!(respond(CacheResponse::from(Err(CacheError::PermissionDenied))), (403, Some(String::from("Forbidden"))));
+        assert_eq!(respond(CacheResponse::from(Err(CacheError::CouldNotReadFile))).0, 500);
+    }
+
+    #[test]
+    fn statuses_can_be_picked_for_errors() {
+        fn hide_forbidden_files(error: &CacheError) -> Status {
+            match *error {
+                CacheError::PermissionDenied => Status::NotFound,
+                ref error => CacheResponse::default_status(error),
+            }
+        }
+        let response = |error: CacheError| respond(CacheResponse::from(Err(error)).status_with(hide_forbidden_files)).0;
+
+        assert_eq!(response(CacheError::PermissionDenied), 404);
+        assert_eq!(response(CacheError::NotFound), 404);
+        assert_eq!(response(CacheError::IsDirectory), 400);
+    }
+}
@@ -1,10 +1,15 @@
-use rocket::http::Status;
+use rocket::http::{Status, ContentType};
 use rocket::response::{Response, Responder, NamedFile};
 use rocket::request::Request;
 use cache::Cache;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
 
 use named_in_memory_file::NamedInMemoryFile;
+use detached_file::DetachedFile;
+use file_source::FileSource;
+use serve_class::ServeClass;
 
 
 /// Wrapper around data that can represent a file - either in memory (cache), or on disk.
@@ -13,14 +18,26 @@ use named_in_memory_file::NamedInMemoryFile;
 /// * An `InMemory` variant indicates that the file was read into the cache and a reference to that file is attached to the variant.
 /// * A `FileSystem` variant indicates that the file is not in the cache, but it can be accessed from the filesystem.
 /// * A `NotFound` variant indicates that the file can not be found in the filesystem or the cache.
+///
+/// `Cache::refresh()` can also return a `FileMissing` variant, and `detach()` returns a `Detached` variant.
+///
+/// An `InMemory` variant holds a lock on the file in the cache until it is dropped.
+/// If the lock shouldn't be held while the response is written, `detach()` the file first.
+/// An `InMemory` variant can't be sent to another thread either, but a detached one can.
 #[derive(Debug)]
 pub enum CachedFile<'a> {
     /// A file that has been loaded into the cache.
     InMemory(NamedInMemoryFile<'a>),
-    /// A file that exists in the filesystem.
-    FileSystem(NamedFile),
+    /// A file that exists in the filesystem, with its path, and why the cache served it from the filesystem,
+    /// if the method that returned it classifies the files it serves.
+    /// The response is written from this handle, so the file is never opened a second time.
+    FileSystem(File, PathBuf, Option<ServeClass>),
+    /// A file that was in the cache, detached from it with `detach()`.
+    Detached(DetachedFile),
     /// The file does not exist in either the cache or the filesystem.
-    NotFound
+    NotFound,
+    /// The file was in the cache when it was refreshed, but it no longer exists in the filesystem.
+    FileMissing,
 }
 
 impl<'a> CachedFile<'a> {
@@ -32,9 +49,103 @@ impl<'a> CachedFile<'a> {
     pub fn open<P: AsRef<Path>>(path: P, cache: &'a Cache) -> CachedFile<'a> {
         cache.get(path)
     }
+
+    /// Releases the lock on the file in the cache, keeping its bytes.
+    ///
+    /// The detached file shares the bytes of the cached file, so it can be served after the file is evicted,
+    /// or the cache is dropped, without copying the bytes.
+    /// A file served from the filesystem keeps the handle it was opened with,
+    /// so it is still served if the file is deleted after it was opened.
+    pub fn detach(self) -> CachedFile<'static> {
+        match self {
+            CachedFile::InMemory(cached_file) => FileSource::from(cached_file).detach().into(),
+            CachedFile::FileSystem(file, path, class) => CachedFile::FileSystem(file, path, class),
+            CachedFile::Detached(detached_file) => CachedFile::Detached(detached_file),
+            CachedFile::NotFound => CachedFile::NotFound,
+            CachedFile::FileMissing => CachedFile::FileMissing,
+        }
+    }
+
+    /// Copies the file, sharing its bytes, so the copy can be kept after this one is responded with.
+    ///
+    /// A file in the cache is copied as a `Detached` file, as the lock on its entry can't be shared.
+    /// The copy is equal to the original.
+    ///
+    /// Returns None for a file served from the filesystem, because copies of an open file would share
+    /// its read position, so responding with one would truncate the response of the other.
+    pub fn try_clone(&self) -> Option<CachedFile<'static>> {
+        match *self {
+            CachedFile::InMemory(ref cached_file) => Some(CachedFile::Detached(DetachedFile {
+                path: cached_file.path.clone(),
+                bytes: cached_file.file.get().bytes.clone(),
+            })),
+            CachedFile::Detached(ref detached_file) => Some(CachedFile::Detached(detached_file.clone())),
+            CachedFile::FileSystem(..) => None,
+            CachedFile::NotFound => Some(CachedFile::NotFound),
+            CachedFile::FileMissing => Some(CachedFile::FileMissing),
+        }
+    }
+
+    /// Where the file was served from, and if it wasn't served from the cache, why.
+    ///
+    /// Files held in memory are `Hit`s, including files that were inserted into the cache by the request that got them,
+    /// which only `Cache::get_classified()` tells apart. A file served from the filesystem has the reason the cache
+    /// gave it, or None if it came from a method that doesn't classify the files it serves, like `get_keyed()`.
+    /// Files that weren't found have no class.
+    pub fn serve_class(&self) -> Option<ServeClass> {
+        match *self {
+            CachedFile::InMemory(_) | CachedFile::Detached(_) => Some(ServeClass::Hit),
+            CachedFile::FileSystem(_, _, class) => class,
+            CachedFile::NotFound | CachedFile::FileMissing => None,
+        }
+    }
+
+    /// The `serve_class()` of the file a request was responded to with, for fairings that log why files missed the cache.
+    ///
+    /// Responding with a `CachedFile` stores its class in the request's local cache, where this reads it.
+    /// Returns None if the request wasn't responded to with a `CachedFile`, or the file had no class.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate rocket;
+    /// # extern crate rocket_file_cache;
+    /// # fn main() {
+    /// use rocket::{Request, Response};
+    /// use rocket::fairing::{Fairing, Info, Kind};
+    /// use rocket_file_cache::CachedFile;
+    ///
+    /// struct AccessLog;
+    ///
+    /// impl Fairing for AccessLog {
+    ///     fn info(&self) -> Info {
+    ///         Info { name: "Access log", kind: Kind::Response }
+    ///     }
+    ///
+    ///     fn on_response(&self, request: &Request, response: &mut Response) {
+    ///         println!("{} {} {:?}", request.uri(), response.status().code, CachedFile::serve_class_of(request));
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn serve_class_of(request: &Request) -> Option<ServeClass> {
+        request.local_cache(|| RespondedClass(None)).0
+    }
+
+    /// Gets the file a `Cache` method served, keeping the reason it was served from the filesystem.
+    pub(crate) fn served(file_source: FileSource<'a>, class: ServeClass) -> CachedFile<'a> {
+        match file_source {
+            FileSource::Fs(file, path, _) => CachedFile::FileSystem(file, path, Some(class)),
+            file_source => file_source.into(),
+        }
+    }
 }
 
 
+/// The class of the file a request was responded to with, stored in the request's local cache.
+struct RespondedClass(Option<ServeClass>);
+
+
 impl<'a> From<NamedInMemoryFile<'a>> for CachedFile<'a> {
     fn from(cached_file: NamedInMemoryFile<'a>) -> CachedFile<'a> {
         CachedFile::InMemory(cached_file)
@@ -43,17 +154,42 @@ impl<'a> From<NamedInMemoryFile<'a>> for CachedFile<'a> {
 
 impl From<NamedFile> for CachedFile<'static> {
     fn from(named_file: NamedFile) -> Self {
-        CachedFile::FileSystem(named_file)
+        let path: PathBuf = named_file.path().to_path_buf();
+        CachedFile::FileSystem(named_file.take_file(), path, None)
+    }
+}
+
+impl<'a> From<FileSource<'a>> for CachedFile<'a> {
+    fn from(file_source: FileSource<'a>) -> CachedFile<'a> {
+        match file_source {
+            FileSource::Cached(cached_file) => CachedFile::InMemory(cached_file),
+            FileSource::Fs(file, path, _) => CachedFile::FileSystem(file, path, None),
+            FileSource::Detached(detached_file) => CachedFile::Detached(detached_file),
+            FileSource::NotFound | FileSource::Deferred(_) => CachedFile::NotFound,
+            FileSource::FileMissing => CachedFile::FileMissing,
+        }
     }
 }
 
 impl<'a> Responder<'a> for CachedFile<'a> {
     fn respond_to(self, request: &Request) -> Result<Response<'a>, Status> {
+        let class: Option<ServeClass> = self.serve_class();
+        request.local_cache(|| RespondedClass(class));
 
         match self {
             CachedFile::InMemory(cached_file) => cached_file.respond_to(request),
-            CachedFile::FileSystem(named_file) => named_file.respond_to(request),
-            CachedFile::NotFound => {
+            CachedFile::FileSystem(file, path, _) => {
+                // Based on NamedFile from rocket::response::NamedFile, which can't be made from an open file.
+                let mut response = file.respond_to(request)?;
+                if let Some(ext) = path.extension() {
+                    if let Some(ct) = ContentType::from_extension(&ext.to_string_lossy()) {
+                        response.set_header(ct);
+                    }
+                }
+                Ok(response)
+            }
+            CachedFile::Detached(detached_file) => detached_file.respond_to(request),
+            CachedFile::NotFound | CachedFile::FileMissing => {
                 error!("Response was `FileNotFound`.",);
                 Err(Status::NotFound)
             }
@@ -62,34 +198,214 @@ impl<'a> Responder<'a> for CachedFile<'a> {
 }
 
 
+/// Files held in memory are equal if they have the same path, and share the same bytes,
+/// so a file in the cache is equal to the files detached or cloned from it, but not to a copy of its contents.
+/// Comparing them doesn't look at the bytes.
+/// Open files can't be compared, so files served from the filesystem are equal if they have the same path.
 impl<'a> PartialEq for CachedFile<'a> {
     fn eq(&self, other: &CachedFile) -> bool {
-        match *self {
-            CachedFile::InMemory(ref lhs_cached_file) => {
-                match *other {
-                    CachedFile::InMemory(ref rhs_cached_file) => (*rhs_cached_file.file).get() == (*lhs_cached_file.file).get(),
-                    CachedFile::FileSystem(_) => false,
-                    CachedFile::NotFound => false
-                }
-            }
-            CachedFile::FileSystem(ref lhs_named_file) => {
-                match *other {
-                    CachedFile::InMemory(_) => false,
-                    CachedFile::FileSystem(ref rhs_named_file) => {
-                        // This just compares the file paths
-                        *lhs_named_file.path() == *rhs_named_file.path()
-                    }
-                    CachedFile::NotFound => false
-                }
+        match (self.in_memory(), other.in_memory()) {
+            (Some((lhs_path, lhs_bytes)), Some((rhs_path, rhs_bytes))) => {
+                lhs_path == rhs_path
+                    && lhs_bytes.as_ptr() == rhs_bytes.as_ptr()
+                    && lhs_bytes.len() == rhs_bytes.len()
             }
-            CachedFile::NotFound => {
-                match *other {
-                    CachedFile::InMemory(_) => false,
-                    CachedFile::FileSystem(_) => false,
-                    CachedFile::NotFound => true
-                }
+            _ => match (self, other) {
+                (CachedFile::FileSystem(_, lhs, _), CachedFile::FileSystem(_, rhs, _)) => lhs == rhs,
+                (&CachedFile::NotFound, &CachedFile::NotFound) => true,
+                (&CachedFile::FileMissing, &CachedFile::FileMissing) => true,
+                _ => false,
+            },
+        }
+    }
+}
+
+impl<'a> Eq for CachedFile<'a> {}
+
+/// Hashes the path of the file, so files that are equal hash alike.
+impl<'a> Hash for CachedFile<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            // Cached and detached files can be equal to each other, so they can't be told apart here.
+            CachedFile::InMemory(ref cached_file) => (0u8, &cached_file.path).hash(state),
+            CachedFile::Detached(ref detached_file) => (0u8, &detached_file.path).hash(state),
+            CachedFile::FileSystem(_, ref path, _) => (1u8, path).hash(state),
+            CachedFile::NotFound => 2u8.hash(state),
+            CachedFile::FileMissing => 3u8.hash(state),
+        }
+    }
+}
+
+impl<'a> CachedFile<'a> {
+    /// The path and bytes of a file held in memory.
+    fn in_memory(&self) -> Option<(&Path, &[u8])> {
+        match *self {
+            CachedFile::InMemory(ref cached_file) => Some((&cached_file.path, cached_file.file.get().bytes.as_slice())),
+            CachedFile::Detached(ref detached_file) => Some((detached_file.path(), detached_file.bytes())),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use self::tempdir::TempDir;
+    use cache_builder::CacheBuilder;
+    use rocket::{Data, State};
+    use rocket::fairing::{Fairing, Info, Kind};
+    use rocket::handler::{Handler, Outcome};
+    use rocket::http::Method;
+    use rocket::local::Client;
+    use rocket::Route;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn detached_files_respond_after_the_cache_and_file_are_gone() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(1024)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new("cached_file").unwrap();
+        let small_path = temp_dir.path().join("small.txt");
+        let large_path = temp_dir.path().join("large.txt");
+        fs::write(&small_path, b"small").unwrap();
+        fs::write(&large_path, vec![b'l'; 2048]).unwrap();
+
+        let small: CachedFile = cache.get(&small_path).detach();
+        let large: CachedFile = cache.get(&large_path).detach();
+        match (&small, &large) {
+            (&CachedFile::Detached(_), &CachedFile::FileSystem(..)) => {}
+            _ => panic!("Expected one detached file and one file served from the filesystem."),
+        }
+        drop(cache);
+        // The file served from the filesystem would not be found if it was opened again by its path.
+        fs::remove_file(&small_path).unwrap();
+        fs::remove_file(&large_path).unwrap();
+
+        let client = Client::new(::rocket::ignite()).unwrap();
+        let request = client.get("/");
+        let mut response = small.respond_to(request.inner()).unwrap();
+        assert_eq!(response.body_bytes(), Some(b"small".to_vec()));
+        let mut response = large.respond_to(request.inner()).unwrap();
+        assert_eq!(response.body_bytes(), Some(vec![b'l'; 2048]));
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    // The hash of a file is only its path, which doesn't change while it is in the set.
+    #[allow(clippy::mutable_key_type)]
+    fn cached_files_can_be_cloned_and_collected() {
+        assert_send_sync::<DetachedFile>();
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(1024)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new("cached_file").unwrap();
+        let a_path = temp_dir.path().join("a.txt");
+        let b_path = temp_dir.path().join("b.txt");
+        let copy_path = temp_dir.path().join("copy.txt");
+        let large_path = temp_dir.path().join("large.txt");
+        fs::write(&a_path, b"same").unwrap();
+        fs::write(&b_path, b"other").unwrap();
+        fs::write(&copy_path, b"same").unwrap();
+        fs::write(&large_path, vec![b'l'; 2048]).unwrap();
+
+        let a: CachedFile<'static> = cache.get(&a_path).try_clone().unwrap();
+        let b: CachedFile<'static> = cache.get(&b_path).try_clone().unwrap();
+        let copy: CachedFile<'static> = cache.get(&copy_path).try_clone().unwrap();
+        // Files with the same contents are still different files.
+        assert_ne!(a, copy);
+        assert_eq!(cache.get(&a_path), a);
+        assert!(cache.get(&large_path).try_clone().is_none());
+        assert_eq!(CachedFile::NotFound.try_clone(), Some(CachedFile::NotFound));
+
+        let mut files: HashSet<CachedFile> = HashSet::new();
+        assert!(files.insert(a.try_clone().unwrap()));
+        assert!(files.insert(b.try_clone().unwrap()));
+        assert!(files.insert(copy.try_clone().unwrap()));
+        assert!(!files.insert(a.try_clone().unwrap()));
+        assert!(files.contains(&cache.get(&b_path)));
+        assert_eq!(files.len(), 3);
+
+        // Clones share the bytes of the file, wherever they are used.
+        let detached: DetachedFile = match a.try_clone() {
+            Some(CachedFile::Detached(detached_file)) => detached_file,
+            _ => panic!("Expected the clone to be detached."),
+        };
+        let threads: Vec<thread::JoinHandle<DetachedFile>> = (0..4)
+            .map(|_| {
+                let detached: DetachedFile = detached.clone();
+                thread::spawn(move || detached)
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(CachedFile::Detached(thread.join().unwrap()), a);
+        }
+    }
+
+    /// Serves one file from the managed cache.
+    struct ServeFile(PathBuf);
+
+    impl Handler for ServeFile {
+        fn handle<'r>(&self, request: &'r Request, _data: Data) -> Outcome<'r> {
+            match request.guard::<State<Cache>>() {
+                ::rocket::request::Outcome::Success(cache) => Outcome::from(request, cache.inner().get(&self.0)),
+                _ => Outcome::failure(Status::InternalServerError),
             }
         }
+    }
+
+    /// Records the class of every response, like a fairing writing an access log.
+    struct ClassLog(Arc<Mutex<Vec<Option<ServeClass>>>>);
+
+    impl Fairing for ClassLog {
+        fn info(&self) -> Info {
+            Info { name: "Class log", kind: Kind::Response }
+        }
+
+        fn on_response(&self, request: &Request, _response: &mut Response) {
+            self.0.lock().unwrap().push(CachedFile::serve_class_of(request));
+        }
+    }
+
+    #[test]
+    fn fairings_can_read_why_a_file_was_served_from_the_filesystem() {
+        let cache: Cache = CacheBuilder::new()
+            .size_limit(1024)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new("cached_file").unwrap();
+        let small_path = temp_dir.path().join("small.txt");
+        let large_path = temp_dir.path().join("large.txt");
+        fs::write(&small_path, b"small").unwrap();
+        fs::write(&large_path, vec![b'l'; 2048]).unwrap();
+        cache.insert(&small_path).unwrap();
 
+        let classes: Arc<Mutex<Vec<Option<ServeClass>>>> = Arc::new(Mutex::new(vec![]));
+        let rocket = ::rocket::ignite()
+            .manage(cache)
+            .mount("/", vec![
+                Route::new(Method::Get, "/small", ServeFile(small_path.clone())),
+                Route::new(Method::Get, "/large", ServeFile(large_path.clone())),
+            ])
+            .attach(ClassLog(classes.clone()));
+        let client = Client::new(rocket).unwrap();
+        assert_eq!(client.get("/large").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/small").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/missing").dispatch().status(), Status::NotFound);
+
+        assert_eq!(*classes.lock().unwrap(), vec![Some(ServeClass::FsTooLarge), Some(ServeClass::Hit), None]);
+        match CachedFile::from(NamedFile::open(&large_path).unwrap()) {
+            ref file @ CachedFile::FileSystem(..) => assert_eq!(file.serve_class(), None),
+            _ => panic!("Expected a file served from the filesystem."),
+        }
     }
 }
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bookkeeping::path_entry_bytes;
+
+
+/// The most paths whose cycles are tracked.
+const MAX_PATHS: usize = 10_000;
+
+/// Counts how often files are evicted and then inserted into the cache again.
+///
+/// A file that keeps cycling in and out of the cache is a sign that the files being requested
+/// don't fit into the cache together.
+///
+/// At most `MAX_PATHS` paths are tracked. Once that many are, the paths that never completed a cycle are forgotten,
+/// and then the paths with the fewest cycles, until at most half of them are left.
+#[derive(Debug)]
+pub(crate) struct ChurnTracker {
+    paths: Mutex<HashMap<PathBuf, PathChurn>>,
+    /// The number of evictions of files that had already been evicted and inserted again before.
+    evictions_of_previously_resident: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct PathChurn {
+    /// The number of times the file was inserted again after being evicted.
+    cycles: usize,
+    /// Whether the file was evicted and hasn't been inserted again since.
+    evicted: bool,
+}
+
+impl ChurnTracker {
+    pub(crate) fn new() -> ChurnTracker {
+        ChurnTracker {
+            paths: Mutex::new(HashMap::new()),
+            evictions_of_previously_resident: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn on_evicted(&self, path: &Path) {
+        let mut paths = self.lock();
+        if paths.len() >= MAX_PATHS && !paths.contains_key(path) {
+            prune(&mut paths);
+        }
+        let churn: &mut PathChurn = paths.entry(path.to_path_buf()).or_insert(PathChurn {
+            cycles: 0,
+            evicted: false,
+        });
+        if churn.cycles > 0 {
+            self.evictions_of_previously_resident.fetch_add(1, Ordering::AcqRel);
+        }
+        churn.evicted = true;
+    }
+
+    /// Completes a cycle if the file was evicted before it was stored.
+    /// Returns true if a cycle was completed.
+    pub(crate) fn on_stored(&self, path: &Path) -> bool {
+        match self.lock().get_mut(path) {
+            Some(ref mut churn) if churn.evicted => {
+                churn.evicted = false;
+                churn.cycles += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The paths that completed at least `min_cycles` cycles, from the most cycles to the fewest.
+    pub(crate) fn report(&self, min_cycles: usize) -> Vec<(PathBuf, usize)> {
+        let mut report: Vec<(PathBuf, usize)> = self.lock()
+            .iter()
+            .filter(|&(_, churn)| churn.cycles > 0 && churn.cycles >= min_cycles)
+            .map(|(path, churn)| (path.clone(), churn.cycles))
+            .collect();
+        report.sort_by(|&(ref lhs_path, lhs_cycles), &(ref rhs_path, rhs_cycles)| {
+            rhs_cycles.cmp(&lhs_cycles).then_with(|| lhs_path.cmp(rhs_path))
+        });
+        report
+    }
+
+    pub(crate) fn evictions_of_previously_resident(&self) -> usize {
+        self.evictions_of_previously_resident.load(Ordering::Acquire)
+    }
+
+    /// Estimates the bytes held by the cycles of every path.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        self.lock().keys().map(|path| path_entry_bytes::<PathChurn>(path)).sum()
+    }
+
+    pub(crate) fn reset(&self) {
+        self.lock().clear();
+        self.evictions_of_previously_resident.store(0, Ordering::Release);
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<PathBuf, PathChurn>> {
+        match self.paths.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// Forgets paths until at most half of `MAX_PATHS` are left, keeping the paths with the most cycles.
+fn prune(paths: &mut HashMap<PathBuf, PathChurn>) {
+    paths.retain(|_, churn| churn.cycles > 0);
+    if paths.len() <= MAX_PATHS / 2 {
+        return;
+    }
+    let mut cycles: Vec<usize> = paths.values().map(|churn| churn.cycles).collect();
+    cycles.sort_by(|lhs, rhs| rhs.cmp(lhs));
+    let fewest_kept: usize = cycles[MAX_PATHS / 2];
+    paths.retain(|_, churn| churn.cycles > fewest_kept);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_with_the_fewest_cycles_are_forgotten_once_too_many_are_tracked() {
+        let tracker = ChurnTracker::new();
+        let cycling = Path::new("www/cycling.html");
+        tracker.on_evicted(cycling);
+        tracker.on_stored(cycling);
+
+        for i in 1..MAX_PATHS {
+            tracker.on_evicted(&PathBuf::from(format!("www/{}.html", i)));
+        }
+        assert_eq!(tracker.lock().len(), MAX_PATHS);
+
+        // Tracking another path forgets the ones that never completed a cycle.
+        tracker.on_evicted(Path::new("www/new.html"));
+        assert_eq!(tracker.lock().len(), 2);
+        assert_eq!(tracker.report(1), vec![(cycling.to_path_buf(), 1)]);
+    }
+}
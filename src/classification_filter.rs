@@ -0,0 +1,116 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+/// The number of bits set for each marker. With ten bits per expected path, this gives about 1% false positives.
+const HASHES: u64 = 7;
+/// The number of bits the filter has for each path it is sized for.
+const BITS_PER_PATH: usize = 10;
+
+
+/// A bloom filter of markers for paths, like the bound a path was found to be outside of.
+///
+/// Its size is fixed when it is created, no matter how many markers are inserted.
+/// A marker that was inserted is always found, but a marker that wasn't may be found as well,
+/// more often the more markers the filter holds beyond the number of paths it was sized for.
+/// Markers can't be removed one at a time, only all at once.
+#[derive(Debug)]
+pub(crate) struct ClassificationFilter {
+    bits: Vec<AtomicU64>,
+}
+
+impl ClassificationFilter {
+    /// Creates a filter sized for the number of paths.
+    pub(crate) fn new(expected_paths: usize) -> ClassificationFilter {
+        let bits: usize = expected_paths.max(1).saturating_mul(BITS_PER_PATH);
+        let words: usize = bits.div_ceil(64);
+        ClassificationFilter {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Records the marker for the path.
+    pub(crate) fn insert(&self, path: &Path, marker: u8) {
+        for bit in self.bit_indices(path, marker) {
+            self.bits[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// Checks if the marker may have been recorded for the path.
+    pub(crate) fn contains(&self, path: &Path, marker: u8) -> bool {
+        self.bit_indices(path, marker)
+            .all(|bit| self.bits[(bit / 64) as usize].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0)
+    }
+
+    /// Forgets every marker.
+    pub(crate) fn reset(&self) {
+        for word in &self.bits {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// The bytes held by the filter, which don't change as markers are inserted.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        self.bits.len() * mem::size_of::<AtomicU64>()
+    }
+
+    /// Gets the bits of the marker for the path, combining two hashes to get as many as are needed.
+    fn bit_indices(&self, path: &Path, marker: u8) -> impl Iterator<Item = u64> {
+        let bit_count: u64 = self.bits.len() as u64 * 64;
+        let first: u64 = hash(path, marker, 0);
+        // The step is odd, so it is never zero, which would give every hash the same bit.
+        let step: u64 = hash(path, marker, 1) | 1;
+        (0..HASHES).map(move |i| first.wrapping_add(i.wrapping_mul(step)) % bit_count)
+    }
+}
+
+fn hash(path: &Path, marker: u8, seed: u8) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    marker.hash(&mut hasher);
+    path.hash(&mut hasher);
+    hasher.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn path(i: usize) -> PathBuf {
+        PathBuf::from(format!("www/generated/{}/page.html", i))
+    }
+
+    #[test]
+    fn inserted_markers_are_always_found() {
+        let filter = ClassificationFilter::new(1000);
+        for i in 0..1000 {
+            filter.insert(&path(i), 1);
+        }
+        assert!((0..1000).all(|i| filter.contains(&path(i), 1)));
+        let false_positives: usize = (1000..11_000).filter(|&i| filter.contains(&path(i), 1)).count();
+        assert!(false_positives < 300, "{} false positives out of 10000", false_positives);
+        // Markers are separate from each other.
+        let other_markers: usize = (0..1000).filter(|&i| filter.contains(&path(i), 2)).count();
+        assert!(other_markers < 30, "{} false positives out of 1000", other_markers);
+
+        filter.reset();
+        assert!(!filter.contains(&path(0), 1));
+    }
+
+    #[test]
+    fn a_million_paths_take_a_fixed_amount_of_memory() {
+        let filter = ClassificationFilter::new(1_000_000);
+        let bytes: usize = filter.bookkeeping_bytes();
+        assert!(bytes <= 1_300_000, "{} bytes", bytes);
+        for i in 0..1_000_000 {
+            filter.insert(&path(i), 1);
+        }
+        assert_eq!(filter.bookkeeping_bytes(), bytes);
+        assert!((0..1_000_000).step_by(97).all(|i| filter.contains(&path(i), 1)));
+    }
+}
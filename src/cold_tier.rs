@@ -0,0 +1,186 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::path::{PathBuf, Path};
+use std::sync::{Mutex, MutexGuard};
+use std::io::{Read, Write};
+
+use flate2;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use in_memory_file::InMemoryFile;
+use bookkeeping::path_entry_bytes;
+
+
+/// The compression level used when storing files in the cold tier.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// Compress quickly, at the expense of a larger size.
+    Fast,
+    /// A balance between compression speed and size.
+    Default,
+    /// Compress to the smallest size, at the expense of speed.
+    Best,
+}
+
+impl Compression {
+    fn level(&self) -> flate2::Compression {
+        match *self {
+            Compression::Fast => flate2::Compression::fast(),
+            Compression::Default => flate2::Compression::default(),
+            Compression::Best => flate2::Compression::best(),
+        }
+    }
+}
+
+
+/// Configures the cold tier of the cache.
+///
+/// Files evicted from the cache are compressed and demoted into the cold tier instead of being dropped.
+/// When a file in the cold tier is requested again, it is decompressed and promoted back into the cache,
+/// without having to read it from the filesystem.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColdTierConfig {
+    /// The number of compressed bytes the cold tier can hold.
+    pub size_limit: usize,
+    /// How hard to compress files being demoted into the cold tier.
+    pub compression: Compression,
+}
+
+
+/// Holds compressed copies of files that were evicted from the cache.
+///
+/// When the cold tier is full, the files that were demoted first are dropped first.
+#[derive(Debug)]
+pub(crate) struct ColdTier {
+    config: ColdTierConfig,
+    state: Mutex<ColdTierState>,
+}
+
+#[derive(Debug)]
+struct ColdTierState {
+    entries: HashMap<PathBuf, Vec<u8>>,
+    /// The order files were demoted in, oldest first.
+    demotion_order: VecDeque<PathBuf>,
+    used_bytes: usize,
+}
+
+impl ColdTier {
+    pub(crate) fn new(config: ColdTierConfig) -> ColdTier {
+        ColdTier {
+            config,
+            state: Mutex::new(ColdTierState {
+                entries: HashMap::new(),
+                demotion_order: VecDeque::new(),
+                used_bytes: 0,
+            }),
+        }
+    }
+
+    /// Compresses the file and stores it, dropping the oldest files if the cold tier runs out of room.
+    pub(crate) fn demote(&self, path: PathBuf, file: &InMemoryFile) {
+        let compressed: Vec<u8> = match compress(file.bytes.as_slice(), self.config.compression) {
+            Ok(compressed) => compressed,
+            Err(_) => {
+                warn!("Could not compress {:?} while demoting it into the cold tier.", path);
+                return;
+            }
+        };
+        if compressed.len() > self.config.size_limit {
+            debug!("Compressed file {:?} does not fit into the cold tier.", path);
+            return;
+        }
+
+        let mut state = self.lock();
+        state.remove(&path);
+        while state.used_bytes + compressed.len() > self.config.size_limit {
+            match state.demotion_order.pop_front() {
+                Some(oldest) => {
+                    if let Some(dropped) = state.entries.remove(&oldest) {
+                        state.used_bytes -= dropped.len();
+                    }
+                }
+                None => break,
+            }
+        }
+        debug!("Demoted {:?} into the cold tier.", path);
+        state.used_bytes += compressed.len();
+        state.demotion_order.push_back(path.clone());
+        state.entries.insert(path, compressed);
+    }
+
+    /// Removes the file from the cold tier and decompresses it.
+    pub(crate) fn take<P: AsRef<Path>>(&self, path: P) -> Option<InMemoryFile> {
+        let compressed: Vec<u8> = self.lock().remove(path.as_ref())?;
+        match decompress(&compressed) {
+            Ok(bytes) => Some(InMemoryFile::from_bytes(bytes)),
+            Err(_) => {
+                warn!("Could not decompress {:?} from the cold tier.", path.as_ref());
+                None
+            }
+        }
+    }
+
+    /// Drops the file from the cold tier.
+    pub(crate) fn remove<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.lock().remove(path.as_ref()).is_some()
+    }
+
+    /// Drops every file from the cold tier.
+    pub(crate) fn clear(&self) {
+        let mut state = self.lock();
+        state.entries.clear();
+        state.demotion_order.clear();
+        state.used_bytes = 0;
+    }
+
+    /// The number of compressed bytes held by the cold tier.
+    pub(crate) fn used_bytes(&self) -> usize {
+        self.lock().used_bytes
+    }
+
+    /// The number of files held by the cold tier.
+    pub(crate) fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Estimates the bytes held by the index of the cold tier, not counting the compressed files themselves.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        let state = self.lock();
+        let entries: usize = state.entries.keys().map(|path| path_entry_bytes::<Vec<u8>>(path)).sum();
+        let demotion_order: usize = state.demotion_order
+            .iter()
+            .map(|path| mem::size_of::<PathBuf>() + path.as_os_str().len())
+            .sum();
+        entries + demotion_order
+    }
+
+    fn lock(&self) -> MutexGuard<ColdTierState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl ColdTierState {
+    fn remove(&mut self, path: &Path) -> Option<Vec<u8>> {
+        let compressed: Vec<u8> = self.entries.remove(path)?;
+        self.used_bytes -= compressed.len();
+        self.demotion_order.retain(|demoted_path| demoted_path.as_path() != path);
+        Some(compressed)
+    }
+}
+
+
+fn compress(bytes: &[u8], compression: Compression) -> ::std::io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), compression.level());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+fn decompress(compressed: &[u8]) -> ::std::io::Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    DeflateDecoder::new(compressed).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,43 @@
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+
+/// Hashes the bytes using 64 bit FNV-1a.
+///
+/// This isn't a cryptographic hash, it is only meant to tell different versions of a file apart,
+/// so it can be used to bust caches that sit between the server and its clients.
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Formats the hash as 16 lowercase hex digits.
+pub(crate) fn to_hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// Parses a hash formatted by `to_hex()`, in either case.
+pub(crate) fn from_hex(hex: &str) -> Option<u64> {
+    if hex.len() != 16 || !hex.chars().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(hex, 16).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_reference_fnv1a_64() {
+        assert_eq!(to_hex(hash(b"")), "cbf29ce484222325");
+        assert_eq!(to_hex(hash(b"a")), "af63dc4c8601ec8c");
+        assert_eq!(to_hex(hash(b"foobar")), "85944171f73967e8");
+        assert_eq!(from_hex("85944171F73967E8"), Some(hash(b"foobar")));
+        assert_eq!(from_hex("+5944171f73967e8"), None);
+        assert_eq!(from_hex("85944171f73967e"), None);
+    }
+}
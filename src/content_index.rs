@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use content_hash;
+
+
+/// Every key of a file stored under its content starts with this, so it can't be mistaken for a path requested by a client.
+const KEY_PREFIX: &str = "#content-hash";
+
+
+/// Stores files under the hash of their content instead of their path, as set with `Cache::set_content_addressed()`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ContentAddressedConfig {
+    /// The extension of the sidecar files that hold the content hash of the file they are next to,
+    /// like `fnv` for `www/app.js.fnv` next to `www/app.js`.
+    ///
+    /// A sidecar holds the hash as the 16 hex digits returned by `Cache::content_hash()`.
+    /// Files without a sidecar are read to be hashed.
+    pub sidecar_extension: Option<String>,
+}
+
+
+/// The key a file is stored under in content addressed mode.
+///
+/// The key keeps the extension of the path, so the file is served with the same content type,
+/// and files with the same content are only shared between paths with the same extension.
+pub(crate) fn content_key(hash: u64, path: &Path) -> PathBuf {
+    let mut key: PathBuf = Path::new(KEY_PREFIX).join(content_hash::to_hex(hash));
+    if let Some(extension) = path.extension() {
+        key.set_extension(extension);
+    }
+    key
+}
+
+/// The hash of the content stored under the key, or None if the key isn't the key of a content.
+pub(crate) fn hash_of_key(key: &Path) -> Option<u64> {
+    let digest: &Path = key.strip_prefix(KEY_PREFIX).ok()?;
+    content_hash::from_hex(digest.file_stem()?.to_str()?)
+}
+
+
+/// The paths that point at a stored content, and the content each path points at.
+#[derive(Debug)]
+pub(crate) struct ContentIndex {
+    config: ContentAddressedConfig,
+    state: Mutex<ContentState>,
+}
+
+#[derive(Debug, Default)]
+struct ContentState {
+    /// The key of the content each path points at.
+    keys: HashMap<PathBuf, PathBuf>,
+    /// The paths pointing at each content, in the order they were pointed at it.
+    paths: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+/// What became of the content a path pointed at before it was pointed elsewhere or forgotten.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Departure {
+    /// The key of the content the path pointed at.
+    pub(crate) key: PathBuf,
+    /// The path still pointing at the content that pointed at it last, which its file is read from,
+    /// or None if no path points at it anymore.
+    pub(crate) remaining: Option<PathBuf>,
+}
+
+impl ContentIndex {
+    pub(crate) fn new(config: ContentAddressedConfig) -> ContentIndex {
+        ContentIndex {
+            config,
+            state: Mutex::new(ContentState::default()),
+        }
+    }
+
+    pub(crate) fn sidecar_extension(&self) -> Option<&str> {
+        self.config.sidecar_extension.as_deref()
+    }
+
+    /// The key of the content the path points at.
+    pub(crate) fn key_of(&self, path: &Path) -> Option<PathBuf> {
+        self.lock().keys.get(path).cloned()
+    }
+
+    /// The paths pointing at the content stored under the key.
+    pub(crate) fn paths_of(&self, key: &Path) -> Vec<PathBuf> {
+        self.lock().paths.get(key).cloned().unwrap_or_default()
+    }
+
+    /// The keys of the contents with the hash, one for every extension it was stored with.
+    pub(crate) fn keys_with_hash(&self, hash: u64) -> Vec<PathBuf> {
+        self.lock().paths.keys().filter(|key| hash_of_key(key) == Some(hash)).cloned().collect()
+    }
+
+    /// Points the path at the content stored under the key, returning what became of the content it pointed at before.
+    pub(crate) fn point(&self, path: PathBuf, key: PathBuf) -> Option<Departure> {
+        let mut state = self.lock();
+        let departure: Option<Departure> = match state.keys.insert(path.clone(), key.clone()) {
+            Some(ref old_key) if *old_key == key => return None,
+            Some(old_key) => Some(leave(&mut state, &path, old_key)),
+            None => None,
+        };
+        state.paths.entry(key).or_default().push(path);
+        departure
+    }
+
+    /// Forgets the content the path points at, returning what became of that content.
+    pub(crate) fn forget(&self, path: &Path) -> Option<Departure> {
+        let mut state = self.lock();
+        let key: PathBuf = state.keys.remove(path)?;
+        Some(leave(&mut state, path, key))
+    }
+
+    /// Estimates the bytes held by the paths and keys, in the same way as `Cache::bookkeeping_bytes()`.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        let state = self.lock();
+        let keys: usize = state.keys
+            .iter()
+            .map(|(path, key)| path.as_os_str().len() + key.as_os_str().len() + 2 * mem::size_of::<PathBuf>())
+            .sum();
+        let paths: usize = state.paths
+            .iter()
+            .map(|(key, paths)| {
+                key.as_os_str().len() + mem::size_of::<(PathBuf, Vec<PathBuf>)>()
+                    + paths.iter().map(|path| path.as_os_str().len() + mem::size_of::<PathBuf>()).sum::<usize>()
+            })
+            .sum();
+        keys + paths
+    }
+
+    fn lock(&self) -> MutexGuard<ContentState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// Takes the path off the paths pointing at the content stored under the key.
+fn leave(state: &mut ContentState, path: &Path, key: PathBuf) -> Departure {
+    let remaining: Option<PathBuf> = match state.paths.get_mut(&key) {
+        Some(paths) => {
+            paths.retain(|pointing| pointing != path);
+            paths.last().cloned()
+        }
+        None => None,
+    };
+    if remaining.is_none() {
+        state.paths.remove(&key);
+    }
+    Departure {
+        key,
+        remaining,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_are_left_once_no_path_points_at_them() {
+        let index = ContentIndex::new(ContentAddressedConfig::default());
+        let key: PathBuf = content_key(0xaf63dc4c8601ec8c, Path::new("www/app.js"));
+        assert_eq!(key, PathBuf::from("#content-hash/af63dc4c8601ec8c.js"));
+        assert_eq!(hash_of_key(&key), Some(0xaf63dc4c8601ec8c));
+        assert_eq!(hash_of_key(Path::new("www/app.js")), None);
+
+        assert_eq!(index.point(PathBuf::from("v1/app.js"), key.clone()), None);
+        assert_eq!(index.point(PathBuf::from("v2/app.js"), key.clone()), None);
+        assert_eq!(index.point(PathBuf::from("v2/app.js"), key.clone()), None);
+        assert_eq!(index.paths_of(&key), vec![PathBuf::from("v1/app.js"), PathBuf::from("v2/app.js")]);
+        assert_eq!(index.keys_with_hash(0xaf63dc4c8601ec8c), vec![key.clone()]);
+
+        let other_key: PathBuf = content_key(1, Path::new("www/app.js"));
+        assert_eq!(
+            index.point(PathBuf::from("v1/app.js"), other_key.clone()),
+            Some(Departure { key: key.clone(), remaining: Some(PathBuf::from("v2/app.js")) })
+        );
+        assert_eq!(index.forget(Path::new("v2/app.js")), Some(Departure { key: key.clone(), remaining: None }));
+        assert!(index.paths_of(&key).is_empty());
+        assert_eq!(index.key_of(Path::new("v1/app.js")), Some(other_key));
+        assert_eq!(index.forget(Path::new("v2/app.js")), None);
+    }
+}
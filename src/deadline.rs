@@ -0,0 +1,37 @@
+use std::time::{Duration, Instant};
+
+
+/// The instant the duration after now, or the latest instant that can be represented if that is too far away,
+/// so a duration like `Duration::from_secs(u64::MAX)` blocks, boosts or backs off for good instead of overflowing.
+pub(crate) fn after(now: Instant, duration: Duration) -> Instant {
+    if let Some(deadline) = now.checked_add(duration) {
+        return deadline;
+    }
+    // Adds the largest steps that still fit, halving the step whenever it doesn't.
+    let mut deadline: Instant = now;
+    let mut step: Duration = duration;
+    while step >= Duration::from_secs(1) {
+        match deadline.checked_add(step) {
+            Some(later) => deadline = later,
+            None => step /= 2,
+        }
+    }
+    deadline
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadlines_that_overflow_saturate() {
+        let now: Instant = Instant::now();
+        assert_eq!(after(now, Duration::from_secs(10)), now + Duration::from_secs(10));
+
+        let never: Instant = after(now, Duration::from_secs(u64::MAX));
+        assert!(never > now + Duration::from_secs(60 * 60 * 24 * 365 * 1000));
+        // The last step that didn't fit was shorter than 2 seconds, so the deadline is less than that from the latest instant.
+        assert!(never.checked_add(Duration::from_secs(2)).is_none());
+    }
+}
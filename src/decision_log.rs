@@ -0,0 +1,110 @@
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use cache::CacheError;
+
+
+/// What the cache did with a request for a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The file was served from the cache.
+    Hit,
+    /// The file wasn't in the cache, and was inserted into it.
+    MissInserted,
+    /// The file wasn't in the cache, and was served from the filesystem for the given reason.
+    MissServedFromFs(CacheError),
+    /// The file couldn't be served.
+    Error,
+}
+
+
+/// A record of how the cache handled a request for a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    /// When the request was handled.
+    pub time: SystemTime,
+    /// The key the file was requested with, after case normalization and aliases were applied.
+    pub path: Arc<Path>,
+    pub outcome: Outcome,
+}
+
+
+/// Keeps the most recent decisions made by the cache, overwriting the oldest ones once it is full.
+#[derive(Debug)]
+pub(crate) struct DecisionLog {
+    capacity: usize,
+    state: Mutex<DecisionLogState>,
+}
+
+#[derive(Debug)]
+struct DecisionLogState {
+    decisions: Vec<Decision>,
+    /// The slot the next decision is written to. Once the log is full, this is also the oldest decision.
+    next: usize,
+}
+
+impl DecisionLog {
+    pub(crate) fn new(capacity: usize) -> DecisionLog {
+        DecisionLog {
+            capacity,
+            state: Mutex::new(DecisionLogState {
+                decisions: Vec::with_capacity(capacity),
+                next: 0,
+            }),
+        }
+    }
+
+    /// Records a decision, overwriting the oldest one if the log is full.
+    pub(crate) fn record(&self, path: &Path, outcome: Outcome) {
+        if self.capacity == 0 {
+            return;
+        }
+        let time: SystemTime = SystemTime::now();
+        let mut state = self.lock();
+        // Repeated requests for the same file are common, so the path of the last decision is shared if it matches.
+        let last: usize = (state.next + self.capacity - 1) % self.capacity;
+        let path: Arc<Path> = match state.decisions.get(last) {
+            Some(decision) if &*decision.path == path => decision.path.clone(),
+            _ => Arc::from(path),
+        };
+
+        let decision: Decision = Decision { time, path, outcome };
+        let next: usize = state.next;
+        if state.decisions.len() < self.capacity {
+            state.decisions.push(decision);
+        } else {
+            state.decisions[next] = decision;
+        }
+        state.next = (next + 1) % self.capacity;
+    }
+
+    /// The recorded decisions, oldest first.
+    pub(crate) fn recent(&self) -> Vec<Decision> {
+        let state = self.lock();
+        let (newer, older) = state.decisions.split_at(state.next);
+        older.iter().chain(newer.iter()).cloned().collect()
+    }
+
+    /// Estimates the bytes held by the log.
+    /// Paths shared between consecutive decisions are counted for each of them, so this errs on the high side.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        let state = self.lock();
+        let paths: usize = state.decisions.iter().map(|decision| decision.path.as_os_str().len()).sum();
+        state.decisions.capacity() * mem::size_of::<Decision>() + paths
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut state = self.lock();
+        state.decisions.clear();
+        state.next = 0;
+    }
+
+    fn lock(&self) -> MutexGuard<DecisionLogState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+#[cfg(feature = "rocket")]
+use rocket::response::{Response, Responder, Body};
+#[cfg(feature = "rocket")]
+use rocket::http::{Status, ContentType};
+#[cfg(feature = "rocket")]
+use rocket::request::Request;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::fmt;
+#[cfg(feature = "rocket")]
+use std::io::Cursor;
+
+use in_memory_file::FileBytes;
+
+
+/// A file that was detached from the cache with `FileSource::detach()` or `CachedFile::detach()`.
+///
+/// It shares the bytes of the cached file instead of holding a lock on its entry,
+/// so it can outlive both the entry and the cache itself.
+/// Detaching a file doesn't copy its bytes.
+#[derive(Clone)]
+pub struct DetachedFile {
+    pub(crate) path: Arc<Path>,
+    pub(crate) bytes: FileBytes,
+}
+
+impl DetachedFile {
+    /// The path the file was cached under.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The bytes of the file, as they were when it was detached.
+    pub fn bytes(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+}
+
+impl fmt::Debug for DetachedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DetachedFile")
+            .field("path", &self.path)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+impl PartialEq for DetachedFile {
+    fn eq(&self, other: &DetachedFile) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+
+/// Streams the detached file to the client, setting the Content-Type according to the file's extension,
+/// like a `NamedInMemoryFile`.
+#[cfg(feature = "rocket")]
+impl<'a> Responder<'a> for DetachedFile {
+    fn respond_to(self, _: &Request) -> Result<Response<'a>, Status> {
+        let mut response = Response::new();
+        if let Some(ext) = self.path.extension() {
+            if let Some(ct) = ContentType::from_extension(&ext.to_string_lossy()) {
+                response.set_header(ct);
+            }
+        }
+        let size: u64 = self.bytes.as_slice().len() as u64;
+        response.set_raw_body(Body::Sized(Cursor::new(self.bytes), size));
+        Ok(response)
+    }
+}
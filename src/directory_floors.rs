@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+
+/// How much of the content under a directory a floor is currently keeping in the cache.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryFloorUsage {
+    /// The directory the floor applies to.
+    pub prefix: PathBuf,
+    /// The number of bytes under the directory that are never evicted.
+    pub min_bytes: usize,
+    /// The number of bytes held by the files this floor accounts for.
+    pub resident_bytes: usize,
+    /// The number of resident bytes the floor is currently protecting from eviction.
+    /// This is the smaller of `resident_bytes` and `min_bytes`.
+    pub protected_bytes: usize,
+}
+
+
+/// The minimum number of bytes that must stay in the cache under each directory with a floor.
+///
+/// Every file is accounted to the floor with the most specific prefix it is under,
+/// so the files under nested floors only count towards the innermost one.
+#[derive(Debug, Default)]
+pub(crate) struct DirectoryFloors {
+    floors: Vec<(PathBuf, usize)>,
+}
+
+impl DirectoryFloors {
+    pub(crate) fn new() -> DirectoryFloors {
+        DirectoryFloors {
+            floors: vec![],
+        }
+    }
+
+    /// Sets the floor for the prefix, replacing any floor it already had.
+    /// A floor of 0 bytes removes the floor.
+    pub(crate) fn set(&mut self, prefix: PathBuf, min_bytes: usize) {
+        self.floors.retain(|(existing, _)| *existing != prefix);
+        if min_bytes > 0 {
+            self.floors.push((prefix, min_bytes));
+        }
+    }
+
+    /// The index of the floor the path is accounted to, if any.
+    fn floor_of(&self, path: &Path) -> Option<usize> {
+        self.floors
+            .iter()
+            .enumerate()
+            .filter(|&(_, (prefix, _))| path.starts_with(prefix))
+            .max_by_key(|&(_, (prefix, _))| prefix.components().count())
+            .map(|(index, _)| index)
+    }
+
+    /// Sums the sizes of the files accounted to each floor, in the order the floors are stored in.
+    pub(crate) fn resident_bytes<'a, I: Iterator<Item = (&'a Path, usize)>>(&self, files: I) -> Vec<usize> {
+        let mut resident_bytes: Vec<usize> = vec![0; self.floors.len()];
+        if self.floors.is_empty() {
+            return resident_bytes;
+        }
+        for (path, size) in files {
+            if let Some(index) = self.floor_of(path) {
+                resident_bytes[index] += size;
+            }
+        }
+        resident_bytes
+    }
+
+    /// Checks if evicting the file would keep its floor satisfied,
+    /// and if so, takes the file out of the resident bytes of its floor.
+    pub(crate) fn allows_eviction(&self, resident_bytes: &mut [usize], path: &Path, size: usize) -> bool {
+        match self.floor_of(path) {
+            Some(index) => {
+                let remaining: usize = usize::saturating_sub(resident_bytes[index], size);
+                if remaining < self.floors[index].1 {
+                    false
+                } else {
+                    resident_bytes[index] = remaining;
+                    true
+                }
+            }
+            None => true,
+        }
+    }
+
+    /// Describes what each floor is protecting, given the resident bytes of each floor.
+    pub(crate) fn usage(&self, resident_bytes: &[usize]) -> Vec<DirectoryFloorUsage> {
+        self.floors
+            .iter()
+            .zip(resident_bytes.iter())
+            .map(|(&(ref prefix, min_bytes), &resident_bytes)| DirectoryFloorUsage {
+                prefix: prefix.clone(),
+                min_bytes,
+                resident_bytes,
+                protected_bytes: usize::min(resident_bytes, min_bytes),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// The maximum number of bytes the files under each directory with a quota may hold in the cache.
+///
+/// The bytes held under each directory are counted as files are stored and removed,
+/// so checking a quota doesn't require looking at every file in the cache.
+/// A file counts towards every quota whose prefix it is under.
+#[derive(Debug, Default)]
+pub(crate) struct DirectoryQuotas {
+    quotas: Vec<DirectoryQuota>,
+}
+
+#[derive(Debug)]
+struct DirectoryQuota {
+    prefix: PathBuf,
+    max_bytes: usize,
+    /// The number of bytes held by the files under the prefix.
+    resident_bytes: AtomicUsize,
+}
+
+impl DirectoryQuotas {
+    pub(crate) fn new() -> DirectoryQuotas {
+        DirectoryQuotas {
+            quotas: vec![],
+        }
+    }
+
+    /// Sets the quota for the prefix, replacing any quota it already had.
+    /// The files already in the cache are given, so the quota starts out with the bytes they hold under the prefix.
+    pub(crate) fn set<'a, I: Iterator<Item = (&'a Path, usize)>>(&mut self, prefix: PathBuf, max_bytes: usize, files: I) {
+        self.remove(&prefix);
+        let resident_bytes: usize = files
+            .filter(|&(path, _)| path.starts_with(&prefix))
+            .map(|(_, size)| size)
+            .sum();
+        self.quotas.push(DirectoryQuota {
+            prefix,
+            max_bytes,
+            resident_bytes: AtomicUsize::new(resident_bytes),
+        });
+    }
+
+    /// Removes the quota for the prefix, returning whether it had one.
+    pub(crate) fn remove(&mut self, prefix: &Path) -> bool {
+        let quota_count: usize = self.quotas.len();
+        self.quotas.retain(|quota| quota.prefix != prefix);
+        self.quotas.len() != quota_count
+    }
+
+    /// Counts a file that was stored in the cache.
+    pub(crate) fn on_stored(&self, path: &Path, size: usize) {
+        for quota in self.quotas.iter().filter(|quota| path.starts_with(&quota.prefix)) {
+            quota.resident_bytes.fetch_add(size, Ordering::AcqRel);
+        }
+    }
+
+    /// Stops counting a file that left the cache.
+    pub(crate) fn on_removed(&self, path: &Path, size: usize) {
+        for quota in self.quotas.iter().filter(|quota| path.starts_with(&quota.prefix)) {
+            let mut resident_bytes: usize = quota.resident_bytes.load(Ordering::Acquire);
+            loop {
+                let remaining: usize = usize::saturating_sub(resident_bytes, size);
+                match quota.resident_bytes.compare_exchange_weak(resident_bytes, remaining, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => break,
+                    Err(actual) => resident_bytes = actual,
+                }
+            }
+        }
+    }
+
+    /// Checks if storing a file of the given size under the path would take any directory over its quota.
+    ///
+    /// If it would, returns the most specific prefix over its quota, along with the number of bytes
+    /// that have to be freed under that prefix so that no quota is exceeded.
+    /// As the most specific prefix is under every other prefix the file is under,
+    /// freeing bytes under it brings all of them back within their quotas.
+    pub(crate) fn overflow(&self, path: &Path, size: usize) -> Option<(PathBuf, usize)> {
+        let mut overflow: Option<(&DirectoryQuota, usize)> = None;
+        for quota in self.quotas.iter().filter(|quota| path.starts_with(&quota.prefix)) {
+            let required_space: usize = usize::saturating_sub(
+                usize::saturating_add(quota.resident_bytes.load(Ordering::Acquire), size),
+                quota.max_bytes
+            );
+            if required_space == 0 {
+                continue;
+            }
+            overflow = match overflow {
+                Some((innermost, most_required_space)) => {
+                    let innermost: &DirectoryQuota = if quota.prefix.components().count() > innermost.prefix.components().count() {
+                        quota
+                    } else {
+                        innermost
+                    };
+                    Some((innermost, usize::max(required_space, most_required_space)))
+                }
+                None => Some((quota, required_space)),
+            };
+        }
+        overflow.map(|(quota, required_space)| (quota.prefix.clone(), required_space))
+    }
+}
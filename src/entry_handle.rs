@@ -0,0 +1,89 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use content_hash;
+use in_memory_file::{FileBytes, InMemoryFile};
+
+
+/// A file in the cache, as returned by `Cache::try_get_cached()`, `Cache::iter()` and `Cache::take()`.
+///
+/// It shares the bytes of the cached file instead of copying them, and holds no lock on its entry,
+/// so it can be kept after the file leaves the cache, and sent to other threads.
+/// Clones share the same file.
+/// Only what the handle exposes is part of the API, so the way the cache stores its files can change.
+#[derive(Clone)]
+pub struct EntryHandle {
+    entry: Arc<Entry>,
+}
+
+struct Entry {
+    path: Arc<Path>,
+    bytes: FileBytes,
+    size: usize,
+    content_hash: Option<u64>,
+    modified: Option<SystemTime>,
+}
+
+impl EntryHandle {
+    /// Creates a handle for the file stored under the path, sharing its bytes.
+    pub(crate) fn new(path: &Path, file: &InMemoryFile) -> EntryHandle {
+        EntryHandle {
+            entry: Arc::new(Entry {
+                path: match file.key {
+                    Some(ref key) => key.clone(),
+                    None => Arc::from(path),
+                },
+                bytes: file.bytes.clone(),
+                size: file.stats.size,
+                content_hash: file.content_hash,
+                modified: file.modified,
+            }),
+        }
+    }
+
+    /// The bytes of the file, as they were when the handle was created.
+    pub fn bytes(&self) -> &[u8] {
+        self.entry.bytes.as_slice()
+    }
+
+    /// The size of the file, as the cache accounts for it.
+    pub fn size(&self) -> usize {
+        self.entry.size
+    }
+
+    /// The key the file is stored under.
+    pub fn path(&self) -> &Path {
+        &self.entry.path
+    }
+
+    /// A short hex digest of the bytes of the file, like `Cache::content_hash()` returns.
+    /// Files the cache hasn't hashed yet are hashed every time this is called.
+    pub fn content_hash(&self) -> String {
+        let hash: u64 = self.entry.content_hash.unwrap_or_else(|| content_hash::hash(self.bytes()));
+        content_hash::to_hex(hash)
+    }
+
+    /// The time the file was last modified, like `Cache::last_modified()` returns.
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        self.entry.modified
+    }
+}
+
+impl fmt::Debug for EntryHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EntryHandle")
+            .field("path", &self.entry.path)
+            .field("size", &self.entry.size)
+            .field("modified", &self.entry.modified)
+            .finish()
+    }
+}
+
+/// Handles are equal if they are for the same path and bytes.
+impl PartialEq for EntryHandle {
+    fn eq(&self, other: &EntryHandle) -> bool {
+        self.entry.path == other.entry.path && self.entry.bytes == other.entry.bytes
+    }
+}
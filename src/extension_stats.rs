@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// How the files with one extension use the cache, as returned by `Cache::extension_stats()`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ExtensionStats {
+    /// The number of bytes held by the files with the extension that are in the cache.
+    pub resident_bytes: usize,
+    /// The number of files with the extension that are in the cache.
+    pub file_count: usize,
+    /// The number of requests for files with the extension that were served from the cache.
+    pub hits: usize,
+    /// The number of requests for files with the extension that weren't in the cache,
+    /// whether they were inserted, served from the filesystem, or not found.
+    pub misses: usize,
+    /// The number of files with the extension that were stored in the cache.
+    pub insertions: usize,
+    /// The number of files with the extension that were evicted to make room for other files.
+    pub evictions: usize,
+}
+
+impl ExtensionStats {
+    /// The share of the requests for files with the extension that were hits, or 0 if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        let requests: usize = self.hits + self.misses;
+        if requests == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / requests as f64
+    }
+
+    /// Adds the other stats to these, for combining the stats of several caches.
+    pub(crate) fn combine(&mut self, other: &ExtensionStats) {
+        self.resident_bytes += other.resident_bytes;
+        self.file_count += other.file_count;
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.insertions += other.insertions;
+        self.evictions += other.evictions;
+    }
+}
+
+
+/// Gets the extension the path is counted under: its extension in lowercase, or an empty string if it has none.
+pub(crate) fn extension_of(path: &Path) -> String {
+    match path.extension() {
+        Some(extension) => extension.to_string_lossy().to_lowercase(),
+        None => String::new(),
+    }
+}
+
+
+/// Gets the extension of the path without copying it, if it is already in lowercase.
+fn lowercase_extension(path: &Path) -> Option<&str> {
+    match path.extension() {
+        Some(extension) => extension.to_str().filter(|extension| !extension.chars().any(char::is_uppercase)),
+        None => Some(""),
+    }
+}
+
+
+/// The bucket that extensions are counted under once `MAX_EXTENSIONS` other extensions are counted.
+///
+/// Any string can be the extension of a file, so a file with this extension is counted in the same bucket.
+pub(crate) const OTHER_EXTENSIONS: &str = "*";
+
+/// The number of extensions that are counted on their own, so requests for arbitrary paths can't grow the counts without bound.
+const MAX_EXTENSIONS: usize = 64;
+
+
+/// Counts the hits, misses, insertions and evictions of the files with each extension.
+///
+/// The resident bytes and files aren't counted, they are added up from the files in the cache when they are asked for,
+/// so they can't drift from what the cache actually holds.
+/// Extensions that were counted before are counted under a read lock, so requests for them don't wait on each other.
+#[derive(Debug)]
+pub(crate) struct ExtensionCounters {
+    extensions: RwLock<HashMap<String, ExtensionCounts>>,
+}
+
+#[derive(Debug, Default)]
+struct ExtensionCounts {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    insertions: AtomicUsize,
+    evictions: AtomicUsize,
+}
+
+impl ExtensionCounters {
+    pub(crate) fn new() -> ExtensionCounters {
+        ExtensionCounters {
+            extensions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn record_request(&self, path: &Path, hit: bool) {
+        self.update(path, |counts| if hit {
+            counts.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            counts.misses.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    pub(crate) fn record_insertion(&self, path: &Path) {
+        self.update(path, |counts| {
+            counts.insertions.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    pub(crate) fn record_eviction(&self, path: &Path) {
+        self.update(path, |counts| {
+            counts.evictions.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    /// The counts of every extension that was counted.
+    pub(crate) fn counts(&self) -> HashMap<String, ExtensionStats> {
+        self.read()
+            .iter()
+            .map(|(extension, counts)| {
+                let stats = ExtensionStats {
+                    hits: counts.hits.load(Ordering::Relaxed),
+                    misses: counts.misses.load(Ordering::Relaxed),
+                    insertions: counts.insertions.load(Ordering::Relaxed),
+                    evictions: counts.evictions.load(Ordering::Relaxed),
+                    ..ExtensionStats::default()
+                };
+                (extension.clone(), stats)
+            })
+            .collect()
+    }
+
+    fn update<F: Fn(&ExtensionCounts)>(&self, path: &Path, update: F) {
+        // Extensions that were counted before are counted in place, so serving a file doesn't allocate.
+        {
+            let extensions = self.read();
+            if let Some(counts) = lowercase_extension(path).and_then(|extension| extensions.get(extension)) {
+                update(counts);
+                return;
+            }
+            if extensions.len() >= MAX_EXTENSIONS {
+                if let Some(counts) = extensions.get(OTHER_EXTENSIONS) {
+                    update(counts);
+                    return;
+                }
+            }
+        }
+        let mut extensions = self.write();
+        let extension: String = extension_of(path);
+        let extension: String = if extensions.contains_key(&extension) || extensions.len() < MAX_EXTENSIONS {
+            extension
+        } else {
+            String::from(OTHER_EXTENSIONS)
+        };
+        update(extensions.entry(extension).or_default());
+    }
+
+    fn read(&self) -> RwLockReadGuard<HashMap<String, ExtensionCounts>> {
+        match self.extensions.read() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn write(&self) -> RwLockWriteGuard<HashMap<String, ExtensionCounts>> {
+        match self.extensions.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_are_lowercase_and_may_be_empty() {
+        assert_eq!(extension_of(Path::new("www/app.JS")), "js");
+        assert_eq!(extension_of(Path::new("www/vendor.min.js")), "js");
+        assert_eq!(extension_of(Path::new("www/LICENSE")), "");
+        assert_eq!(extension_of(Path::new("www/.hidden")), "");
+    }
+
+    #[test]
+    fn extensions_past_the_limit_share_a_bucket() {
+        let counters = ExtensionCounters::new();
+        for i in 0..MAX_EXTENSIONS + 10 {
+            counters.record_request(Path::new(&format!("www/file.e{}", i)), false);
+        }
+        counters.record_request(Path::new("www/file.e0"), true);
+        let counts: HashMap<String, ExtensionStats> = counters.counts();
+        assert_eq!(counts.len(), MAX_EXTENSIONS + 1);
+        assert_eq!(counts["e0"].hits, 1);
+        assert_eq!(counts[OTHER_EXTENSIONS].misses, 10);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use bookkeeping::path_entry_bytes;
+
+
+/// Remembers the path each relative path requested with `Cache::get_source_with_fallbacks()` resolved to,
+/// so later requests don't have to look for it in every root again.
+#[derive(Debug)]
+pub(crate) struct FallbackResolutions {
+    state: Mutex<FallbackState>,
+}
+
+#[derive(Debug)]
+struct FallbackState {
+    /// The path each relative path was found at.
+    resolved: HashMap<PathBuf, PathBuf>,
+    /// The relative paths that were found at each path, so they can be forgotten together.
+    relatives: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl FallbackResolutions {
+    pub(crate) fn new() -> FallbackResolutions {
+        FallbackResolutions {
+            state: Mutex::new(FallbackState {
+                resolved: HashMap::new(),
+                relatives: HashMap::new(),
+            }),
+        }
+    }
+
+    /// The path the relative path was last found at.
+    pub(crate) fn get(&self, relative: &Path) -> Option<PathBuf> {
+        self.lock().resolved.get(relative).cloned()
+    }
+
+    pub(crate) fn remember(&self, relative: &Path, resolved: &Path) {
+        let mut state = self.lock();
+        if let Some(previous) = state.resolved.insert(relative.to_path_buf(), resolved.to_path_buf()) {
+            state.forget_relative(&previous, relative);
+        }
+        state.relatives.entry(resolved.to_path_buf()).or_default().push(relative.to_path_buf());
+    }
+
+    /// Forgets every relative path that was found at the path,
+    /// so they are looked for in every root again the next time they are requested.
+    pub(crate) fn forget(&self, resolved: &Path) {
+        let mut state = self.lock();
+        if let Some(relatives) = state.relatives.remove(resolved) {
+            for relative in relatives {
+                state.resolved.remove(&relative);
+            }
+        }
+    }
+
+    /// Estimates the bytes held by the resolutions, in both directions.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        let state = self.lock();
+        let resolved: usize = state.resolved
+            .iter()
+            .map(|(relative, resolved)| path_entry_bytes::<PathBuf>(relative) + resolved.as_os_str().len())
+            .sum();
+        let relatives: usize = state.relatives
+            .iter()
+            .map(|(resolved, relatives)| {
+                path_entry_bytes::<Vec<PathBuf>>(resolved)
+                    + relatives.iter().map(|relative| mem::size_of::<PathBuf>() + relative.as_os_str().len()).sum::<usize>()
+            })
+            .sum();
+        resolved + relatives
+    }
+
+    fn lock(&self) -> MutexGuard<FallbackState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl FallbackState {
+    fn forget_relative(&mut self, resolved: &Path, relative: &Path) {
+        let is_empty: bool = match self.relatives.get_mut(resolved) {
+            Some(relatives) => {
+                relatives.retain(|existing| existing.as_path() != relative);
+                relatives.is_empty()
+            }
+            None => false,
+        };
+        if is_empty {
+            self.relatives.remove(resolved);
+        }
+    }
+}
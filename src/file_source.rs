@@ -0,0 +1,146 @@
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Cursor};
+use std::path::{Path, PathBuf};
+
+use named_in_memory_file::NamedInMemoryFile;
+use detached_file::DetachedFile;
+
+
+/// A file returned by the cache, without ties to any web framework.
+///
+/// With the `rocket` feature, which is enabled by default, the `Cache` methods that return a `CachedFile`
+/// build it from one of these, so it can be used as a Rocket responder.
+/// Other frameworks can use the `_source` methods of the `Cache`, and serve the file with `bytes()` or `into_reader()`.
+///
+/// Like a `CachedFile`, a `Cached` variant holds a lock on the file in the cache until it is dropped.
+/// Use `detach()` to release the lock before the file is served.
+/// A `Cached` variant can't be sent to another thread either, but a detached one can.
+#[derive(Debug)]
+pub enum FileSource<'a> {
+    /// A file that has been loaded into the cache.
+    Cached(NamedInMemoryFile<'a>),
+    /// A file that is served from the filesystem, with its path, and its metadata from when it was opened.
+    /// The file is always served from this handle, so it is never opened a second time.
+    Fs(File, PathBuf, Metadata),
+    /// A file that was in the cache, detached from it with `detach()`.
+    Detached(DetachedFile),
+    /// The file does not exist in either the cache or the filesystem.
+    NotFound,
+    /// The file was in the cache when it was refreshed, but it no longer exists in the filesystem.
+    FileMissing,
+    /// A file to be served from the filesystem, which is left for `Cache::resolve()` to open.
+    /// The cache never returns it from any other method.
+    #[doc(hidden)]
+    Deferred(PathBuf),
+}
+
+impl<'a> FileSource<'a> {
+    /// Opens the file in the filesystem.
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> io::Result<FileSource<'a>> {
+        let file: File = File::open(path.as_ref())?;
+        let metadata: Metadata = file.metadata()?;
+        Ok(FileSource::Fs(file, path.as_ref().to_path_buf(), metadata))
+    }
+
+    /// Releases the lock on the file in the cache, keeping its bytes.
+    ///
+    /// The detached file shares the bytes of the cached file, so it can be served after the file is evicted,
+    /// or the cache is dropped, without copying the bytes.
+    /// A file served from the filesystem keeps the handle it was opened with,
+    /// so it is still served if the file is deleted after it was opened.
+    pub fn detach(self) -> FileSource<'static> {
+        match self {
+            FileSource::Cached(cached_file) => FileSource::Detached(DetachedFile {
+                path: cached_file.path.clone(),
+                bytes: cached_file.file.get().bytes.clone(),
+            }),
+            FileSource::Fs(file, path, metadata) => FileSource::Fs(file, path, metadata),
+            FileSource::Detached(detached_file) => FileSource::Detached(detached_file),
+            FileSource::NotFound => FileSource::NotFound,
+            FileSource::FileMissing => FileSource::FileMissing,
+            FileSource::Deferred(path) => FileSource::Deferred(path),
+        }
+    }
+
+    /// The bytes of the file, if it is held in the cache or was detached from it.
+    /// Files served from the filesystem have to be read with `into_reader()` instead.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match *self {
+            FileSource::Cached(ref cached_file) => Some(cached_file.file.get().bytes.as_slice()),
+            FileSource::Detached(ref detached_file) => Some(detached_file.bytes()),
+            _ => None,
+        }
+    }
+
+    /// The path of the file, which can be used to pick a content type for it.
+    pub fn path(&self) -> Option<&Path> {
+        match *self {
+            FileSource::Cached(ref cached_file) => Some(&*cached_file.path),
+            FileSource::Fs(_, ref path, _) => Some(path),
+            FileSource::Detached(ref detached_file) => Some(detached_file.path()),
+            FileSource::Deferred(ref path) => Some(path),
+            FileSource::NotFound | FileSource::FileMissing => None,
+        }
+    }
+
+    /// The number of bytes in the file, or None if it wasn't found.
+    pub(crate) fn size(&self) -> Option<usize> {
+        match *self {
+            FileSource::Cached(ref cached_file) => Some(cached_file.file.get().stats.size),
+            FileSource::Fs(_, _, ref metadata) => Some(metadata.len() as usize),
+            FileSource::Detached(ref detached_file) => Some(detached_file.bytes().len()),
+            FileSource::NotFound | FileSource::FileMissing | FileSource::Deferred(_) => None,
+        }
+    }
+
+    /// Turns the file into a reader of its contents.
+    /// Returns None if the file wasn't found.
+    pub fn into_reader(self) -> Option<Box<dyn Read + 'a>> {
+        match self {
+            FileSource::Cached(cached_file) => Some(Box::new(CachedFileReader {
+                cached_file,
+                position: 0,
+            })),
+            FileSource::Fs(file, _, _) => Some(Box::new(file)),
+            FileSource::Detached(detached_file) => Some(Box::new(Cursor::new(detached_file.bytes))),
+            FileSource::NotFound | FileSource::FileMissing | FileSource::Deferred(_) => None,
+        }
+    }
+}
+
+impl<'a> From<NamedInMemoryFile<'a>> for FileSource<'a> {
+    fn from(cached_file: NamedInMemoryFile<'a>) -> FileSource<'a> {
+        FileSource::Cached(cached_file)
+    }
+}
+
+impl<'a> PartialEq for FileSource<'a> {
+    fn eq(&self, other: &FileSource) -> bool {
+        match (self, other) {
+            (FileSource::Cached(lhs), FileSource::Cached(rhs)) => lhs.file.get() == rhs.file.get(),
+            // Open files can't be compared, so this just compares the file paths
+            (FileSource::Fs(_, lhs, _), FileSource::Fs(_, rhs, _)) => lhs == rhs,
+            (FileSource::Detached(lhs), FileSource::Detached(rhs)) => lhs == rhs,
+            (&FileSource::NotFound, &FileSource::NotFound) => true,
+            (&FileSource::FileMissing, &FileSource::FileMissing) => true,
+            (FileSource::Deferred(lhs), FileSource::Deferred(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+
+
+/// Reads the bytes of a file in the cache, holding the lock on the file until it is dropped.
+struct CachedFileReader<'a> {
+    cached_file: NamedInMemoryFile<'a>,
+    position: usize,
+}
+
+impl<'a> Read for CachedFileReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut bytes: &[u8] = &self.cached_file.file.get().bytes.as_slice()[self.position..];
+        let read: usize = bytes.read(buf)?;
+        self.position += read;
+        Ok(read)
+    }
+}
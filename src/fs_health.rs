@@ -0,0 +1,99 @@
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use deadline;
+
+
+/// Stops the cache from touching the filesystem for misses while the filesystem keeps failing.
+///
+/// Once `errors` filesystem errors happen in a row, the cache only serves the files it holds.
+/// Misses fail fast, without reading metadata, reading files or evicting anything, until the `backoff` has passed.
+/// The first miss after that probes the filesystem: if it succeeds the cache goes back to normal,
+/// and if it fails the cache backs off again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DegradedModeConfig {
+    /// The number of consecutive filesystem errors that make the cache serve only the files it holds.
+    pub errors: usize,
+    /// How long misses fail fast before the filesystem is probed again.
+    pub backoff: Duration,
+}
+
+
+/// Counts consecutive filesystem errors, and remembers whether the cache is serving only the files it holds.
+#[derive(Debug)]
+pub(crate) struct FsHealth {
+    config: DegradedModeConfig,
+    state: Mutex<FsHealthState>,
+}
+
+#[derive(Debug)]
+struct FsHealthState {
+    consecutive_errors: usize,
+    /// When the filesystem may be probed again, if the cache is degraded.
+    degraded_until: Option<Instant>,
+}
+
+impl FsHealth {
+    pub(crate) fn new(config: DegradedModeConfig) -> FsHealth {
+        FsHealth {
+            config,
+            state: Mutex::new(FsHealthState {
+                consecutive_errors: 0,
+                degraded_until: None,
+            }),
+        }
+    }
+
+    /// Records that the filesystem failed, degrading the cache if it has failed too often in a row.
+    pub(crate) fn on_error(&self, now: Instant) {
+        let mut state = self.lock();
+        state.consecutive_errors = state.consecutive_errors.saturating_add(1);
+        if state.consecutive_errors >= self.config.errors {
+            if state.degraded_until.is_none() {
+                warn!(
+                    "The filesystem failed {} times in a row, serving only cached files for {:?}.",
+                    state.consecutive_errors,
+                    self.config.backoff
+                );
+            }
+            state.degraded_until = Some(deadline::after(now, self.config.backoff));
+        }
+    }
+
+    /// Records that the filesystem worked, which ends the degraded state.
+    pub(crate) fn on_success(&self) {
+        let mut state = self.lock();
+        if state.degraded_until.is_some() {
+            info!("The filesystem works again, serving misses normally.");
+        }
+        state.consecutive_errors = 0;
+        state.degraded_until = None;
+    }
+
+    /// Checks if a miss may use the filesystem.
+    ///
+    /// Once the backoff has passed, the miss that asks first is let through to probe the filesystem,
+    /// and the misses after it fail fast for another backoff unless the probe succeeds.
+    pub(crate) fn allows_miss(&self, now: Instant) -> bool {
+        let mut state = self.lock();
+        match state.degraded_until {
+            Some(until) if now < until => false,
+            Some(_) => {
+                state.degraded_until = Some(deadline::after(now, self.config.backoff));
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub(crate) fn is_degraded(&self) -> bool {
+        self.lock().degraded_until.is_some()
+    }
+
+    fn lock(&self) -> MutexGuard<FsHealthState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+
+/// Refines how much work a single call to `Cache::get_with()` may do when the file isn't in the cache.
+///
+/// These are per-call limits on top of the cache's own settings,
+/// useful for routes that care more about latency than about populating the cache.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::{CacheBuilder, GetOptions};
+///
+/// let cache = CacheBuilder::new().build().unwrap();
+/// let file = cache.get_source_with(
+///     "www/index.html",
+///     GetOptions::new()
+///         .max_eviction_candidates(4)
+///         .skip_insert_above(1024 * 1024),
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GetOptions {
+    pub(crate) max_eviction_candidates: Option<usize>,
+    pub(crate) skip_insert_above: Option<usize>,
+    /// Files served from the filesystem are left for the caller to open, for `Cache::resolve()`.
+    pub(crate) defer_open: bool,
+}
+
+impl GetOptions {
+    /// Creates options that don't limit the call any further than the cache's own settings.
+    pub fn new() -> GetOptions {
+        GetOptions {
+            max_eviction_candidates: None,
+            skip_insert_above: None,
+            defer_open: false,
+        }
+    }
+
+    /// Limits how many files will be considered for eviction to make room for the requested file.
+    /// If room can't be made by evicting at most this many files, the file is served from the
+    /// filesystem instead, and nothing is evicted.
+    pub fn max_eviction_candidates(&mut self, max_eviction_candidates: usize) -> &mut Self {
+        self.max_eviction_candidates = Some(max_eviction_candidates);
+        self
+    }
+
+    /// Files larger than this number of bytes are served from the filesystem without attempting
+    /// to insert them into the cache.
+    pub fn skip_insert_above(&mut self, size: usize) -> &mut Self {
+        self.skip_insert_above = Some(size);
+        self
+    }
+
+    /// Tightens the `max_file_size` of the cache for this call only, like `skip_insert_above()`.
+    /// `None` leaves the cache's own limit in place, which also bounds any size given here.
+    ///
+    /// Files above the size are served from the filesystem, but unlike files above the `max_file_size`,
+    /// they aren't remembered as too large, so later calls with other options can still insert them.
+    /// This suits routes that know the whole file won't be needed, like one serving ranges of videos.
+    pub fn max_insert_size(&mut self, size: Option<usize>) -> &mut Self {
+        self.skip_insert_above = size;
+        self
+    }
+}
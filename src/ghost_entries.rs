@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bookkeeping::path_entry_bytes;
+
+
+/// Remembers the keys and sizes of recently evicted files, but not their bytes,
+/// to estimate how many misses a larger cache would have served from memory.
+///
+/// The ghost entries stand for the space a larger cache would have beyond the size limit of this one.
+/// Once they add up to more than that budget, the entries evicted longest ago are forgotten.
+#[derive(Debug)]
+pub(crate) struct GhostEntries {
+    /// The number of bytes the ghost entries may add up to.
+    budget: usize,
+    state: Mutex<GhostState>,
+    /// The number of misses for files that still had a ghost entry.
+    hits: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct GhostState {
+    /// The size of each file with a ghost entry, and the sequence number of its eviction.
+    entries: HashMap<PathBuf, (usize, u64)>,
+    /// The files with ghost entries, by the sequence number of their eviction, evicted longest ago first.
+    order: BTreeMap<u64, PathBuf>,
+    /// The number of bytes the ghost entries add up to.
+    used_bytes: usize,
+    next_sequence: u64,
+}
+
+impl GhostEntries {
+    pub(crate) fn new(budget: usize) -> GhostEntries {
+        GhostEntries {
+            budget,
+            state: Mutex::new(GhostState {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                used_bytes: 0,
+                next_sequence: 0,
+            }),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    /// Remembers the evicted file, forgetting the entries evicted longest ago until the rest fit into the budget.
+    /// Files larger than the budget aren't remembered at all.
+    pub(crate) fn on_evicted(&self, path: &Path, size: usize) {
+        let mut state = self.lock();
+        state.remove(path);
+        if size > self.budget {
+            return;
+        }
+        let sequence: u64 = state.next_sequence;
+        state.next_sequence += 1;
+        state.entries.insert(path.to_path_buf(), (size, sequence));
+        state.order.insert(sequence, path.to_path_buf());
+        state.used_bytes += size;
+        while state.used_bytes > self.budget {
+            let oldest: PathBuf = match state.order.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            state.remove(&oldest);
+        }
+    }
+
+    /// Forgets the ghost entry of a file that is requested, as it is read from the filesystem again.
+    /// Returns false if it had none.
+    pub(crate) fn take(&self, path: &Path) -> bool {
+        self.lock().remove(path)
+    }
+
+    /// Counts a miss for a file that had a ghost entry.
+    pub(crate) fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Forgets the ghost entry of a file that was stored in the cache again.
+    pub(crate) fn on_stored(&self, path: &Path) {
+        self.lock().remove(path);
+    }
+
+    pub(crate) fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Estimates the bytes held by the ghost entries, which are kept both by path and by sequence number.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        self.lock()
+            .entries
+            .keys()
+            .map(|path| path_entry_bytes::<(usize, u64)>(path) + path_entry_bytes::<u64>(path))
+            .sum::<usize>()
+            + mem::size_of::<GhostState>()
+    }
+
+    fn lock(&self) -> MutexGuard<GhostState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl GhostState {
+    /// Forgets the ghost entry of the file. Returns false if it had none.
+    fn remove(&mut self, path: &Path) -> bool {
+        match self.entries.remove(path) {
+            Some((size, sequence)) => {
+                self.order.remove(&sequence);
+                self.used_bytes -= size;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_evicted_longest_ago_are_forgotten_first() {
+        let ghosts = GhostEntries::new(2000);
+        ghosts.on_evicted(Path::new("www/a.html"), 1000);
+        ghosts.on_evicted(Path::new("www/b.html"), 1000);
+        ghosts.on_evicted(Path::new("www/c.html"), 1000);
+        ghosts.on_evicted(Path::new("www/huge.mp4"), 5000);
+
+        assert!(!ghosts.take(Path::new("www/a.html")));
+        assert!(ghosts.take(Path::new("www/b.html")));
+        assert!(!ghosts.take(Path::new("www/huge.mp4")));
+        // Each entry is only taken once.
+        assert!(!ghosts.take(Path::new("www/b.html")));
+
+        ghosts.on_stored(Path::new("www/c.html"));
+        assert!(!ghosts.take(Path::new("www/c.html")));
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::path::{PathBuf, Path};
+use std::sync::{Arc, Mutex, MutexGuard, Condvar};
+use std::time::Duration;
+
+
+/// A marker for a file that is currently being read into memory by one thread.
+///
+/// Other threads that miss on the same path wait on this marker instead of reading the file themselves.
+pub(crate) struct InFlightRead {
+    finished: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl InFlightRead {
+    fn new() -> InFlightRead {
+        InFlightRead {
+            finished: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Blocks until the read is finished or the timeout elapses.
+    /// Returns true if the read finished in time.
+    pub(crate) fn wait(&self, timeout: Duration) -> bool {
+        let finished = lock(&self.finished);
+        match self.condvar.wait_timeout_while(finished, timeout, |finished| !*finished) {
+            Ok((finished, _)) => *finished,
+            Err(poisoned) => *poisoned.into_inner().0,
+        }
+    }
+
+    fn finish(&self) {
+        *lock(&self.finished) = true;
+        self.condvar.notify_all();
+    }
+}
+
+
+/// The role a thread plays for a given miss.
+pub(crate) enum Flight<'a> {
+    /// This thread is responsible for reading the file. Other threads will be woken when the guard drops.
+    Leader(LeaderGuard<'a>),
+    /// Another thread is already reading the file.
+    Follower(Arc<InFlightRead>),
+}
+
+
+/// Keeps track of which paths are currently being read into the cache, so concurrent misses for the
+/// same path only cause a single read from the filesystem.
+pub(crate) struct InFlightReads {
+    reads: Mutex<HashMap<PathBuf, Arc<InFlightRead>>>,
+}
+
+impl InFlightReads {
+    pub(crate) fn new() -> InFlightReads {
+        InFlightReads {
+            reads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers interest in reading the file at the path.
+    /// The first caller becomes the leader, every subsequent caller until the leader finishes becomes a follower.
+    pub(crate) fn join<P: AsRef<Path>>(&self, path: P) -> Flight {
+        let mut reads = lock(&self.reads);
+        if let Some(in_flight_read) = reads.get(path.as_ref()) {
+            return Flight::Follower(in_flight_read.clone());
+        }
+        let in_flight_read = Arc::new(InFlightRead::new());
+        reads.insert(path.as_ref().to_path_buf(), in_flight_read.clone());
+        Flight::Leader(LeaderGuard {
+            reads: self,
+            path: path.as_ref().to_path_buf(),
+            in_flight_read,
+        })
+    }
+}
+
+
+/// Removes the in-flight marker and wakes any waiting followers when dropped,
+/// even if the leader unwinds.
+pub(crate) struct LeaderGuard<'a> {
+    reads: &'a InFlightReads,
+    path: PathBuf,
+    in_flight_read: Arc<InFlightRead>,
+}
+
+impl<'a> Drop for LeaderGuard<'a> {
+    fn drop(&mut self) {
+        lock(&self.reads.reads).remove(&self.path);
+        self.in_flight_read.finish();
+    }
+}
+
+
+/// Locks the mutex, recovering the data if another thread panicked while holding the lock.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
@@ -1,49 +1,280 @@
 
 use std::path::Path;
 use std::io::BufReader;
-use std::fs::File;
+use std::fs::{File, Metadata};
 use std::io;
 use std::io::Read;
 use std::fmt;
+use std::time::{SystemTime, Instant};
+use std::sync::Arc;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+use storage::Storage;
+use small_file_arena::SlabSlice;
+#[cfg(all(unix, feature = "mmap"))]
+use storage::Mapping;
+
+
+/// The bytes of a file held in memory.
+#[derive(Clone)]
+pub(crate) enum FileBytes {
+    /// Bytes that were read from the filesystem and are owned by the cache.
+    /// They are shared with any file detached from the cache, so they stay alive after the entry is evicted.
+    Owned(Arc<Vec<u8>>),
+    /// Bytes embedded in the binary. These are served without ever being copied.
+    Static(&'static [u8]),
+    /// A memory mapping of the file, shared like owned bytes.
+    #[cfg(all(unix, feature = "mmap"))]
+    Mapped(Arc<Mapping>),
+    /// The bytes of a small file, copied into a slab shared with other small files.
+    Slab(Arc<SlabSlice>),
+}
+
+impl FileBytes {
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        match *self {
+            FileBytes::Owned(ref bytes) => bytes.as_slice(),
+            FileBytes::Static(bytes) => bytes,
+            #[cfg(all(unix, feature = "mmap"))]
+            FileBytes::Mapped(ref mapping) => mapping.as_slice(),
+            FileBytes::Slab(ref slab_slice) => slab_slice.as_slice(),
+        }
+    }
+
+    /// The slab the bytes are kept in, if they are kept in one.
+    pub(crate) fn slab_id(&self) -> Option<usize> {
+        match *self {
+            FileBytes::Slab(ref slab_slice) => Some(slab_slice.slab_id()),
+            _ => None,
+        }
+    }
+}
+
+impl AsRef<[u8]> for FileBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl fmt::Debug for FileBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The byte array shouldn't be visible in the log.
+        write!(f, "<{} bytes>", self.as_slice().len())
+    }
+}
+
+impl PartialEq for FileBytes {
+    fn eq(&self, other: &FileBytes) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+
+/// Identifies a file in the filesystem, so a file that was replaced by another one can be told apart from it,
+/// even if they have the same size and modification time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FileIdentity {
+    device: u64,
+    inode: u64,
+}
+
+impl FileIdentity {
+    /// Gets the identity of the file the metadata was read from, on platforms that have one.
+    #[cfg(unix)]
+    pub(crate) fn of(metadata: &Metadata) -> Option<FileIdentity> {
+        Some(FileIdentity {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+        })
+    }
+
+    /// The index of a file on Windows can't be read on stable Rust, so files have no identity there.
+    #[cfg(not(unix))]
+    pub(crate) fn of(_metadata: &Metadata) -> Option<FileIdentity> {
+        None
+    }
+}
 
 
 /// The structure that represents a file in memory.
 /// Keeps an up to date record of its stats so the cache can use this information to remove the file
 /// from the cache.
-#[derive(Clone, PartialEq)]
-pub struct InMemoryFile {
-    pub(crate) bytes: Vec<u8>,
-    pub stats: FileStats,
+#[derive(Clone)]
+pub(crate) struct InMemoryFile {
+    pub(crate) bytes: FileBytes,
+    pub(crate) stats: FileStats,
+    /// The hash of the bytes, computed the first time it is requested.
+    pub(crate) content_hash: Option<u64>,
+    /// The number of accesses since the priority of the file was last calculated.
+    pub(crate) accesses_since_priority_update: usize,
+    /// When the file was last modified in the filesystem, if it was read from the filesystem.
+    pub(crate) modified: Option<SystemTime>,
+    /// The identity of the file in the filesystem when it was read, if the platform has one.
+    pub(crate) identity: Option<FileIdentity>,
+    /// The key the file is stored under, once it is stored in the cache.
+    /// It is shared with the files handed out for this one, so serving the file doesn't copy its path.
+    pub(crate) key: Option<Arc<Path>>,
+    /// When the file was first stored in the cache, by the clock of the cache.
+    /// Refreshing or replacing the bytes of the file keeps the time it was first stored.
+    pub(crate) inserted_at: Option<Instant>,
+    /// When the file was last stored or served, by the clock of the cache.
+    /// Only recorded while a victim comparator is set, as the clock would otherwise be read on every hit.
+    pub(crate) last_accessed: Option<Instant>,
+    /// The generation of the cache when the file was last stored or replaced.
+    pub(crate) generation: u64,
+    /// Whether the file was soft purged, so it must be checked against the filesystem before it is served again.
+    pub(crate) stale: bool,
+    /// Whether the bytes were pushed with `Cache::update_bytes()` instead of read from the filesystem,
+    /// so they must not be checked against the file in the filesystem.
+    pub(crate) pushed: bool,
+}
+
+/// Files are equal if their contents and stats are, regardless of where they are stored.
+impl PartialEq for InMemoryFile {
+    fn eq(&self, other: &InMemoryFile) -> bool {
+        self.bytes == other.bytes
+            && self.stats == other.stats
+            && self.content_hash == other.content_hash
+            && self.accesses_since_priority_update == other.accesses_since_priority_update
+            && self.modified == other.modified
+    }
 }
 
 impl fmt::Debug for InMemoryFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // The byte array shouldn't be visible in the log.
-        write!(
-            f,
-            "SizedFile {{ bytes: ..., size: {}, priority: {} }}",
-            self.stats.size,
-            self.stats.priority
-        )
+        // The byte array shouldn't be visible in the log, so the bytes only show their length.
+        f.debug_struct("InMemoryFile")
+            .field("size", &self.stats.size)
+            .field("bytes", &self.bytes)
+            .field("access_count", &self.stats.access_count)
+            .field("priority", &self.stats.priority)
+            .finish()
     }
 }
 
 
 impl InMemoryFile {
+    /// Creates an InMemoryFile with the bytes, which hasn't been accessed or stored in the cache yet.
+    fn new(bytes: FileBytes, modified: Option<SystemTime>, identity: Option<FileIdentity>) -> InMemoryFile {
+        let stats = FileStats {
+            size: bytes.as_slice().len(),
+            access_count: 0,
+            priority: 0,
+            last_access: 0,
+        };
+        InMemoryFile {
+            bytes,
+            stats,
+            content_hash: None,
+            accesses_since_priority_update: 0,
+            modified,
+            identity,
+            key: None,
+            inserted_at: None,
+            last_accessed: None,
+            generation: 0,
+            stale: false,
+            pushed: false,
+        }
+    }
+
     /// Reads the file at the path into an InMemoryFile.
-    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<InMemoryFile> {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> io::Result<InMemoryFile> {
         let file = File::open(path.as_ref())?;
+        let metadata: Metadata = file.metadata()?;
+        let modified: Option<SystemTime> = metadata.modified().ok();
+        let identity: Option<FileIdentity> = FileIdentity::of(&metadata);
         let mut reader = BufReader::new(file);
         let mut bytes: Vec<u8> = vec![];
-        let size: usize = reader.read_to_end(&mut bytes)?;
+        reader.read_to_end(&mut bytes)?;
 
-        let stats = FileStats {
-            size,
-            access_count: 0,
-            priority: 0,
+        Ok(InMemoryFile::new(FileBytes::Owned(Arc::new(bytes)), modified, identity))
+    }
+
+    /// Reads the file at the path into the storage.
+    pub(crate) fn open_with<P: AsRef<Path>>(path: P, storage: Storage) -> io::Result<InMemoryFile> {
+        match storage {
+            Storage::Heap => InMemoryFile::open(path),
+            #[cfg(all(unix, feature = "mmap"))]
+            Storage::Mmap => InMemoryFile::map(path),
+        }
+    }
+
+    /// Memory maps the file at the path.
+    #[cfg(all(unix, feature = "mmap"))]
+    fn map<P: AsRef<Path>>(path: P) -> io::Result<InMemoryFile> {
+        let file = File::open(path.as_ref())?;
+        let metadata = file.metadata()?;
+        let modified: Option<SystemTime> = metadata.modified().ok();
+        let identity: Option<FileIdentity> = FileIdentity::of(&metadata);
+        let size: usize = metadata.len() as usize;
+        // Empty files can't be mapped.
+        let bytes: FileBytes = if size == 0 {
+            FileBytes::Owned(Arc::new(Vec::new()))
+        } else {
+            FileBytes::Mapped(Arc::new(Mapping::map(&file, size)?))
         };
 
-        Ok(InMemoryFile { bytes, stats })
+        Ok(InMemoryFile::new(bytes, modified, identity))
+    }
+
+    /// Creates an InMemoryFile from bytes that are already in memory.
+    pub(crate) fn from_bytes(bytes: Vec<u8>) -> InMemoryFile {
+        InMemoryFile::new(FileBytes::Owned(Arc::new(bytes)), None, None)
+    }
+
+    /// Creates an InMemoryFile that serves bytes embedded in the binary without copying them.
+    pub(crate) fn from_static(bytes: &'static [u8]) -> InMemoryFile {
+        InMemoryFile::new(FileBytes::Static(bytes), None, None)
+    }
+
+    /// Creates an InMemoryFile whose stats claim a different size than its bytes have,
+    /// to test what happens when they disagree.
+    #[cfg(test)]
+    pub(crate) fn with_declared_size(bytes: Vec<u8>, declared_size: usize) -> InMemoryFile {
+        let mut file: InMemoryFile = InMemoryFile::from_bytes(bytes);
+        file.stats.size = declared_size;
+        file
+    }
+
+    /// The size the cache believes the file has, which is what it accounts for and what responses declare.
+    pub(crate) fn declared_size(&self) -> usize {
+        self.stats.size
+    }
+
+    /// Whether the declared size of the file disagrees with the number of bytes it actually holds.
+    pub(crate) fn has_size_mismatch(&self) -> bool {
+        self.bytes.as_slice().len() != self.declared_size()
+    }
+
+    /// The size to declare in a response serving the file.
+    ///
+    /// This is the declared size, unless it disagrees with the bytes that will actually be written,
+    /// in which case the length of the bytes is used, so clients never get a wrong Content-Length.
+    #[cfg(any(feature = "rocket", test))]
+    pub(crate) fn response_size(&self) -> usize {
+        let actual_size: usize = self.bytes.as_slice().len();
+        if actual_size != self.declared_size() {
+            error!(
+                "A cached file declares {} bytes, but holds {} bytes. Responding with the bytes it holds.",
+                self.declared_size(),
+                actual_size
+            );
+        }
+        actual_size
+    }
+
+    /// Embedded files have no backing file in the filesystem,
+    /// so they can't be refreshed and must never be evicted.
+    pub(crate) fn is_embedded(&self) -> bool {
+        matches!(self.bytes, FileBytes::Static(_))
+    }
+
+    /// Whether the bytes came from the file in the filesystem, so they can be revalidated against it.
+    /// Embedded files and files whose bytes were pushed with `Cache::update_bytes()` are only refreshed when asked to.
+    pub(crate) fn is_backed_by_fs(&self) -> bool {
+        !self.is_embedded() && !self.pushed
     }
 }
 
@@ -63,4 +294,8 @@ pub struct FileStats {
     /// This is updated every time the access count is incremented by running the cache's `priority_function`
     /// on the `size` and `access_count`.
     pub priority: usize,
+    /// When the file was last stored or accessed in the cache.
+    /// This is a sequence number shared by all files in the cache, not a timestamp,
+    /// so a file with a smaller number was used longer ago than a file with a larger one.
+    pub last_access: usize,
 }
\ No newline at end of file
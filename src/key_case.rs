@@ -0,0 +1,143 @@
+use std::path::{Component, PathBuf, Path};
+
+
+/// Determines whether paths that differ only in case refer to the same entry in the cache.
+///
+/// Whichever is chosen, repeated and trailing separators and `.` components are removed from paths
+/// before they are used as keys, so every spelling of a path refers to the same entry.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum KeyCase {
+    /// Paths are used as keys in the case they are given in. This is the default.
+    #[default]
+    Sensitive,
+    /// The ASCII letters of paths are lowercased before they are used as keys,
+    /// so `WWW/Logo.PNG` and `www/logo.png` share one entry.
+    /// The file is read from the spelling its key was first requested with.
+    Insensitive,
+}
+
+impl KeyCase {
+    /// Normalizes the path into the key it should be stored under.
+    /// Returns None if the path is already its own key.
+    ///
+    /// Whatever the case setting, the path is cleaned up lexically first, see `clean()`.
+    pub(crate) fn normalize(&self, path: &Path) -> Option<PathBuf> {
+        let cleaned: Option<PathBuf> = clean(path);
+        match *self {
+            KeyCase::Sensitive => cleaned,
+            KeyCase::Insensitive => {
+                let normalized: PathBuf = lowercase(cleaned.as_ref().map_or(path, PathBuf::as_path));
+                // Paths compare by their components, so the bytes are compared to tell the spellings apart.
+                if normalized.as_os_str() == path.as_os_str() {
+                    None
+                } else {
+                    Some(normalized)
+                }
+            }
+        }
+    }
+}
+
+
+/// Spells the path the one way its components can be spelled: without repeated or trailing separators,
+/// and without `.` components, so `www/css//app.css`, `www/./css/app.css` and `www/css/app.css/`
+/// all become `www/css/app.css`.
+/// This never reads the filesystem, so `..` components and symlinks are left alone.
+///
+/// Returns None if the path is already spelled that way, or if nothing would be left of it.
+fn clean(path: &Path) -> Option<PathBuf> {
+    if is_clean(path) {
+        return None;
+    }
+    let cleaned: PathBuf = path.components().filter(|&component| component != Component::CurDir).collect();
+    if cleaned.as_os_str().is_empty() {
+        None
+    } else {
+        Some(cleaned)
+    }
+}
+
+/// Checks if the path is spelled the way `clean()` would spell it, without allocating,
+/// by adding up the length of the path its components would be joined into.
+fn is_clean(path: &Path) -> bool {
+    let mut length: usize = 0;
+    let mut needs_separator: bool = false;
+    for component in path.components() {
+        match component {
+            Component::CurDir => return false,
+            Component::Prefix(_) | Component::RootDir => {
+                length += component.as_os_str().len();
+                needs_separator = false;
+            }
+            Component::ParentDir | Component::Normal(_) => {
+                if needs_separator {
+                    length += 1;
+                }
+                length += component.as_os_str().len();
+                needs_separator = true;
+            }
+        }
+    }
+    length == path.as_os_str().len()
+}
+
+
+/// Lowercases the ASCII letters of the path, leaving every other byte untouched.
+#[cfg(unix)]
+fn lowercase(path: &Path) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    PathBuf::from(OsString::from_vec(path.as_os_str().as_bytes().to_ascii_lowercase()))
+}
+
+/// Lowercases the ASCII letters of the path if it is valid unicode, leaving it untouched otherwise.
+#[cfg(not(unix))]
+fn lowercase(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(path) => PathBuf::from(path.to_ascii_lowercase()),
+        None => path.to_path_buf(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensitive_keys_are_not_normalized() {
+        assert_eq!(KeyCase::Sensitive.normalize(Path::new("WWW/Logo.PNG")), None);
+    }
+
+    #[test]
+    fn keys_are_cleaned_up_lexically() {
+        for &spelling in &["www/css//app.css", "www/css/app.css/", "./www/./css/app.css", "www///css/.//app.css//"] {
+            assert_eq!(KeyCase::Sensitive.normalize(Path::new(spelling)), Some(PathBuf::from("www/css/app.css")), "{}", spelling);
+        }
+        assert_eq!(KeyCase::Insensitive.normalize(Path::new("WWW//App.css/")), Some(PathBuf::from("www/app.css")));
+        assert_eq!(KeyCase::Sensitive.normalize(Path::new("/srv/www/app.css")), None);
+        assert_eq!(KeyCase::Sensitive.normalize(Path::new("www/../app.css")), None);
+        assert_eq!(KeyCase::Sensitive.normalize(Path::new("/")), None);
+        assert_eq!(KeyCase::Sensitive.normalize(Path::new("./")), None);
+    }
+
+    #[test]
+    fn insensitive_keys_are_lowercased() {
+        assert_eq!(KeyCase::Insensitive.normalize(Path::new("WWW/Logo.PNG")), Some(PathBuf::from("www/logo.png")));
+        // Only ASCII letters are folded, so a key doesn't depend on the unicode case tables.
+        assert_eq!(KeyCase::Insensitive.normalize(Path::new("WWW/Ünïcode.PNG")), Some(PathBuf::from("www/Ünïcode.png")));
+        assert_eq!(KeyCase::Insensitive.normalize(Path::new("www/logo.png")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_bytes_are_left_intact() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path: &Path = Path::new(OsStr::from_bytes(b"WWW/\xFFLogo\xFE.PNG"));
+        let expected: &Path = Path::new(OsStr::from_bytes(b"www/\xFFlogo\xFE.png"));
+        assert_eq!(KeyCase::Insensitive.normalize(path), Some(expected.to_path_buf()));
+    }
+}
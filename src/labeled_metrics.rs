@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+
+/// The hits and misses counted for one label, as returned by `Cache::labeled_summary()`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LabeledSummary {
+    /// The number of files requested with the label that were served from the cache.
+    pub hits: usize,
+    /// The number of files requested with the label that weren't in the cache,
+    /// whether they were inserted, served from the filesystem, or not found.
+    pub misses: usize,
+}
+
+
+/// Counts hits and misses separately for each label files are requested with.
+#[derive(Debug)]
+pub(crate) struct LabeledMetrics {
+    labels: Mutex<HashMap<String, LabeledSummary>>,
+}
+
+impl LabeledMetrics {
+    pub(crate) fn new() -> LabeledMetrics {
+        LabeledMetrics {
+            labels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Only a `CacheHandle` records labels, so this is only needed with Rocket.
+    #[cfg(feature = "rocket")]
+    pub(crate) fn record(&self, label: &str, hit: bool) {
+        let mut labels = self.lock();
+        // Labels that were counted before are counted in place, so requesting a file doesn't copy its label.
+        if !labels.contains_key(label) {
+            labels.insert(label.to_string(), LabeledSummary::default());
+        }
+        let summary: &mut LabeledSummary = labels.get_mut(label).expect("The label was just inserted.");
+        if hit {
+            summary.hits = summary.hits.saturating_add(1);
+        } else {
+            summary.misses = summary.misses.saturating_add(1);
+        }
+    }
+
+    pub(crate) fn summary(&self, label: &str) -> LabeledSummary {
+        self.lock().get(label).cloned().unwrap_or_default()
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<String, LabeledSummary>> {
+        match self.labels.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+
+
+/// A phase of serving a file that is timed by the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Looking the file up in the cache, and updating its stats if it was found.
+    Lookup,
+    /// Reading the metadata of a file that isn't in the cache, to get its size.
+    Metadata,
+    /// Choosing the files to evict to make room for a new file.
+    EvictionSelection,
+    /// Reading a new file into memory.
+    FileRead,
+    /// Locking the file in the cache, or opening the file in the filesystem, to respond with it.
+    Response,
+}
+
+const PHASES: [Phase; 5] = [Phase::Lookup, Phase::Metadata, Phase::EvictionSelection, Phase::FileRead, Phase::Response];
+
+/// The upper bounds of the latency buckets, in microseconds.
+/// Anything slower than the last bound falls into one more bucket.
+const BUCKET_BOUNDS_MICROS: [u64; 7] = [1, 10, 100, 1_000, 10_000, 100_000, 1_000_000];
+const BUCKETS: usize = 8;
+
+
+/// How long each phase of serving files took, as counted by `Cache::latency_report()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyReport {
+    /// The upper bounds of the buckets the timings are counted in, from the fastest to the slowest.
+    /// There is one more bucket than there are bounds, which counts the timings slower than the last bound.
+    pub bucket_bounds: Vec<Duration>,
+    /// The timings of each phase.
+    pub phases: Vec<PhaseLatency>,
+}
+
+impl LatencyReport {
+    /// Gets the timings of the phase.
+    pub fn phase(&self, phase: Phase) -> &PhaseLatency {
+        self.phases
+            .iter()
+            .find(|latency| latency.phase == phase)
+            .expect("Every phase is in the report.")
+    }
+}
+
+
+/// How long one phase of serving files took, each time it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseLatency {
+    pub phase: Phase,
+    /// The number of timings that fell into each of the buckets of the report.
+    pub counts: Vec<usize>,
+    /// The sum of every timing.
+    pub total: Duration,
+    /// The number of timings that took longer than the budget of the phase.
+    pub over_budget: usize,
+}
+
+impl PhaseLatency {
+    /// The number of times the phase was timed.
+    pub fn count(&self) -> usize {
+        self.counts.iter().sum()
+    }
+}
+
+
+/// Counts the timings of each phase into fixed buckets.
+#[derive(Debug)]
+pub(crate) struct LatencyRecorder {
+    phases: Vec<PhaseRecorder>,
+}
+
+#[derive(Debug)]
+struct PhaseRecorder {
+    counts: Vec<AtomicUsize>,
+    total_nanos: AtomicU64,
+    over_budget: AtomicUsize,
+    budget: Option<Duration>,
+}
+
+impl LatencyRecorder {
+    pub(crate) fn new() -> LatencyRecorder {
+        LatencyRecorder {
+            phases: PHASES
+                .iter()
+                .map(|_| PhaseRecorder {
+                    counts: (0..BUCKETS).map(|_| AtomicUsize::new(0)).collect(),
+                    total_nanos: AtomicU64::new(0),
+                    over_budget: AtomicUsize::new(0),
+                    budget: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn record(&self, phase: Phase, elapsed: Duration) {
+        let recorder: &PhaseRecorder = &self.phases[phase as usize];
+        let micros: u64 = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros());
+        let bucket: usize = BUCKET_BOUNDS_MICROS
+            .iter()
+            .position(|&bound| micros < bound)
+            .unwrap_or(BUCKETS - 1);
+        recorder.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos: u64 = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+        recorder.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        if recorder.budget.is_some_and(|budget| elapsed > budget) {
+            recorder.over_budget.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn budget(&self, phase: Phase) -> Option<Duration> {
+        self.phases[phase as usize].budget
+    }
+
+    pub(crate) fn set_budget(&mut self, phase: Phase, budget: Option<Duration>) {
+        self.phases[phase as usize].budget = budget;
+    }
+
+    pub(crate) fn report(&self) -> LatencyReport {
+        LatencyReport {
+            bucket_bounds: BUCKET_BOUNDS_MICROS.iter().map(|&micros| Duration::from_micros(micros)).collect(),
+            phases: PHASES
+                .iter()
+                .zip(self.phases.iter())
+                .map(|(&phase, recorder)| PhaseLatency {
+                    phase,
+                    counts: recorder.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect(),
+                    total: Duration::from_nanos(recorder.total_nanos.load(Ordering::Relaxed)),
+                    over_budget: recorder.over_budget.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
@@ -1,19 +1,150 @@
-#![feature(test)]
+#![cfg_attr(all(test, feature = "nightly-bench"), feature(test))]
 
+#[cfg(feature = "rocket")]
 extern crate rocket;
 #[macro_use]
 extern crate log;
 
 extern crate concurrent_hashmap;
+extern crate flate2;
+#[cfg(all(unix, feature = "mmap"))]
+extern crate libc;
 
 mod cache;
 mod in_memory_file;
 pub mod named_in_memory_file;
 mod cache_builder;
 mod priority_function;
+#[cfg(feature = "rocket")]
 mod cached_file;
+mod file_source;
+mod detached_file;
+mod in_flight;
+mod cold_tier;
+mod summary;
+mod cache_registry;
+mod content_hash;
+mod get_options;
+mod key_case;
+mod read_limiter;
+mod observer;
+mod admission;
+mod verify;
+mod decision_log;
+mod prefetch;
+mod refresh_options;
+mod directory_floors;
+mod directory_quotas;
+mod boosts;
+mod serve_class;
+mod churn;
+mod thrash_protection;
+mod fallbacks;
+mod latency;
+mod storage;
+mod tombstones;
+mod served_bytes;
+mod bookkeeping;
+mod replay;
+mod simulate;
+mod policy;
+mod fs_health;
+mod batch_plan;
+mod small_file_arena;
+mod labeled_metrics;
+mod manifest;
+mod resolution;
+mod size;
+mod classification_filter;
+mod extension_stats;
+mod ghost_entries;
+mod spill;
+mod zombie_bytes;
+mod snapshot;
+mod refresh_limiter;
+mod entry_handle;
+mod content_index;
+mod deadline;
+mod operations;
+#[cfg(feature = "rocket")]
+mod rocket_config;
+#[cfg(feature = "rocket")]
+mod cache_handle;
+#[cfg(feature = "rocket")]
+mod cache_response;
+#[cfg(feature = "admin")]
+pub mod admin;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(test)]
+mod alloc_counter;
 
-pub use cache::Cache;
+pub use cache::{Cache, CacheError, InvalidKeyReason, OvershootConfig, CacheInsertionSuccess, EvictedEntry, Recompute, Consistency, Revalidation, OnMissing, CountPolicy, Mode, IndexKey};
 pub use cache_builder::{CacheBuilder, CacheBuildError};
+#[cfg(feature = "rocket")]
 pub use cached_file::CachedFile;
+#[cfg(feature = "rocket")]
+pub use cache_response::CacheResponse;
+pub use file_source::FileSource;
+pub use detached_file::DetachedFile;
+pub use entry_handle::EntryHandle;
+pub use content_index::ContentAddressedConfig;
+pub use thrash_protection::ThrashConfig;
+pub use boosts::Boost;
+pub use policy::{VictimCandidate, VictimComparator};
+pub use fs_health::DegradedModeConfig;
+pub use refresh_limiter::RateLimit;
+pub use small_file_arena::SmallFileArenaConfig;
+pub use labeled_metrics::LabeledSummary;
+pub use manifest::{ManifestReport, ManifestOutcome, ManifestError};
+pub use resolution::Resolution;
+pub use size::{parse_size, ParseSizeError};
+#[cfg(feature = "rocket")]
+pub use rocket_config::ConfigError;
+#[cfg(feature = "rocket")]
+pub use cache_handle::CacheHandle;
+pub use in_memory_file::FileStats;
 pub use priority_function::*;
+pub use cold_tier::{ColdTierConfig, Compression};
+pub use summary::CacheSummary;
+pub use snapshot::CacheSnapshot;
+pub use extension_stats::ExtensionStats;
+pub use cache_registry::CacheRegistry;
+pub use get_options::GetOptions;
+pub use key_case::KeyCase;
+pub use observer::{CacheObserver, EvictionVote, EvictionFilter};
+pub use admission::{AdmissionPrediction, AdmissionToken};
+pub use verify::{VerifyReport, Inconsistency, InvariantViolation};
+pub use decision_log::{Decision, Outcome};
+pub use prefetch::{PrefetchReport, PrefetchSkipReason, PrefetchConfig, PreloadOptions, PreloadProgress};
+pub use refresh_options::{RefreshOptions, RefreshOutcome};
+pub use directory_floors::DirectoryFloorUsage;
+pub use serve_class::ServeClass;
+pub use latency::{LatencyReport, PhaseLatency, Phase};
+pub use storage::Storage;
+pub use served_bytes::PathMetrics;
+pub use replay::{Trace, TraceOp, ReplayReport};
+pub use spill::{SpillSummary, RestoreOptions, RestoreSummary};
+pub use simulate::{simulate, SimulationResult};
+
+
+/// Fails to compile if a type that is meant to be shared between the threads of a server
+/// stops being `Send` or `Sync`, for example because a field with a raw pointer or an `Rc` was added to it.
+///
+/// Files that hold a lock on an entry of the cache, `FileSource::Cached` and `CachedFile::InMemory`,
+/// hold a read guard of the map, so they can't be sent to another thread. Detach them first.
+#[allow(dead_code)]
+fn assert_thread_safety() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send_sync_clone<T: Send + Sync + Clone>() {}
+
+    assert_send_sync::<Cache>();
+    assert_send_sync::<CacheBuilder>();
+    assert_send_sync::<CacheRegistry>();
+    assert_send_sync::<in_memory_file::InMemoryFile>();
+    assert_send_sync::<DetachedFile>();
+    assert_send_sync_clone::<EntryHandle>();
+    assert_send_sync::<CacheSummary>();
+    assert_send_sync::<CacheError>();
+    assert_send_sync::<fn(usize, usize) -> usize>();
+}
@@ -0,0 +1,88 @@
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use cache::CacheError;
+
+
+/// What `Cache::refresh_from_manifest()` did with a path listed in the manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManifestOutcome {
+    /// The file was in the cache, and was read again from the filesystem.
+    Refreshed,
+    /// The file was in the cache, but no longer exists in the filesystem, so it was removed.
+    Removed,
+    /// The file wasn't in the cache, and was inserted into it.
+    Preloaded,
+    /// The file wasn't in the cache, and preloading wasn't asked for.
+    NotCached,
+    /// The file couldn't be refreshed or preloaded for the given reason.
+    Failed(CacheError),
+}
+
+
+/// Why a line of a manifest couldn't be used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManifestError {
+    /// The line is an absolute path, but manifests list paths relative to their root.
+    NotRelative,
+    /// The line isn't valid UTF-8.
+    InvalidUtf8,
+    /// The manifest couldn't be read past the line, so the rest of it was ignored.
+    Unreadable(io::ErrorKind),
+}
+
+
+/// The paths listed in a manifest, and what `Cache::refresh_from_manifest()` did with each of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestReport {
+    /// Every path in the manifest, joined to the root, and what happened to it, in the order they were listed.
+    pub outcomes: Vec<(PathBuf, ManifestOutcome)>,
+    /// The lines that couldn't be used, numbered from 1, and why.
+    pub errors: Vec<(usize, ManifestError)>,
+    /// Whether the refresh was cancelled with the flag set by `PreloadOptions::cancel_flag()`,
+    /// so the paths listed after the last one in `outcomes` weren't touched.
+    pub cancelled: bool,
+}
+
+
+/// Reads the paths listed in a manifest, one per line, joined to the root if there is one.
+/// Blank lines and lines starting with `#` are skipped.
+pub(crate) fn read_manifest<R: BufRead>(mut reader: R, root: Option<&Path>) -> (Vec<PathBuf>, Vec<(usize, ManifestError)>) {
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut errors: Vec<(usize, ManifestError)> = vec![];
+    let mut line: Vec<u8> = vec![];
+    let mut line_number: usize = 0;
+    loop {
+        line.clear();
+        line_number += 1;
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(error) => {
+                errors.push((line_number, ManifestError::Unreadable(error.kind())));
+                break;
+            }
+        }
+        let entry: &str = match str::from_utf8(&line) {
+            Ok(entry) => entry.trim(),
+            Err(_) => {
+                errors.push((line_number, ManifestError::InvalidUtf8));
+                continue;
+            }
+        };
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+        let path: &Path = Path::new(entry);
+        if path.has_root() {
+            errors.push((line_number, ManifestError::NotRelative));
+            continue;
+        }
+        paths.push(match root {
+            Some(root) => root.join(path),
+            None => path.to_path_buf(),
+        });
+    }
+    (paths, errors)
+}
@@ -1,9 +1,16 @@
+#[cfg(feature = "rocket")]
 use rocket::response::{Response, Responder};
+#[cfg(feature = "rocket")]
 use rocket::http::{Status, ContentType};
+#[cfg(feature = "rocket")]
 use rocket::request::Request;
+#[cfg(feature = "rocket")]
 use rocket::response::Body;
 
+#[cfg(feature = "rocket")]
 use std::result;
+#[cfg(feature = "rocket")]
+use std::io::Cursor;
 use std::sync::Arc;
 use std::path::{PathBuf, Path};
 
@@ -19,8 +26,8 @@ use std::fmt;
 /// This struct is created when when a request to the cache is made.
 /// The CachedFile knows its path, so it can set the content type when it is serialized to a response.
 pub struct NamedInMemoryFile<'a> {
-    pub(crate) path: PathBuf,
-    pub(crate) file: Arc<Accessor<'a, PathBuf, InMemoryFile>>,
+    pub(crate) path: Arc<Path>,
+    pub(crate) file: Accessor<'a, PathBuf, InMemoryFile>,
 }
 
 
@@ -34,9 +41,14 @@ impl<'a> Debug for NamedInMemoryFile<'a> {
 impl<'a> NamedInMemoryFile<'a> {
     /// Reads the file at the path into a NamedInMemoryFile.
     pub(crate) fn new<P: AsRef<Path>>(path: P, m: Accessor<'a, PathBuf, InMemoryFile>) -> NamedInMemoryFile<'a> {
+        // Files in the cache share their key, so only files that were never stored need their path copied.
+        let path: Arc<Path> = match m.get().key {
+            Some(ref key) => key.clone(),
+            None => Arc::from(path.as_ref()),
+        };
         NamedInMemoryFile {
-            path: path.as_ref().to_path_buf(),
-            file: Arc::new(m),
+            path,
+            file: m,
         }
     }
 }
@@ -49,6 +61,7 @@ impl<'a> NamedInMemoryFile<'a> {
 /// extension, convert the `CachedFile` to a `File`, and respond with that instead.
 ///
 /// Based on NamedFile from rocket::response::NamedFile
+#[cfg(feature = "rocket")]
 impl<'a> Responder<'a> for NamedInMemoryFile<'a> {
     fn respond_to(self, _: &Request) -> result::Result<Response<'a>, Status> {
         let mut response = Response::new();
@@ -58,11 +71,10 @@ impl<'a> Responder<'a> for NamedInMemoryFile<'a> {
             }
         }
 
-        unsafe {
-            let cloned_wrapper: *const Accessor<'a, PathBuf, InMemoryFile> = Arc::into_raw(self.file);
-            response.set_raw_body( Body::Sized((*cloned_wrapper).get().bytes.as_slice(), (*cloned_wrapper).get().stats.size as u64) );
-            let _ = Arc::from_raw(cloned_wrapper); // To prevent a memory leak, an Arc needs to be reconstructed from the raw pointer.
-        }
+        // The body shares the bytes of the cached file instead of copying them,
+        // so the lock on the file is released as soon as the response is built.
+        let file: &InMemoryFile = self.file.get();
+        response.set_raw_body(Body::Sized(Cursor::new(file.bytes.clone()), file.response_size() as u64));
 
         Ok(response)
     }
@@ -0,0 +1,32 @@
+use std::path::Path;
+
+use cache::EvictedEntry;
+use in_memory_file::FileStats;
+
+
+/// Receives notifications about changes the cache makes on its own.
+///
+/// Every method has a default implementation that does nothing,
+/// so implementors only need to implement the notifications they care about.
+/// The methods are called while the cache is in the middle of an operation,
+/// so they should be quick, and must not call back into the cache.
+pub trait CacheObserver: Send + Sync {
+    /// Called for every file evicted from the cache to make room for other files,
+    /// in the order the files were chosen for eviction.
+    fn on_evict(&self, _evicted_entry: &EvictedEntry) {}
+}
+
+
+/// What an eviction filter set with `Cache::set_eviction_filter()` says about evicting a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionVote {
+    /// The file may be evicted in its usual order.
+    Allow,
+    /// The file is evicted before the files that are allowed, as if it had a lower priority than all of them.
+    Prefer,
+    /// The file is never evicted, and the next candidate is considered instead.
+    Deny,
+}
+
+/// Decides which files may be evicted, as set with `Cache::set_eviction_filter()`.
+pub type EvictionFilter = Box<dyn Fn(&Path, &FileStats) -> EvictionVote + Send + Sync>;
@@ -0,0 +1,107 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+thread_local! {
+    /// How many operations the thread is inside of, so operations that call each other are only counted once.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+
+/// Counts the operations that change the cache while they are in progress,
+/// so the invariants of the cache are only checked when no other operation can be halfway through a change.
+///
+/// Operations are only counted in debug builds, as only debug builds check the invariants.
+#[derive(Debug)]
+pub(crate) struct Operations {
+    /// The operations in progress, on every thread.
+    in_flight: AtomicUsize,
+    /// The operations that were ever started, which tells if another one started while the invariants were checked.
+    started: AtomicUsize,
+}
+
+impl Operations {
+    pub(crate) fn new() -> Operations {
+        Operations {
+            in_flight: AtomicUsize::new(0),
+            started: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts an operation on the thread, which ends when the returned guard is dropped.
+    pub(crate) fn begin(&self) -> Operation {
+        if !cfg!(debug_assertions) {
+            return Operation { operations: self, outermost: false };
+        }
+        let outermost: bool = DEPTH.with(|depth| {
+            depth.set(depth.get() + 1);
+            depth.get() == 1
+        });
+        if outermost {
+            self.in_flight.fetch_add(1, Ordering::SeqCst);
+            self.started.fetch_add(1, Ordering::SeqCst);
+        }
+        Operation { operations: self, outermost }
+    }
+
+    /// Runs the check if the operation of this thread is the only one in progress,
+    /// or if no operation is, and no other operation starts while the check runs.
+    /// Returns None if the check couldn't be run alone.
+    pub(crate) fn check_alone<T, F: FnOnce() -> T>(&self, check: F) -> Option<T> {
+        let own: usize = DEPTH.with(|depth| if depth.get() > 0 { 1 } else { 0 });
+        if self.in_flight.load(Ordering::SeqCst) != own {
+            return None;
+        }
+        let started: usize = self.started.load(Ordering::SeqCst);
+        let result: T = check();
+        if self.in_flight.load(Ordering::SeqCst) != own || self.started.load(Ordering::SeqCst) != started {
+            return None;
+        }
+        Some(result)
+    }
+}
+
+/// An operation in progress, which ends when it is dropped.
+pub(crate) struct Operation<'a> {
+    operations: &'a Operations,
+    /// If the operation isn't part of another operation of the thread.
+    outermost: bool,
+}
+
+impl<'a> Drop for Operation<'a> {
+    fn drop(&mut self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+        if self.outermost {
+            self.operations.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn checks_only_run_while_no_other_operation_is_in_progress() {
+        let operations: Arc<Operations> = Arc::new(Operations::new());
+        assert_eq!(operations.check_alone(|| 1), Some(1));
+
+        {
+            let _outer: Operation = operations.begin();
+            let _nested: Operation = operations.begin();
+            assert_eq!(operations.check_alone(|| 2), Some(2));
+
+            // An operation starting on another thread while the check runs makes the check untrustworthy.
+            let other: Arc<Operations> = operations.clone();
+            assert_eq!(operations.check_alone(|| thread::spawn(move || { other.begin(); }).join().unwrap()), None);
+        }
+        assert_eq!(operations.check_alone(|| 3), Some(3));
+    }
+}
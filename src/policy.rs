@@ -0,0 +1,317 @@
+use std::cmp::{self, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cache::CacheError;
+use in_memory_file::FileStats;
+
+
+/// Orders files with the same priority for eviction, like `Cache::set_tie_breaker()`.
+pub(crate) type TieBreaker = fn(&Path, &FileStats, &Path, &FileStats) -> Ordering;
+
+/// Orders every file for eviction, as set with `Cache::set_victim_comparator()`.
+pub type VictimComparator = Box<dyn Fn(&VictimCandidate, &VictimCandidate) -> Ordering + Send + Sync>;
+
+
+/// A file in the cache that could be evicted, as it is given to the comparator set with `Cache::set_victim_comparator()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VictimCandidate<'a> {
+    /// The key the file is stored under.
+    pub path: &'a Path,
+    /// The stats of the file. The priority is the one the file is evicted by, including any boosts.
+    pub stats: &'a FileStats,
+    /// When the file was last stored or served from the cache, by the clock of the cache.
+    /// Access times are only recorded while a victim comparator is set, so files that weren't used since have none.
+    pub last_accessed: Option<Instant>,
+    /// When the file was first stored in the cache, by the clock of the cache.
+    pub inserted_at: Option<Instant>,
+}
+
+
+/// A file in the cache that could be evicted, with the stats it is evicted by.
+///
+/// The key is shared with the file in the cache, so listing every file as a candidate doesn't copy their paths.
+/// Only the keys of the files chosen for eviction are copied.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CandidateFile {
+    pub(crate) key: Arc<Path>,
+    pub(crate) stats: FileStats,
+    /// When the file was stored in the cache, if it is known.
+    pub(crate) inserted_at: Option<Instant>,
+    /// When the file was last stored or served, if access times are recorded.
+    pub(crate) last_accessed: Option<Instant>,
+}
+
+impl CandidateFile {
+    fn as_victim(&self) -> VictimCandidate {
+        VictimCandidate {
+            path: &self.key,
+            stats: &self.stats,
+            last_accessed: self.last_accessed,
+            inserted_at: self.inserted_at,
+        }
+    }
+}
+
+
+/// The room a new file needs, and what it may do to get it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RoomRequest {
+    /// The number of bytes that must be freed for the new file to fit.
+    pub(crate) required_space: usize,
+    /// The number of bytes that should be freed once the new file is known to be worth its space,
+    /// so that the following insertions don't have to evict anything.
+    /// The files freed beyond `required_space` aren't compared against the priority of the new file.
+    pub(crate) batch_space: usize,
+    /// The priority of the new file. If the files that would have to be evicted for it add up to more, nothing is evicted.
+    pub(crate) new_file_priority: usize,
+    /// The most files that may be evicted. If room can't be made by evicting this many files, nothing is evicted.
+    pub(crate) max_candidates: Option<usize>,
+}
+
+
+/// Checks that a file of the size can be stored in a cache with the file size bounds.
+pub(crate) fn check_size_bounds(size: usize, min_file_size: usize, max_file_size: usize) -> Result<(), CacheError> {
+    if size > max_file_size || size < min_file_size {
+        return Err(CacheError::FileSizeOutOfBounds);
+    }
+    Ok(())
+}
+
+/// The largest file a cache with the size limit stores, if no file may take more than the fraction of the cache.
+pub(crate) fn max_file_size_for_fraction(size_limit: usize, fraction: f32) -> usize {
+    (size_limit as f64 * fraction as f64) as usize
+}
+
+/// The number of bytes that have to be freed for a file of the size to fit under the limit.
+///
+/// The bytes are added up in 64 bits, so a cache and a file of a few GiB together can't overflow on 32-bit platforms.
+/// If more bytes are needed than a usize can hold, all of them are.
+pub(crate) fn required_space(used_bytes: usize, size: usize, limit: usize) -> usize {
+    let needed: u64 = total_bytes(used_bytes, size).saturating_sub(limit as u64);
+    cmp::min(needed, usize::MAX as u64) as usize
+}
+
+/// Checks if a file of the size fits into the cache without evicting anything.
+/// A file has to be smaller than the size limit itself, even if the cache may go over it for a while.
+pub(crate) fn fits_without_eviction(used_bytes: usize, size: usize, size_limit: usize, effective_size_limit: usize) -> bool {
+    size < size_limit && total_bytes(used_bytes, size) <= effective_size_limit as u64
+}
+
+/// The bytes the cache would hold with a file of the size added, in 64 bits.
+fn total_bytes(used_bytes: usize, size: usize) -> u64 {
+    (used_bytes as u64).saturating_add(size as u64)
+}
+
+/// Sorts the candidates from the highest priority to the lowest, so the next file to evict can be popped off the end.
+/// Files with the same priority are ordered by the tie breaker, so the one to be evicted first comes last.
+pub(crate) fn order_for_eviction(candidates: &mut [CandidateFile], tie_breaker: TieBreaker) {
+    candidates.sort_by(|l, r| {
+        r.stats.priority.cmp(&l.stats.priority)
+            .then_with(|| tie_breaker(&r.key, &r.stats, &l.key, &l.stats))
+    });
+}
+
+/// Sorts the candidates by the comparator alone, so the one it evicts first comes last, like `order_for_eviction()`.
+pub(crate) fn order_by_comparator(candidates: &mut [CandidateFile], comparator: &VictimComparator) {
+    candidates.sort_by(|l, r| comparator(&r.as_victim(), &l.as_victim()));
+}
+
+/// Gets the candidates that have been in the cache for at least `min_residency`, keeping their order.
+/// Candidates without a known insertion time are kept.
+pub(crate) fn settled(candidates: &[CandidateFile], now: Instant, min_residency: Duration) -> Vec<CandidateFile> {
+    candidates
+        .iter()
+        .filter(|candidate| {
+            candidate.inserted_at.is_none_or(|inserted_at| {
+                now.checked_duration_since(inserted_at).is_some_and(|age| age >= min_residency)
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Chooses the files to evict to make room for a new file, from candidates ordered by `order_for_eviction()`.
+///
+/// The lowest priority candidates are chosen until the required space is freed.
+/// If their priorities add up to more than the priority of the new file, they are, in aggregate,
+/// more important than it, and nothing is evicted.
+/// Once the new file is known to be worth its space, more candidates are chosen until the batch space is freed.
+/// Candidates that `allows_eviction` turns down, given their path and size, are skipped.
+///
+/// Returns the paths and sizes of the files to evict, in the order they should be evicted.
+pub(crate) fn select_victims<F>(mut candidates: Vec<CandidateFile>, request: &RoomRequest, mut allows_eviction: F) -> Result<Vec<(PathBuf, usize)>, CacheError>
+    where F: FnMut(&Path, usize) -> bool
+{
+    let mut possibly_freed_space: usize = 0;
+    let mut priority_score_to_free: usize = 0;
+    let mut victims: Vec<(PathBuf, usize)> = vec![];
+    let at_max_candidates = |victims: &Vec<(PathBuf, usize)>| {
+        request.max_candidates.is_some_and(|max_candidates| victims.len() >= max_candidates)
+    };
+
+    while possibly_freed_space < request.required_space {
+        if at_max_candidates(&victims) {
+            return Err(CacheError::TooManyEvictionCandidates);
+        }
+        let lowest: CandidateFile = candidates.pop().ok_or(CacheError::NoMoreFilesToRemove)?;
+        if !allows_eviction(&lowest.key, lowest.stats.size) {
+            continue;
+        }
+        possibly_freed_space = possibly_freed_space.saturating_add(lowest.stats.size);
+        priority_score_to_free = priority_score_to_free.saturating_add(lowest.stats.priority);
+        victims.push((lowest.key.to_path_buf(), lowest.stats.size));
+
+        if priority_score_to_free > request.new_file_priority {
+            return Err(CacheError::NewPriorityIsNotHighEnough);
+        }
+    }
+    while possibly_freed_space < request.batch_space && !at_max_candidates(&victims) {
+        let lowest: CandidateFile = match candidates.pop() {
+            Some(lowest) => lowest,
+            None => break,
+        };
+        if !allows_eviction(&lowest.key, lowest.stats.size) {
+            continue;
+        }
+        possibly_freed_space = possibly_freed_space.saturating_add(lowest.stats.size);
+        victims.push((lowest.key.to_path_buf(), lowest.stats.size));
+    }
+    Ok(victims)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use priority_function::default_tie_breaker;
+
+    fn candidate(key: &str, size: usize, priority: usize, last_access: usize) -> CandidateFile {
+        CandidateFile {
+            key: Arc::from(Path::new(key)),
+            stats: FileStats {
+                size,
+                access_count: 1,
+                priority,
+                last_access,
+            },
+            inserted_at: None,
+            last_accessed: None,
+        }
+    }
+
+    /// Candidates a, b and c of 100 bytes each, with priorities 30, 20 and 10, ordered for eviction.
+    fn candidates() -> Vec<CandidateFile> {
+        let mut candidates: Vec<CandidateFile> = vec![candidate("b", 100, 20, 0), candidate("c", 100, 10, 0), candidate("a", 100, 30, 0)];
+        order_for_eviction(&mut candidates, default_tie_breaker);
+        candidates
+    }
+
+    fn request(required_space: usize, new_file_priority: usize) -> RoomRequest {
+        RoomRequest {
+            required_space,
+            batch_space: required_space,
+            new_file_priority,
+            max_candidates: None,
+        }
+    }
+
+    fn victims(victims: &[&str]) -> Vec<(PathBuf, usize)> {
+        victims.iter().map(|&victim| (PathBuf::from(victim), 100)).collect()
+    }
+
+    #[test]
+    fn files_outside_of_the_size_bounds_are_rejected() {
+        assert_eq!(check_size_bounds(100, 10, 1000), Ok(()));
+        assert_eq!(check_size_bounds(10, 10, 1000), Ok(()));
+        assert_eq!(check_size_bounds(1000, 10, 1000), Ok(()));
+        assert_eq!(check_size_bounds(9, 10, 1000), Err(CacheError::FileSizeOutOfBounds));
+        assert_eq!(check_size_bounds(1001, 10, 1000), Err(CacheError::FileSizeOutOfBounds));
+    }
+
+    #[test]
+    fn space_is_required_beyond_the_limit() {
+        assert_eq!(required_space(600, 300, 1000), 0);
+        assert_eq!(required_space(800, 300, 1000), 100);
+        assert_eq!(required_space(usize::MAX, 300, 1000), usize::MAX - 1000);
+        assert!(fits_without_eviction(600, 400, 1000, 1000));
+        assert!(!fits_without_eviction(600, 401, 1000, 1000));
+        // Files as large as the size limit never fit, even when the cache may overshoot it.
+        assert!(!fits_without_eviction(0, 1000, 1000, 1100));
+        assert!(fits_without_eviction(900, 150, 1000, 1100));
+    }
+
+    #[test]
+    fn space_is_counted_past_the_2_and_4_gib_boundaries() {
+        let gib_2: usize = 1 << 31;
+        let gib_4: usize = u32::MAX as usize;
+        // These sums overflow an isize or a usize on 32-bit platforms.
+        assert_eq!(required_space(gib_2 - 100, 100, gib_2), 0);
+        assert_eq!(required_space(gib_2 - 100, 101, gib_2), 1);
+        assert_eq!(required_space(gib_2, gib_2, gib_2), gib_2);
+        assert_eq!(required_space(gib_4 - 10, 100, gib_4), 90);
+        assert!(fits_without_eviction(gib_2 - 100, 100, gib_2 + 1, gib_2));
+        assert!(!fits_without_eviction(gib_2 - 100, 101, gib_2 + 1, gib_2));
+        assert!(!fits_without_eviction(gib_4 - 10, 100, gib_4, gib_4));
+        assert!(!fits_without_eviction(gib_4, gib_4 - 1, gib_4, gib_4));
+    }
+
+    #[test]
+    fn ties_are_ordered_by_the_tie_breaker() {
+        let mut candidates: Vec<CandidateFile> = vec![candidate("new", 100, 10, 2), candidate("high", 100, 20, 0), candidate("old", 100, 10, 1)];
+        order_for_eviction(&mut candidates, default_tie_breaker);
+        let order: Vec<&str> = candidates.iter().rev().map(|candidate| candidate.key.to_str().unwrap()).collect();
+        assert_eq!(order, vec!["old", "new", "high"]);
+    }
+
+    #[test]
+    fn the_lowest_priority_files_are_chosen_until_there_is_room() {
+        assert_eq!(select_victims(candidates(), &request(0, 0), |_, _| true), Ok(vec![]));
+        assert_eq!(select_victims(candidates(), &request(150, 30), |_, _| true), Ok(victims(&["c", "b"])));
+        // Freeing more than is required doesn't compare the extra files against the new file's priority.
+        let batch = RoomRequest { batch_space: 300, ..request(100, 10) };
+        assert_eq!(select_victims(candidates(), &batch, |_, _| true), Ok(victims(&["c", "b", "a"])));
+    }
+
+    #[test]
+    fn files_that_add_up_to_a_higher_priority_are_kept() {
+        assert_eq!(select_victims(candidates(), &request(100, 9), |_, _| true), Err(CacheError::NewPriorityIsNotHighEnough));
+        assert_eq!(select_victims(candidates(), &request(150, 29), |_, _| true), Err(CacheError::NewPriorityIsNotHighEnough));
+        // The priorities saturate instead of overflowing.
+        let highest: Vec<CandidateFile> = vec![candidate("x", 100, usize::MAX, 0), candidate("y", 100, usize::MAX, 0)];
+        assert_eq!(select_victims(highest, &request(200, usize::MAX), |_, _| true), Ok(victims(&["y", "x"])));
+    }
+
+    #[test]
+    fn room_that_cant_be_made_is_reported() {
+        assert_eq!(select_victims(candidates(), &request(301, 100), |_, _| true), Err(CacheError::NoMoreFilesToRemove));
+        assert_eq!(select_victims(vec![], &request(1, 100), |_, _| true), Err(CacheError::NoMoreFilesToRemove));
+
+        let limited = RoomRequest { max_candidates: Some(1), ..request(150, 100) };
+        assert_eq!(select_victims(candidates(), &limited, |_, _| true), Err(CacheError::TooManyEvictionCandidates));
+        // The limit also stops the batch.
+        let batch = RoomRequest { batch_space: 300, max_candidates: Some(2), ..request(100, 100) };
+        assert_eq!(select_victims(candidates(), &batch, |_, _| true), Ok(victims(&["c", "b"])));
+    }
+
+    #[test]
+    fn only_files_that_have_been_resident_long_enough_are_settled() {
+        let now: Instant = Instant::now();
+        let mut candidates: Vec<CandidateFile> = candidates();
+        candidates[0].inserted_at = Some(now - Duration::from_secs(10));
+        candidates[1].inserted_at = Some(now - Duration::from_secs(5));
+        candidates[2].inserted_at = Some(now);
+        let settled: Vec<CandidateFile> = settled(&candidates, now, Duration::from_secs(5));
+        assert_eq!(settled, candidates[..2].to_vec());
+        // Files without an insertion time are always settled.
+        assert_eq!(super::settled(&[candidate("a", 100, 0, 0)], now, Duration::from_secs(5)).len(), 1);
+    }
+
+    #[test]
+    fn files_that_may_not_be_evicted_are_skipped() {
+        let protect_c = |key: &Path, _: usize| key != Path::new("c");
+        assert_eq!(select_victims(candidates(), &request(100, 100), protect_c), Ok(victims(&["b"])));
+        assert_eq!(select_victims(candidates(), &request(300, 100), protect_c), Err(CacheError::NoMoreFilesToRemove));
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use cache::CacheError;
+
+
+/// The reason `Cache::prefetch_top()` didn't insert a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefetchSkipReason {
+    /// Inserting the file would have exceeded the byte budget of the prefetch.
+    OverBudget,
+    /// The cache didn't have enough free space for the file. Prefetching never evicts files.
+    CacheFull,
+    /// The file couldn't be inserted for the given reason.
+    Rejected(CacheError),
+}
+
+
+/// The files considered by `Cache::prefetch_top()`, and what happened to each of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefetchReport {
+    /// The files that were inserted, from the highest priority to the lowest.
+    pub inserted: Vec<PathBuf>,
+    /// The files that weren't inserted, and why.
+    pub skipped: Vec<(PathBuf, PrefetchSkipReason)>,
+    /// The number of bytes that were inserted.
+    pub inserted_bytes: usize,
+    /// Whether the prefetch was cancelled with the flag set by `PreloadOptions::cancel_flag()`,
+    /// so the files after the last one in the report weren't considered.
+    pub cancelled: bool,
+}
+
+
+/// How far a preload has come, as given to the callback set by `PreloadOptions::progress()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreloadProgress {
+    /// The number of files that were looked at so far.
+    pub scanned: usize,
+    /// The number of files that were read into the cache so far.
+    pub inserted: usize,
+    /// The number of files that were looked at, but weren't read into the cache.
+    pub skipped: usize,
+    /// The number of bytes the cache holds, as returned by `Cache::used_bytes()`.
+    pub bytes_used: usize,
+}
+
+
+/// The callback set by `PreloadOptions::progress()`.
+type ProgressCallback<'a> = Box<dyn Fn(PreloadProgress) + 'a>;
+
+
+/// Reports the progress of a preload, like `Cache::prefetch_top_with()`, and lets it be cancelled.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::{CacheBuilder, PreloadOptions};
+/// use std::sync::Arc;
+/// use std::sync::atomic::AtomicBool;
+///
+/// let cache = CacheBuilder::new().build().unwrap();
+/// // Set by the shutdown handler.
+/// let shutting_down = Arc::new(AtomicBool::new(false));
+/// let report = cache.prefetch_top_with(
+///     1024 * 1024 * 10,
+///     PreloadOptions::new()
+///         .progress(100, |progress| println!("Warmed up {} files", progress.inserted))
+///         .cancel_flag(shutting_down.clone()),
+/// );
+/// ```
+pub struct PreloadOptions<'a> {
+    progress: Option<(usize, ProgressCallback<'a>)>,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl<'a> Default for PreloadOptions<'a> {
+    fn default() -> PreloadOptions<'a> {
+        PreloadOptions::new()
+    }
+}
+
+impl<'a> PreloadOptions<'a> {
+    /// Creates options that neither report progress nor can be cancelled.
+    pub fn new() -> PreloadOptions<'a> {
+        PreloadOptions {
+            progress: None,
+            cancel: None,
+        }
+    }
+
+    /// Calls the callback each time `every` more files have been looked at, and once more when the preload stops.
+    pub fn progress<F: Fn(PreloadProgress) + 'a>(&mut self, every: usize, callback: F) -> &mut Self {
+        self.progress = Some((every.max(1), Box::new(callback)));
+        self
+    }
+
+    /// Stops the preload before it looks at the next file once the flag is set,
+    /// so a shutdown doesn't have to wait for the cache to warm up.
+    pub fn cancel_flag(&mut self, cancel: Arc<AtomicBool>) -> &mut Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+
+/// Counts the files a preload has looked at, to report its progress and check if it was cancelled.
+pub(crate) struct PreloadTracker<'o, 'a: 'o> {
+    options: &'o PreloadOptions<'a>,
+    steps: usize,
+    reported_steps: Option<usize>,
+}
+
+impl<'o, 'a> PreloadTracker<'o, 'a> {
+    pub(crate) fn new(options: &'o PreloadOptions<'a>) -> PreloadTracker<'o, 'a> {
+        PreloadTracker {
+            options,
+            steps: 0,
+            reported_steps: None,
+        }
+    }
+
+    /// Called before the preload looks at the next file, with the progress made so far.
+    /// Returns false if the preload was cancelled, and should stop.
+    pub(crate) fn next(&mut self, progress: PreloadProgress) -> bool {
+        if let Some((every, ref callback)) = self.options.progress {
+            if self.steps >= self.reported_steps.unwrap_or(0) + every {
+                callback(progress);
+                self.reported_steps = Some(self.steps);
+            }
+        }
+        if self.options.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Acquire)) {
+            return false;
+        }
+        self.steps += 1;
+        true
+    }
+
+    /// Reports the progress once more when the preload stops, unless it was just reported.
+    pub(crate) fn finish(&mut self, progress: PreloadProgress) {
+        if let Some((_, ref callback)) = self.options.progress {
+            if self.reported_steps != Some(self.steps) {
+                callback(progress);
+            }
+        }
+    }
+}
+
+
+/// How `Cache::set_sibling_prefetch()` reads the files next to a file that was inserted after a miss.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrefetchConfig {
+    /// The most siblings that are inserted for one miss.
+    pub max_siblings: usize,
+    /// The most bytes that are inserted for one miss.
+    pub byte_budget: usize,
+    /// The most misses whose siblings can wait to be prefetched. The siblings of later misses aren't prefetched.
+    pub queue_capacity: usize,
+}
+
+
+/// The files whose siblings wait to be prefetched, oldest first.
+#[derive(Debug)]
+pub(crate) struct SiblingPrefetch {
+    config: PrefetchConfig,
+    pending: Mutex<VecDeque<PathBuf>>,
+}
+
+impl SiblingPrefetch {
+    pub(crate) fn new(config: PrefetchConfig) -> SiblingPrefetch {
+        SiblingPrefetch {
+            config,
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub(crate) fn config(&self) -> PrefetchConfig {
+        self.config
+    }
+
+    /// Queues the siblings of the file to be prefetched, unless the queue is full or they are queued already.
+    pub(crate) fn push(&self, path: &Path) {
+        let mut pending = self.lock();
+        if pending.len() >= self.config.queue_capacity || pending.iter().any(|queued| queued == path) {
+            return;
+        }
+        pending.push_back(path.to_path_buf());
+    }
+
+    /// Takes the file that has waited longest for its siblings to be prefetched.
+    pub(crate) fn pop(&self) -> Option<PathBuf> {
+        self.lock().pop_front()
+    }
+
+    /// Estimates the bytes held by the paths in the queue.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        self.lock()
+            .iter()
+            .map(|path| mem::size_of::<PathBuf>() + path.as_os_str().len())
+            .sum::<usize>()
+            + mem::size_of::<VecDeque<PathBuf>>()
+    }
+
+    fn lock(&self) -> MutexGuard<VecDeque<PathBuf>> {
+        match self.pending.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+
+/// The most entries of a directory that are looked at to find the siblings of a file,
+/// so prefetching the siblings of a file in a large directory doesn't hold up the request that prefetches them.
+const MAX_SCANNED_ENTRIES: usize = 256;
+
+
+/// Lists the other files in the directory of the file, sorted by name so prefetching is repeatable.
+/// Only the first `MAX_SCANNED_ENTRIES` entries of the directory are looked at.
+/// Directories and anything that can't be read are left out.
+pub(crate) fn sibling_files(path: &Path) -> Vec<PathBuf> {
+    let directory: &Path = path.parent().unwrap_or(Path::new(""));
+    // A key without a directory is relative to the working directory, and so are its siblings.
+    let entries = match fs::read_dir(if directory.as_os_str().is_empty() { Path::new(".") } else { directory }) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut siblings: Vec<PathBuf> = entries
+        .take(MAX_SCANNED_ENTRIES)
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|file_type| file_type.is_file()).unwrap_or(false))
+        .map(|entry| directory.join(entry.file_name()))
+        .filter(|sibling| sibling != path)
+        .collect();
+    siblings.sort();
+    siblings
+}
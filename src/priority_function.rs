@@ -1,4 +1,31 @@
 use std::usize;
+use std::cmp::Ordering;
+use std::path::Path;
+use in_memory_file::FileStats;
+
+
+/// The highest priority a file can have.
+///
+/// The cache clamps the priorities returned by its priority function to this value,
+/// so the priorities of many files can be added together without getting close to overflowing.
+pub const MAX_PRIORITY: usize = u32::MAX as usize;
+
+
+/// How a priority function behaved over a grid of access counts and sizes, as reported by `Cache::validate_priority_function()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriorityFunctionReport {
+    /// The number of access counts and sizes the function was run with.
+    pub samples: usize,
+    /// Whether the function gave every sample the same priority, after clamping.
+    /// The cache then evicts files in the order of its tie breaker alone.
+    pub constant: bool,
+    /// The access counts and sizes at which one more access lowered the priority of a file,
+    /// so the more popular file would be evicted first.
+    pub decreasing_with_accesses: Vec<(usize, usize)>,
+    /// The number of samples whose priority was above `MAX_PRIORITY`, and was clamped to it.
+    /// Clamped files can no longer be told apart by their priority.
+    pub clamped: usize,
+}
 
 /// The default priority function used for determining if a file should be in the cache.
 ///
@@ -30,7 +57,7 @@ pub fn access_priority_function(access_count: usize, _: usize) -> usize {
 /// The smaller the file, the higher priority it will have.
 /// Does not take into account the number of accesses the file has.
 pub fn small_files_priority_function(_: usize, size: usize) -> usize {
-    usize::checked_div(usize::MAX, size).unwrap_or(0) // don't give any priority to completely empty files.
+    usize::checked_div(MAX_PRIORITY, size).unwrap_or(0) // don't give any priority to completely empty files.
 }
 
 /// Favor small files with respect to the number of times file was accessed.
@@ -39,10 +66,24 @@ pub fn small_files_priority_function(_: usize, size: usize) -> usize {
 /// Does take into account the number of accesses the file has.
 pub fn small_files_access_priority_function(access_count: usize, size: usize) -> usize {
     match usize::checked_mul(
-        usize::checked_div(usize::MAX, size).unwrap_or(0),
+        usize::checked_div(MAX_PRIORITY, size).unwrap_or(0),
         access_count,
     ) {
         Some(v) => v,
-        None => usize::MAX, // If the multiplication overflows, then the file will have the maximum priority.
+        None => MAX_PRIORITY, // If the multiplication overflows, then the file will have the maximum priority.
     }
 }
+
+
+/// The default tie breaker used to order files with the same priority for eviction.
+///
+/// The file that was accessed longest ago is evicted first.
+/// If both were last accessed at the same time, the larger file is evicted first, as it frees more space.
+/// If they are the same size too, the file whose path sorts first is evicted first.
+///
+/// Returns `Ordering::Less` if the first file should be evicted before the second one.
+pub fn default_tie_breaker(path: &Path, stats: &FileStats, other_path: &Path, other_stats: &FileStats) -> Ordering {
+    stats.last_access.cmp(&other_stats.last_access)
+        .then_with(|| other_stats.size.cmp(&stats.size))
+        .then_with(|| path.cmp(other_path))
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// Limits how many files can be read into memory at the same time.
+///
+/// Permits are never waited on. If none are available, the caller is expected to serve the file
+/// some other way, instead of queuing up another in-memory read.
+#[derive(Debug)]
+pub(crate) struct ReadLimiter {
+    max_reads: Option<usize>,
+    /// Shared with the permits, so a permit can outlive the borrow of the limiter on the thread doing the read.
+    active_reads: Arc<AtomicUsize>,
+    /// The most reads that have been in progress at once.
+    peak_reads: AtomicUsize,
+}
+
+impl ReadLimiter {
+    pub(crate) fn new(max_reads: Option<usize>) -> ReadLimiter {
+        ReadLimiter {
+            max_reads,
+            active_reads: Arc::new(AtomicUsize::new(0)),
+            peak_reads: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes a permit to read a file, or returns None if the maximum number of reads are already in progress.
+    pub(crate) fn try_acquire(&self) -> Option<ReadPermit> {
+        let mut active_reads: usize = self.active_reads.load(Ordering::Acquire);
+        loop {
+            if self.max_reads.is_some_and(|max_reads| active_reads >= max_reads) {
+                return None;
+            }
+            match self.active_reads.compare_exchange_weak(active_reads, active_reads + 1, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(actual) => active_reads = actual,
+            }
+        }
+
+        let mut peak_reads: usize = self.peak_reads.load(Ordering::Acquire);
+        while active_reads + 1 > peak_reads {
+            match self.peak_reads.compare_exchange_weak(peak_reads, active_reads + 1, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(actual) => peak_reads = actual,
+            }
+        }
+        Some(ReadPermit { active_reads: self.active_reads.clone() })
+    }
+
+    /// The most reads that have been in progress at once.
+    #[cfg(test)]
+    pub(crate) fn peak_reads(&self) -> usize {
+        self.peak_reads.load(Ordering::Acquire)
+    }
+}
+
+
+/// Allows one file to be read into memory. The permit is returned when this is dropped.
+///
+/// A read that outlives its deadline keeps its permit until it really ends, so hung reads still count against the limit.
+pub(crate) struct ReadPermit {
+    active_reads: Arc<AtomicUsize>,
+}
+
+impl Drop for ReadPermit {
+    fn drop(&mut self) {
+        self.active_reads.fetch_sub(1, Ordering::AcqRel);
+    }
+}
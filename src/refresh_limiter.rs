@@ -0,0 +1,92 @@
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+
+/// The rate at which refreshes triggered by serving requests are allowed, as a token bucket.
+///
+/// A full bucket holds `burst` tokens, and every refresh takes one.
+/// The bucket refills at `per_second` tokens per second, up to `burst`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// The number of tokens the bucket refills by every second.
+    pub per_second: u32,
+    /// The number of tokens a full bucket holds, which is the number of refreshes allowed at once.
+    pub burst: u32,
+}
+
+
+/// Limits how often files are refreshed while serving requests for them.
+#[derive(Debug)]
+pub(crate) struct RefreshLimiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    /// When the bucket was last refilled, or None if no token was taken yet, so the bucket is full.
+    refilled_at: Option<Instant>,
+}
+
+impl RefreshLimiter {
+    pub(crate) fn new(limit: RateLimit) -> RefreshLimiter {
+        RefreshLimiter {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.burst as f64,
+                refilled_at: None,
+            }),
+        }
+    }
+
+    /// Takes a token for a refresh, or returns false if the bucket is empty, so the refresh should be deferred.
+    pub(crate) fn try_take(&self, now: Instant) -> bool {
+        let mut bucket = self.lock();
+        match bucket.refilled_at {
+            Some(refilled_at) if now > refilled_at => {
+                let elapsed: Duration = now - refilled_at;
+                let seconds: f64 = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+                bucket.tokens = (bucket.tokens + seconds * self.limit.per_second as f64).min(self.limit.burst as f64);
+                bucket.refilled_at = Some(now);
+            }
+            // Threads can read the clock out of order, so an earlier time refills nothing.
+            Some(_) => {}
+            None => bucket.refilled_at = Some(now),
+        }
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn lock(&self) -> MutexGuard<Bucket> {
+        match self.bucket.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_refill_up_to_the_burst() {
+        let now: Instant = Instant::now();
+        let limiter = RefreshLimiter::new(RateLimit { per_second: 2, burst: 3 });
+        assert!((0..3).all(|_| limiter.try_take(now)));
+        assert!(!limiter.try_take(now));
+
+        // Half a second refills one token.
+        assert!(limiter.try_take(now + Duration::from_millis(500)));
+        assert!(!limiter.try_take(now + Duration::from_millis(500)));
+
+        // An idle bucket never holds more than the burst.
+        let later: Instant = now + Duration::from_secs(60);
+        assert_eq!((0..10).filter(|_| limiter.try_take(later)).count(), 3);
+    }
+}
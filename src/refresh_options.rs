@@ -0,0 +1,85 @@
+
+/// Controls what `Cache::refresh_with()` keeps from the stats of the file it replaces.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::{CacheBuilder, RefreshOptions};
+///
+/// let cache = CacheBuilder::new().build().unwrap();
+/// // The file was replaced by something unrelated, so its popularity shouldn't carry over.
+/// cache.refresh_source_with("www/index.html", RefreshOptions::new().reset_access_count(true));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RefreshOptions {
+    pub(crate) reset_access_count: bool,
+    pub(crate) recompute_priority: bool,
+    pub(crate) keep_when_too_large: bool,
+}
+
+impl Default for RefreshOptions {
+    fn default() -> RefreshOptions {
+        RefreshOptions::new()
+    }
+}
+
+impl RefreshOptions {
+    /// Creates options that keep the access count of the file and recalculate its priority for its new size,
+    /// which is what `Cache::refresh()` does.
+    pub fn new() -> RefreshOptions {
+        RefreshOptions {
+            reset_access_count: false,
+            recompute_priority: true,
+            keep_when_too_large: false,
+        }
+    }
+
+    /// Sets whether the access count of the file is reset to 0 when it is refreshed.
+    pub fn reset_access_count(&mut self, reset_access_count: bool) -> &mut Self {
+        self.reset_access_count = reset_access_count;
+        self
+    }
+
+    /// Sets whether the priority of the file is recalculated when it is refreshed.
+    /// If it isn't, the file keeps its old priority until it is next recalculated.
+    pub fn recompute_priority(&mut self, recompute_priority: bool) -> &mut Self {
+        self.recompute_priority = recompute_priority;
+        self
+    }
+
+    /// Sets whether the cached copy of a file is kept when the file grew too large to stay in the cache,
+    /// because it is larger than the maximum file size, or lower priority files can't be evicted to make room for it.
+    /// By default, the file is removed from the cache, so it is served from the filesystem instead of as a stale copy.
+    pub fn keep_when_too_large(&mut self, keep_when_too_large: bool) -> &mut Self {
+        self.keep_when_too_large = keep_when_too_large;
+        self
+    }
+}
+
+
+/// What `Cache::try_refresh()` did with a file.
+///
+/// The cached copy of the file is only replaced once its new bytes have been read.
+/// If the file can't be refreshed, the cached copy and its stats are left as they were,
+/// unless the file no longer exists and `Cache::set_on_missing()` removes such files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RefreshOutcome {
+    /// The file was read again, and replaced the cached copy.
+    Refreshed,
+    /// The file isn't in the cache, so there was nothing to refresh.
+    NotCached,
+    /// The file is embedded in the binary, or the cache is frozen, so the cached copy is kept as it is.
+    NotRefreshable,
+    /// The file grew too large to stay in the cache, so it was removed from it.
+    /// In content addressed mode, this is also the outcome for a file whose new content has the hash of other content.
+    Evicted,
+    /// The file grew too large to stay in the cache, and the cached copy was kept,
+    /// as `RefreshOptions::keep_when_too_large()` says.
+    TooLarge,
+    /// The file no longer exists in the filesystem.
+    FileMissing,
+    /// The path is no longer a file in the filesystem, like a file that was replaced by a directory.
+    NotAFile,
+    /// The file couldn't be read again, like a file that was deleted after it was checked.
+    OpenFailed,
+}
@@ -0,0 +1,254 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use cache::{Cache, EvictedEntry};
+use cache_builder::{CacheBuilder, CacheBuildError};
+use observer::CacheObserver;
+use serve_class::ServeClass;
+
+
+/// An operation on the cache, as recorded in a `Trace`.
+///
+/// Each operation has the time it happened at, to the microsecond, counted from when the trace started recording.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceOp {
+    /// A request for the file, with the size it was served with, or None if it wasn't found.
+    Get { path: PathBuf, size: Option<usize>, time: Duration },
+    /// A removal of the file from the cache.
+    Remove { path: PathBuf, time: Duration },
+    /// A refresh of the file, with the size it was refreshed to, or None if it no longer exists.
+    Refresh { path: PathBuf, size: Option<usize>, time: Duration },
+}
+
+
+/// The operations a cache handled while it was recording, in the order it handled them,
+/// as returned by `Cache::trace()`.
+///
+/// A trace can be saved, and replayed against a cache with a different configuration,
+/// to see which files it would have kept without needing the files themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub ops: Vec<TraceOp>,
+}
+
+impl Trace {
+    /// Writes the trace as text, one operation per line.
+    ///
+    /// Fails with `InvalidInput` if a path isn't valid unicode, or contains a line break,
+    /// as it couldn't be read back.
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for op in &self.ops {
+            let (kind, path, size, time): (&str, &Path, Option<usize>, Duration) = match *op {
+                TraceOp::Get { ref path, size, time } => ("get", path, size, time),
+                TraceOp::Remove { ref path, time } => ("remove", path, None, time),
+                TraceOp::Refresh { ref path, size, time } => ("refresh", path, size, time),
+            };
+            let path: &str = match path.to_str() {
+                Some(path) if !path.contains('\n') && !path.contains('\r') => path,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} can't be saved in a trace", path))),
+            };
+            let size: String = size.map_or(String::from("-"), |size| size.to_string());
+            writeln!(writer, "{} {} {} {}", kind, time.as_micros(), size, path)?;
+        }
+        writer.flush()
+    }
+
+    /// Reads a trace written by `save()`.
+    ///
+    /// Fails with `InvalidData` if a line isn't an operation.
+    pub fn load<R: Read>(reader: R) -> io::Result<Trace> {
+        let mut ops: Vec<TraceOp> = vec![];
+        for line in BufReader::new(reader).lines() {
+            let line: String = line?;
+            if line.is_empty() {
+                continue;
+            }
+            match parse_op(&line) {
+                Some(op) => ops.push(op),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} isn't a trace operation", line))),
+            }
+        }
+        Ok(Trace { ops })
+    }
+
+    /// Runs the operations of the trace against a new cache built with the config, and reports what it did.
+    ///
+    /// The files are replaced by zeroed bytes of their recorded sizes, so they don't have to exist,
+    /// but the cache holds up to its size limit of those bytes while the trace is replayed.
+    /// Only the configuration of the builder is used: anything set on the recorded cache after it was built,
+    /// like aliases or a cold tier, isn't part of the replay.
+    /// Time based features, like thrash protection, see the time of the replay instead of the recorded times.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::{CacheBuilder, Trace, TraceOp};
+    /// use std::path::PathBuf;
+    /// use std::time::Duration;
+    ///
+    /// let trace = Trace {
+    ///     ops: vec![
+    ///         TraceOp::Get { path: PathBuf::from("www/index.html"), size: Some(100), time: Duration::from_secs(0) },
+    ///         TraceOp::Get { path: PathBuf::from("www/index.html"), size: Some(100), time: Duration::from_secs(1) },
+    ///     ],
+    /// };
+    /// let report = trace.replay_against(CacheBuilder::new().size_limit(1024)).unwrap();
+    /// assert_eq!(report.resident, vec![PathBuf::from("www/index.html")]);
+    /// assert_eq!(report.hit_rate(), 0.5);
+    /// ```
+    pub fn replay_against(&self, config: &CacheBuilder) -> Result<ReplayReport, CacheBuildError> {
+        let mut cache: Cache = config.build()?;
+        let evictions = EvictionRecorder::new();
+        cache.set_observer(Some(Box::new(evictions.clone())));
+
+        let mut requests: usize = 0;
+        let mut hits: usize = 0;
+        for op in &self.ops {
+            match *op {
+                TraceOp::Get { ref path, size, .. } => {
+                    requests += 1;
+                    if cache.replay_get(path, size) == ServeClass::Hit {
+                        hits += 1;
+                    }
+                }
+                TraceOp::Remove { ref path, .. } => {
+                    cache.remove(path);
+                }
+                TraceOp::Refresh { ref path, size, .. } => cache.replay_refresh(path, size),
+            }
+        }
+
+        let mut resident: Vec<PathBuf> = cache.file_map.iter().map(|(path, _)| path.clone()).collect();
+        resident.sort();
+        Ok(ReplayReport {
+            resident,
+            used_bytes: cache.used_bytes(),
+            requests,
+            hits,
+            evictions: evictions.evictions(),
+        })
+    }
+}
+
+
+/// What a cache did when a `Trace` was replayed against it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayReport {
+    /// The files in the cache after the last operation, in order.
+    pub resident: Vec<PathBuf>,
+    /// The number of bytes held by the files in the cache after the last operation.
+    pub used_bytes: usize,
+    /// The number of requests for files in the trace.
+    pub requests: usize,
+    /// The number of requests that were served from the cache, not counting the ones that inserted the file.
+    pub hits: usize,
+    /// The files evicted to make room for other files, in the order they were evicted.
+    pub evictions: Vec<PathBuf>,
+}
+
+impl ReplayReport {
+    /// The fraction of requests that were hits, or 0 if there were no requests.
+    pub fn hit_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.requests as f64
+    }
+}
+
+
+/// Records the operations of a cache while it is recording a trace.
+#[derive(Debug)]
+pub(crate) struct TraceRecorder {
+    start: Instant,
+    ops: Mutex<Vec<TraceOp>>,
+}
+
+impl TraceRecorder {
+    pub(crate) fn new(start: Instant) -> TraceRecorder {
+        TraceRecorder {
+            start,
+            ops: Mutex::new(vec![]),
+        }
+    }
+
+    pub(crate) fn record_get(&self, path: &Path, size: Option<usize>, now: Instant) {
+        let time: Duration = self.elapsed(now);
+        lock(&self.ops).push(TraceOp::Get { path: path.to_path_buf(), size, time });
+    }
+
+    pub(crate) fn record_remove(&self, path: &Path, now: Instant) {
+        let time: Duration = self.elapsed(now);
+        lock(&self.ops).push(TraceOp::Remove { path: path.to_path_buf(), time });
+    }
+
+    pub(crate) fn record_refresh(&self, path: &Path, size: Option<usize>, now: Instant) {
+        let time: Duration = self.elapsed(now);
+        lock(&self.ops).push(TraceOp::Refresh { path: path.to_path_buf(), size, time });
+    }
+
+    pub(crate) fn trace(&self) -> Trace {
+        Trace { ops: lock(&self.ops).clone() }
+    }
+
+    /// The time since the recording started, to the microsecond, which is what a saved trace keeps.
+    fn elapsed(&self, now: Instant) -> Duration {
+        let elapsed: Duration = now.checked_duration_since(self.start).unwrap_or_default();
+        Duration::from_micros(elapsed.as_micros() as u64)
+    }
+}
+
+
+/// Collects the paths of the files evicted from the cache a trace is replayed or simulated against.
+/// Clones share the evictions, so one can be given to the cache as its observer while another one reads them.
+#[derive(Clone)]
+pub(crate) struct EvictionRecorder {
+    evictions: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl EvictionRecorder {
+    pub(crate) fn new() -> EvictionRecorder {
+        EvictionRecorder {
+            evictions: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// The evicted files, in the order they were evicted.
+    pub(crate) fn evictions(&self) -> Vec<PathBuf> {
+        lock(&self.evictions).clone()
+    }
+}
+
+impl CacheObserver for EvictionRecorder {
+    fn on_evict(&self, evicted_entry: &EvictedEntry) {
+        lock(&self.evictions).push(evicted_entry.path.clone());
+    }
+}
+
+
+/// Parses a line written by `Trace::save()`.
+fn parse_op(line: &str) -> Option<TraceOp> {
+    let mut fields = line.splitn(4, ' ');
+    let kind: &str = fields.next()?;
+    let time: Duration = Duration::from_micros(fields.next()?.parse().ok()?);
+    let size: Option<usize> = match fields.next()? {
+        "-" => None,
+        size => Some(size.parse().ok()?),
+    };
+    let path: PathBuf = PathBuf::from(fields.next()?);
+    match kind {
+        "get" => Some(TraceOp::Get { path, size, time }),
+        "remove" => Some(TraceOp::Remove { path, time }),
+        "refresh" => Some(TraceOp::Refresh { path, size, time }),
+        _ => None,
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
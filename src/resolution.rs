@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+
+use named_in_memory_file::NamedInMemoryFile;
+use serve_class::ServeClass;
+
+
+/// Where `Cache::resolve()` decided a file should be served from, without opening it.
+#[derive(Debug)]
+pub enum Resolution<'a> {
+    /// The file is in the cache. Like any cached file, it stays locked until it is dropped.
+    Cached(NamedInMemoryFile<'a>),
+    /// The file should be served from the filesystem, for the given reason.
+    /// The cache didn't open it, so the caller opens the file at the path however it likes.
+    PassThrough {
+        /// The path of the file in the filesystem.
+        path: PathBuf,
+        /// Why the file isn't served from the cache.
+        reason: ServeClass,
+    },
+    /// The file can't be served, because its path was rejected, it doesn't exist, or the cache is degraded.
+    NotFound,
+}
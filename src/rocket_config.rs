@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use rocket::Config;
+use rocket::config::{Table, Value};
+
+use cache_builder::CacheBuilder;
+use size::{parse_size, ParseSizeError};
+
+
+/// The extra in the Rocket config whose tables are the named configurations of caches.
+pub(crate) const CACHE_TABLE: &str = "cache";
+
+
+/// Why a cache couldn't be configured with `CacheBuilder::from_rocket_config()`.
+///
+/// Keys are given as their path in the extras of the Rocket config, like `cache.images.size_limit`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// There is no table of settings for the cache.
+    MissingTable(String),
+    /// The table has a key that isn't a setting of the cache, like a misspelled one.
+    UnknownKey(String),
+    /// The value of the key has the wrong type, like a table where a size was expected.
+    BadType {
+        key: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The value of the key is a string that isn't a size.
+    BadSize {
+        key: String,
+        value: String,
+        error: ParseSizeError,
+    },
+    /// The value of the key is a number that the setting can't have, like a negative size.
+    OutOfRange {
+        key: String,
+        value: i64,
+    },
+}
+
+
+/// Configures a cache builder with the settings in the named table of the `cache` extra.
+pub(crate) fn builder_from_config(config: &Config, table_name: &str) -> Result<CacheBuilder, ConfigError> {
+    let path: String = format!("{}.{}", CACHE_TABLE, table_name);
+    let value: &Value = config
+        .get_table(CACHE_TABLE)
+        .ok()
+        .and_then(|caches| caches.get(table_name))
+        .ok_or_else(|| ConfigError::MissingTable(path.clone()))?;
+    let table: &Table = value.as_table().ok_or_else(|| ConfigError::BadType {
+        key: path.clone(),
+        expected: "a table",
+        found: value.type_str(),
+    })?;
+
+    let mut builder: CacheBuilder = CacheBuilder::new();
+    for (name, value) in table {
+        let key: String = format!("{}.{}", path, name);
+        match name.as_str() {
+            "size_limit" => {
+                builder.size_limit(size_setting(key, value)?);
+            }
+            "min_file_size" => {
+                builder.min_file_size(size_setting(key, value)?);
+            }
+            "max_file_size" => {
+                builder.max_file_size(size_setting(key, value)?);
+            }
+            "concurrency" => {
+                builder.concurrency(integer_setting(key, value, 1, u16::MAX as u64)? as u16);
+            }
+            "accesses_per_refresh" => {
+                builder.accesses_per_refresh(integer_setting(key, value, 1, usize::MAX as u64)? as usize);
+            }
+            "coalesce_timeout_ms" => {
+                builder.coalesce_timeout(Duration::from_millis(integer_setting(key, value, 0, u64::MAX)?));
+            }
+            _ => return Err(ConfigError::UnknownKey(key)),
+        }
+    }
+    Ok(builder)
+}
+
+/// Reads a number of bytes, given as a size like `"10MiB"` or as a plain number.
+fn size_setting(key: String, value: &Value) -> Result<usize, ConfigError> {
+    if let Some(size) = value.as_str() {
+        return match parse_size(size) {
+            Ok(bytes) if bytes <= usize::MAX as u64 => Ok(bytes as usize),
+            Ok(_) => Err(ConfigError::BadSize {
+                key,
+                value: size.to_string(),
+                error: ParseSizeError::TooLarge,
+            }),
+            Err(error) => Err(ConfigError::BadSize {
+                key,
+                value: size.to_string(),
+                error,
+            }),
+        };
+    }
+    match value.as_integer() {
+        Some(_) => integer_setting(key, value, 0, usize::MAX as u64).map(|bytes| bytes as usize),
+        None => Err(ConfigError::BadType {
+            key,
+            expected: "a size",
+            found: value.type_str(),
+        }),
+    }
+}
+
+/// Reads a whole number between the bounds.
+fn integer_setting(key: String, value: &Value, min: u64, max: u64) -> Result<u64, ConfigError> {
+    let number: i64 = value.as_integer().ok_or_else(|| ConfigError::BadType {
+        key: key.clone(),
+        expected: "an integer",
+        found: value.type_str(),
+    })?;
+    if number < 0 || (number as u64) < min || (number as u64) > max {
+        return Err(ConfigError::OutOfRange { key, value: number });
+    }
+    Ok(number as u64)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::config::Environment;
+    use cache::Cache;
+
+    fn config(images: Table) -> Config {
+        let mut caches: Table = Table::new();
+        caches.insert(String::from("images"), Value::Table(images));
+        caches.insert(String::from("scripts"), Value::from("64MiB"));
+        Config::build(Environment::Development)
+            .extra(CACHE_TABLE, caches)
+            .finalize()
+            .unwrap()
+    }
+
+    fn table(settings: Vec<(&str, Value)>) -> Table {
+        settings.into_iter().map(|(name, value)| (name.to_string(), value)).collect()
+    }
+
+    #[test]
+    fn caches_are_built_from_their_table() {
+        let config: Config = config(table(vec![
+            ("size_limit", Value::from("100MiB")),
+            ("min_file_size", Value::from("4 KB")),
+            ("max_file_size", Value::from(6 * 1024 * 1024i64)),
+            ("concurrency", Value::from(4i64)),
+            ("coalesce_timeout_ms", Value::from(250i64)),
+        ]));
+        let cache: Cache = CacheBuilder::from_rocket_config(&config, "images").unwrap().build().unwrap();
+        assert_eq!(cache.size_limit, 100 * 1024 * 1024);
+        assert_eq!(cache.min_file_size, 4000);
+        assert_eq!(cache.max_file_size, 6 * 1024 * 1024);
+        assert_eq!(cache.coalesce_timeout, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn malformed_settings_are_errors() {
+        let error = |settings: Vec<(&str, Value)>| CacheBuilder::from_rocket_config(&config(table(settings)), "images").unwrap_err();
+
+        assert_eq!(
+            error(vec![("size_limit", Value::from("lots"))]),
+            ConfigError::BadSize {
+                key: String::from("cache.images.size_limit"),
+                value: String::from("lots"),
+                error: ParseSizeError::InvalidNumber(String::from("lots")),
+            }
+        );
+        assert_eq!(
+            error(vec![("max_file_size", Value::from(true))]),
+            ConfigError::BadType {
+                key: String::from("cache.images.max_file_size"),
+                expected: "a size",
+                found: "boolean",
+            }
+        );
+        assert_eq!(
+            error(vec![("min_file_size", Value::from(-1i64))]),
+            ConfigError::OutOfRange { key: String::from("cache.images.min_file_size"), value: -1 }
+        );
+        assert_eq!(
+            error(vec![("accesses_per_refresh", Value::from(0i64))]),
+            ConfigError::OutOfRange { key: String::from("cache.images.accesses_per_refresh"), value: 0 }
+        );
+        assert_eq!(
+            error(vec![("size_limt", Value::from("1MB"))]),
+            ConfigError::UnknownKey(String::from("cache.images.size_limt"))
+        );
+    }
+
+    #[test]
+    fn caches_must_have_a_table() {
+        let config: Config = config(Table::new());
+        assert_eq!(
+            CacheBuilder::from_rocket_config(&config, "videos").unwrap_err(),
+            ConfigError::MissingTable(String::from("cache.videos"))
+        );
+        assert_eq!(
+            CacheBuilder::from_rocket_config(&config, "scripts").unwrap_err(),
+            ConfigError::BadType {
+                key: String::from("cache.scripts"),
+                expected: "a table",
+                found: "string",
+            }
+        );
+        assert_eq!(
+            CacheBuilder::from_rocket_config(&Config::development(), "images").unwrap_err(),
+            ConfigError::MissingTable(String::from("cache.images"))
+        );
+    }
+}
@@ -0,0 +1,42 @@
+
+/// Where a file returned by `Cache::get_classified()` was served from, and if it wasn't served from the cache, why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ServeClass {
+    /// The file was already in the cache.
+    Hit,
+    /// The file wasn't in the cache, and was inserted into it.
+    Inserted,
+    /// The file was served from the filesystem because it is larger than the `max_file_size`,
+    /// or than the room the cache could make for it.
+    FsTooLarge,
+    /// The file was served from the filesystem because it is smaller than the `min_file_size`.
+    FsTooSmall,
+    /// The file was served from the filesystem because the files it would have replaced have a higher priority.
+    FsPriorityTooLow,
+    /// The file was served from the filesystem because making room for it would have evicted
+    /// more files than the request allowed.
+    FsEvictionBudget,
+    /// The file was served from the filesystem because it kept being evicted and inserted again,
+    /// and thrash protection is keeping it out of the cache for a while.
+    FsThrashing,
+    /// The file was served from the filesystem because it was removed with `Cache::remove_and_block()`,
+    /// and is kept out of the cache until the block ends.
+    FsBlocked,
+    /// The file was served from the filesystem because the cache has a size limit of 0,
+    /// so it passes every request through without storing anything.
+    FsDisabled,
+    /// The file was served from the filesystem because its path was added to the bypass list with `Cache::always_bypass()`.
+    FsBypassed,
+    /// The file was served from the filesystem because it is larger than the request allowed to be inserted,
+    /// see `GetOptions::skip_insert_above()`.
+    FsSkipped,
+    /// The file was served from the filesystem because a limit of the cache rejected it,
+    /// like a directory quota, the eviction filter, or the memory ceiling.
+    FsRejected,
+    /// The file was served from the filesystem because the cache is frozen or in manual mode,
+    /// so it doesn't insert files on its own.
+    FsManual,
+    /// The file was served from the filesystem for any other reason,
+    /// like the file failing to be read, or another request not reading it into the cache in time.
+    FsError,
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use file_source::FileSource;
+use bookkeeping::path_entry_bytes;
+
+
+/// What the cache knows about one path, as returned by `Cache::path_metrics()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathMetrics {
+    /// The number of times the file was requested.
+    pub access_count: usize,
+    /// The number of bytes offered from memory for the path, since the served bytes were last reset.
+    pub bytes_served_from_cache: usize,
+    /// The number of bytes offered from the filesystem for the path, since the served bytes were last reset.
+    pub bytes_served_from_fs: usize,
+}
+
+
+/// Counts the bytes offered in responses, by where they were served from.
+///
+/// The size of a file is counted when the cache decides where to serve it from.
+/// The cache never learns how much of it is actually written to the client,
+/// so these are the bytes offered, not the bytes that went over the socket.
+///
+/// The totals are atomics, so counting them never locks. The bytes of each path are only counted once enabled,
+/// for up to a maximum number of paths.
+#[derive(Debug)]
+pub(crate) struct ServedBytes {
+    from_cache: AtomicUsize,
+    from_fs: AtomicUsize,
+    /// The most paths whose bytes are counted, or None if bytes aren't counted for each path.
+    max_paths: Option<usize>,
+    /// The bytes offered from the cache and from the filesystem for each path.
+    paths: Mutex<HashMap<PathBuf, (usize, usize)>>,
+}
+
+impl ServedBytes {
+    pub(crate) fn new() -> ServedBytes {
+        ServedBytes {
+            from_cache: AtomicUsize::new(0),
+            from_fs: AtomicUsize::new(0),
+            max_paths: None,
+            paths: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the most paths whose bytes are counted, forgetting the bytes counted for every path so far.
+    pub(crate) fn set_max_paths(&mut self, max_paths: Option<usize>) {
+        self.max_paths = max_paths;
+        self.lock().clear();
+    }
+
+    /// Counts the size of the file that is about to be served for the path.
+    pub(crate) fn record(&self, path: &Path, file: &FileSource) {
+        let (from_cache, from_fs): (usize, usize) = match *file {
+            FileSource::Cached(ref cached_file) => (cached_file.file.get().stats.size, 0),
+            FileSource::Detached(ref detached_file) => (detached_file.bytes().len(), 0),
+            FileSource::Fs(_, _, ref metadata) => (0, metadata.len() as usize),
+            FileSource::NotFound | FileSource::FileMissing | FileSource::Deferred(_) => return,
+        };
+        self.from_cache.fetch_add(from_cache, Ordering::Relaxed);
+        self.from_fs.fetch_add(from_fs, Ordering::Relaxed);
+
+        let max_paths: usize = match self.max_paths {
+            Some(max_paths) => max_paths,
+            None => return,
+        };
+        let mut paths = self.lock();
+        // Paths that were served before are counted in place, so serving a file doesn't copy its path.
+        if let Some(served) = paths.get_mut(path) {
+            served.0 = served.0.saturating_add(from_cache);
+            served.1 = served.1.saturating_add(from_fs);
+            return;
+        }
+        if paths.len() < max_paths {
+            paths.insert(path.to_path_buf(), (from_cache, from_fs));
+        }
+    }
+
+    pub(crate) fn served_from_cache(&self) -> usize {
+        self.from_cache.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn served_from_fs(&self) -> usize {
+        self.from_fs.load(Ordering::Relaxed)
+    }
+
+    /// The bytes offered from the cache and from the filesystem for the path.
+    pub(crate) fn path(&self, path: &Path) -> (usize, usize) {
+        self.lock().get(path).cloned().unwrap_or((0, 0))
+    }
+
+    /// Estimates the bytes held by the counts of every path.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        self.lock().keys().map(|path| path_entry_bytes::<(usize, usize)>(path)).sum()
+    }
+
+    pub(crate) fn reset(&self) {
+        self.lock().clear();
+        self.from_cache.store(0, Ordering::Relaxed);
+        self.from_fs.store(0, Ordering::Relaxed);
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<PathBuf, (usize, usize)>> {
+        match self.paths.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::cmp;
+use std::path::PathBuf;
+
+use cache::Cache;
+use cache_builder::{CacheBuilder, CacheBuildError};
+use replay::EvictionRecorder;
+use serve_class::ServeClass;
+
+
+/// What a cache did with the requests of a workload, as returned by `simulate()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationResult {
+    /// The number of requests in the workload.
+    pub requests: usize,
+    /// The number of requests for files that were already in the cache.
+    pub hits: usize,
+    /// The number of bytes served from the cache, counting the requests that inserted their file.
+    pub bytes_served_from_cache: u64,
+    /// The number of bytes that would have been served from the filesystem.
+    pub bytes_served_from_fs: u64,
+    /// The files evicted to make room for other files, in the order they were evicted.
+    pub evictions: Vec<PathBuf>,
+    /// The files in the cache after the last request, in order.
+    pub resident: Vec<PathBuf>,
+}
+
+impl SimulationResult {
+    /// The fraction of requests that were hits, or 0 if there were no requests.
+    pub fn hit_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.requests as f64
+    }
+}
+
+
+/// Runs the requests of the workload, each a path and the size of its file, against a new cache built with the config.
+///
+/// The cache decides what to admit and evict exactly like it does when serving files,
+/// but the files are replaced by zeroed bytes of their sizes, so they don't have to exist.
+/// The cache holds up to its size limit of those bytes while the workload is simulated.
+/// This allows comparing priority functions and size limits on an access log before deploying them.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::{simulate, small_files_priority_function, CacheBuilder};
+/// use std::path::PathBuf;
+///
+/// let workload: Vec<(PathBuf, u64)> = vec![
+///     (PathBuf::from("www/video.mp4"), 900),
+///     (PathBuf::from("www/index.html"), 200),
+///     (PathBuf::from("www/index.html"), 200),
+/// ];
+/// let default = simulate(&workload, CacheBuilder::new().size_limit(1000)).unwrap();
+/// let small_files = simulate(&workload, CacheBuilder::new().size_limit(1000).priority_function(small_files_priority_function)).unwrap();
+/// assert!(small_files.hit_rate() > default.hit_rate());
+/// ```
+pub fn simulate(workload: &[(PathBuf, u64)], config: &CacheBuilder) -> Result<SimulationResult, CacheBuildError> {
+    let mut cache: Cache = config.build()?;
+    let evictions = EvictionRecorder::new();
+    cache.set_observer(Some(Box::new(evictions.clone())));
+
+    let mut result = SimulationResult {
+        requests: 0,
+        hits: 0,
+        bytes_served_from_cache: 0,
+        bytes_served_from_fs: 0,
+        evictions: vec![],
+        resident: vec![],
+    };
+    for &(ref path, size) in workload {
+        result.requests += 1;
+        let class: ServeClass = cache.replay_get(path, Some(cmp::min(size, usize::MAX as u64) as usize));
+        match class {
+            ServeClass::Hit => {
+                result.hits += 1;
+                result.bytes_served_from_cache = result.bytes_served_from_cache.saturating_add(size);
+            }
+            ServeClass::Inserted => result.bytes_served_from_cache = result.bytes_served_from_cache.saturating_add(size),
+            _ => result.bytes_served_from_fs = result.bytes_served_from_fs.saturating_add(size),
+        }
+    }
+
+    result.evictions = evictions.evictions();
+    result.resident = cache.file_map.iter().map(|(path, _)| path.clone()).collect();
+    result.resident.sort();
+    Ok(result)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use priority_function::small_files_priority_function;
+
+    fn workload(requests: &[(&str, u64)]) -> Vec<(PathBuf, u64)> {
+        requests.iter().map(|&(path, size)| (PathBuf::from(path), size)).collect()
+    }
+
+    #[test]
+    fn repeated_requests_for_files_that_fit_are_hits() {
+        let requests = workload(&[("a", 100), ("a", 100), ("b", 100), ("a", 100), ("b", 100)]);
+        let result = simulate(&requests, CacheBuilder::new().size_limit(1000)).unwrap();
+        assert_eq!(result.hits, 3);
+        assert_eq!(result.hit_rate(), 0.6);
+        assert_eq!(result.bytes_served_from_cache, 500);
+        assert_eq!(result.bytes_served_from_fs, 0);
+        assert!(result.evictions.is_empty());
+        assert_eq!(result.resident, vec![PathBuf::from("a"), PathBuf::from("b")]);
+    }
+
+    #[test]
+    fn files_that_only_fit_one_at_a_time_evict_each_other() {
+        // Each file has the priority of the one in the cache when it is requested, or more, so it replaces it.
+        let requests = workload(&[("a", 600), ("b", 600), ("a", 600), ("b", 600)]);
+        let result = simulate(&requests, CacheBuilder::new().size_limit(1000)).unwrap();
+        assert_eq!(result.hit_rate(), 0.0);
+        assert_eq!(result.bytes_served_from_cache, 2400);
+        assert_eq!(result.evictions, vec![PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("a")]);
+        assert_eq!(result.resident, vec![PathBuf::from("b")]);
+    }
+
+    #[test]
+    fn priority_functions_can_be_compared() {
+        let requests = workload(&[("big", 900), ("small", 200), ("small", 200), ("small", 200), ("small", 200)]);
+
+        // sqrt(900) = 30 keeps the big file in until the small file has 3 accesses: 3 * floor(sqrt(200)) = 42.
+        let default = simulate(&requests, CacheBuilder::new().size_limit(1000)).unwrap();
+        assert_eq!(default.hit_rate(), 0.2);
+        assert_eq!(default.bytes_served_from_fs, 400);
+        assert_eq!(default.evictions, vec![PathBuf::from("big")]);
+
+        let small_files = simulate(&requests, CacheBuilder::new().size_limit(1000).priority_function(small_files_priority_function)).unwrap();
+        assert_eq!(small_files.hit_rate(), 0.6);
+        assert_eq!(small_files.bytes_served_from_fs, 0);
+        assert_eq!(small_files.resident, vec![PathBuf::from("small")]);
+    }
+}
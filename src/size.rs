@@ -0,0 +1,100 @@
+/// Why a size couldn't be parsed by `parse_size()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseSizeError {
+    /// The string is empty.
+    Empty,
+    /// The size doesn't start with a whole number of units, like `-5MB` or `1.5GB`.
+    InvalidNumber(String),
+    /// The unit after the number isn't one of the units `parse_size()` knows.
+    UnknownUnit(String),
+    /// The size is more bytes than fit into 64 bits.
+    TooLarge,
+}
+
+
+/// Parses a human friendly size, like `512KiB` or `10 MB`, into a number of bytes.
+///
+/// The size is a whole number, optionally followed by a unit.
+/// Units are case insensitive: `B`, the decimal `KB`, `MB`, `GB` and `TB`,
+/// which are powers of 1000, and the binary `KiB`, `MiB`, `GiB` and `TiB`, which are powers of 1024.
+/// `K`, `M`, `G` and `T` are the same as their decimal units, and a number without a unit is a number of bytes.
+/// Underscores can be used to separate the digits, like `1_000_000`.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::{parse_size, ParseSizeError};
+///
+/// assert_eq!(parse_size("512KiB"), Ok(512 * 1024));
+/// assert_eq!(parse_size("10 MB"), Ok(10_000_000));
+/// assert_eq!(parse_size("4096"), Ok(4096));
+/// assert_eq!(parse_size("10 MiBs"), Err(ParseSizeError::UnknownUnit(String::from("MiBs"))));
+/// ```
+pub fn parse_size(size: &str) -> Result<u64, ParseSizeError> {
+    let size: &str = size.trim();
+    if size.is_empty() {
+        return Err(ParseSizeError::Empty);
+    }
+    let unit_start: usize = size
+        .find(|c: char| !c.is_ascii_digit() && c != '_')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(unit_start);
+    let digits: String = number.chars().filter(|&c| c != '_').collect();
+    if digits.is_empty() || unit.starts_with('.') || unit.starts_with(',') {
+        return Err(ParseSizeError::InvalidNumber(size.to_string()));
+    }
+    let multiplier: u64 = unit_multiplier(unit.trim())?;
+    // The number is only digits, so it can only fail to parse by being too large.
+    let number: u64 = digits.parse().map_err(|_| ParseSizeError::TooLarge)?;
+    number.checked_mul(multiplier).ok_or(ParseSizeError::TooLarge)
+}
+
+/// Gets the number of bytes in one of the unit.
+fn unit_multiplier(unit: &str) -> Result<u64, ParseSizeError> {
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000 * 1000,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "t" | "tb" => 1000 * 1000 * 1000 * 1000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(ParseSizeError::UnknownUnit(unit.to_string())),
+    };
+    Ok(multiplier)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_are_decimal_or_binary_and_case_insensitive() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert_eq!(parse_size("10MB"), Ok(10_000_000));
+        assert_eq!(parse_size("10mb"), Ok(10_000_000));
+        assert_eq!(parse_size("10M"), Ok(10_000_000));
+        assert_eq!(parse_size("512KiB"), Ok(512 * 1024));
+        assert_eq!(parse_size(" 100 MiB "), Ok(100 * 1024 * 1024));
+        assert_eq!(parse_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("3tb"), Ok(3_000_000_000_000));
+        assert_eq!(parse_size("1_000_000"), Ok(1_000_000));
+    }
+
+    #[test]
+    fn malformed_sizes_are_errors() {
+        assert_eq!(parse_size(""), Err(ParseSizeError::Empty));
+        assert_eq!(parse_size("   "), Err(ParseSizeError::Empty));
+        assert_eq!(parse_size("MB"), Err(ParseSizeError::InvalidNumber(String::from("MB"))));
+        assert_eq!(parse_size("-5MB"), Err(ParseSizeError::InvalidNumber(String::from("-5MB"))));
+        assert_eq!(parse_size("1.5GB"), Err(ParseSizeError::InvalidNumber(String::from("1.5GB"))));
+        assert_eq!(parse_size("10 parsecs"), Err(ParseSizeError::UnknownUnit(String::from("parsecs"))));
+        assert_eq!(parse_size("99999999999999999999"), Err(ParseSizeError::TooLarge));
+        assert_eq!(parse_size("20000000TiB"), Err(ParseSizeError::TooLarge));
+        assert_eq!(parse_size(&format!("{}", u64::MAX)), Ok(u64::MAX));
+    }
+}
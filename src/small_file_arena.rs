@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use in_memory_file::FileBytes;
+
+
+/// Packs the bytes of small files into shared slabs, instead of giving every file its own allocation.
+///
+/// Files smaller than the `threshold` are copied into slabs of `slab_size` bytes.
+/// A slab is freed once every file in it has been evicted, and files are moved out of slabs that are
+/// mostly empty, so slabs don't pile up as small files come and go.
+/// Slabs count against the size limit of the cache as a whole, including the space in them that isn't used,
+/// so the `slab_size` should be small compared to the size limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmallFileArenaConfig {
+    /// Files with fewer bytes than this are kept in slabs.
+    pub threshold: usize,
+    /// The number of bytes of each slab.
+    pub slab_size: usize,
+}
+
+
+/// A fixed block of memory that the bytes of small files are copied into, one after the other.
+///
+/// Every range of the slab is written once, while the arena is locked, before it is handed out,
+/// and is never written again, so the ranges handed out can be read without any locking.
+pub(crate) struct Slab {
+    id: usize,
+    ptr: *mut u8,
+    capacity: usize,
+    /// The bytes of the files in the slab that are still referenced.
+    live_bytes: AtomicUsize,
+}
+
+// Ranges of the slab are only written before they are shared, and the memory is only freed once it is dropped.
+unsafe impl Send for Slab {}
+unsafe impl Sync for Slab {}
+
+impl Slab {
+    fn new(id: usize, capacity: usize) -> Slab {
+        let bytes: Box<[u8]> = vec![0; capacity].into_boxed_slice();
+        Slab {
+            id,
+            ptr: Box::into_raw(bytes) as *mut u8,
+            capacity,
+            live_bytes: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Drop for Slab {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(self.ptr, self.capacity)));
+        }
+    }
+}
+
+impl fmt::Debug for Slab {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Slab {{ id: {}, capacity: {} }}", self.id, self.capacity)
+    }
+}
+
+
+/// The bytes of one file in a slab. The slab is kept alive for as long as the file is.
+#[derive(Debug)]
+pub(crate) struct SlabSlice {
+    slab: Arc<Slab>,
+    offset: usize,
+    len: usize,
+}
+
+impl SlabSlice {
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.slab.ptr.add(self.offset), self.len) }
+    }
+
+    pub(crate) fn slab_id(&self) -> usize {
+        self.slab.id
+    }
+}
+
+impl Drop for SlabSlice {
+    fn drop(&mut self) {
+        self.slab.live_bytes.fetch_sub(self.len, Ordering::AcqRel);
+    }
+}
+
+
+/// Hands out the space in the slabs.
+#[derive(Debug)]
+pub(crate) struct SmallFileArena {
+    config: SmallFileArenaConfig,
+    state: Mutex<ArenaState>,
+}
+
+#[derive(Debug)]
+struct ArenaState {
+    /// The slab files are currently copied into, and the offset of its first unused byte.
+    current: Option<(Arc<Slab>, usize)>,
+    /// Every slab that may still be alive. Slabs are only kept alive by the files in them.
+    slabs: Vec<Weak<Slab>>,
+    next_id: usize,
+}
+
+impl SmallFileArena {
+    pub(crate) fn new(config: SmallFileArenaConfig) -> SmallFileArena {
+        SmallFileArena {
+            config,
+            state: Mutex::new(ArenaState {
+                current: None,
+                slabs: Vec::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// Copies the bytes into a slab, if they are small enough to be kept in one.
+    pub(crate) fn store(&self, bytes: &[u8]) -> Option<FileBytes> {
+        let len: usize = bytes.len();
+        if len == 0 || len >= self.config.threshold || len > self.config.slab_size {
+            return None;
+        }
+        let mut state = self.lock();
+        let fits: bool = state.current.as_ref().is_some_and(|&(ref slab, offset)| offset + len <= slab.capacity);
+        if !fits {
+            let slab: Arc<Slab> = Arc::new(Slab::new(state.next_id, self.config.slab_size));
+            state.next_id += 1;
+            state.slabs.retain(|slab| slab.upgrade().is_some());
+            state.slabs.push(Arc::downgrade(&slab));
+            state.current = Some((slab, 0));
+        }
+        let (ref slab, ref mut offset) = *state.current.as_mut().expect("A slab with room was just made.");
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), slab.ptr.add(*offset), len);
+        }
+        slab.live_bytes.fetch_add(len, Ordering::AcqRel);
+        let slab_slice = SlabSlice {
+            slab: slab.clone(),
+            offset: *offset,
+            len,
+        };
+        *offset += len;
+        Some(FileBytes::Slab(Arc::new(slab_slice)))
+    }
+
+    /// The bytes of every slab that is still alive, used or not.
+    pub(crate) fn slab_bytes(&self) -> usize {
+        self.live_slabs().iter().map(|slab| slab.capacity).sum()
+    }
+
+    /// The bytes of the files in the slabs that are still referenced.
+    pub(crate) fn live_bytes(&self) -> usize {
+        self.live_slabs().iter().map(|slab| slab.live_bytes.load(Ordering::Acquire)).sum()
+    }
+
+    /// The bytes of the slabs that don't hold any referenced file.
+    pub(crate) fn unused_bytes(&self) -> usize {
+        self.live_slabs()
+            .iter()
+            .map(|slab| slab.capacity.saturating_sub(slab.live_bytes.load(Ordering::Acquire)))
+            .sum()
+    }
+
+    /// Gets the slabs that are less than half full and no longer being filled,
+    /// if the unused space in all of the slabs is worth moving files for.
+    pub(crate) fn sparse_slabs(&self) -> HashSet<usize> {
+        if self.unused_bytes() <= self.config.slab_size.max(self.live_bytes()) {
+            return HashSet::new();
+        }
+        let current: Option<usize> = self.lock().current.as_ref().map(|(slab, _)| slab.id);
+        self.live_slabs()
+            .iter()
+            .filter(|slab| Some(slab.id) != current && slab.live_bytes.load(Ordering::Acquire) * 2 < slab.capacity)
+            .map(|slab| slab.id)
+            .collect()
+    }
+
+    fn live_slabs(&self) -> Vec<Arc<Slab>> {
+        let mut state = self.lock();
+        let slabs: Vec<Arc<Slab>> = state.slabs.iter().filter_map(|slab| slab.upgrade()).collect();
+        // Slabs are freed when their last file is, so their entries are forgotten lazily.
+        if slabs.len() < state.slabs.len() {
+            state.slabs.retain(|slab| slab.upgrade().is_some());
+        }
+        slabs
+    }
+
+    fn lock(&self) -> MutexGuard<ArenaState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arena() -> SmallFileArena {
+        SmallFileArena::new(SmallFileArenaConfig {
+            threshold: 100,
+            slab_size: 256,
+        })
+    }
+
+    #[test]
+    fn small_files_share_slabs_and_large_ones_dont() {
+        let arena: SmallFileArena = arena();
+        let a: FileBytes = arena.store(&[1; 99]).unwrap();
+        let b: FileBytes = arena.store(&[2; 50]).unwrap();
+        assert!(arena.store(&[3; 100]).is_none());
+        assert!(arena.store(&[]).is_none());
+
+        assert_eq!(a.as_slice(), &[1; 99][..]);
+        assert_eq!(b.as_slice(), &[2; 50][..]);
+        assert_eq!(arena.slab_bytes(), 256);
+        assert_eq!(arena.live_bytes(), 149);
+    }
+
+    #[test]
+    fn slabs_are_freed_once_their_files_are_dropped() {
+        let arena: SmallFileArena = arena();
+        let files: Vec<FileBytes> = (0..10).map(|i| arena.store(&[i; 90]).unwrap()).collect();
+        assert_eq!(arena.slab_bytes(), 256 * 5);
+        assert_eq!(files[9].as_slice(), &[9; 90][..]);
+
+        // The slab being filled is kept until another one replaces it.
+        drop(files);
+        assert_eq!(arena.slab_bytes(), 256);
+        assert_eq!(arena.live_bytes(), 0);
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use in_memory_file::FileStats;
+use summary::CacheSummary;
+
+
+/// A view of the cache at the time it was taken with `Cache::snapshot()`, which never changes afterwards.
+///
+/// Taking a snapshot copies what the cache knows about its files once, so a dashboard can take it quickly
+/// while holding the lock of a cache behind a `Mutex`, and render it after releasing the lock.
+/// Clones share the copied state, so a snapshot can be handed to several threads without copying it again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheSnapshot {
+    state: Arc<SnapshotState>,
+}
+
+#[derive(Debug, PartialEq)]
+struct SnapshotState {
+    summary: CacheSummary,
+    /// The files in the cache with their stats, sorted by path.
+    files: Vec<(PathBuf, FileStats)>,
+}
+
+impl CacheSnapshot {
+    /// Creates a snapshot from the summary and the files of the cache, in any order.
+    pub(crate) fn new(summary: CacheSummary, mut files: Vec<(PathBuf, FileStats)>) -> CacheSnapshot {
+        files.sort_by(|l, r| l.0.cmp(&r.0));
+        CacheSnapshot {
+            state: Arc::new(SnapshotState {
+                summary,
+                files,
+            }),
+        }
+    }
+
+    /// The summary of the cache, like `Cache::summary()` returned it.
+    pub fn summary(&self) -> &CacheSummary {
+        &self.state.summary
+    }
+
+    /// The number of bytes held by the files in the cache, like `Cache::used_bytes()` returned it.
+    pub fn used_bytes(&self) -> usize {
+        self.state.summary.used_bytes
+    }
+
+    /// The number of files in the cache.
+    pub fn file_count(&self) -> usize {
+        self.state.files.len()
+    }
+
+    /// The files in the cache with their stats, sorted by path.
+    pub fn files(&self) -> &[(PathBuf, FileStats)] {
+        &self.state.files
+    }
+
+    /// The keys of the files in the cache, sorted.
+    pub fn keys(&self) -> Vec<&Path> {
+        self.state.files.iter().map(|(path, _)| path.as_path()).collect()
+    }
+
+    /// Whether the cache held a file under the key.
+    ///
+    /// Unlike `Cache::contains_key()`, this doesn't follow aliases, or normalize the case of the key.
+    pub fn contains_key<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.stats_for(path).is_some()
+    }
+
+    /// The stats of the file the cache held under the key, or None if it held none.
+    pub fn stats_for<P: AsRef<Path>>(&self, path: P) -> Option<FileStats> {
+        self.state.files
+            .binary_search_by(|(key, _)| key.as_path().cmp(path.as_ref()))
+            .ok()
+            .map(|index| self.state.files[index].1.clone())
+    }
+}
@@ -0,0 +1,249 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(unix)]
+use std::ffi::OsStr;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+
+
+/// The bytes every spill file starts with.
+const MAGIC: &[u8; 8] = b"RFCSPILL";
+/// The version of the format written by `Cache::spill()`. Spill files of other versions aren't restored.
+const VERSION: u32 = 1;
+/// The bytes of a record besides its path and the bytes of its file:
+/// the length of the path, the access count, whether it has a modification time, and the time.
+const FIXED_RECORD_BYTES: u64 = 4 + 8 + 1 + 8 + 4;
+
+
+/// What `Cache::spill()` wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SpillSummary {
+    /// The number of files written to the spill file.
+    pub entries: usize,
+    /// The number of bytes of the files written to the spill file, not counting their paths and stats.
+    pub bytes: usize,
+    /// The number of files that weren't written, because their paths can't be stored on this platform.
+    /// Embedded files aren't written either, but aren't counted, as they are seeded again at startup.
+    pub skipped: usize,
+}
+
+
+/// How `Cache::restore_spill()` treats the files in a spill file.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RestoreOptions {
+    /// Checks every file against the filesystem, and skips the ones whose modification time or size changed
+    /// since they were spilled, so they are read again when they are requested.
+    pub verify_mtime: bool,
+}
+
+
+/// What `Cache::restore_spill()` restored.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RestoreSummary {
+    /// The number of files that were stored in the cache.
+    pub restored: usize,
+    /// The number of bytes of the files that were stored in the cache.
+    pub restored_bytes: usize,
+    /// The number of files that were skipped, because they changed in the filesystem since they were spilled,
+    /// or no longer exist there.
+    pub stale: usize,
+    /// The number of files that were skipped for any other reason: they were already in the cache,
+    /// were outside of its size bounds, or didn't fit into its free space.
+    pub skipped: usize,
+}
+
+
+/// A file read back from a spill file.
+#[derive(Debug)]
+pub(crate) struct SpillRecord {
+    pub(crate) path: PathBuf,
+    pub(crate) access_count: usize,
+    pub(crate) modified: Option<SystemTime>,
+    pub(crate) bytes: Vec<u8>,
+}
+
+
+pub(crate) fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())
+}
+
+/// Checks that the reader starts with the header of a spill file of the current version.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut header: [u8; 12] = [0; 12];
+    reader.read_exact(&mut header).map_err(|_| invalid_data("the spill file is too short to have a header"))?;
+    if &header[..8] != MAGIC {
+        return Err(invalid_data("the file isn't a spill file"));
+    }
+    let version: u32 = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    if version != VERSION {
+        return Err(invalid_data(&format!("the spill file has version {}, but only version {} can be restored", version, VERSION)));
+    }
+    Ok(())
+}
+
+/// Writes a file as a record prefixed by its length.
+/// Returns false without writing anything if the path can't be stored on this platform.
+pub(crate) fn write_record<W: Write>(writer: &mut W, path: &Path, access_count: usize, modified: Option<SystemTime>, bytes: &[u8]) -> io::Result<bool> {
+    let path_bytes: &[u8] = match path_to_bytes(path) {
+        Some(path_bytes) => path_bytes,
+        None => return Ok(false),
+    };
+    let modified: Option<Duration> = modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+    let record_length: u64 = FIXED_RECORD_BYTES + path_bytes.len() as u64 + bytes.len() as u64;
+    writer.write_all(&record_length.to_le_bytes())?;
+    writer.write_all(&(path_bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(path_bytes)?;
+    writer.write_all(&(access_count as u64).to_le_bytes())?;
+    writer.write_all(&[modified.is_some() as u8])?;
+    writer.write_all(&modified.map_or(0, |modified| modified.as_secs()).to_le_bytes())?;
+    writer.write_all(&modified.map_or(0, |modified| modified.subsec_nanos()).to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(true)
+}
+
+/// Reads the next record, or None at the end of the spill file.
+///
+/// A record that is cut short, or whose fields don't fit into its length, is an `InvalidData` error.
+/// Bytes are only read as they are found, so a corrupt length can't make this allocate more than the file holds.
+pub(crate) fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<SpillRecord>> {
+    let mut length_bytes: [u8; 8] = [0; 8];
+    let mut read: usize = 0;
+    while read < length_bytes.len() {
+        match reader.read(&mut length_bytes[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(invalid_data("the spill file ends in the length of a record")),
+            Ok(n) => read += n,
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    let record_length: u64 = u64::from_le_bytes(length_bytes);
+    if record_length < FIXED_RECORD_BYTES {
+        return Err(invalid_data("a record of the spill file is too short"));
+    }
+    let mut record: Vec<u8> = vec![];
+    reader.take(record_length).read_to_end(&mut record)?;
+    if (record.len() as u64) < record_length {
+        return Err(invalid_data("the spill file ends in the middle of a record"));
+    }
+
+    let mut fields: Fields = Fields { bytes: &record };
+    let path_length: usize = u32::from_le_bytes(fields.array4()?) as usize;
+    let path: PathBuf = path_from_bytes(fields.take(path_length)?)
+        .ok_or_else(|| invalid_data("a path in the spill file can't be used on this platform"))?;
+    let access_count: u64 = u64::from_le_bytes(fields.array8()?);
+    let has_modified: bool = fields.take(1)?[0] != 0;
+    let seconds: u64 = u64::from_le_bytes(fields.array8()?);
+    let nanos: u32 = u32::from_le_bytes(fields.array4()?);
+    if nanos >= 1_000_000_000 {
+        return Err(invalid_data("a modification time in the spill file is invalid"));
+    }
+    let modified: Option<SystemTime> = if has_modified {
+        UNIX_EPOCH.checked_add(Duration::new(seconds, nanos))
+    } else {
+        None
+    };
+    let bytes: Vec<u8> = fields.bytes.to_vec();
+    Ok(Some(SpillRecord {
+        path,
+        access_count: if access_count > usize::MAX as u64 { usize::MAX } else { access_count as usize },
+        modified,
+        bytes,
+    }))
+}
+
+/// The fields of a record that haven't been read yet.
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if length > self.bytes.len() {
+            return Err(invalid_data("a field of a record runs past the end of the record"));
+        }
+        let (field, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(field)
+    }
+
+    fn array4(&mut self) -> io::Result<[u8; 4]> {
+        let field: &[u8] = self.take(4)?;
+        Ok([field[0], field[1], field[2], field[3]])
+    }
+
+    fn array8(&mut self) -> io::Result<[u8; 8]> {
+        let mut array: [u8; 8] = [0; 8];
+        array.copy_from_slice(self.take(8)?);
+        Ok(array)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Option<&[u8]> {
+    Some(path.as_os_str().as_bytes())
+}
+
+/// Paths are stored as UTF-8 where they aren't just bytes, so the ones that aren't valid unicode can't be stored.
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Option<&[u8]> {
+    path.to_str().map(|path| path.as_bytes())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    Some(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    ::std::str::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_spill_files_are_errors() {
+        let modified: SystemTime = UNIX_EPOCH + Duration::new(1_500_000_000, 123);
+        let mut spill: Vec<u8> = vec![];
+        write_header(&mut spill).unwrap();
+        assert!(write_record(&mut spill, Path::new("www/index.html"), 7, Some(modified), b"<html></html>").unwrap());
+        let header_length: usize = 12;
+
+        let mut reader: &[u8] = &spill;
+        read_header(&mut reader).unwrap();
+        let record: SpillRecord = read_record(&mut reader).unwrap().unwrap();
+        assert_eq!(record.path, PathBuf::from("www/index.html"));
+        assert_eq!(record.access_count, 7);
+        assert_eq!(record.modified, Some(modified));
+        assert_eq!(record.bytes, b"<html></html>".to_vec());
+        assert!(read_record(&mut reader).unwrap().is_none());
+
+        // Every cut short version of the file is an error, and never a panic.
+        for length in 0..spill.len() {
+            let mut reader: &[u8] = &spill[..length];
+            let result: io::Result<Option<SpillRecord>> = read_header(&mut reader).and_then(|_| read_record(&mut reader));
+            match result {
+                Ok(None) => assert_eq!(length, header_length),
+                Ok(Some(_)) => panic!("A record was read from {} of {} bytes", length, spill.len()),
+                Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+            }
+        }
+
+        // A path length that runs past the end of its record.
+        let mut corrupt: Vec<u8> = spill.clone();
+        corrupt[header_length + 8..header_length + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader: &[u8] = &corrupt;
+        read_header(&mut reader).unwrap();
+        assert_eq!(read_record(&mut reader).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
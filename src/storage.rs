@@ -0,0 +1,77 @@
+#[cfg(all(unix, feature = "mmap"))]
+use std::fs::File;
+#[cfg(all(unix, feature = "mmap"))]
+use std::io;
+#[cfg(all(unix, feature = "mmap"))]
+use std::os::unix::io::AsRawFd;
+#[cfg(all(unix, feature = "mmap"))]
+use std::ptr;
+#[cfg(all(unix, feature = "mmap"))]
+use std::slice;
+
+#[cfg(all(unix, feature = "mmap"))]
+use libc;
+
+
+/// Where the cache keeps the bytes of the files it reads from the filesystem.
+///
+/// Whichever storage is used, files are served, hashed, refreshed, and counted against the size limit the same way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Storage {
+    /// Files are read into memory allocated by the cache. This is the default.
+    Heap,
+    /// Files are memory mapped, so their pages are shared with the operating system's page cache,
+    /// and can be reclaimed by the operating system under memory pressure.
+    /// The mapping is kept alive by the entry in the cache, and by any file detached from it.
+    ///
+    /// The mapping is not a copy of the file.
+    /// If the file is modified in place, the cached file changes with it, without the cache knowing,
+    /// and if it is truncated, reading the cached file can crash the process.
+    /// Only map files that are replaced rather than modified, for example by renaming a new file over them.
+    ///
+    /// Only available on unix, with the `mmap` feature.
+    #[cfg(all(unix, feature = "mmap"))]
+    Mmap,
+}
+
+
+/// A read-only memory mapping of a whole file, unmapped when it is dropped.
+#[cfg(all(unix, feature = "mmap"))]
+#[derive(Debug)]
+pub(crate) struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only, and is only unmapped once it is dropped.
+#[cfg(all(unix, feature = "mmap"))]
+unsafe impl Send for Mapping {}
+#[cfg(all(unix, feature = "mmap"))]
+unsafe impl Sync for Mapping {}
+
+#[cfg(all(unix, feature = "mmap"))]
+impl Mapping {
+    /// Maps the first `len` bytes of the file. The length must not be 0.
+    pub(crate) fn map(file: &File, len: usize) -> io::Result<Mapping> {
+        let ptr: *mut libc::c_void = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr, len })
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(all(unix, feature = "mmap"))]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
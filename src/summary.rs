@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use directory_floors::DirectoryFloorUsage;
+use extension_stats::ExtensionStats;
+
+
+/// A summary of the state of the cache at the time it was taken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheSummary {
+    /// The number of bytes the cache can hold.
+    pub size_limit: usize,
+    /// The number of bytes held by the files in the cache.
+    pub used_bytes: usize,
+    /// The number of files in the cache.
+    pub file_count: usize,
+    /// The number of paths the cache is tracking access counts for.
+    pub tracked_paths: usize,
+    /// The number of compressed bytes held by the cold tier.
+    pub cold_tier_used_bytes: usize,
+    /// The number of files held by the cold tier.
+    pub cold_tier_file_count: usize,
+    /// What each directory floor of the cache is protecting.
+    pub directory_floors: Vec<DirectoryFloorUsage>,
+    /// The number of evictions of files that had already been evicted and inserted again before,
+    /// since the churn counters were last reset.
+    pub evictions_of_previously_resident: usize,
+    /// The number of bytes offered in responses served from memory, since the served bytes were last reset.
+    /// Files are counted whole when the cache decides where to serve them from,
+    /// so this is what was offered to the client, not what was actually written to the socket.
+    pub bytes_served_from_cache: usize,
+    /// The number of bytes offered in responses served from the filesystem, counted like `bytes_served_from_cache`.
+    pub bytes_served_from_fs: usize,
+    /// The estimated number of bytes the cache holds to keep track of paths, as returned by `Cache::bookkeeping_bytes()`.
+    pub bookkeeping_bytes: usize,
+    /// The number of times a cached file was served whose declared size disagreed with the bytes it holds.
+    /// Responses use the length of the bytes in that case, so this should always be 0 unless something is broken.
+    pub size_mismatches: usize,
+    /// The number of times a cached file no longer matched its checksum when it was hit,
+    /// if checksums are verified with `Cache::set_verify_checksums()`.
+    pub checksum_mismatches: usize,
+    /// Whether the cache is serving only the files it holds, because the filesystem kept failing.
+    /// Combined summaries are degraded if any of their caches are.
+    pub degraded: bool,
+    /// The number of bytes held by the slabs of the small file arena, used or not.
+    pub small_file_slab_bytes: usize,
+    /// The number of bytes of the files in the slabs of the small file arena.
+    /// Divided by `small_file_slab_bytes`, this is how well the slabs are used.
+    pub small_file_live_bytes: usize,
+    /// How the files with each extension use the cache, as returned by `Cache::extension_stats()`.
+    pub extensions: Vec<(String, ExtensionStats)>,
+    /// The number of misses for files that were evicted recently enough that a cache with the size of
+    /// the shadow limit set with `Cache::set_shadow_limit()` would likely still have held them.
+    /// This is the number of hits a larger cache could gain.
+    pub ghost_hits: usize,
+}
+
+impl CacheSummary {
+    /// Formats the summary as a JSON object, with the same field names as the struct.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rocket_file_cache::CacheBuilder;
+    ///
+    /// let cache = CacheBuilder::new().size_limit(1024).build().unwrap();
+    /// let json: String = cache.summary().to_json();
+    /// assert!(json.starts_with("{\"size_limit\":1024,\"used_bytes\":0,"));
+    /// ```
+    pub fn to_json(&self) -> String {
+        let directory_floors: Vec<String> = self.directory_floors
+            .iter()
+            .map(|floor| {
+                format!(
+                    "{{\"prefix\":{},\"min_bytes\":{},\"resident_bytes\":{},\"protected_bytes\":{}}}",
+                    json_string(&floor.prefix),
+                    floor.min_bytes,
+                    floor.resident_bytes,
+                    floor.protected_bytes
+                )
+            })
+            .collect();
+        let extensions: Vec<String> = self.extensions
+            .iter()
+            .map(|(extension, stats)| {
+                format!(
+                    "{}:{{\"resident_bytes\":{},\"file_count\":{},\"hits\":{},\"misses\":{},\"insertions\":{},\"evictions\":{}}}",
+                    json_string(Path::new(extension)),
+                    stats.resident_bytes,
+                    stats.file_count,
+                    stats.hits,
+                    stats.misses,
+                    stats.insertions,
+                    stats.evictions
+                )
+            })
+            .collect();
+        format!(
+            "{{\"size_limit\":{},\"used_bytes\":{},\"file_count\":{},\"tracked_paths\":{},\"cold_tier_used_bytes\":{},\"cold_tier_file_count\":{},\"directory_floors\":[{}],\"evictions_of_previously_resident\":{},\"bytes_served_from_cache\":{},\"bytes_served_from_fs\":{},\"bookkeeping_bytes\":{},\"size_mismatches\":{},\"checksum_mismatches\":{},\"degraded\":{},\"small_file_slab_bytes\":{},\"small_file_live_bytes\":{},\"extensions\":{{{}}},\"ghost_hits\":{}}}",
+            self.size_limit,
+            self.used_bytes,
+            self.file_count,
+            self.tracked_paths,
+            self.cold_tier_used_bytes,
+            self.cold_tier_file_count,
+            directory_floors.join(","),
+            self.evictions_of_previously_resident,
+            self.bytes_served_from_cache,
+            self.bytes_served_from_fs,
+            self.bookkeeping_bytes,
+            self.size_mismatches,
+            self.checksum_mismatches,
+            self.degraded,
+            self.small_file_slab_bytes,
+            self.small_file_live_bytes,
+            extensions.join(","),
+            self.ghost_hits
+        )
+    }
+
+    /// A summary of nothing, used as the starting point when combining summaries.
+    pub(crate) fn empty() -> CacheSummary {
+        CacheSummary {
+            size_limit: 0,
+            used_bytes: 0,
+            file_count: 0,
+            tracked_paths: 0,
+            cold_tier_used_bytes: 0,
+            cold_tier_file_count: 0,
+            directory_floors: vec![],
+            evictions_of_previously_resident: 0,
+            bytes_served_from_cache: 0,
+            bytes_served_from_fs: 0,
+            bookkeeping_bytes: 0,
+            size_mismatches: 0,
+            checksum_mismatches: 0,
+            degraded: false,
+            small_file_slab_bytes: 0,
+            small_file_live_bytes: 0,
+            extensions: vec![],
+            ghost_hits: 0,
+        }
+    }
+
+    /// Adds the other summary's values to this summary.
+    /// The directory floors of both summaries are kept side by side, as they belong to different caches.
+    pub(crate) fn combine(self, other: &CacheSummary) -> CacheSummary {
+        let mut directory_floors: Vec<DirectoryFloorUsage> = self.directory_floors;
+        directory_floors.extend(other.directory_floors.iter().cloned());
+        let mut extensions: Vec<(String, ExtensionStats)> = self.extensions;
+        for (extension, stats) in &other.extensions {
+            match extensions.iter_mut().find(|&&mut (ref existing, _)| existing == extension) {
+                Some(&mut (_, ref mut existing_stats)) => existing_stats.combine(stats),
+                None => extensions.push((extension.clone(), *stats)),
+            }
+        }
+        extensions.sort_by(|a, b| a.0.cmp(&b.0));
+        CacheSummary {
+            size_limit: self.size_limit.saturating_add(other.size_limit),
+            used_bytes: self.used_bytes + other.used_bytes,
+            file_count: self.file_count + other.file_count,
+            tracked_paths: self.tracked_paths + other.tracked_paths,
+            cold_tier_used_bytes: self.cold_tier_used_bytes + other.cold_tier_used_bytes,
+            cold_tier_file_count: self.cold_tier_file_count + other.cold_tier_file_count,
+            directory_floors,
+            evictions_of_previously_resident: self.evictions_of_previously_resident + other.evictions_of_previously_resident,
+            bytes_served_from_cache: self.bytes_served_from_cache + other.bytes_served_from_cache,
+            bytes_served_from_fs: self.bytes_served_from_fs + other.bytes_served_from_fs,
+            bookkeeping_bytes: self.bookkeeping_bytes + other.bookkeeping_bytes,
+            size_mismatches: self.size_mismatches + other.size_mismatches,
+            checksum_mismatches: self.checksum_mismatches + other.checksum_mismatches,
+            degraded: self.degraded || other.degraded,
+            small_file_slab_bytes: self.small_file_slab_bytes + other.small_file_slab_bytes,
+            small_file_live_bytes: self.small_file_live_bytes + other.small_file_live_bytes,
+            extensions,
+            ghost_hits: self.ghost_hits + other.ghost_hits,
+        }
+    }
+}
+
+
+/// Formats the path as a JSON string, replacing anything that isn't valid unicode.
+fn json_string(path: &Path) -> String {
+    let mut json: String = String::from("\"");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
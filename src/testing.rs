@@ -0,0 +1,143 @@
+//! Helpers for tests and benchmarks of code that serves files with the cache, enabled with the `testing` feature.
+
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use file_source::FileSource;
+
+
+/// Creates a file of random bytes with the name in the directory, and returns its path.
+/// Every file gets different bytes, so files can't be mistaken for each other.
+///
+/// # Panics
+///
+/// Panics if the file can't be written.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::testing::create_sized_file;
+/// use std::env;
+///
+/// let path = create_sized_file(env::temp_dir(), 1024, "create_sized_file_example.bin");
+/// assert_eq!(path.metadata().unwrap().len(), 1024);
+/// ```
+pub fn create_sized_file<P: AsRef<Path>>(dir: P, size: usize, name: &str) -> PathBuf {
+    let path: PathBuf = dir.as_ref().join(name);
+    let file: File = File::create(&path).expect("Could not create the test file.");
+    let mut writer = BufWriter::new(file);
+    let mut random_bytes = RandomBytes::new();
+    let mut chunk: [u8; 4096] = [0; 4096];
+    let mut remaining: usize = size;
+    while remaining > 0 {
+        let length: usize = if remaining < chunk.len() { remaining } else { chunk.len() };
+        random_bytes.fill(&mut chunk[..length]);
+        writer.write_all(&chunk[..length]).expect("Could not write the test file.");
+        remaining -= length;
+    }
+    writer.flush().expect("Could not write the test file.");
+    path
+}
+
+/// Reads the whole file, wherever it is served from.
+///
+/// This copies the file, so it is only meant for checking what the cache serves in tests.
+///
+/// # Panics
+///
+/// Panics if the file wasn't found, or can't be read.
+///
+/// # Example
+///
+/// ```
+/// use rocket_file_cache::CacheBuilder;
+/// use rocket_file_cache::testing::{create_sized_file, read_all};
+/// use std::env;
+/// use std::fs;
+///
+/// let cache = CacheBuilder::new().build().unwrap();
+/// let path = create_sized_file(env::temp_dir(), 1024, "read_all_example.bin");
+/// let bytes: Vec<u8> = read_all(cache.get_source(&path));
+/// assert_eq!(bytes, fs::read(&path).unwrap());
+/// ```
+pub fn read_all(file: FileSource) -> Vec<u8> {
+    let mut reader = file.into_reader().expect("Expected the file to be found.");
+    let mut bytes: Vec<u8> = vec![];
+    reader.read_to_end(&mut bytes).expect("Could not read the file.");
+    bytes
+}
+
+
+/// A xorshift generator, seeded differently every time it is created.
+/// The bytes only have to differ between files, so it doesn't have to be any good.
+struct RandomBytes {
+    state: u64,
+}
+
+impl RandomBytes {
+    fn new() -> RandomBytes {
+        // Every RandomState is created with new random keys.
+        let seed: u64 = RandomState::new().build_hasher().finish();
+        RandomBytes {
+            // The state of a xorshift generator must never be 0.
+            state: seed | 1,
+        }
+    }
+
+    fn fill(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            let random: [u8; 8] = self.state.to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use self::tempdir::TempDir;
+    use cache_builder::CacheBuilder;
+    use std::fs;
+
+    #[test]
+    fn sized_files_have_the_size_and_differ() {
+        let temp_dir = TempDir::new("testing").unwrap();
+        let a: PathBuf = create_sized_file(temp_dir.path(), 10_000, "a.bin");
+        let b: PathBuf = create_sized_file(temp_dir.path(), 10_000, "b.bin");
+        let empty: PathBuf = create_sized_file(temp_dir.path(), 0, "empty.bin");
+
+        assert_eq!(fs::metadata(&a).unwrap().len(), 10_000);
+        assert_eq!(fs::metadata(&empty).unwrap().len(), 0);
+        assert_ne!(fs::read(&a).unwrap(), fs::read(&b).unwrap());
+    }
+
+    #[test]
+    fn read_all_reads_files_wherever_they_are_served_from() {
+        let cache = CacheBuilder::new()
+            .size_limit(1024)
+            .build()
+            .unwrap();
+        let temp_dir = TempDir::new("testing").unwrap();
+        let small: PathBuf = create_sized_file(temp_dir.path(), 100, "small.bin");
+        let large: PathBuf = create_sized_file(temp_dir.path(), 2048, "large.bin");
+
+        match cache.get_source(&small) {
+            file @ FileSource::Cached(_) => assert_eq!(read_all(file), fs::read(&small).unwrap()),
+            _ => panic!("Expected the small file to be cached."),
+        }
+        match cache.get_source(&large) {
+            file @ FileSource::Fs(..) => assert_eq!(read_all(file), fs::read(&large).unwrap()),
+            _ => panic!("Expected the large file to be served from the filesystem."),
+        }
+        assert_eq!(read_all(cache.get_source(&small).detach()), fs::read(&small).unwrap());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use bookkeeping::path_entry_bytes;
+use deadline;
+
+
+/// Stops files that keep being evicted and inserted again from being inserted for a while.
+///
+/// A file that is evicted and inserted again at least `cycles` times within the `window` is served from
+/// the filesystem, without being considered for insertion, until the `cooldown` has passed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThrashConfig {
+    /// The number of cycles within the window that make a file be penalized.
+    pub cycles: usize,
+    /// How far back cycles are counted.
+    pub window: Duration,
+    /// How long a penalized file is kept out of the cache.
+    pub cooldown: Duration,
+}
+
+
+/// Remembers when files completed their recent cycles, and which files are penalized.
+#[derive(Debug)]
+pub(crate) struct ThrashProtection {
+    config: ThrashConfig,
+    state: Mutex<ThrashState>,
+}
+
+#[derive(Debug)]
+struct ThrashState {
+    /// When each file completed the cycles that are still within the window, oldest first.
+    cycles: HashMap<PathBuf, VecDeque<Instant>>,
+    /// When the penalty of each penalized file ends.
+    penalties: HashMap<PathBuf, Instant>,
+    /// When the cycles outside of the window and the expired penalties of every file were last forgotten.
+    last_pruned: Option<Instant>,
+}
+
+impl ThrashProtection {
+    pub(crate) fn new(config: ThrashConfig) -> ThrashProtection {
+        ThrashProtection {
+            config,
+            state: Mutex::new(ThrashState {
+                cycles: HashMap::new(),
+                penalties: HashMap::new(),
+                last_pruned: None,
+            }),
+        }
+    }
+
+    /// Records that the file was inserted again after it was evicted,
+    /// penalizing it if it has done so too often within the window.
+    pub(crate) fn on_cycle(&self, path: &Path, now: Instant) {
+        let mut state = self.lock();
+        // Files that stopped cycling are forgotten at most once per window, so every cycle doesn't scan every file.
+        if state.last_pruned.is_none_or(|last_pruned| now >= deadline::after(last_pruned, self.config.window)) {
+            self.prune(&mut state, now);
+        }
+        let penalized: bool = {
+            let cycles: &mut VecDeque<Instant> = state.cycles.entry(path.to_path_buf()).or_default();
+            cycles.push_back(now);
+            while cycles.front().is_some_and(|&cycle| now.duration_since(cycle) > self.config.window) {
+                cycles.pop_front();
+            }
+            cycles.len() >= self.config.cycles
+        };
+        if penalized {
+            debug!("{:?} is thrashing, keeping it out of the cache for {:?}.", path, self.config.cooldown);
+            state.cycles.remove(path);
+            state.penalties.insert(path.to_path_buf(), deadline::after(now, self.config.cooldown));
+        }
+    }
+
+    /// Forgets the files whose every cycle is outside of the window, and the penalties that have ended.
+    fn prune(&self, state: &mut ThrashState, now: Instant) {
+        let window: Duration = self.config.window;
+        state.cycles.retain(|_, cycles| cycles.back().is_some_and(|&cycle| now <= deadline::after(cycle, window)));
+        state.penalties.retain(|_, &mut until| now < until);
+        state.last_pruned = Some(now);
+    }
+
+    /// Checks if the file is penalized, forgetting its penalty if the cooldown has passed.
+    pub(crate) fn is_penalized(&self, path: &Path, now: Instant) -> bool {
+        let mut state = self.lock();
+        match state.penalties.get(path).cloned() {
+            Some(until) if now < until => true,
+            Some(_) => {
+                state.penalties.remove(path);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// The files that are penalized, and when their penalties end.
+    pub(crate) fn penalties(&self, now: Instant) -> Vec<(PathBuf, Instant)> {
+        let mut penalties: Vec<(PathBuf, Instant)> = self.lock()
+            .penalties
+            .iter()
+            .filter(|&(_, &until)| now < until)
+            .map(|(path, &until)| (path.clone(), until))
+            .collect();
+        penalties.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+        penalties
+    }
+
+    /// Estimates the bytes held by the cycles and penalties of every file.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        let state = self.lock();
+        let cycles: usize = state.cycles
+            .iter()
+            .map(|(path, cycles)| path_entry_bytes::<VecDeque<Instant>>(path) + cycles.capacity() * mem::size_of::<Instant>())
+            .sum();
+        let penalties: usize = state.penalties.keys().map(|path| path_entry_bytes::<Instant>(path)).sum();
+        cycles + penalties
+    }
+
+    fn lock(&self) -> MutexGuard<ThrashState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protection() -> ThrashProtection {
+        ThrashProtection::new(ThrashConfig {
+            cycles: 3,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(300),
+        })
+    }
+
+    #[test]
+    fn files_are_penalized_after_enough_cycles_within_the_window() {
+        let protection = protection();
+        let start: Instant = Instant::now();
+        let path = Path::new("www/index.html");
+
+        protection.on_cycle(path, start);
+        protection.on_cycle(path, start + Duration::from_secs(10));
+        assert!(!protection.is_penalized(path, start + Duration::from_secs(10)));
+
+        protection.on_cycle(path, start + Duration::from_secs(20));
+        assert!(protection.is_penalized(path, start + Duration::from_secs(20)));
+        assert_eq!(
+            protection.penalties(start + Duration::from_secs(20)),
+            vec![(path.to_path_buf(), start + Duration::from_secs(320))]
+        );
+
+        // The penalty is honored for the whole cooldown, and then expires.
+        assert!(protection.is_penalized(path, start + Duration::from_secs(319)));
+        assert!(!protection.is_penalized(path, start + Duration::from_secs(320)));
+        assert!(protection.penalties(start + Duration::from_secs(320)).is_empty());
+    }
+
+    #[test]
+    fn cycles_outside_of_the_window_are_forgotten() {
+        let protection = protection();
+        let start: Instant = Instant::now();
+        let path = Path::new("www/index.html");
+
+        protection.on_cycle(path, start);
+        protection.on_cycle(path, start + Duration::from_secs(50));
+        protection.on_cycle(path, start + Duration::from_secs(100));
+        assert!(!protection.is_penalized(path, start + Duration::from_secs(100)));
+
+        protection.on_cycle(path, start + Duration::from_secs(110));
+        assert!(protection.is_penalized(path, start + Duration::from_secs(110)));
+    }
+
+    #[test]
+    fn files_that_stopped_cycling_are_forgotten() {
+        let protection = protection();
+        let start: Instant = Instant::now();
+        let quiet = Path::new("www/quiet.html");
+        let penalized = Path::new("www/penalized.html");
+
+        protection.on_cycle(quiet, start);
+        for i in 0..3 {
+            protection.on_cycle(penalized, start + Duration::from_secs(i));
+        }
+        assert_eq!(protection.lock().cycles.len(), 1);
+        assert_eq!(protection.lock().penalties.len(), 1);
+
+        // Once the window has passed, the cycles of the quiet file are forgotten, but the penalty is kept.
+        protection.on_cycle(Path::new("www/other.html"), start + Duration::from_secs(100));
+        assert!(!protection.lock().cycles.contains_key(quiet));
+        assert!(protection.is_penalized(penalized, start + Duration::from_secs(100)));
+
+        // Once the cooldown has passed, the penalty is forgotten too, even if the file is never checked again.
+        protection.on_cycle(Path::new("www/other.html"), start + Duration::from_secs(400));
+        assert!(protection.lock().penalties.is_empty());
+        assert_eq!(protection.lock().cycles.len(), 1);
+    }
+}
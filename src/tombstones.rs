@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+use bookkeeping::path_entry_bytes;
+
+
+/// Paths removed with `Cache::remove_and_block()`, which are kept out of the cache until their block ends.
+#[derive(Debug)]
+pub(crate) struct Tombstones {
+    /// When the block of each blocked path ends.
+    blocked: Mutex<HashMap<PathBuf, Instant>>,
+}
+
+impl Tombstones {
+    pub(crate) fn new() -> Tombstones {
+        Tombstones {
+            blocked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Blocks the path until the instant, replacing any block it already had.
+    pub(crate) fn block(&self, path: &Path, until: Instant) {
+        self.lock().insert(path.to_path_buf(), until);
+    }
+
+    /// Ends the block of the path. Returns false if it wasn't blocked.
+    pub(crate) fn unblock(&self, path: &Path) -> bool {
+        self.lock().remove(path).is_some()
+    }
+
+    /// Checks if the path is blocked, forgetting its block if it has ended.
+    pub(crate) fn is_blocked(&self, path: &Path, now: Instant) -> bool {
+        let mut blocked = self.lock();
+        match blocked.get(path).cloned() {
+            Some(until) if now < until => true,
+            Some(_) => {
+                blocked.remove(path);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Estimates the bytes held by the blocks.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        self.lock().keys().map(|path| path_entry_bytes::<Instant>(path)).sum()
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<PathBuf, Instant>> {
+        match self.blocked.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+
+/// A problem found by `Cache::verify()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inconsistency {
+    /// The file in the filesystem has a different size than the cached copy.
+    SizeDiffers {
+        path: PathBuf,
+        cached_size: usize,
+        file_size: usize,
+    },
+    /// The file in the filesystem was modified after the cached copy was read.
+    ModifiedSinceCached {
+        path: PathBuf,
+    },
+    /// The file no longer exists in the filesystem.
+    FileMissing {
+        path: PathBuf,
+    },
+    /// The file is in the cache, but its accesses aren't being counted.
+    MissingAccessCount {
+        path: PathBuf,
+    },
+    /// The number of bytes the cache believes it holds differs from the sizes of the files it holds.
+    UsedBytesDrift {
+        counted: usize,
+        actual: usize,
+    },
+}
+
+
+/// The result of checking the cache against itself and the filesystem with `Cache::verify()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    /// Every problem that was found, in no particular order.
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl VerifyReport {
+    /// Checks if no problems were found.
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+}
+
+
+/// A broken invariant of the cache's bookkeeping, found by `Cache::check_invariants()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvariantViolation {
+    /// The size in the stats of the file differs from the number of bytes it holds.
+    SizeMismatch {
+        path: PathBuf,
+        stats_size: usize,
+        bytes: usize,
+    },
+    /// The number of bytes the cache believes it holds differs from the sizes of the files it holds.
+    UsedBytesDrift {
+        counted: usize,
+        actual: usize,
+    },
+    /// The file is in the cache, but its accesses aren't being counted.
+    MissingAccessCount {
+        path: PathBuf,
+    },
+    /// The priority of the file isn't what the priority function gives for its access count and size.
+    StalePriority {
+        path: PathBuf,
+        priority: usize,
+        expected: usize,
+    },
+}
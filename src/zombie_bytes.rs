@@ -0,0 +1,86 @@
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use in_memory_file::FileBytes;
+
+
+/// Keeps track of the bytes of files that left the cache while responses or detached files still shared them,
+/// so they stay in memory without being counted in the used bytes.
+///
+/// Only weak references are kept, so tracking the bytes never keeps them alive.
+/// References whose bytes were dropped are forgotten lazily, whenever the bytes are counted or another file is tracked.
+#[derive(Debug)]
+pub(crate) struct ZombieBytes {
+    zombies: Mutex<Vec<(Weak<Vec<u8>>, usize)>>,
+}
+
+impl ZombieBytes {
+    pub(crate) fn new() -> ZombieBytes {
+        ZombieBytes {
+            zombies: Mutex::new(vec![]),
+        }
+    }
+
+    /// Tracks the bytes of a file that was taken out of the cache, if anything besides the file still shares them.
+    ///
+    /// Only bytes the cache owns are tracked. Embedded and mapped bytes aren't held in the memory of the cache,
+    /// and the bytes of small files are kept in slabs that are counted until the slab is dropped.
+    pub(crate) fn on_removed(&self, bytes: &FileBytes) {
+        let bytes: &Arc<Vec<u8>> = match *bytes {
+            FileBytes::Owned(ref bytes) => bytes,
+            _ => return,
+        };
+        if Arc::strong_count(bytes) <= 1 {
+            return;
+        }
+        let mut zombies = self.lock();
+        prune(&mut zombies);
+        zombies.push((Arc::downgrade(bytes), bytes.len()));
+    }
+
+    /// Adds up the bytes that are still alive, forgetting the ones that were dropped.
+    pub(crate) fn bytes(&self) -> usize {
+        let mut zombies = self.lock();
+        prune(&mut zombies);
+        zombies.iter().map(|&(_, size)| size).sum()
+    }
+
+    /// Estimates the bytes held by the weak references, not counting the bytes they point to.
+    pub(crate) fn bookkeeping_bytes(&self) -> usize {
+        self.lock().capacity() * mem::size_of::<(Weak<Vec<u8>>, usize)>()
+    }
+
+    fn lock(&self) -> MutexGuard<Vec<(Weak<Vec<u8>>, usize)>> {
+        match self.zombies.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// Forgets the bytes that are no longer shared by anything.
+fn prune(zombies: &mut Vec<(Weak<Vec<u8>>, usize)>) {
+    zombies.retain(|(weak, _)| weak.strong_count() > 0);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_bytes_are_forgotten() {
+        let zombies = ZombieBytes::new();
+        let shared: Arc<Vec<u8>> = Arc::new(vec![0; 100]);
+        let kept: FileBytes = FileBytes::Owned(shared.clone());
+        zombies.on_removed(&FileBytes::Owned(shared));
+        // Bytes nothing else shares are dropped with the file, so they aren't tracked.
+        zombies.on_removed(&FileBytes::Owned(Arc::new(vec![0; 50])));
+        zombies.on_removed(&FileBytes::Static(b"embedded"));
+        assert_eq!(zombies.bytes(), 100);
+
+        drop(kept);
+        assert_eq!(zombies.bytes(), 0);
+        assert_eq!(zombies.lock().len(), 0);
+    }
+}